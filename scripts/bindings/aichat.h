@@ -0,0 +1,35 @@
+/* C API of the aichat_core library (built as a cdylib: libaichat_core.so / .dylib / aichat_core.dll). */
+
+#ifndef AICHAT_H
+#define AICHAT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct AichatHandle AichatHandle;
+
+typedef void (*AichatStreamCallback)(const char *text, void *user_data);
+
+/* Create a client. `config_dir` and `model` may be NULL to use the defaults. Returns NULL on failure,
+   e.g. when there is no config file. */
+AichatHandle *aichat_init(const char *config_dir, const char *model);
+
+/* Send a prompt and return the whole reply (free with aichat_string_free). Returns NULL on failure. */
+char *aichat_send(AichatHandle *handle, const char *prompt);
+
+/* Send a prompt and call `callback` with each chunk of the reply. Returns 0 on success, -1 on failure. */
+int aichat_stream(AichatHandle *handle, const char *prompt, AichatStreamCallback callback, void *user_data);
+
+/* The last error of the calling thread, or NULL. */
+const char *aichat_last_error(void);
+
+void aichat_string_free(char *text);
+
+void aichat_free(AichatHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AICHAT_H */
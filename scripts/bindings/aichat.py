@@ -0,0 +1,122 @@
+"""Python bindings for the aichat_core C API (see aichat.h).
+
+    from aichat import Aichat
+    client = Aichat(model="openai:gpt-4o-mini")
+    print(client.send("Hello"))
+    for chunk in client.stream("Tell me a joke"):
+        print(chunk, end="", flush=True)
+
+The shared library is looked up in $AICHAT_LIB, then next to this file, then on the system library path.
+"""
+
+import ctypes
+import ctypes.util
+import os
+import queue
+import sys
+import threading
+
+__all__ = ["Aichat", "AichatError"]
+
+_CALLBACK = ctypes.CFUNCTYPE(None, ctypes.c_char_p, ctypes.c_void_p)
+
+
+class AichatError(Exception):
+    pass
+
+
+def _load_library():
+    if os.environ.get("AICHAT_LIB"):
+        return ctypes.CDLL(os.environ["AICHAT_LIB"])
+    if sys.platform == "win32":
+        name = "aichat_core.dll"
+    elif sys.platform == "darwin":
+        name = "libaichat_core.dylib"
+    else:
+        name = "libaichat_core.so"
+    local = os.path.join(os.path.dirname(os.path.abspath(__file__)), name)
+    if os.path.exists(local):
+        return ctypes.CDLL(local)
+    found = ctypes.util.find_library("aichat_core")
+    if found is None:
+        raise AichatError("Cannot find the aichat_core library, set AICHAT_LIB")
+    return ctypes.CDLL(found)
+
+
+_lib = _load_library()
+_lib.aichat_init.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+_lib.aichat_init.restype = ctypes.c_void_p
+_lib.aichat_send.argtypes = [ctypes.c_void_p, ctypes.c_char_p]
+_lib.aichat_send.restype = ctypes.c_void_p
+_lib.aichat_stream.argtypes = [ctypes.c_void_p, ctypes.c_char_p, _CALLBACK, ctypes.c_void_p]
+_lib.aichat_stream.restype = ctypes.c_int
+_lib.aichat_last_error.argtypes = []
+_lib.aichat_last_error.restype = ctypes.c_char_p
+_lib.aichat_string_free.argtypes = [ctypes.c_void_p]
+_lib.aichat_string_free.restype = None
+_lib.aichat_free.argtypes = [ctypes.c_void_p]
+_lib.aichat_free.restype = None
+
+
+def _encode(value):
+    return None if value is None else value.encode("utf-8")
+
+
+def _last_error():
+    message = _lib.aichat_last_error()
+    return AichatError(message.decode("utf-8") if message else "Unknown error")
+
+
+class Aichat:
+    def __init__(self, config_dir=None, model=None):
+        self._handle = _lib.aichat_init(_encode(config_dir), _encode(model))
+        if not self._handle:
+            raise _last_error()
+
+    def send(self, prompt):
+        ptr = _lib.aichat_send(self._handle, _encode(prompt))
+        if not ptr:
+            raise _last_error()
+        try:
+            return ctypes.string_at(ptr).decode("utf-8")
+        finally:
+            _lib.aichat_string_free(ptr)
+
+    def stream(self, prompt):
+        """Yield the reply chunk by chunk."""
+        chunks = queue.Queue()
+        done = object()
+
+        @_CALLBACK
+        def on_text(text, _user_data):
+            chunks.put(text.decode("utf-8"))
+
+        def run():
+            if _lib.aichat_stream(self._handle, _encode(prompt), on_text, None) != 0:
+                chunks.put(_last_error())
+            chunks.put(done)
+
+        thread = threading.Thread(target=run, daemon=True)
+        thread.start()
+        while True:
+            chunk = chunks.get()
+            if chunk is done:
+                break
+            if isinstance(chunk, AichatError):
+                raise chunk
+            yield chunk
+        thread.join()
+
+    def close(self):
+        if self._handle:
+            _lib.aichat_free(self._handle)
+            self._handle = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *_):
+        self.close()
+
+    def __del__(self):
+        self.close()
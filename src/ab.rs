@@ -0,0 +1,131 @@
+use crate::app::print_list;
+use crate::batch::{create_batch_input, grade_reply, run_batch, DEFAULT_BATCH_CONCURRENCY};
+use crate::config::GlobalConfig;
+use crate::utils::{dimmed_text, render_table};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+
+/// The `--variants` file of `--ab`
+#[derive(Debug, Deserialize)]
+struct Variants {
+    input: Option<String>,
+    /// What the grader should look for
+    criteria: Option<String>,
+    variants: Vec<Variant>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Variant {
+    name: String,
+    prompt: String,
+}
+
+pub async fn run(
+    config: &GlobalConfig,
+    variants_path: &str,
+    runs: usize,
+    judge: Option<&str>,
+    text: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let content = fs::read_to_string(variants_path)
+        .with_context(|| format!("Failed to read variants at '{variants_path}'"))?;
+    let variants: Variants = serde_yaml::from_str(&content)
+        .with_context(|| format!("Invalid variants at '{variants_path}'"))?;
+    let Some(text) = text.or(variants.input.clone()) else {
+        bail!("No input, pass it as text or set `input` in '{variants_path}'")
+    };
+    if variants.variants.is_empty() {
+        bail!("No variants in '{variants_path}'");
+    }
+    let runs = runs.max(1);
+
+    let mut inputs = vec![];
+    for variant in &variants.variants {
+        for _ in 0..runs {
+            inputs.push(create_batch_input(
+                config,
+                None,
+                &variant.name,
+                &variant.prompt,
+                &text,
+            )?);
+        }
+    }
+    let mut outputs = run_batch(inputs, DEFAULT_BATCH_CONCURRENCY)
+        .await
+        .into_iter();
+    let criteria = variants
+        .criteria
+        .as_deref()
+        .unwrap_or("The reply is helpful, correct and well written.");
+
+    let mut rows = vec![];
+    for variant in &variants.variants {
+        let (mut errors, mut latency, mut input_tokens, mut output_tokens) = (0, 0, 0, 0);
+        let mut scores = vec![];
+        let mut sample = None;
+        for output in outputs.by_ref().take(runs) {
+            let output = match output {
+                Ok(v) => v,
+                Err(err) => {
+                    errors += 1;
+                    warn!("Variant '{}' failed, {err:#}", variant.name);
+                    continue;
+                }
+            };
+            latency += output.latency_ms.unwrap_or_default();
+            input_tokens += output.input_tokens.unwrap_or_default();
+            output_tokens += output.output_tokens.unwrap_or_default();
+            if let Some(judge) = judge {
+                match grade_reply(config, judge, criteria, &text, &output.text).await {
+                    Ok(score) => scores.push(score),
+                    Err(err) => warn!("Failed to grade '{}', {err:#}", variant.name),
+                }
+            }
+            sample.get_or_insert(output.text);
+        }
+        if !json {
+            println!("{}", dimmed_text(&format!("── {} ──", variant.name)));
+            println!(
+                "{}\n",
+                sample.as_deref().unwrap_or("(no successful reply)").trim()
+            );
+        }
+        let ok = (runs - errors) as u64;
+        let average = |total: u64| match ok {
+            0 => "-".to_string(),
+            _ => (total / ok).to_string(),
+        };
+        rows.push(vec![
+            variant.name.clone(),
+            runs.to_string(),
+            errors.to_string(),
+            average(latency),
+            average(input_tokens),
+            average(output_tokens),
+            match scores.is_empty() {
+                true => "-".to_string(),
+                false => format!("{:.1}", scores.iter().sum::<f64>() / scores.len() as f64),
+            },
+        ]);
+    }
+
+    let headers = [
+        "VARIANT",
+        "RUNS",
+        "ERRORS",
+        "LATENCY_MS",
+        "INPUT_TOKENS",
+        "OUTPUT_TOKENS",
+        "SCORE",
+    ];
+    if json {
+        print_list(true, &headers, rows);
+    } else {
+        println!("{}", render_table(&headers, &rows));
+    }
+    Ok(())
+}
@@ -0,0 +1,708 @@
+//! The `aichat` command line: parse the arguments, then run the REPL, the server or a command.
+
+use crate::cli::Cli;
+use crate::client::{
+    call_chat_completions, call_chat_completions_streaming, error_body, error_json, list_models,
+    need_refresh_models, openai_build_chat_completions_body, sync_models, ErrorClass, Model,
+    ModelType,
+};
+use crate::config::{
+    ensure_parent_exists, import_roles, list_agents, load_env_file, Config, GlobalConfig, Input,
+    RoleLike, RolesImport, WorkingMode, CODE_ROLE, EXPLAIN_SHELL_ROLE, LAST_SESSION_NAME,
+    SHELL_ROLE, TEMP_SESSION_NAME, TRANSLATE_ROLE,
+};
+#[cfg(feature = "voice")]
+use crate::realtime;
+use crate::render::render_error;
+use crate::repl::Repl;
+use crate::tui::Tui;
+use crate::utils::*;
+use crate::{ab, chain, daemon, eval, serve, speak, stats, stream_stdin, summarize, translate};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use inquire::validator::Validation;
+use inquire::Text;
+use parking_lot::RwLock;
+use simplelog::{format_description, ConfigBuilder, LevelFilter, SimpleLogger, WriteLogger};
+use std::{
+    env,
+    io::{stdin, Read},
+    process,
+    sync::Arc,
+};
+
+pub async fn main() -> Result<()> {
+    // Legacy consoles print escapes verbatim unless virtual terminal processing is on
+    #[cfg(windows)]
+    if nu_ansi_term::enable_ansi_support().is_err() {
+        env::set_var("NO_COLOR", "1");
+    }
+    load_env_file()?;
+    let mut cli = Cli::parse();
+    if cli.screenshot {
+        cli.file.push(SCREENSHOT_PATH.into());
+    }
+    let text = cli.text();
+    let text = match cli.stream_stdin {
+        true => text,
+        false => aggregate_text(text)?,
+    };
+    let working_mode = if cli.serve.is_some() {
+        WorkingMode::Serve
+    } else if text.is_none() && cli.file.is_empty() && cli.summarize.is_none() {
+        WorkingMode::Repl
+    } else {
+        WorkingMode::Cmd
+    };
+    setup_logger(working_mode.is_serve())?;
+    let config = Arc::new(RwLock::new(Config::init(working_mode)?));
+    let error_format_json = cli.error_format == "json";
+    let verbose = cli.verbose;
+    if let Err(err) = run(config, cli, text).await {
+        let exit_code = ErrorClass::of(&err).exit_code();
+        if error_format_json {
+            eprintln!("{}", error_json(&err, verbose));
+        } else {
+            if let Some(body) = error_body(&err).filter(|_| verbose) {
+                eprintln!("{}", dimmed_text(&format!("Raw error body: {body}")));
+            }
+            render_error(err);
+        }
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
+
+async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()> {
+    let abort_signal = create_abort_signal();
+    set_quiet(cli.quiet);
+    if cli.offline {
+        config.write().offline = true;
+    }
+    set_offline(config.read().offline);
+
+    if let Some(addr) = cli.serve {
+        return serve::run(config, addr).await;
+    }
+    if cli.info {
+        config.write().cli_info_flag = true;
+    }
+
+    if cli.sync_models {
+        for (client_name, ret) in sync_models(&config).await? {
+            match ret {
+                Ok(count) => println!("✓ Synced {count} models from '{client_name}'"),
+                Err(err) => eprintln!("✗ Failed to sync models from '{client_name}', {err}"),
+            }
+        }
+        return Ok(());
+    }
+    if let Some(source) = &cli.import_roles {
+        let report = import_roles(source).await?;
+        print_roles_import(&report);
+        return Ok(());
+    }
+    if !is_offline() && need_refresh_models(&config.read()) {
+        if config.read().working_mode.is_cmd() {
+            // A one-shot command would exit before the refresh completes, a child process does it
+            if let Err(err) = spawn_sync_models() {
+                warn!("Failed to refresh models, {err}");
+            }
+        } else {
+            let config = config.clone();
+            tokio::spawn(async move {
+                if let Err(err) = sync_models(&config).await {
+                    warn!("Failed to refresh models, {err}");
+                }
+            });
+        }
+    }
+    if cli.list_models {
+        list_chat_models(&config.read(), cli.json);
+        return Ok(());
+    }
+    if cli.list_roles {
+        list_roles(cli.json);
+        return Ok(());
+    }
+    if cli.list_agents {
+        print_list(cli.json, &["NAME"], names_to_rows(list_agents()));
+        return Ok(());
+    }
+    if cli.list_rags {
+        print_list(cli.json, &["NAME"], names_to_rows(Config::list_rags()));
+        return Ok(());
+    }
+    if let Some(period) = &cli.stats {
+        return stats::run(period.as_deref().unwrap_or("30d"), cli.json);
+    }
+    if cli.dry_run {
+        config.write().dry_run = true;
+    }
+    if cli.model.is_some() {
+        // The model given on the command line is used for every role and mode
+        config.write().default_models.clear();
+    }
+
+    if let Some(agent) = &cli.agent {
+        let session = cli
+            .session
+            .as_ref()
+            .map(|v| match v {
+                Some(v) => v.as_str(),
+                None => TEMP_SESSION_NAME,
+            })
+            .or(cli.last.then_some(LAST_SESSION_NAME));
+        if !cli.agent_variable.is_empty() {
+            config.write().cli_agent_variables = Some(
+                cli.agent_variable
+                    .chunks(2)
+                    .map(|v| (v[0].to_string(), v[1].to_string()))
+                    .collect(),
+            );
+        }
+
+        Config::use_agent(&config, agent, session, abort_signal.clone()).await?
+    } else {
+        if let Some(prompt) = &cli.prompt {
+            config.write().use_prompt(prompt)?;
+        } else if let Some(name) = &cli.role {
+            config.write().use_role(name)?;
+        } else if cli.execute {
+            config.write().use_role(SHELL_ROLE)?;
+        } else if cli.code {
+            config.write().use_role(CODE_ROLE)?;
+        } else if let Some(lang) = &cli.translate {
+            config
+                .write()
+                .use_role(&format!("{TRANSLATE_ROLE}#{lang}"))?;
+        }
+        if let Some(session) = &cli.session {
+            config
+                .write()
+                .use_session(session.as_ref().map(|v| v.as_str()))?;
+        } else if cli.last {
+            config.write().use_session(Some(LAST_SESSION_NAME))?;
+        }
+        if let Some(rag) = &cli.rag {
+            Config::use_rag(&config, Some(rag), abort_signal.clone()).await?;
+        }
+    }
+    if cli.list_sessions {
+        list_sessions(&config.read(), cli.json);
+        return Ok(());
+    }
+    if let Some(model_id) = &cli.model {
+        config.write().set_model(model_id)?;
+    }
+    if cli.daemon {
+        return daemon::run(&config).await;
+    }
+    if let Some(path) = &cli.eval {
+        return eval::run(&config, path, cli.json).await;
+    }
+    if cli.ab {
+        let variants = cli.variants.as_deref().unwrap_or_default();
+        return ab::run(
+            &config,
+            variants,
+            cli.runs,
+            cli.judge.as_deref(),
+            text,
+            cli.json,
+        )
+        .await;
+    }
+    if cli.no_stream {
+        config.write().stream = false;
+    }
+    if let Some(pipe) = &cli.pipe {
+        config.write().output_pipe = Some(pipe.clone());
+    }
+    if cli.empty_session {
+        config.write().empty_session()?;
+    }
+    if cli.save_session {
+        config.write().set_save_session_this_time()?;
+    }
+    if cli.info {
+        let info = config.read().info()?;
+        println!("{}", info);
+        return Ok(());
+    }
+    if cli.stream_stdin {
+        return stream_stdin::run(&config, text, cli.window, abort_signal).await;
+    }
+    if let Some(path) = &cli.summarize {
+        return summarize::run(&config, path, abort_signal).await;
+    }
+    if cli.realtime {
+        #[cfg(feature = "voice")]
+        return realtime::run(&config, abort_signal).await;
+        #[cfg(not(feature = "voice"))]
+        bail!("Realtime is unavailable, aichat was built without the `voice` feature");
+    }
+    if let Some(chain) = &cli.chain {
+        return chain::run(&config, chain, text, &cli.file, cli.verbose, abort_signal).await;
+    }
+    let is_repl = config.read().working_mode.is_repl();
+    if cli.execute && !is_repl {
+        if cfg!(target_os = "macos") && !*IS_STDIN_TERMINAL {
+            bail!("Unable to read the pipe for shell execution on MacOS")
+        }
+        let input = create_input(&config, text, &cli.file, cli.full, abort_signal.clone()).await?;
+        shell_execute(&config, &SHELL, input, abort_signal.clone()).await?;
+        return Ok(());
+    }
+    config.write().apply_prelude()?;
+    match is_repl {
+        false => {
+            let mut input =
+                create_input(&config, text, &cli.file, cli.full, abort_signal.clone()).await?;
+            input.use_embeddings(abort_signal.clone()).await?;
+            if let Some(lang) = &cli.translate {
+                return translate::run(&config, lang, input, abort_signal).await;
+            }
+            if cli.output_format == "openai-json" {
+                return start_directive_json(&config, input, abort_signal).await;
+            }
+            start_directive(&config, input, cli.code, abort_signal).await
+        }
+        true => {
+            if !*IS_STDOUT_TERMINAL {
+                bail!("No TTY for REPL")
+            }
+            match cli.tui {
+                true => Tui::init(&config)?.run().await,
+                false => start_interactive(&config).await,
+            }
+        }
+    }
+}
+
+#[async_recursion::async_recursion]
+pub(crate) async fn start_directive(
+    config: &GlobalConfig,
+    input: Input,
+    code_mode: bool,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let client = input.create_client()?;
+    let extract_code = !*IS_STDOUT_TERMINAL && code_mode;
+    config.write().before_chat_completion(&input)?;
+    let (output, tool_results) = if !input.stream() || extract_code {
+        call_chat_completions(&input, extract_code, client.as_ref(), abort_signal.clone()).await?
+    } else {
+        call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+    };
+    config
+        .write()
+        .after_chat_completion(&input, &output, &tool_results)?;
+
+    if !tool_results.is_empty() {
+        start_directive(
+            config,
+            input.merge_tool_results(output.text, tool_results),
+            code_mode,
+            abort_signal,
+        )
+        .await?;
+    } else if config.read().should_auto_continue(&input, &output) {
+        let last_message = config.read().last_message.clone();
+        if let Some((mut input, output)) = last_message {
+            input.set_continue_output(&output.text);
+            start_directive(config, input, code_mode, abort_signal).await?;
+        }
+    } else {
+        speak::maybe_speak(config, &input, &output.text).await?;
+    }
+
+    config.write().exit_session()?;
+    Ok(())
+}
+
+/// `--output-format openai-json`, one request like `curl` would send, with tool calls returned
+/// rather than run
+async fn start_directive_json(
+    config: &GlobalConfig,
+    input: Input,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let client = input.create_client()?;
+    let data = input.prepare_completion_data(client.model(), false)?;
+    let mut body = openai_build_chat_completions_body(data, client.model());
+    config.write().before_chat_completion(&input)?;
+    let output = abortable_run_with_spinner(
+        client.chat_completions(input.clone()),
+        "Generating",
+        abort_signal,
+    )
+    .await?;
+    config.write().after_chat_completion(&input, &output, &[])?;
+
+    let created = chrono::Utc::now().timestamp();
+    let model = client.model().name();
+    let mut exchange =
+        serve::chat_completion_json(&serve::generate_completion_id(), model, created, &output);
+    exchange["messages"] = body["messages"].take();
+    println!("{}", serde_json::to_string_pretty(&exchange)?);
+    config.write().exit_session()?;
+    Ok(())
+}
+
+async fn start_interactive(config: &GlobalConfig) -> Result<()> {
+    let mut repl: Repl = Repl::init(config)?;
+    repl.run().await
+}
+
+#[async_recursion::async_recursion]
+async fn shell_execute(
+    config: &GlobalConfig,
+    shell: &Shell,
+    mut input: Input,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let client = input.create_client()?;
+    config.write().before_chat_completion(&input)?;
+    let ret = abortable_run_with_spinner(
+        client.chat_completions(input.clone()),
+        "Generating",
+        abort_signal.clone(),
+    )
+    .await;
+    let mut output = ret?;
+    if let Ok(true) = CODE_BLOCK_RE.is_match(&output.text) {
+        output.text = extract_block(&output.text);
+    }
+    config.write().after_chat_completion(&input, &output, &[])?;
+    let eval_str = output.text;
+    if eval_str.is_empty() {
+        bail!("No command generated");
+    }
+    if config.read().dry_run {
+        config.read().print_markdown(&eval_str)?;
+        return Ok(());
+    }
+    if *IS_STDOUT_TERMINAL {
+        let options = ["execute", "revise", "describe", "copy", "quit"];
+        let command = color_text(eval_str.trim(), nu_ansi_term::Color::Rgb(255, 165, 0));
+        let first_letter_color = nu_ansi_term::Color::Cyan;
+        let prompt_text = options
+            .iter()
+            .map(|v| format!("{}{}", color_text(&v[0..1], first_letter_color), &v[1..]))
+            .collect::<Vec<String>>()
+            .join(&dimmed_text(" | "));
+        loop {
+            println!("{command}");
+            let answer = Text::new(&format!("{prompt_text}:"))
+                .with_default("e")
+                .with_validator(
+                    |input: &str| match matches!(input, "e" | "r" | "d" | "c" | "q") {
+                        true => Ok(Validation::Valid),
+                        false => Ok(Validation::Invalid(
+                            "Invalid option, choice one of e, r, d, c or q".into(),
+                        )),
+                    },
+                )
+                .prompt()?;
+
+            match answer.as_str() {
+                "e" => {
+                    config.read().shell_guard()?.check(&eval_str)?;
+                    let args = vec![shell.arg.clone(), eval_str.clone()];
+                    let (cmd, args) = match &config.read().shell_sandbox {
+                        Some(sandbox) => wrap_in_sandbox(sandbox, &shell.cmd, &args)?,
+                        None => (shell.cmd.clone(), args),
+                    };
+                    debug!("{cmd} {args:?}");
+                    let code = run_command(&cmd, &args, None)?;
+                    if code == 0 && config.read().save_shell_history {
+                        let _ = append_to_shell_history(&shell.name, &eval_str, code);
+                    }
+                    process::exit(code);
+                }
+                "r" => {
+                    let revision = Text::new("Enter your revision:").prompt()?;
+                    let text = format!("{}\n{revision}", input.text());
+                    input.set_text(text);
+                    return shell_execute(config, shell, input, abort_signal.clone()).await;
+                }
+                "d" => {
+                    let role = config.read().retrieve_role(EXPLAIN_SHELL_ROLE)?;
+                    let input = Input::from_str(config, &eval_str, Some(role));
+                    if input.stream() {
+                        call_chat_completions_streaming(
+                            &input,
+                            client.as_ref(),
+                            abort_signal.clone(),
+                        )
+                        .await?;
+                    } else {
+                        call_chat_completions(&input, false, client.as_ref(), abort_signal.clone())
+                            .await?;
+                    }
+                    println!();
+                    continue;
+                }
+                "c" => {
+                    set_text(&eval_str)?;
+                    println!("{}", dimmed_text("✓ Copied the command."));
+                }
+                _ => {}
+            }
+            break;
+        }
+    } else {
+        println!("{}", eval_str);
+    }
+    Ok(())
+}
+
+/// Run `--sync-models` detached, so that the command doesn't wait for it.
+fn spawn_sync_models() -> Result<()> {
+    process::Command::new(env::current_exe()?)
+        .arg("--sync-models")
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+fn list_chat_models(config: &Config, json: bool) {
+    // Only the table lists aliases, scripts parse the plain and json output
+    let aliases: Vec<_> = if *IS_STDOUT_TERMINAL && !json {
+        config
+            .model_aliases
+            .iter()
+            .filter_map(|(alias, model_id)| {
+                Model::retrieve_model(config, model_id, ModelType::Chat)
+                    .ok()
+                    .map(|model| (alias.clone(), model))
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    let rows = list_models(config, ModelType::Chat)
+        .into_iter()
+        .map(|model| (model.id(), model.clone()))
+        .chain(aliases)
+        .map(|(id, model)| {
+            let data = model.data();
+            let mut capabilities = vec![];
+            if data.supports_vision {
+                capabilities.push("vision");
+            }
+            if data.supports_function_calling {
+                capabilities.push("tools");
+            }
+            vec![
+                id,
+                format_option_value(&data.max_input_tokens),
+                format_option_value(&data.max_output_tokens),
+                format_option_value(&data.input_price),
+                format_option_value(&data.output_price),
+                capabilities.join(","),
+            ]
+        })
+        .collect();
+    print_list(
+        json,
+        &[
+            "MODEL",
+            "CONTEXT",
+            "MAX_OUTPUT",
+            "INPUT_PRICE",
+            "OUTPUT_PRICE",
+            "CAPABILITIES",
+        ],
+        rows,
+    );
+}
+
+fn list_roles(json: bool) {
+    let rows = Config::list_roles(true)
+        .into_iter()
+        .map(|name| {
+            let role = Config::load_role(&name).unwrap_or_default();
+            vec![
+                name,
+                role.model_id().unwrap_or_default().to_string(),
+                format_option_value(&role.temperature()),
+                format_option_value(&role.use_tools()),
+                role.description(),
+            ]
+        })
+        .collect();
+    print_list(
+        json,
+        &["NAME", "MODEL", "TEMPERATURE", "USE_TOOLS", "DESCRIPTION"],
+        rows,
+    );
+}
+
+fn list_sessions(config: &Config, json: bool) {
+    let rows = config
+        .list_sessions()
+        .into_iter()
+        .map(|name| {
+            let path = config.session_file(&name);
+            let messages = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|v| serde_yaml::from_str::<serde_yaml::Value>(&v).ok())
+                .and_then(|v| v["messages"].as_sequence().map(|v| v.len()));
+            let updated = std::fs::metadata(&path)
+                .and_then(|v| v.modified())
+                .ok()
+                .map(|v| {
+                    chrono::DateTime::<chrono::Local>::from(v)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                });
+            vec![
+                name,
+                format_option_value(&messages),
+                format_option_value(&updated),
+            ]
+        })
+        .collect();
+    print_list(json, &["NAME", "MESSAGES", "UPDATED"], rows);
+}
+
+fn print_roles_import(report: &RolesImport) {
+    let RolesImport {
+        namespace,
+        added,
+        updated,
+        unchanged,
+        removed,
+        conflicts,
+    } = report;
+    for (label, names) in [("Added", added), ("Updated", updated), ("Removed", removed)] {
+        if !names.is_empty() {
+            println!("{label}: {}", names.join(", "));
+        }
+    }
+    for conflict in conflicts {
+        eprintln!("{}", warning_text(&format!("⚠ {conflict}")));
+    }
+    println!(
+        "✓ Imported {} roles into '{namespace}/' ({} unchanged)",
+        added.len() + updated.len() + unchanged.len(),
+        unchanged.len()
+    );
+}
+
+fn names_to_rows(names: Vec<String>) -> Vec<Vec<String>> {
+    names.into_iter().map(|v| vec![v]).collect()
+}
+
+/// Print a table on a terminal, bare names (the first column) when piped, or json objects keyed by lowercased headers.
+pub(crate) fn print_list(json: bool, headers: &[&str], rows: Vec<Vec<String>>) {
+    if json {
+        let items: Vec<serde_json::Value> = rows
+            .iter()
+            .map(|row| {
+                let item: serde_json::Map<String, serde_json::Value> = headers
+                    .iter()
+                    .zip(row)
+                    .map(|(header, cell)| {
+                        let value = if cell.is_empty() || cell == "-" {
+                            serde_json::Value::Null
+                        } else {
+                            serde_json::from_str::<serde_json::Number>(cell)
+                                .map(serde_json::Value::Number)
+                                .unwrap_or_else(|_| cell.clone().into())
+                        };
+                        (header.to_lowercase(), value)
+                    })
+                    .collect();
+                item.into()
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&items).unwrap_or_default()
+        );
+    } else if *IS_STDOUT_TERMINAL {
+        println!("{}", render_table(headers, &rows));
+    } else {
+        for row in rows {
+            println!("{}", row[0]);
+        }
+    }
+}
+
+fn aggregate_text(text: Option<String>) -> Result<Option<String>> {
+    let text = if *IS_STDIN_TERMINAL {
+        text
+    } else {
+        let mut stdin_text = String::new();
+        stdin().read_to_string(&mut stdin_text)?;
+        if let Some(text) = text {
+            Some(format!("{text}\n{stdin_text}"))
+        } else {
+            Some(stdin_text)
+        }
+    };
+    Ok(text)
+}
+
+async fn create_input(
+    config: &GlobalConfig,
+    text: Option<String>,
+    file: &[String],
+    full: bool,
+    abort_signal: AbortSignal,
+) -> Result<Input> {
+    let input = Input::builder(config)
+        .text(&text.unwrap_or_default())
+        .files(file.to_vec())
+        .full(full)
+        .spinner(abort_signal)
+        .build()
+        .await?;
+    if input.is_empty() {
+        bail!("No input");
+    }
+    Ok(input)
+}
+
+fn setup_logger(is_serve: bool) -> Result<()> {
+    let (log_level, log_path) = Config::log_config(is_serve)?;
+    if log_level == LevelFilter::Off {
+        return Ok(());
+    }
+    let crate_name = env!("CARGO_CRATE_NAME");
+    let log_filter = match std::env::var(get_env_name("log_filter")) {
+        // The modules moved into the library, `aichat::serve` is now `aichat_core::serve`
+        Ok(v) => match v.strip_prefix("aichat::") {
+            Some(path) => format!("{crate_name}::{path}"),
+            None => v,
+        },
+        Err(_) => match is_serve {
+            true => format!("{crate_name}::serve"),
+            false => crate_name.into(),
+        },
+    };
+    let config = ConfigBuilder::new()
+        .add_filter_allow(log_filter)
+        .set_time_format_custom(format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
+        ))
+        .set_thread_level(LevelFilter::Off)
+        .build();
+    match log_path {
+        None => {
+            SimpleLogger::init(log_level, config)?;
+        }
+        Some(log_path) => {
+            ensure_parent_exists(&log_path)?;
+            let log_file = std::fs::File::create(log_path)?;
+            WriteLogger::init(log_level, config, log_file)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Run many inputs concurrently, used by `--ab`, `--eval` and `--summarize`.
+
+use crate::client::{ChatCompletionsOutput, Model, ModelType};
+use crate::config::{GlobalConfig, Input, Role, RoleLike};
+
+use anyhow::Result;
+use futures_util::{stream, StreamExt};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+/// Send every input (non-streaming) with at most `concurrency` requests in flight, keeping the order.
+pub async fn run_batch(
+    inputs: Vec<Input>,
+    concurrency: usize,
+) -> Vec<Result<ChatCompletionsOutput>> {
+    run_batch_with_progress(inputs, concurrency, |_, _| {}).await
+}
+
+/// Like [`run_batch`], calling `progress(done, total)` as each input completes.
+pub async fn run_batch_with_progress(
+    inputs: Vec<Input>,
+    concurrency: usize,
+    progress: impl Fn(usize, usize),
+) -> Vec<Result<ChatCompletionsOutput>> {
+    let total = inputs.len();
+    let done = AtomicUsize::new(0);
+    let (done, progress) = (&done, &progress);
+    stream::iter(inputs)
+        .map(|input| async move {
+            let ret = async {
+                let client = input.create_client()?;
+                let start = Instant::now();
+                let mut output = client.chat_completions(input).await?;
+                output.latency_ms = Some(start.elapsed().as_millis() as u64);
+                Ok(output)
+            }
+            .await;
+            progress(done.fetch_add(1, Ordering::SeqCst) + 1, total);
+            ret
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// An input using `prompt` as a temporary role, sent to `model_id` (or the current model).
+pub fn create_batch_input(
+    config: &GlobalConfig,
+    model_id: Option<&str>,
+    name: &str,
+    prompt: &str,
+    text: &str,
+) -> Result<Input> {
+    let model = match model_id {
+        Some(model_id) => Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?,
+        None => config.read().current_model().clone(),
+    };
+    let mut role = Role::new(name, prompt);
+    role.set_model(&model);
+    Ok(Input::from_str(config, text, Some(role)))
+}
+
+/// Ask the grader model to score `reply` from 1 to 10 against `criteria`.
+pub async fn grade_reply(
+    config: &GlobalConfig,
+    grader_model: &str,
+    criteria: &str,
+    request: &str,
+    reply: &str,
+) -> Result<f64> {
+    let prompt = "You are a strict grader. Score how well the reply satisfies the criteria on a scale of 1 to 10. Answer with the number only.";
+    let text =
+        format!("Criteria:\n{criteria}\n\nRequest:\n{request}\n\nReply:\n{reply}\n\nScore (1-10):");
+    let input = create_batch_input(config, Some(grader_model), "grader", prompt, &text)?;
+    let mut outputs = run_batch(vec![input], 1).await;
+    let output = outputs.remove(0)?;
+    parse_score(&output.text)
+        .ok_or_else(|| anyhow::anyhow!("Invalid grader reply: {}", output.text.trim()))
+}
+
+fn parse_score(text: &str) -> Option<f64> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .filter_map(|v| v.trim_matches('.').parse::<f64>().ok())
+        .next()
+        .map(|v| v.clamp(0.0, 10.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("8"), Some(8.0));
+        assert_eq!(parse_score("Score: 7.5/10"), Some(7.5));
+        assert_eq!(parse_score("none"), None);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::batch::{run_batch, DEFAULT_BATCH_CONCURRENCY};
+use crate::client::{call_chat_completions, call_chat_completions_streaming, Model, ModelType};
+use crate::config::{GlobalConfig, Input, Role, RoleLike};
+use crate::utils::{abortable_run_with_spinner, dimmed_text, AbortSignal};
+
+use anyhow::{anyhow, bail, Result};
+
+/// One step of `--chain`: `ROLE[:ARG...][@MODEL]`
+#[derive(Debug, Clone, PartialEq)]
+struct ChainStep {
+    role: String,
+    model_id: Option<String>,
+}
+
+/// Run `--chain`, e.g. `extract -> summarize, keywords -> translate:fr`.
+///
+/// Stages separated by `->` run in order, the output of a stage is the input of the next.
+/// The comma separated steps of a stage run concurrently and their outputs are joined.
+pub async fn run(
+    config: &GlobalConfig,
+    chain: &str,
+    text: Option<String>,
+    files: &[String],
+    verbose: bool,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let stages = parse_chain(chain)?;
+    let text = text.unwrap_or_default();
+    if text.trim().is_empty() && files.is_empty() {
+        bail!("No input");
+    }
+    let total = stages.len();
+    let mut previous: Option<String> = None;
+    for (i, stage) in stages.iter().enumerate() {
+        let mut inputs = vec![];
+        for step in stage {
+            let role = step_role(config, step)?;
+            let input = match &previous {
+                Some(text) => Input::from_str(config, text, Some(role)),
+                None => {
+                    Input::builder(config)
+                        .text(&text)
+                        .files(files.to_vec())
+                        .role(role)
+                        .spinner(abort_signal.clone())
+                        .build()
+                        .await?
+                }
+            };
+            inputs.push(input);
+        }
+        if i + 1 == total && inputs.len() == 1 {
+            let input = &inputs[0];
+            if verbose {
+                println!("{}", dimmed_text(&format!("── {} ──", stage[0].role)));
+            }
+            let client = input.create_client()?;
+            if input.stream() {
+                call_chat_completions_streaming(input, client.as_ref(), abort_signal).await?;
+            } else {
+                call_chat_completions(input, false, client.as_ref(), abort_signal).await?;
+            }
+            return Ok(());
+        }
+        let outputs = abortable_run_with_spinner(
+            async { Ok(run_batch(inputs, DEFAULT_BATCH_CONCURRENCY).await) },
+            &format!("Running stage {}/{total}", i + 1),
+            abort_signal.clone(),
+        )
+        .await?;
+        let mut texts = vec![];
+        for (step, output) in stage.iter().zip(outputs) {
+            let output = output.map_err(|err| err.context(format!("Step '{}'", step.role)))?;
+            if verbose || i + 1 == total {
+                println!("{}", dimmed_text(&format!("── {} ──", step.role)));
+                config.read().print_markdown(&output.text)?;
+            }
+            texts.push((step.role.clone(), output.text));
+        }
+        previous = Some(join_outputs(texts));
+    }
+    Ok(())
+}
+
+fn parse_chain(chain: &str) -> Result<Vec<Vec<ChainStep>>> {
+    let stages = chain
+        .split("->")
+        .map(|stage| {
+            stage
+                .split(',')
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+                .map(|step| {
+                    let (role, model_id) = match step.split_once('@') {
+                        Some((role, model_id)) => (role.trim(), Some(model_id.trim().to_string())),
+                        None => (step, None),
+                    };
+                    ChainStep {
+                        role: role.replace(':', "#"),
+                        model_id,
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    if stages.iter().any(|v| v.is_empty()) {
+        bail!("Invalid chain '{chain}', expect steps like 'extract -> summarize -> translate:fr'");
+    }
+    Ok(stages)
+}
+
+/// The role of a step, `translate#fr` falls back to the builtin `%translate%#fr`.
+fn step_role(config: &GlobalConfig, step: &ChainStep) -> Result<Role> {
+    let mut role = config.read().retrieve_role(&step.role).or_else(|err| {
+        let (name, args) = match step.role.split_once('#') {
+            Some((name, args)) => (name, format!("#{args}")),
+            None => (step.role.as_str(), String::new()),
+        };
+        config
+            .read()
+            .retrieve_role(&format!("%{name}%{args}"))
+            .map_err(|_| err)
+    })?;
+    if let Some(model_id) = &step.model_id {
+        let model = Model::retrieve_model(&config.read(), model_id, ModelType::Chat)
+            .map_err(|err| anyhow!("Step '{}': {err}", step.role))?;
+        role.set_model(&model);
+    }
+    Ok(role)
+}
+
+fn join_outputs(texts: Vec<(String, String)>) -> String {
+    if texts.len() == 1 {
+        return texts.into_iter().map(|(_, text)| text).collect();
+    }
+    texts
+        .into_iter()
+        .map(|(role, text)| format!("## {role}\n\n{text}"))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chain() {
+        let step = |role: &str, model_id: Option<&str>| ChainStep {
+            role: role.into(),
+            model_id: model_id.map(|v| v.into()),
+        };
+        assert_eq!(
+            parse_chain("extract -> summarize, keywords@openai:gpt-4o -> translate:fr").unwrap(),
+            vec![
+                vec![step("extract", None)],
+                vec![
+                    step("summarize", None),
+                    step("keywords", Some("openai:gpt-4o"))
+                ],
+                vec![step("translate#fr", None)],
+            ]
+        );
+        assert!(parse_chain("extract -> -> summarize").is_err());
+    }
+}
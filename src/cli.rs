@@ -15,6 +15,9 @@ pub struct Cli {
     /// Start or join a session
     #[clap(short = 's', long)]
     pub session: Option<Option<String>>,
+    /// Resume the most recently modified session
+    #[clap(long)]
+    pub last: bool,
     /// Ensure the session is empty
     #[clap(long)]
     pub empty_session: bool,
@@ -30,6 +33,9 @@ pub struct Cli {
     /// Start a RAG
     #[clap(long)]
     pub rag: Option<String>,
+    /// Run the prompts of `schedules` on their cron expressions
+    #[clap(long)]
+    pub daemon: bool,
     /// Serve the LLM API and WebAPP
     #[clap(long, value_name = "ADDRESS")]
     pub serve: Option<Option<String>>,
@@ -39,15 +45,59 @@ pub struct Cli {
     /// Output code only
     #[clap(short = 'c', long)]
     pub code: bool,
+    /// Translate the input into a language, keeping code blocks untouched
+    #[clap(long, value_name = "LANG")]
+    pub translate: Option<String>,
+    /// Summarize a file or url, chunking inputs beyond the context window
+    #[clap(long, value_name = "FILE|URL")]
+    pub summarize: Option<String>,
+    /// Pass the reply of each role to the next, e.g. "extract -> summarize -> translate:fr"
+    #[clap(long, value_name = "CHAIN")]
+    pub chain: Option<String>,
+    /// Print the intermediate results of --chain and the raw body of API errors
+    #[clap(long)]
+    pub verbose: bool,
+    /// Talk with the model live over OpenAI Realtime or Gemini Live (experimental)
+    #[clap(long)]
+    pub realtime: bool,
+    /// Follow stdin and check each window of lines against the instruction
+    #[clap(long)]
+    pub stream_stdin: bool,
+    /// Lines per window of --stream-stdin
+    #[clap(
+        long,
+        value_name = "LINES",
+        default_value_t = 100,
+        requires = "stream_stdin"
+    )]
+    pub window: usize,
+    /// Start the full-screen TUI instead of the REPL
+    #[clap(long)]
+    pub tui: bool,
     /// Include files with the message
     #[clap(short = 'f', long, value_name = "FILE")]
     pub file: Vec<String>,
+    /// Attach tables in full instead of their profile
+    #[clap(long)]
+    pub full: bool,
+    /// Attach a screenshot, `-f @screen:region` to select a region
+    #[clap(long)]
+    pub screenshot: bool,
     /// Turn off stream mode
     #[clap(short = 'S', long)]
     pub no_stream: bool,
-    /// Display the message without sending it
+    /// Pipe the reply through a command, overrides `output_pipe`
+    #[clap(long, value_name = "COMMAND")]
+    pub pipe: Option<String>,
+    /// Suppress spinners, warnings and progress messages
+    #[clap(short = 'q', long)]
+    pub quiet: bool,
+    /// Display the message without sending it, or reply with the configured `mock`
     #[clap(long)]
     pub dry_run: bool,
+    /// Forbid network access, only dry runs and local clients (on localhost) work
+    #[clap(long)]
+    pub offline: bool,
     /// Display information
     #[clap(long)]
     pub info: bool,
@@ -66,6 +116,39 @@ pub struct Cli {
     /// List all RAGs
     #[clap(long)]
     pub list_rags: bool,
+    /// Import roles from a roles.yaml, role file, directory or git repo into roles.d/
+    #[clap(long, value_name = "URL|GIT|PATH")]
+    pub import_roles: Option<String>,
+    /// Fetch the model lists of configured providers into models-override.yaml
+    #[clap(long)]
+    pub sync_models: bool,
+    /// Compare prompt variants, see --variants
+    #[clap(long, requires = "variants")]
+    pub ab: bool,
+    /// YAML file with the prompt variants for --ab
+    #[clap(long, value_name = "FILE")]
+    pub variants: Option<String>,
+    /// How many times --ab runs each variant
+    #[clap(long, value_name = "N", default_value_t = 3)]
+    pub runs: usize,
+    /// Grader model scoring the replies of --ab
+    #[clap(long, value_name = "MODEL")]
+    pub judge: Option<String>,
+    /// Show the recorded usage by day, model and role over a period: 7d, 4w, 3m or all [default: 30d]
+    #[clap(long, value_name = "PERIOD")]
+    pub stats: Option<Option<String>>,
+    /// Evaluate models against the test cases in a YAML file
+    #[clap(long, value_name = "FILE")]
+    pub eval: Option<String>,
+    /// Print the --list-*, --stats, --ab and --eval output as JSON
+    #[clap(long)]
+    pub json: bool,
+    /// Print errors as text or as a JSON object on stderr
+    #[clap(long, value_name = "FORMAT", value_parser = ["text", "json"], default_value = "text")]
+    pub error_format: String,
+    /// Print the reply as text or as an OpenAI chat completion with the messages sent
+    #[clap(long, value_name = "FORMAT", value_parser = ["text", "openai-json"], default_value = "text")]
+    pub output_format: String,
     /// Input text
     #[clap(trailing_var_arg = true)]
     text: Vec<String>,
@@ -85,3 +168,18 @@ impl Cli {
         Some(text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_requires_stream_stdin() {
+        assert!(Cli::try_parse_from(["aichat", "--window", "50", "hi"]).is_err());
+        let cli =
+            Cli::try_parse_from(["aichat", "--stream-stdin", "--window", "50", "hi"]).unwrap();
+        assert_eq!(cli.window, 50);
+        let cli = Cli::try_parse_from(["aichat", "hi"]).unwrap();
+        assert_eq!(cli.window, 100);
+    }
+}
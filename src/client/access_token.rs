@@ -1,33 +1,106 @@
 use anyhow::{anyhow, Result};
 use chrono::Utc;
 use indexmap::IndexMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use std::{future::Future, sync::Arc};
+
+/// Refresh tokens this many seconds before they expire
+const REFRESH_MARGIN: i64 = 300;
 
 lazy_static::lazy_static! {
     static ref ACCESS_TOKENS: RwLock<IndexMap<String, (String, i64)>> =
         RwLock::new(IndexMap::new());
+    static ref REFRESH_LOCKS: Mutex<IndexMap<String, Arc<tokio::sync::Mutex<()>>>> =
+        Mutex::new(IndexMap::new());
 }
 
-pub fn get_access_token(client_name: &str) -> Result<String> {
+pub fn get_access_token(key: &str) -> Result<String> {
     ACCESS_TOKENS
         .read()
-        .get(client_name)
+        .get(key)
         .map(|(token, _)| token.clone())
         .ok_or_else(|| anyhow!("Invalid access token"))
 }
 
-pub fn is_valid_access_token(client_name: &str) -> bool {
+pub fn is_valid_access_token(key: &str) -> bool {
+    check_access_token(key, REFRESH_MARGIN)
+}
+
+pub fn set_access_token(key: &str, token: String, expires_at: i64) {
+    let mut access_tokens = ACCESS_TOKENS.write();
+    let entry = access_tokens.entry(key.to_string()).or_default();
+    entry.0 = token;
+    entry.1 = expires_at;
+}
+
+/// Ensure a fresh access token is cached under `key`.
+///
+/// Concurrent callers share a single in-flight refresh. `fetch` returns `(token, expires_at)`.
+/// If refreshing fails while the cached token has not actually expired yet, it keeps being used.
+pub async fn refresh_access_token<F, Fut>(key: &str, fetch: F) -> Result<()>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(String, i64)>>,
+{
+    if is_valid_access_token(key) {
+        return Ok(());
+    }
+    let lock = REFRESH_LOCKS
+        .lock()
+        .entry(key.to_string())
+        .or_default()
+        .clone();
+    let _guard = lock.lock().await;
+    if is_valid_access_token(key) {
+        return Ok(());
+    }
+    match fetch().await {
+        Ok((token, expires_at)) => {
+            set_access_token(key, token, expires_at);
+            Ok(())
+        }
+        Err(err) => {
+            if check_access_token(key, 0) {
+                warn!("Failed to refresh access token '{key}', {err}");
+                Ok(())
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+fn check_access_token(key: &str, margin: i64) -> bool {
     let access_tokens = ACCESS_TOKENS.read();
-    let (token, expires_at) = match access_tokens.get(client_name) {
+    let (token, expires_at) = match access_tokens.get(key) {
         Some(v) => v,
         None => return false,
     };
-    !token.is_empty() && Utc::now().timestamp() < *expires_at
+    !token.is_empty() && Utc::now().timestamp() + margin < *expires_at
 }
 
-pub fn set_access_token(client_name: &str, token: String, expires_at: i64) {
-    let mut access_tokens = ACCESS_TOKENS.write();
-    let entry = access_tokens.entry(client_name.to_string()).or_default();
-    entry.0 = token;
-    entry.1 = expires_at;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_refresh_access_token() {
+        let now = Utc::now().timestamp();
+        let key = "test-refresh";
+
+        set_access_token(key, "old".into(), now + 60);
+        assert!(!is_valid_access_token(key));
+        let ret = refresh_access_token(key, || async { Err(anyhow!("offline")) }).await;
+        assert!(ret.is_ok());
+        assert_eq!(get_access_token(key).unwrap(), "old");
+
+        refresh_access_token(key, || async { Ok(("new".to_string(), now + 3600)) })
+            .await
+            .unwrap();
+        assert_eq!(get_access_token(key).unwrap(), "new");
+
+        set_access_token(key, "old".into(), now - 1);
+        let ret = refresh_access_token(key, || async { Err(anyhow!("offline")) }).await;
+        assert!(ret.is_err());
+    }
 }
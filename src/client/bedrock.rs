@@ -1,6 +1,6 @@
 use super::*;
 
-use crate::utils::{base64_decode, encode_uri, hex_encode, hmac_sha256, sha256};
+use crate::utils::{base64_decode, check_offline, encode_uri, hex_encode, hmac_sha256, sha256};
 
 use anyhow::{bail, Context, Result};
 use aws_smithy_eventstream::frame::{DecodedFrame, MessageFrameDecoder};
@@ -204,6 +204,7 @@ async fn chat_completions_streaming(
         catch_error(&data, status.as_u16())?;
         bail!("Invalid response data: {data}");
     }
+    handler.connected();
 
     let mut function_name = String::new();
     let mut function_arguments = String::new();
@@ -254,17 +255,18 @@ async fn chat_completions_streaming(
                                 function_arguments.push_str(input);
                             }
                         }
-                        "contentBlockStop" => {
-                            if !function_name.is_empty() {
-                                let arguments: Value = function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                                handler.tool_call(ToolCall::new(
-                                    function_name.clone(),
-                                    arguments,
-                                    Some(function_id.clone()),
-                                ))?;
-                            }
+                        "contentBlockStop" if !function_name.is_empty() => {
+                            let arguments: Value = function_arguments.parse().with_context(|| {
+                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
+                            })?;
+                            handler.tool_call(ToolCall::new(
+                                function_name.clone(),
+                                arguments,
+                                Some(function_id.clone()),
+                            ))?;
+                        }
+                        "messageStop" => {
+                            handler.set_finish_reason(data["stopReason"].as_str());
                         }
                         _ => {}
                     }
@@ -310,8 +312,18 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         top_p,
         functions,
         stream: _,
+        stop,
+        response_format,
+        seed: _,
     } = data;
 
+    if response_format == Some(ResponseFormat::Json) {
+        bail!(
+            "The model '{}' doesn't support `response_format: json`",
+            model.id()
+        );
+    }
+
     let system_message = extract_system_message(&mut messages);
 
     let mut network_image_urls = vec![];
@@ -319,7 +331,7 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
     let messages: Vec<Value> = messages
         .into_iter()
         .flat_map(|message| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) => vec![json!({
                     "role": role,
@@ -435,6 +447,9 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
     if let Some(v) = top_p {
         body["inferenceConfig"]["topP"] = v.into();
     }
+    if let Some(v) = stop {
+        body["inferenceConfig"]["stopSequences"] = v.into();
+    }
     if let Some(functions) = functions {
         let tools: Vec<_> = functions
             .iter()
@@ -490,6 +505,8 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: None,
         input_tokens: data["usage"]["inputTokens"].as_u64(),
         output_tokens: data["usage"]["outputTokens"].as_u64(),
+        finish_reason: data["stopReason"].as_str().map(|v| v.to_string()),
+        ..Default::default()
     };
     Ok(output)
 }
@@ -529,6 +546,7 @@ fn aws_fetch(
     let region = &credentials.region;
 
     let endpoint = format!("https://{}{}", host, uri);
+    check_offline(&endpoint)?;
 
     let now: DateTime<Utc> = Utc::now();
     let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
@@ -1,6 +1,7 @@
 use super::*;
 
 use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
 use reqwest::RequestBuilder;
 use serde::Deserialize;
 use serde_json::{json, Value};
@@ -78,69 +79,95 @@ pub async fn claude_chat_completions_streaming(
     handler: &mut SseHandler,
     _model: &Model,
 ) -> Result<()> {
-    let mut function_name = String::new();
-    let mut function_arguments = String::new();
-    let mut function_id = String::new();
+    let mut state = ClaudeStreamState::default();
+    let last_active = handler.last_active();
     let handle = |message: SseMmessage| -> Result<bool> {
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
-        if let Some(typ) = data["type"].as_str() {
-            match typ {
-                "content_block_start" => {
-                    if let (Some("tool_use"), Some(name), Some(id)) = (
-                        data["content_block"]["type"].as_str(),
-                        data["content_block"]["name"].as_str(),
-                        data["content_block"]["id"].as_str(),
-                    ) {
-                        if !function_name.is_empty() {
-                            let arguments: Value =
-                                function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                            handler.tool_call(ToolCall::new(
-                                function_name.clone(),
-                                arguments,
-                                Some(function_id.clone()),
-                            ))?;
+        state.process(&data, handler)
+    };
+
+    sse_stream(builder, last_active, handle).await
+}
+
+/// Tracks the content blocks of a Claude message stream.
+///
+/// Text and tool_use blocks may be interleaved, so tool calls are keyed by block index
+/// and emitted once their block stops.
+#[derive(Debug, Default)]
+struct ClaudeStreamState {
+    tool_uses: IndexMap<u64, (String, String, String)>,
+}
+
+impl ClaudeStreamState {
+    fn process(&mut self, data: &Value, handler: &mut SseHandler) -> Result<bool> {
+        let index = data["index"].as_u64().unwrap_or_default();
+        match data["type"].as_str() {
+            Some("content_block_start") => {
+                let block = &data["content_block"];
+                match block["type"].as_str() {
+                    Some("tool_use") => {
+                        if let (Some(name), Some(id)) =
+                            (block["name"].as_str(), block["id"].as_str())
+                        {
+                            handler.tool_call_start(name, Some(id))?;
+                            self.tool_uses
+                                .insert(index, (name.into(), id.into(), String::new()));
                         }
-                        function_name = name.into();
-                        function_arguments.clear();
-                        function_id = id.into();
                     }
+                    Some("text") => {
+                        if let Some(text) = block["text"].as_str() {
+                            handler.text(text)?;
+                        }
+                    }
+                    _ => {}
                 }
-                "content_block_delta" => {
+            }
+            Some("content_block_delta") => match data["delta"]["type"].as_str() {
+                Some("text_delta") => {
                     if let Some(text) = data["delta"]["text"].as_str() {
                         handler.text(text)?;
-                    } else if let (true, Some(partial_json)) = (
-                        !function_name.is_empty(),
-                        data["delta"]["partial_json"].as_str(),
-                    ) {
-                        function_arguments.push_str(partial_json);
                     }
                 }
-                "content_block_stop" => {
-                    if !function_name.is_empty() {
-                        let arguments: Value = if function_arguments.is_empty() {
-                            json!({})
-                        } else {
-                            function_arguments.parse().with_context(|| {
-                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                            })?
-                        };
-                        handler.tool_call(ToolCall::new(
-                            function_name.clone(),
-                            arguments,
-                            Some(function_id.clone()),
-                        ))?;
+                Some("input_json_delta") => {
+                    if let (Some((_, _, arguments)), Some(partial_json)) = (
+                        self.tool_uses.get_mut(&index),
+                        data["delta"]["partial_json"].as_str(),
+                    ) {
+                        arguments.push_str(partial_json);
+                        handler.tool_call_delta(partial_json)?;
                     }
                 }
                 _ => {}
+            },
+            Some("content_block_stop") => {
+                if let Some((name, id, arguments)) = self.tool_uses.shift_remove(&index) {
+                    let arguments: Value = if arguments.is_empty() {
+                        json!({})
+                    } else {
+                        arguments.parse().with_context(|| {
+                            format!("Tool call '{name}' have non-JSON arguments '{arguments}'")
+                        })?
+                    };
+                    handler.tool_call(ToolCall::new(name, arguments, Some(id)))?;
+                }
             }
+            Some("message_start") => {
+                let usage = &data["message"]["usage"];
+                handler.set_usage(
+                    usage["input_tokens"].as_u64(),
+                    usage["output_tokens"].as_u64(),
+                );
+            }
+            Some("message_delta") => {
+                handler.set_usage(None, data["usage"]["output_tokens"].as_u64());
+                handler.set_finish_reason(data["delta"]["stop_reason"].as_str());
+            }
+            Some("message_stop") => return Ok(true),
+            _ => {}
         }
         Ok(false)
-    };
-
-    sse_stream(builder, handle).await
+    }
 }
 
 pub fn claude_build_chat_completions_body(
@@ -153,8 +180,18 @@ pub fn claude_build_chat_completions_body(
         top_p,
         functions,
         stream,
+        stop,
+        response_format,
+        seed: _,
     } = data;
 
+    if response_format == Some(ResponseFormat::Json) {
+        bail!(
+            "The model '{}' doesn't support `response_format: json`",
+            model.id()
+        );
+    }
+
     let system_message = extract_system_message(&mut messages);
 
     let mut network_image_urls = vec![];
@@ -162,7 +199,7 @@ pub fn claude_build_chat_completions_body(
     let messages: Vec<Value> = messages
         .into_iter()
         .flat_map(|message| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) => vec![json!({
                     "role": role,
@@ -267,6 +304,9 @@ pub fn claude_build_chat_completions_body(
     if stream {
         body["stream"] = true.into();
     }
+    if let Some(v) = stop {
+        body["stop_sequences"] = v.into();
+    }
     if let Some(functions) = functions {
         body["tools"] = functions
             .iter()
@@ -283,7 +323,19 @@ pub fn claude_build_chat_completions_body(
 }
 
 pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
-    let text = data["content"][0]["text"].as_str().unwrap_or_default();
+    let text = data["content"]
+        .as_array()
+        .map(|content| {
+            content
+                .iter()
+                .filter_map(|content| match content["type"].as_str() {
+                    Some("text") => content["text"].as_str(),
+                    _ => None,
+                })
+                .collect::<Vec<&str>>()
+                .join("\n\n")
+        })
+        .unwrap_or_default();
 
     let mut tool_calls = vec![];
     if let Some(calls) = data["content"].as_array().map(|content| {
@@ -317,11 +369,91 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
     }
 
     let output = ChatCompletionsOutput {
-        text: text.to_string(),
+        text,
         tool_calls,
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["output_tokens"].as_u64(),
+        finish_reason: data["stop_reason"].as_str().map(|v| v.to_string()),
+        ..Default::default()
     };
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::create_abort_signal;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn test_claude_rejects_json_response_format() {
+        let data = ChatCompletionsData {
+            messages: vec![Message::new(
+                MessageRole::User,
+                MessageContent::Text("hi".into()),
+            )],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+            stop: None,
+            response_format: Some(ResponseFormat::Json),
+            seed: None,
+        };
+        let model = Model::new("claude", "claude-3-5-sonnet");
+        let err = claude_build_chat_completions_body(data.clone(), &model).unwrap_err();
+        assert!(err.to_string().contains("response_format"));
+        let data = ChatCompletionsData {
+            response_format: Some(ResponseFormat::Text),
+            ..data
+        };
+        assert!(claude_build_chat_completions_body(data, &model).is_ok());
+    }
+
+    #[test]
+    fn test_claude_stream_interleaved_blocks() {
+        let events = [
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Let me check."}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"city\": "}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"Paris\"}"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "content_block_start", "index": 2, "content_block": {"type": "tool_use", "id": "toolu_2", "name": "get_time", "input": {}}}),
+            json!({"type": "content_block_stop", "index": 2}),
+            json!({"type": "message_stop"}),
+        ];
+        let (tx, mut rx) = unbounded_channel();
+        let mut handler = SseHandler::new(tx, create_abort_signal());
+        let mut state = ClaudeStreamState::default();
+        let mut stopped = false;
+        for event in &events {
+            stopped = state.process(event, &mut handler).unwrap();
+        }
+        assert!(stopped);
+
+        let mut kinds = vec![];
+        while let Ok(event) = rx.try_recv() {
+            kinds.push(match event {
+                SseEvent::Text(_) => "text",
+                SseEvent::ToolCallStart { .. } => "start",
+                SseEvent::ToolCallDelta(_) => "delta",
+                SseEvent::ToolCall(_) => "call",
+                SseEvent::Done => "done",
+            });
+        }
+        assert_eq!(
+            kinds,
+            ["text", "start", "delta", "delta", "call", "start", "call"]
+        );
+
+        let (text, tool_calls, _) = handler.take();
+        assert_eq!(text, "Let me check.");
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].arguments, json!({"city": "Paris"}));
+        assert_eq!(tool_calls[1].arguments, json!({}));
+    }
+}
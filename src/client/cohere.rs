@@ -54,6 +54,9 @@ fn prepare_chat_completions(
         if let Some(top_p) = obj.remove("top_p") {
             obj.insert("p".to_string(), top_p);
         }
+        if let Some(stop) = obj.remove("stop") {
+            obj.insert("stop_sequences".to_string(), stop);
+        }
     }
 
     let mut request_data = RequestData::new(url, body);
@@ -129,6 +132,7 @@ async fn chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
+    let last_active = handler.last_active();
     let handle = |message: SseMmessage| -> Result<bool> {
         if message.data == "[DONE]" {
             return Ok(true);
@@ -180,13 +184,16 @@ async fn chat_completions_streaming(
                     function_arguments.clear();
                     function_id.clear();
                 }
+                "message-end" => {
+                    handler.set_finish_reason(data["delta"]["finish_reason"].as_str());
+                }
                 _ => {}
             }
         }
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    sse_stream(builder, last_active, handle).await
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
@@ -251,6 +258,8 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["billed_units"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["billed_units"]["output_tokens"].as_u64(),
+        finish_reason: data["finish_reason"].as_str().map(|v| v.to_string()),
+        ..Default::default()
     };
     Ok(output)
 }
@@ -2,7 +2,10 @@ use super::access_token::*;
 use super::openai_compatible::*;
 use super::*;
 
+use crate::utils::check_offline;
+
 use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
 use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
@@ -41,7 +44,7 @@ impl Client for ErnieClient {
     ) -> Result<ChatCompletionsOutput> {
         prepare_access_token(self, client).await?;
         let request_data = prepare_chat_completions(self, data)?;
-        let builder = self.request_builder(client, request_data);
+        let builder = self.request_builder(client, request_data)?;
         chat_completions(builder, &self.model).await
     }
 
@@ -53,7 +56,7 @@ impl Client for ErnieClient {
     ) -> Result<()> {
         prepare_access_token(self, client).await?;
         let request_data = prepare_chat_completions(self, data)?;
-        let builder = self.request_builder(client, request_data);
+        let builder = self.request_builder(client, request_data)?;
         chat_completions_streaming(builder, handler, &self.model).await
     }
 
@@ -64,7 +67,7 @@ impl Client for ErnieClient {
     ) -> Result<EmbeddingsOutput> {
         prepare_access_token(self, client).await?;
         let request_data = prepare_embeddings(self, data)?;
-        let builder = self.request_builder(client, request_data);
+        let builder = self.request_builder(client, request_data)?;
         embeddings(builder, &self.model).await
     }
 
@@ -75,7 +78,7 @@ impl Client for ErnieClient {
     ) -> Result<RerankOutput> {
         prepare_access_token(self, client).await?;
         let request_data = prepare_rerank(self, data)?;
-        let builder = self.request_builder(client, request_data);
+        let builder = self.request_builder(client, request_data)?;
         rerank(builder, &self.model).await
     }
 }
@@ -138,17 +141,15 @@ fn prepare_rerank(self_: &ErnieClient, data: &RerankData) -> Result<RequestData>
 }
 
 async fn prepare_access_token(self_: &ErnieClient, client: &ReqwestClient) -> Result<()> {
-    let client_name = self_.name();
-    if !is_valid_access_token(client_name) {
-        let api_key = self_.get_api_key()?;
-        let secret_key = self_.get_secret_key()?;
-
+    let api_key = self_.get_api_key()?;
+    let secret_key = self_.get_secret_key()?;
+    refresh_access_token(self_.name(), || async {
         let token = fetch_access_token(client, &api_key, &secret_key)
             .await
             .with_context(|| "Failed to fetch access token")?;
-        set_access_token(client_name, token, 86400);
-    }
-    Ok(())
+        Ok((token, Utc::now().timestamp() + 86400))
+    })
+    .await
 }
 
 async fn chat_completions(
@@ -166,9 +167,11 @@ async fn chat_completions_streaming(
     handler: &mut SseHandler,
     _model: &Model,
 ) -> Result<()> {
+    let last_active = handler.last_active();
     let handle = |message: SseMmessage| -> Result<bool> {
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
+        handler.set_finish_reason(data["finish_reason"].as_str());
         if let Some(function) = data["function_call"].as_object() {
             if let (Some(name), Some(arguments)) = (
                 function.get("name").and_then(|v| v.as_str()),
@@ -185,7 +188,7 @@ async fn chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    sse_stream(builder, last_active, handle).await
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
@@ -222,6 +225,9 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Valu
         top_p,
         functions,
         stream,
+        stop,
+        response_format: _,
+        seed: _,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -229,7 +235,7 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Valu
     let messages: Vec<Value> = messages
         .into_iter()
         .flat_map(|message| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results,  ..
@@ -270,6 +276,9 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Valu
     if let Some(v) = top_p {
         body["top_p"] = v.into();
     }
+    if let Some(v) = stop {
+        body["stop"] = v.into();
+    }
 
     if stream {
         body["stream"] = true.into();
@@ -307,6 +316,8 @@ fn extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsOutput>
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["prompt_tokens"].as_u64(),
         output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        finish_reason: data["finish_reason"].as_str().map(|v| v.to_string()),
+        ..Default::default()
     };
     Ok(output)
 }
@@ -317,6 +328,7 @@ async fn fetch_access_token(
     secret_key: &str,
 ) -> Result<String> {
     let url = format!("{ACCESS_TOKEN_URL}?grant_type=client_credentials&client_id={api_key}&client_secret={secret_key}");
+    check_offline(ACCESS_TOKEN_URL)?;
     let value: Value = client.get(&url).send().await?.json().await?;
     let result = value["access_token"].as_str().ok_or_else(|| {
         if let Some(err_msg) = value["error_description"].as_str() {
@@ -0,0 +1,309 @@
+use super::Model;
+
+use serde_json::{json, Value};
+use std::fmt;
+
+/// An error response of the API, keeping the HTTP status for `ErrorClass`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+    /// The error code or type of the provider, e.g. `insufficient_quota`
+    pub code: Option<String>,
+    /// What the user can do about it
+    pub hint: Option<String>,
+    /// The raw error body, printed with `--verbose`
+    pub body: Value,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: &str, body: Value) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+            code: None,
+            hint: None,
+            body,
+        }
+    }
+
+    /// Decode the body with the decoder of the client and work out a hint.
+    pub fn explain(&mut self, decoder: ErrorDecoder, model: &Model) {
+        if let Some((message, code)) = decoder(&self.body) {
+            self.message = message;
+            self.code = code;
+        }
+        self.hint = error_hint(self, model);
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(code) = &self.code {
+            write!(f, " ({code})")?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, " — {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Decodes the error body of a provider into the message and the error code
+pub type ErrorDecoder = fn(&Value) -> Option<(String, Option<String>)>;
+
+/// The error decoder of a client type
+pub fn error_decoder(client_type: &str) -> ErrorDecoder {
+    match client_type {
+        "claude" => decode_claude_error,
+        "gemini" | "vertexai" => decode_gemini_error,
+        _ => decode_openai_error,
+    }
+}
+
+fn decode_openai_error(data: &Value) -> Option<(String, Option<String>)> {
+    let error = &data["error"];
+    if let Some(message) = error.as_str() {
+        return Some((message.to_string(), None));
+    }
+    let message = error["message"].as_str()?;
+    let code = error["code"]
+        .as_str()
+        .or_else(|| error["type"].as_str())
+        .map(|v| v.to_string());
+    Some((message.to_string(), code))
+}
+
+fn decode_claude_error(data: &Value) -> Option<(String, Option<String>)> {
+    let error = &data["error"];
+    let message = error["message"].as_str()?;
+    Some((
+        message.to_string(),
+        error["type"].as_str().map(|v| v.to_string()),
+    ))
+}
+
+fn decode_gemini_error(data: &Value) -> Option<(String, Option<String>)> {
+    let error = match &data[0]["error"] {
+        Value::Null => &data["error"],
+        error => error,
+    };
+    let message = error["message"].as_str()?;
+    Some((
+        message.to_string(),
+        error["status"].as_str().map(|v| v.to_string()),
+    ))
+}
+
+fn error_hint(error: &ApiError, model: &Model) -> Option<String> {
+    let code = error.code.as_deref().unwrap_or_default().to_lowercase();
+    let message = error.message.to_lowercase();
+    let has = |keys: &[&str]| keys.iter().any(|v| code.contains(v) || message.contains(v));
+    let hint = if has(&["insufficient_quota", "quota", "billing", "credit balance"]) {
+        "insufficient quota, check the plan and billing of the provider".into()
+    } else if matches!(error.status, 401 | 403)
+        || has(&["invalid_api_key", "unauthenticated", "permission_denied"])
+    {
+        format!("check the api_key of client '{}'", model.client_name())
+    } else if error.status == 404 || has(&["model_not_found", "not_found"]) {
+        format!(
+            "model '{}' may not exist, run --sync-models or pick one from --list-models",
+            model.id()
+        )
+    } else if error.status == 413 || CONTEXT_OVERFLOW_HINTS.iter().any(|v| message.contains(v)) {
+        "shorten the input, .compress the session or use a model with a larger context".into()
+    } else if error.status == 429 || has(&["rate_limit", "resource_exhausted"]) {
+        "rate limited, wait a moment and retry".into()
+    } else if error.status >= 500 || has(&["overloaded"]) {
+        "the provider is having trouble, retry later".into()
+    } else {
+        return None;
+    };
+    Some(hint)
+}
+
+/// The kind of a failure, mapped to the exit code of command mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    General,
+    Auth,
+    RateLimit,
+    ContextOverflow,
+    Network,
+    Abort,
+}
+
+const CONTEXT_OVERFLOW_HINTS: [&str; 6] = [
+    "max_input_tokens",
+    "context window",
+    "context length",
+    "context_length_exceeded",
+    "prompt is too long",
+    "too many tokens",
+];
+
+impl ErrorClass {
+    pub fn of(err: &anyhow::Error) -> Self {
+        if err.chain().any(|v| v.to_string().starts_with("Aborted")) {
+            return Self::Abort;
+        }
+        let message = err
+            .chain()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+            .to_lowercase();
+        let is_context_overflow = CONTEXT_OVERFLOW_HINTS.iter().any(|v| message.contains(v));
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<ApiError>() {
+                return match err.status {
+                    401 | 403 => Self::Auth,
+                    429 => Self::RateLimit,
+                    413 => Self::ContextOverflow,
+                    _ if is_context_overflow => Self::ContextOverflow,
+                    _ => Self::General,
+                };
+            }
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                if err.is_connect() || err.is_timeout() || err.is_request() || err.is_body() {
+                    return Self::Network;
+                }
+            }
+        }
+        if is_context_overflow {
+            return Self::ContextOverflow;
+        }
+        Self::General
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::General => "general",
+            Self::Auth => "auth",
+            Self::RateLimit => "rate_limit",
+            Self::ContextOverflow => "context_overflow",
+            Self::Network => "network",
+            Self::Abort => "abort",
+        }
+    }
+
+    /// 2 is left to clap for usage errors, 130 follows the shell convention for Ctrl+C.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::General => 1,
+            Self::Auth => 3,
+            Self::RateLimit => 4,
+            Self::ContextOverflow => 5,
+            Self::Network => 6,
+            Self::Abort => 130,
+        }
+    }
+}
+
+/// The error object printed on stderr with `--error-format json`, the raw body is only included with `--verbose`
+pub fn error_json(err: &anyhow::Error, verbose: bool) -> Value {
+    let class = ErrorClass::of(err);
+    let api_error = err.chain().find_map(|v| v.downcast_ref::<ApiError>());
+    let causes: Vec<String> = err.chain().skip(1).map(|v| v.to_string()).collect();
+    let mut value = json!({
+        "error": {
+            "type": class.as_str(),
+            "message": err.to_string(),
+            "status": api_error.map(|v| v.status),
+            "code": api_error.and_then(|v| v.code.clone()),
+            "hint": api_error.and_then(|v| v.hint.clone()),
+            "causes": causes,
+            "exit_code": class.exit_code(),
+        }
+    });
+    if let Some(api_error) = api_error.filter(|_| verbose) {
+        value["error"]["body"] = api_error.body.clone();
+    }
+    value
+}
+
+/// The raw error body of the provider, if any
+pub fn error_body(err: &anyhow::Error) -> Option<&Value> {
+    err.chain()
+        .find_map(|v| v.downcast_ref::<ApiError>())
+        .map(|v| &v.body)
+        .filter(|v| !v.is_null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_error_class() {
+        let api_error = |status: u16, message: &str| {
+            anyhow::Error::new(ApiError::new(status, message, Value::Null))
+        };
+        assert_eq!(
+            ErrorClass::of(&api_error(401, "Invalid API key")),
+            ErrorClass::Auth
+        );
+        assert_eq!(
+            ErrorClass::of(&api_error(429, "Slow down").context("Failed to call chat-completions")),
+            ErrorClass::RateLimit
+        );
+        assert_eq!(
+            ErrorClass::of(&api_error(
+                400,
+                "This model's maximum context length is 8192 tokens (code: context_length_exceeded)"
+            )),
+            ErrorClass::ContextOverflow
+        );
+        assert_eq!(
+            ErrorClass::of(&anyhow!("Exceed max_input_tokens limit")),
+            ErrorClass::ContextOverflow
+        );
+        assert_eq!(ErrorClass::of(&anyhow!("Aborted!")), ErrorClass::Abort);
+        assert_eq!(
+            ErrorClass::of(&anyhow!("Unknown role")),
+            ErrorClass::General
+        );
+        assert_eq!(
+            error_json(&api_error(429, "Slow down"), false)["error"]["status"],
+            429
+        );
+    }
+
+    #[test]
+    fn test_explain_error() {
+        let model = Model::new("openai", "gpt-x");
+        let explain = |client_type: &str, status: u16, body: Value| {
+            let mut err = ApiError::new(status, "", body);
+            err.explain(error_decoder(client_type), &model);
+            err.to_string()
+        };
+        assert_eq!(
+            explain(
+                "openai",
+                404,
+                json!({"error": {"message": "The model `gpt-x` does not exist", "type": "invalid_request_error", "code": "model_not_found"}})
+            ),
+            "The model `gpt-x` does not exist (model_not_found) — model 'openai:gpt-x' may not exist, run --sync-models or pick one from --list-models"
+        );
+        assert_eq!(
+            explain(
+                "claude",
+                529,
+                json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}})
+            ),
+            "Overloaded (overloaded_error) — the provider is having trouble, retry later"
+        );
+        assert_eq!(
+            explain(
+                "gemini",
+                429,
+                json!([{"error": {"code": 429, "message": "You exceeded your current quota", "status": "RESOURCE_EXHAUSTED"}}])
+            ),
+            "You exceeded your current quota (RESOURCE_EXHAUSTED) — insufficient quota, check the plan and billing of the provider"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use super::openai::openai_build_chat_completions_body;
+use super::*;
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Client as ReqwestClient;
+use serde::Deserialize;
+use serde_json::Value;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecConfig {
+    pub name: Option<String>,
+    pub command: String,
+    #[serde(default)]
+    pub models: Vec<ModelData>,
+    pub patch: Option<RequestPatch>,
+    pub extra: Option<ExtraConfig>,
+}
+
+impl ExecClient {
+    pub const PROMPTS: [PromptAction<'static>; 3] = [
+        ("name", "Client Name:", true, PromptKind::String),
+        ("command", "Command:", true, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+    ];
+}
+
+#[async_trait::async_trait]
+impl Client for ExecClient {
+    client_common_fns!();
+
+    async fn chat_completions_inner(
+        &self,
+        _client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<ChatCompletionsOutput> {
+        let mut output = ChatCompletionsOutput::default();
+        self.run(data, false, |event| {
+            match event {
+                ExecEvent::Text(text) => output.text.push_str(&text),
+                ExecEvent::ToolCall(call) => output.tool_calls.push(call),
+                ExecEvent::Usage(input_tokens, output_tokens) => {
+                    output.input_tokens = input_tokens;
+                    output.output_tokens = output_tokens;
+                }
+                ExecEvent::FinishReason(reason) => output.finish_reason = Some(reason),
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(output)
+    }
+
+    async fn chat_completions_streaming_inner(
+        &self,
+        _client: &ReqwestClient,
+        handler: &mut SseHandler,
+        data: ChatCompletionsData,
+    ) -> Result<()> {
+        handler.connected();
+        self.run(data, true, |event| match event {
+            ExecEvent::Text(text) => handler.text(&text),
+            ExecEvent::ToolCall(call) => handler.tool_call(call),
+            ExecEvent::Usage(input_tokens, output_tokens) => {
+                handler.set_usage(input_tokens, output_tokens);
+                Ok(())
+            }
+            ExecEvent::FinishReason(reason) => {
+                handler.set_finish_reason(Some(&reason));
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+pub(super) enum ExecEvent {
+    Text(String),
+    ToolCall(ToolCall),
+    Usage(Option<u64>, Option<u64>),
+    FinishReason(String),
+}
+
+impl ExecClient {
+    /// Write the OpenAI-style request body to the command's stdin, then read one event per stdout line.
+    async fn run<F>(&self, data: ChatCompletionsData, stream: bool, mut on_event: F) -> Result<()>
+    where
+        F: FnMut(ExecEvent) -> Result<()>,
+    {
+        let mut body = openai_build_chat_completions_body(data, &self.model);
+        body["stream"] = stream.into();
+        let mut request_data = RequestData::new("", body);
+        self.patch_request_data(&mut request_data);
+        let body = request_data.body;
+        debug!("Exec {} {body}", self.config.command);
+        run_command(
+            &self.config.command,
+            body.to_string().into_bytes(),
+            |line| match parse_exec_line(line)? {
+                Some(event) => on_event(event),
+                None => Ok(()),
+            },
+        )
+        .await
+    }
+}
+
+/// Run `command` with `input` on stdin and pass each stdout line to `on_line`.
+///
+/// Stdin is written by its own task, a command that replies before reading all of its input can't block on a full pipe.
+async fn run_command<F>(command: &str, input: Vec<u8>, mut on_line: F) -> Result<()>
+where
+    F: FnMut(&str) -> Result<()>,
+{
+    let args =
+        shell_words::split(command).with_context(|| format!("Invalid command '{command}'"))?;
+    let Some((program, args)) = args.split_first() else {
+        bail!("Empty command");
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run '{command}'"))?;
+
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin"))?;
+    let stdin = tokio::spawn(async move {
+        // A command that exits without reading all of its input closes the pipe, that's not an error here
+        let _ = stdin.write_all(&input).await;
+    });
+    let stderr = child.stderr.take().ok_or_else(|| anyhow!("No stderr"))?;
+    let stderr = tokio::spawn(async move {
+        let mut output = String::new();
+        let _ = BufReader::new(stderr).read_to_string(&mut output).await;
+        output
+    });
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout"))?;
+    let mut lines = BufReader::new(stdout).lines();
+    let ret = async {
+        while let Some(line) = lines.next_line().await? {
+            on_line(&line)?;
+        }
+        Ok(())
+    }
+    .await;
+    if ret.is_err() {
+        stdin.abort();
+        return ret;
+    }
+    let _ = stdin.await;
+
+    let status = child.wait().await?;
+    if !status.success() {
+        let stderr = stderr.await.unwrap_or_default();
+        bail!("Command exited with {status}: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+/// Lines are JSON events (`{"text"}`, `{"tool_call"}`, `{"usage"}`, `{"finish_reason"}` or `{"error"}`), anything else is plain text.
+pub(super) fn parse_exec_line(line: &str) -> Result<Option<ExecEvent>> {
+    let data = match serde_json::from_str::<Value>(line) {
+        Ok(v) if v.is_object() => v,
+        _ => return Ok(Some(ExecEvent::Text(format!("{line}\n")))),
+    };
+    if let Some(error) = data.get("error") {
+        let message = error
+            .as_str()
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| error.to_string());
+        bail!("{message}");
+    }
+    if let Some(text) = data["text"].as_str() {
+        return Ok(Some(ExecEvent::Text(text.to_string())));
+    }
+    if let Some(name) = data["tool_call"]["name"].as_str() {
+        return Ok(Some(ExecEvent::ToolCall(ToolCall::new(
+            name.to_string(),
+            data["tool_call"]["arguments"].clone(),
+            data["tool_call"]["id"].as_str().map(|v| v.to_string()),
+        ))));
+    }
+    if data.get("usage").is_some() {
+        return Ok(Some(ExecEvent::Usage(
+            data["usage"]["input_tokens"].as_u64(),
+            data["usage"]["output_tokens"].as_u64(),
+        )));
+    }
+    if let Some(reason) = data["finish_reason"].as_str() {
+        return Ok(Some(ExecEvent::FinishReason(reason.to_string())));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exec_line() {
+        let text = |line| match parse_exec_line(line).unwrap() {
+            Some(ExecEvent::Text(v)) => v,
+            _ => panic!("not a text event"),
+        };
+        assert_eq!(text(r#"{"text":"Hi"}"#), "Hi");
+        assert_eq!(text("plain output"), "plain output\n");
+        assert!(matches!(
+            parse_exec_line(r#"{"tool_call":{"name":"get_time","arguments":{}}}"#).unwrap(),
+            Some(ExecEvent::ToolCall(_))
+        ));
+        assert_eq!(
+            parse_exec_line(r#"{"error":"quota exceeded"}"#)
+                .err()
+                .unwrap()
+                .to_string(),
+            "quota exceeded"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command_large_input() {
+        // `cat` writes its output while it's still reading, way past the size of a pipe buffer
+        let input = "a".repeat(1024 * 1024);
+        let mut output = String::new();
+        run_command("cat", input.clone().into_bytes(), |line| {
+            output.push_str(line);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(output, input);
+    }
+}
@@ -13,6 +13,7 @@ pub struct GeminiConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
     pub api_base: Option<String>,
+    pub safety_settings: Option<Value>,
     #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
@@ -36,6 +37,7 @@ impl_client_trait!(
     ),
     (prepare_embeddings, embeddings),
     (noop_prepare_rerank, noop_rerank),
+    (prepare_realtime),
 );
 
 fn prepare_chat_completions(
@@ -60,13 +62,40 @@ fn prepare_chat_completions(
         api_key
     );
 
-    let body = gemini_build_chat_completions_body(data, &self_.model)?;
+    let mut body = gemini_build_chat_completions_body(data, &self_.model)?;
+    gemini_apply_safety_settings(&mut body, &self_.config.safety_settings)?;
+    if self_.global_config.read().web_search {
+        gemini_apply_web_search(&mut body);
+    }
 
     let request_data = RequestData::new(url, body);
 
     Ok(request_data)
 }
 
+#[cfg(feature = "voice")]
+fn prepare_realtime(self_: &GeminiClient) -> Result<RealtimeRequest> {
+    let api_key = self_.get_api_key()?;
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let (host, version) = api_base
+        .trim_end_matches('/')
+        .rsplit_once('/')
+        .with_context(|| format!("Invalid api_base '{api_base}'"))?;
+    let url = format!(
+        "{}/ws/google.ai.generativelanguage.{version}.GenerativeService.BidiGenerateContent?key={api_key}",
+        websocket_url(host)
+    );
+
+    Ok(RealtimeRequest {
+        provider: RealtimeProvider::Gemini,
+        model: self_.model.name().to_string(),
+        data: RequestData::new(url, Value::Null),
+    })
+}
+
 fn prepare_embeddings(self_: &GeminiClient, data: &EmbeddingsData) -> Result<RequestData> {
     let api_key = self_.get_api_key()?;
     let api_base = self_
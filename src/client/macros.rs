@@ -52,10 +52,10 @@ macro_rules! register_client {
                 pub fn list_models(local_config: &$config) -> Vec<Model> {
                     let client_name = Self::name(local_config);
                     if local_config.models.is_empty() {
-                        if let Some(models) = $crate::client::ALL_PREDEFINED_MODELS.iter().find(|v| {
-                            v.platform == $name ||
+                        if let Some(models) = $crate::client::find_predefined_models(|platform| {
+                            platform == $name ||
                                 ($name == OpenAICompatibleClient::NAME
-                                    && local_config.name.as_ref().map(|name| name.starts_with(&v.platform)).unwrap_or_default())
+                                    && local_config.name.as_ref().map(|name| name.starts_with(platform)).unwrap_or_default())
                         }) {
                             return Model::from_config(client_name, &models.models);
                         }
@@ -99,6 +99,13 @@ macro_rules! register_client {
             anyhow::bail!("Unknown client '{}'", client)
         }
 
+        pub fn client_type(config: &$crate::config::Config, client_name: &str) -> Option<&'static str> {
+            config.clients.iter().find_map(|v| match v {
+                $(ClientConfig::$config(c) if $client::name(c) == client_name => Some($client::NAME),)+
+                _ => None,
+            })
+        }
+
         static ALL_CLIENT_NAMES: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
 
         pub fn list_client_names(config: &$crate::config::Config) -> Vec<&'static String> {
@@ -131,6 +138,34 @@ macro_rules! register_client {
             models.iter().collect()
         }
 
+        static CLIENT_MODELS: std::sync::OnceLock<indexmap::IndexMap<String, std::sync::OnceLock<Vec<$crate::client::Model>>>> = std::sync::OnceLock::new();
+
+        /// The models of one client, without listing those of the others unless they already are
+        pub fn list_client_models(config: &$crate::config::Config, client_name: &str) -> Vec<&'static $crate::client::Model> {
+            if let Some(models) = ALL_MODELS.get() {
+                return models.iter().filter(|v| v.client_name() == client_name).collect();
+            }
+            let clients = CLIENT_MODELS.get_or_init(|| {
+                list_client_names(config)
+                    .into_iter()
+                    .map(|v| (v.clone(), std::sync::OnceLock::new()))
+                    .collect()
+            });
+            let Some(models) = clients.get(client_name) else {
+                return vec![];
+            };
+            models
+                .get_or_init(|| {
+                    config.clients.iter().flat_map(|v| match v {
+                        $(ClientConfig::$config(c) if $client::name(c) == client_name => $client::list_models(c),)+
+                        _ => vec![],
+                    })
+                    .collect()
+                })
+                .iter()
+                .collect()
+        }
+
         pub fn list_models(config: &$crate::config::Config, model_type: $crate::client::ModelType) -> Vec<&'static $crate::client::Model> {
             list_all_models(config).into_iter().filter(|v| v.model_type() == model_type).collect()
         }
@@ -156,6 +191,10 @@ macro_rules! client_common_fns {
             Self::name(&self.config)
         }
 
+        fn client_type(&self) -> &'static str {
+            Self::NAME
+        }
+
         fn model(&self) -> &Model {
             &self.model
         }
@@ -173,6 +212,7 @@ macro_rules! impl_client_trait {
         ($prepare_chat_completions:path, $chat_completions:path, $chat_completions_streaming:path),
         ($prepare_embeddings:path, $embeddings:path),
         ($prepare_rerank:path, $rerank:path),
+        $(($prepare_realtime:path),)?
     ) => {
         #[async_trait::async_trait]
         impl $crate::client::Client for $crate::client::$client {
@@ -184,7 +224,7 @@ macro_rules! impl_client_trait {
                 data: $crate::client::ChatCompletionsData,
             ) -> anyhow::Result<$crate::client::ChatCompletionsOutput> {
                 let request_data = $prepare_chat_completions(self, data)?;
-                let builder = self.request_builder(client, request_data);
+                let builder = self.request_builder(client, request_data)?;
                 $chat_completions(builder, self.model()).await
             }
 
@@ -195,7 +235,7 @@ macro_rules! impl_client_trait {
                 data: $crate::client::ChatCompletionsData,
             ) -> Result<()> {
                 let request_data = $prepare_chat_completions(self, data)?;
-                let builder = self.request_builder(client, request_data);
+                let builder = self.request_builder(client, request_data)?;
                 $chat_completions_streaming(builder, handler, self.model()).await
             }
 
@@ -205,7 +245,7 @@ macro_rules! impl_client_trait {
                 data: &$crate::client::EmbeddingsData,
             ) -> Result<$crate::client::EmbeddingsOutput> {
                 let request_data = $prepare_embeddings(self, data)?;
-                let builder = self.request_builder(client, request_data);
+                let builder = self.request_builder(client, request_data)?;
                 $embeddings(builder, self.model()).await
             }
 
@@ -215,9 +255,16 @@ macro_rules! impl_client_trait {
                 data: &$crate::client::RerankData,
             ) -> Result<$crate::client::RerankOutput> {
                 let request_data = $prepare_rerank(self, data)?;
-                let builder = self.request_builder(client, request_data);
+                let builder = self.request_builder(client, request_data)?;
                 $rerank(builder, self.model()).await
             }
+
+            $(
+                #[cfg(feature = "voice")]
+                fn prepare_realtime(&self) -> anyhow::Result<$crate::client::RealtimeRequest> {
+                    $prepare_realtime(self)
+                }
+            )?
         }
     };
 }
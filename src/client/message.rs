@@ -1,25 +1,42 @@
 use crate::{function::ToolResult, utils::dimmed_text};
 
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+    /// Pinned messages are kept verbatim when the session is compressed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
 }
 
 impl Default for Message {
     fn default() -> Self {
         Self {
             role: MessageRole::User,
-            content: MessageContent::Text(String::new()),
+            content: MessageContent::Text("".into()),
+            citations: vec![],
+            metadata: None,
+            pinned: false,
         }
     }
 }
 
 impl Message {
     pub fn new(role: MessageRole, content: MessageContent) -> Self {
-        Self { role, content }
+        Self {
+            role,
+            content,
+            citations: vec![],
+            metadata: None,
+            pinned: false,
+        }
     }
 
     pub fn merge_system(&mut self, system: &str) {
@@ -27,18 +44,16 @@ impl Message {
             MessageContent::Text(text) => {
                 self.content = MessageContent::Array(vec![
                     MessageContentPart::Text {
-                        text: system.to_string(),
-                    },
-                    MessageContentPart::Text {
-                        text: text.to_string(),
+                        text: system.into(),
                     },
+                    MessageContentPart::Text { text: text.clone() },
                 ]);
             }
             MessageContent::Array(list) => {
                 list.insert(
                     0,
                     MessageContentPart::Text {
-                        text: system.to_string(),
+                        text: system.into(),
                     },
                 );
             }
@@ -67,10 +82,12 @@ impl MessageRole {
     }
 }
 
+/// Texts and images are behind `Arc` so that cloning the history or a message holding
+/// a large attachment doesn't copy it.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum MessageContent {
-    Text(String),
+    Text(Arc<str>),
     Array(Vec<MessageContentPart>),
     // Note: This type is primarily for convenience and does not exist in OpenAI's API.
     ToolCalls(MessageContentToolCalls),
@@ -126,14 +143,14 @@ impl MessageContent {
 
     pub fn merge_prompt(&mut self, replace_fn: impl Fn(&str) -> String) {
         match self {
-            MessageContent::Text(text) => *text = replace_fn(text),
+            MessageContent::Text(text) => *text = replace_fn(text).into(),
             MessageContent::Array(list) => {
                 if list.is_empty() {
                     list.push(MessageContentPart::Text {
-                        text: replace_fn(""),
+                        text: replace_fn("").into(),
                     })
                 } else if let Some(MessageContentPart::Text { text }) = list.get_mut(0) {
-                    *text = replace_fn(text)
+                    *text = replace_fn(text).into()
                 }
             }
             MessageContent::ToolCalls(_) => {}
@@ -147,7 +164,7 @@ impl MessageContent {
                 let mut parts = vec![];
                 for item in list {
                     if let MessageContentPart::Text { text } = item {
-                        parts.push(text.clone())
+                        parts.push(text.as_ref())
                     }
                 }
                 parts.join("\n\n")
@@ -160,13 +177,13 @@ impl MessageContent {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum MessageContentPart {
-    Text { text: String },
+    Text { text: Arc<str> },
     ImageUrl { image_url: ImageUrl },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ImageUrl {
-    pub url: String,
+    pub url: Arc<str>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -192,6 +209,140 @@ impl MessageContentToolCalls {
     }
 }
 
+/// How a reply was produced, recorded on assistant messages of sessions.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct MessageMetadata {
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    pub timestamp: String,
+}
+
+impl MessageMetadata {
+    /// Fold a continuation of the same reply into this metadata.
+    pub fn merge(&mut self, other: MessageMetadata) {
+        fn add(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+            match (a, b) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or_default() + b.unwrap_or_default()),
+            }
+        }
+        self.input_tokens = add(self.input_tokens, other.input_tokens);
+        self.output_tokens = add(self.output_tokens, other.output_tokens);
+        self.latency_ms = add(self.latency_ms, other.latency_ms);
+        self.provider = other.provider.or(self.provider.take());
+        self.cost = match (self.cost, other.cost) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or_default() + b.unwrap_or_default()),
+        };
+        self.timestamp = other.timestamp;
+    }
+}
+
+/// A source backing a reply, from provider grounding or RAG.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Citation {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub title: String,
+    /// Char offsets in the reply text where the footnote marker goes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub offsets: Vec<usize>,
+}
+
+impl Citation {
+    pub fn new(url: &str, title: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            title: title.to_string(),
+            offsets: vec![],
+        }
+    }
+
+    /// Add a citation, merging it into an existing one with the same url.
+    pub fn merge(citations: &mut Vec<Citation>, citation: Citation) {
+        match citations.iter_mut().find(|v| v.url == citation.url) {
+            Some(exist) => {
+                if exist.title.is_empty() {
+                    exist.title = citation.title;
+                }
+                for offset in citation.offsets {
+                    if !exist.offsets.contains(&offset) {
+                        exist.offsets.push(offset);
+                    }
+                }
+            }
+            None => citations.push(citation),
+        }
+    }
+}
+
+/// Render `text` with superscript footnote markers followed by the footnote list.
+pub fn render_citations(text: &str, citations: &[Citation]) -> String {
+    if citations.is_empty() {
+        return text.to_string();
+    }
+    let mut markers: Vec<(usize, usize)> = citations
+        .iter()
+        .enumerate()
+        .flat_map(|(i, citation)| citation.offsets.iter().map(move |offset| (*offset, i + 1)))
+        .collect();
+    markers.sort_unstable();
+    let mut output = String::new();
+    let mut markers = markers.into_iter().peekable();
+    for (i, ch) in text.chars().enumerate() {
+        while let Some((_, number)) = markers.next_if(|(offset, _)| *offset == i) {
+            output.push_str(&superscript(number));
+        }
+        output.push(ch);
+    }
+    for (_, number) in markers {
+        output.push_str(&superscript(number));
+    }
+    format!("{output}{}", format_footnotes(citations))
+}
+
+pub fn format_footnotes(citations: &[Citation]) -> String {
+    let mut output = String::from("\n\n---\n");
+    for (i, citation) in citations.iter().enumerate() {
+        if citation.title.is_empty() {
+            output.push_str(&format!("\n{}. <{}>", i + 1, citation.url));
+        } else {
+            output.push_str(&format!(
+                "\n{}. {} <{}>",
+                i + 1,
+                citation.title,
+                citation.url
+            ));
+        }
+    }
+    output
+}
+
+fn superscript(number: usize) -> String {
+    const DIGITS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+    let digits: String = number
+        .to_string()
+        .chars()
+        .map(|v| DIGITS[v.to_digit(10).unwrap_or_default() as usize])
+        .collect();
+    format!("⁽{digits}⁾")
+}
+
 pub fn patch_system_message(messages: &mut Vec<Message>) {
     if messages[0].role.is_system() {
         let system_message = messages.remove(0);
@@ -210,3 +361,35 @@ pub fn extract_system_message(messages: &mut Vec<Message>) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_citations() {
+        let mut citations = vec![];
+        Citation::merge(
+            &mut citations,
+            Citation {
+                url: "https://a.com".into(),
+                title: "A".into(),
+                offsets: vec![5],
+            },
+        );
+        Citation::merge(
+            &mut citations,
+            Citation {
+                url: "https://b.com".into(),
+                title: String::new(),
+                offsets: vec![5, 11],
+            },
+        );
+        Citation::merge(&mut citations, Citation::new("https://a.com", "A2"));
+        assert_eq!(citations.len(), 2);
+        assert_eq!(
+            render_citations("Hello world", &citations),
+            "Hello⁽¹⁾⁽²⁾ world⁽²⁾\n\n---\n\n1. A <https://a.com>\n2. <https://b.com>"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use crate::config::{Config, Input};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+static REPLAY_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// Canned responses used instead of the echo when `dry_run` is on.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockConfig {
+    /// Fixed reply, `{{input}}` is replaced with the user input
+    pub text: Option<String>,
+    /// Replay replies from a file in turn, separated by lines of `---`
+    pub file: Option<String>,
+    /// Milliseconds before the reply starts
+    #[serde(default)]
+    pub latency: u64,
+    /// Milliseconds between streamed tokens
+    pub token_delay: Option<u64>,
+    /// Fail with this message instead of replying
+    pub error: Option<String>,
+    /// Emit this many tokens before failing with `error`
+    pub error_after: Option<usize>,
+}
+
+impl MockConfig {
+    pub fn token_delay(&self) -> u64 {
+        self.token_delay.unwrap_or(10)
+    }
+
+    pub async fn wait_latency(&self) {
+        if self.latency > 0 {
+            tokio::time::sleep(Duration::from_millis(self.latency)).await;
+        }
+    }
+
+    /// The error to raise after `emitted` tokens, if any
+    pub fn error_at(&self, emitted: usize) -> Result<()> {
+        if let Some(error) = &self.error {
+            if emitted >= self.error_after.unwrap_or_default() {
+                bail!("{error}");
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn mock_reply(config: &Config, input: &Input) -> Result<String> {
+    let Some(mock) = &config.mock else {
+        return Ok(input.echo_messages());
+    };
+    if let Some(file) = &mock.file {
+        let content = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to load mock replies at '{file}'"))?;
+        let replies = split_replies(&content);
+        let index = REPLAY_INDEX.fetch_add(1, Ordering::SeqCst);
+        return replies
+            .get(index % replies.len().max(1))
+            .map(|v| v.to_string())
+            .ok_or_else(|| anyhow!("No mock replies in '{file}'"));
+    }
+    match &mock.text {
+        Some(text) => Ok(text.replace("{{input}}", &input.raw())),
+        None => Ok(input.echo_messages()),
+    }
+}
+
+fn split_replies(content: &str) -> Vec<&str> {
+    content
+        .split("\n---\n")
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_replies() {
+        let content = "first\n---\nsecond\nline\n---\n\n";
+        assert_eq!(split_replies(content), vec!["first", "second\nline"]);
+    }
+
+    #[test]
+    fn test_error_at() {
+        let mock = MockConfig {
+            error: Some("rate limited".into()),
+            error_after: Some(2),
+            ..Default::default()
+        };
+        assert!(mock.error_at(1).is_ok());
+        assert_eq!(mock.error_at(2).unwrap_err().to_string(), "rate limited");
+    }
+}
@@ -1,17 +1,31 @@
 mod access_token;
 mod common;
+mod error;
 mod message;
+mod mock;
 #[macro_use]
 mod macros;
 mod model;
+mod oauth;
+#[cfg(feature = "voice")]
+mod realtime;
 mod stream;
+mod sync_models;
+mod transport;
 
 pub use crate::function::ToolCall;
 pub use crate::utils::PromptKind;
 pub use common::*;
+pub use error::*;
 pub use message::*;
+pub use mock::*;
 pub use model::*;
+pub use openai::openai_build_chat_completions_body;
+#[cfg(feature = "voice")]
+pub use realtime::*;
 pub use stream::*;
+pub use sync_models::*;
+pub use transport::*;
 
 register_client!(
     (openai, "openai", OpenAIConfig, OpenAIClient),
@@ -33,8 +47,12 @@ register_client!(
     (vertexai, "vertexai", VertexAIConfig, VertexAIClient),
     (bedrock, "bedrock", BedrockConfig, BedrockClient),
     (ernie, "ernie", ErnieConfig, ErnieClient),
+    (exec, "exec", ExecConfig, ExecClient),
+    (plugin, "plugin", PluginConfig, PluginClient),
 );
 
+pub use self::plugin::plugin_client_configs;
+
 pub const OPENAI_COMPATIBLE_PLATFORMS: [(&str, &str); 21] = [
     ("ai21", "https://api.ai21.com/studio/v1"),
     ("cloudflare", ""),
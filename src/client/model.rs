@@ -1,14 +1,17 @@
 use super::{
-    list_all_models, list_client_names,
+    list_client_models, list_client_names,
     message::{Message, MessageContent, MessageContentPart},
     ApiPatch, MessageContentToolCalls, RequestPatch,
 };
 
 use crate::config::Config;
-use crate::utils::{estimate_token_length, format_option_value};
+use crate::utils::{
+    estimate_token_length, format_option_value, is_clearly_within, quick_token_length,
+};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fmt::Display;
 
 const PER_MESSAGES_TOKENS: usize = 5;
@@ -18,6 +21,7 @@ const BASIS_TOKENS: usize = 2;
 pub struct Model {
     client_name: String,
     data: ModelData,
+    patch: Option<Value>,
 }
 
 impl Default for Model {
@@ -31,6 +35,7 @@ impl Model {
         Self {
             client_name: client_name.into(),
             data: ModelData::new(name),
+            patch: None,
         }
     }
 
@@ -40,12 +45,17 @@ impl Model {
             .map(|v| Model {
                 client_name: client_name.to_string(),
                 data: v.clone(),
+                patch: None,
             })
             .collect()
     }
 
     pub fn retrieve_model(config: &Config, model_id: &str, model_type: ModelType) -> Result<Self> {
-        let models = list_all_models(config);
+        let model_id = config
+            .model_aliases
+            .get(model_id)
+            .map(|v| v.as_str())
+            .unwrap_or(model_id);
         let (client_name, model_name) = match model_id.split_once(':') {
             Some((client_name, model_name)) => {
                 if model_name.is_empty() {
@@ -56,6 +66,8 @@ impl Model {
             }
             None => (model_id, None),
         };
+        // Listing the models of every client would parse all the predefined ones
+        let models = list_client_models(config, client_name);
         match model_name {
             Some(model_name) => {
                 if let Some(model) = models.iter().find(|v| v.id() == model_id) {
@@ -223,51 +235,52 @@ impl Model {
         self
     }
 
-    pub fn messages_tokens(&self, messages: &[Message]) -> usize {
-        messages
-            .iter()
-            .map(|v| match &v.content {
-                MessageContent::Text(text) => estimate_token_length(text),
-                MessageContent::Array(list) => list
-                    .iter()
-                    .map(|v| match v {
-                        MessageContentPart::Text { text } => estimate_token_length(text),
-                        MessageContentPart::ImageUrl { .. } => 0,
-                    })
-                    .sum(),
-                MessageContent::ToolCalls(MessageContentToolCalls {
-                    tool_results, text, ..
-                }) => {
-                    estimate_token_length(text)
-                        + tool_results
-                            .iter()
-                            .map(|v| {
-                                serde_json::to_string(v)
-                                    .map(|v| estimate_token_length(&v))
-                                    .unwrap_or_default()
-                            })
-                            .sum::<usize>()
-                }
-            })
-            .sum()
+    /// Merged into the chat completions body after the client's patches
+    pub fn patch(&self) -> Option<&Value> {
+        self.patch.as_ref()
     }
 
-    pub fn total_tokens(&self, messages: &[Message]) -> usize {
-        if messages.is_empty() {
-            return 0;
-        }
-        let num_messages = messages.len();
-        let message_tokens = self.messages_tokens(messages);
-        if messages[num_messages - 1].role.is_user() {
-            num_messages * PER_MESSAGES_TOKENS + message_tokens
+    pub fn set_patch(&mut self, patch: Option<Value>) -> &mut Self {
+        self.patch = patch;
+        self
+    }
+
+    /// Characters per token of the tokenizer family, calibrates `quick_tokens`
+    pub fn chars_per_token(&self) -> f32 {
+        let name = self.name();
+        if name.contains("claude") {
+            3.5
+        } else if name.contains("gemini") || name.contains("gemma") {
+            4.2
         } else {
-            (num_messages - 1) * PER_MESSAGES_TOKENS + message_tokens
+            4.0
         }
     }
 
+    pub fn quick_tokens(&self, text: &str) -> usize {
+        quick_token_length(text, self.chars_per_token())
+    }
+
+    pub fn messages_tokens(&self, messages: &[Message]) -> usize {
+        count_messages_tokens(messages, estimate_token_length)
+    }
+
+    pub fn total_tokens(&self, messages: &[Message]) -> usize {
+        with_messages_overhead(messages, self.messages_tokens(messages))
+    }
+
+    /// `total_tokens` from the byte length of the messages
+    pub fn quick_total_tokens(&self, messages: &[Message]) -> usize {
+        let message_tokens = count_messages_tokens(messages, |v| self.quick_tokens(v));
+        with_messages_overhead(messages, message_tokens)
+    }
+
     pub fn guard_max_input_tokens(&self, messages: &[Message]) -> Result<()> {
-        let total_tokens = self.total_tokens(messages) + BASIS_TOKENS;
         if let Some(max_input_tokens) = self.data.max_input_tokens {
+            if is_clearly_within(self.quick_total_tokens(messages), max_input_tokens) {
+                return Ok(());
+            }
+            let total_tokens = self.total_tokens(messages) + BASIS_TOKENS;
             if total_tokens >= max_input_tokens {
                 bail!("Exceed max_input_tokens limit")
             }
@@ -276,6 +289,47 @@ impl Model {
     }
 }
 
+fn count_messages_tokens(messages: &[Message], count: impl Fn(&str) -> usize) -> usize {
+    messages
+        .iter()
+        .map(|v| match &v.content {
+            MessageContent::Text(text) => count(text),
+            MessageContent::Array(list) => list
+                .iter()
+                .map(|v| match v {
+                    MessageContentPart::Text { text } => count(text),
+                    MessageContentPart::ImageUrl { .. } => 0,
+                })
+                .sum(),
+            MessageContent::ToolCalls(MessageContentToolCalls {
+                tool_results, text, ..
+            }) => {
+                count(text)
+                    + tool_results
+                        .iter()
+                        .map(|v| {
+                            serde_json::to_string(v)
+                                .map(|v| count(&v))
+                                .unwrap_or_default()
+                        })
+                        .sum::<usize>()
+            }
+        })
+        .sum()
+}
+
+fn with_messages_overhead(messages: &[Message], message_tokens: usize) -> usize {
+    if messages.is_empty() {
+        return 0;
+    }
+    let num_messages = messages.len();
+    if messages[num_messages - 1].role.is_user() {
+        num_messages * PER_MESSAGES_TOKENS + message_tokens
+    } else {
+        (num_messages - 1) * PER_MESSAGES_TOKENS + message_tokens
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModelData {
     pub name: String,
@@ -314,7 +368,7 @@ impl ModelData {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PredefinedModels {
     pub platform: String,
     pub models: Vec<ModelData>,
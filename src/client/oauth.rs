@@ -0,0 +1,328 @@
+use super::access_token::*;
+use super::ApiError;
+
+use crate::config::Config;
+use crate::utils::{
+    check_offline, decrypt_data, encrypt_data, get_env_name, random_bytes, set_text,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use reqwest::Client as ReqwestClient;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    env, fs,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const OAUTH_DIR_NAME: &str = "oauth";
+/// The key goes next to the tokens only on platforms without a local data dir
+const FALLBACK_OAUTH_KEY_FILE_NAME: &str = ".key";
+const OAUTH_KEY_FILE_NAME: &str = "oauth.key";
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Used when the provider issues tokens without `expires_in` (e.g. GitHub OAuth apps)
+const DEFAULT_EXPIRES_IN: i64 = 365 * 86400;
+
+const OAUTH_PROVIDERS: [(&str, &str, &str); 2] = [
+    (
+        "github",
+        "https://github.com/login/device/code",
+        "https://github.com/login/oauth/access_token",
+    ),
+    (
+        "google",
+        "https://oauth2.googleapis.com/device/code",
+        "https://oauth2.googleapis.com/token",
+    ),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthConfig {
+    pub provider: Option<String>,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+    pub device_authorization_url: Option<String>,
+    pub token_url: Option<String>,
+}
+
+impl OAuthConfig {
+    fn endpoints(&self) -> Result<(String, String)> {
+        let preset = self.provider.as_deref().and_then(|provider| {
+            OAUTH_PROVIDERS
+                .iter()
+                .find(|(name, _, _)| *name == provider)
+        });
+        let device_authorization_url = self
+            .device_authorization_url
+            .clone()
+            .or_else(|| preset.map(|(_, v, _)| v.to_string()))
+            .ok_or_else(|| anyhow!("Miss 'oauth.device_authorization_url'"))?;
+        let token_url = self
+            .token_url
+            .clone()
+            .or_else(|| preset.map(|(_, _, v)| v.to_string()))
+            .ok_or_else(|| anyhow!("Miss 'oauth.token_url'"))?;
+        Ok((device_authorization_url, token_url))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OAuthTokens {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: i64,
+}
+
+pub fn oauth_access_token_key(client_name: &str) -> String {
+    format!("oauth:{client_name}")
+}
+
+/// Ensure a valid OAuth access token is cached for the client.
+///
+/// Tries the saved token, then the refresh token, and finally runs the device flow interactively.
+pub async fn prepare_oauth_access_token(
+    client: &ReqwestClient,
+    client_name: &str,
+    oauth: &OAuthConfig,
+) -> Result<()> {
+    let key = oauth_access_token_key(client_name);
+    refresh_access_token(&key, || async {
+        let (_, token_url) = oauth.endpoints()?;
+        let saved = load_tokens(client_name).unwrap_or_else(|err| {
+            warn!("Failed to load oauth tokens of '{client_name}', {err}");
+            None
+        });
+        if let Some(tokens) = saved {
+            if tokens.expires_at > Utc::now().timestamp() + 60 {
+                return Ok((tokens.access_token, tokens.expires_at));
+            }
+            if let Some(refresh_token) = &tokens.refresh_token {
+                match refresh_tokens(client, oauth, &token_url, refresh_token).await {
+                    Ok(tokens) => {
+                        save_tokens(client_name, &tokens)?;
+                        return Ok((tokens.access_token, tokens.expires_at));
+                    }
+                    Err(err) => warn!("Failed to refresh oauth token of '{client_name}', {err}"),
+                }
+            }
+        }
+        if !std::io::stderr().is_terminal() {
+            bail!("Authorizing '{client_name}' requires an interactive terminal");
+        }
+        let tokens = run_device_flow(client, oauth)
+            .await
+            .with_context(|| format!("Failed to authorize '{client_name}'"))?;
+        save_tokens(client_name, &tokens)?;
+        Ok((tokens.access_token, tokens.expires_at))
+    })
+    .await
+}
+
+/// Drop the cached access token so that the next request refreshes it.
+pub fn invalidate_oauth_access_token(client_name: &str) {
+    set_access_token(&oauth_access_token_key(client_name), String::new(), 0);
+    if let Ok(Some(mut tokens)) = load_tokens(client_name) {
+        tokens.expires_at = 0;
+        let _ = save_tokens(client_name, &tokens);
+    }
+}
+
+pub fn is_unauthorized_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|v| v.downcast_ref::<ApiError>())
+        .any(|v| v.status == 401)
+}
+
+async fn run_device_flow(client: &ReqwestClient, oauth: &OAuthConfig) -> Result<OAuthTokens> {
+    let (device_authorization_url, token_url) = oauth.endpoints()?;
+    check_offline(&device_authorization_url)?;
+    let mut params = vec![("client_id", oauth.client_id.as_str())];
+    if let Some(scope) = &oauth.scope {
+        params.push(("scope", scope));
+    }
+    let data: Value = client
+        .post(&device_authorization_url)
+        .header("Accept", "application/json")
+        .form(&params)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let (Some(device_code), Some(user_code), Some(verification_uri)) = (
+        data["device_code"].as_str(),
+        data["user_code"].as_str(),
+        data["verification_uri"]
+            .as_str()
+            .or_else(|| data["verification_url"].as_str()),
+    ) else {
+        bail!("Invalid device authorization response: {data}")
+    };
+    let mut interval = data["interval"].as_u64().unwrap_or(5);
+    let expires_at = Utc::now().timestamp() + data["expires_in"].as_i64().unwrap_or(900);
+
+    let copied = set_text(user_code).is_ok();
+    eprintln!(
+        "Open {verification_uri} and enter the code {user_code}{}",
+        if copied { " (copied to clipboard)" } else { "" }
+    );
+
+    let mut params = vec![
+        ("client_id", oauth.client_id.as_str()),
+        ("device_code", device_code),
+        ("grant_type", DEVICE_CODE_GRANT_TYPE),
+    ];
+    if let Some(client_secret) = &oauth.client_secret {
+        params.push(("client_secret", client_secret));
+    }
+    loop {
+        if Utc::now().timestamp() > expires_at {
+            bail!("The device code has expired");
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        let data: Value = client
+            .post(&token_url)
+            .header("Accept", "application/json")
+            .form(&params)
+            .send()
+            .await?
+            .json()
+            .await?;
+        match data["error"].as_str() {
+            Some("authorization_pending") => {}
+            Some("slow_down") => interval += 5,
+            Some(err) => bail!(
+                "{}",
+                data["error_description"]
+                    .as_str()
+                    .unwrap_or(err)
+                    .to_string()
+            ),
+            None => return extract_tokens(&data, None),
+        }
+    }
+}
+
+async fn refresh_tokens(
+    client: &ReqwestClient,
+    oauth: &OAuthConfig,
+    token_url: &str,
+    refresh_token: &str,
+) -> Result<OAuthTokens> {
+    let mut params = vec![
+        ("client_id", oauth.client_id.as_str()),
+        ("refresh_token", refresh_token),
+        ("grant_type", "refresh_token"),
+    ];
+    if let Some(client_secret) = &oauth.client_secret {
+        params.push(("client_secret", client_secret));
+    }
+    check_offline(token_url)?;
+    let data: Value = client
+        .post(token_url)
+        .header("Accept", "application/json")
+        .form(&params)
+        .send()
+        .await?
+        .json()
+        .await?;
+    extract_tokens(&data, Some(refresh_token))
+}
+
+fn extract_tokens(data: &Value, refresh_token: Option<&str>) -> Result<OAuthTokens> {
+    let access_token = match data["access_token"].as_str() {
+        Some(v) => v.to_string(),
+        None => match data["error_description"].as_str() {
+            Some(err) => bail!("{err}"),
+            None => bail!("Invalid token response: {data}"),
+        },
+    };
+    let expires_in = data["expires_in"].as_i64().unwrap_or(DEFAULT_EXPIRES_IN);
+    Ok(OAuthTokens {
+        access_token,
+        refresh_token: data["refresh_token"]
+            .as_str()
+            .or(refresh_token)
+            .map(|v| v.to_string()),
+        expires_at: Utc::now().timestamp() + expires_in,
+    })
+}
+
+fn oauth_dir() -> PathBuf {
+    Config::local_path(OAUTH_DIR_NAME)
+}
+
+fn load_tokens(client_name: &str) -> Result<Option<OAuthTokens>> {
+    let path = oauth_dir().join(format!("{client_name}.bin"));
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(&path)?;
+    let data = decrypt_data(&load_key()?, &data)?;
+    Ok(Some(serde_json::from_slice(&data)?))
+}
+
+fn save_tokens(client_name: &str, tokens: &OAuthTokens) -> Result<()> {
+    let path = oauth_dir().join(format!("{client_name}.bin"));
+    let data = encrypt_data(&load_key()?, &serde_json::to_vec(tokens)?)?;
+    write_private_file(&path, &data)
+        .with_context(|| format!("Failed to save oauth tokens to '{}'", path.display()))
+}
+
+/// The key stays out of the config dir, so a copied or synced config dir doesn't carry
+/// both the encrypted tokens and the key to them.
+fn key_path() -> PathBuf {
+    if let Ok(path) = env::var(get_env_name("oauth_key_file")) {
+        return PathBuf::from(path);
+    }
+    match dirs::data_local_dir() {
+        Some(dir) => dir.join(env!("CARGO_PKG_NAME")).join(OAUTH_KEY_FILE_NAME),
+        None => oauth_dir().join(FALLBACK_OAUTH_KEY_FILE_NAME),
+    }
+}
+
+fn load_key() -> Result<Vec<u8>> {
+    let path = key_path();
+    if path.exists() {
+        return Ok(fs::read(&path)?);
+    }
+    let key = random_bytes(32)?;
+    write_private_file(&path, &key)
+        .with_context(|| format!("Failed to create oauth key at '{}'", path.display()))?;
+    Ok(key)
+}
+
+fn write_private_file(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_unauthorized_error() {
+        let err: anyhow::Error = ApiError::new(401, "Invalid token", Value::Null).into();
+        assert!(is_unauthorized_error(
+            &err.context("Failed to call chat-completions api")
+        ));
+        let err: anyhow::Error =
+            ApiError::new(400, "Max tokens must be below 4010", Value::Null).into();
+        assert!(!is_unauthorized_error(&err));
+        assert!(!is_unauthorized_error(&anyhow!(
+            "Unauthorized tool 'fs_write'"
+        )));
+    }
+}
@@ -13,6 +13,7 @@ pub struct OpenAIConfig {
     pub api_key: Option<String>,
     pub api_base: Option<String>,
     pub organization_id: Option<String>,
+    pub project_id: Option<String>,
     #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
@@ -22,11 +23,24 @@ pub struct OpenAIConfig {
 impl OpenAIClient {
     config_get_fn!(api_key, get_api_key);
     config_get_fn!(api_base, get_api_base);
+    config_get_fn!(organization_id, get_organization_id);
+    config_get_fn!(project_id, get_project_id);
 
     pub const PROMPTS: [PromptAction<'static>; 1] =
         [("api_key", "API Key:", true, PromptKind::String)];
 }
 
+impl OpenAIClient {
+    pub async fn fetch_models(&self) -> Result<Vec<ModelData>> {
+        let api_key = self.get_api_key()?;
+        let api_base = self.get_api_base().unwrap_or_else(|_| API_BASE.to_string());
+        let url = format!("{}/models", api_base.trim_end_matches('/'));
+        let builder = self.build_client()?.get(url).bearer_auth(api_key);
+        let data = fetch_models_json(builder).await?;
+        parse_openai_models(&data)
+    }
+}
+
 impl_client_trait!(
     OpenAIClient,
     (
@@ -36,6 +50,7 @@ impl_client_trait!(
     ),
     (prepare_embeddings, openai_embeddings),
     (noop_prepare_rerank, noop_rerank),
+    (prepare_realtime),
 );
 
 fn prepare_chat_completions(
@@ -49,14 +64,15 @@ fn prepare_chat_completions(
 
     let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
 
-    let body = openai_build_chat_completions_body(data, &self_.model);
+    let mut body = openai_build_chat_completions_body(data, &self_.model);
+    if self_.global_config.read().web_search && self_.model.name().contains("search") {
+        body["web_search_options"] = json!({});
+    }
 
     let mut request_data = RequestData::new(url, body);
 
     request_data.bearer_auth(api_key);
-    if let Some(organization_id) = &self_.config.organization_id {
-        request_data.header("OpenAI-Organization", organization_id);
-    }
+    set_openai_headers(self_, &mut request_data);
 
     Ok(request_data)
 }
@@ -74,26 +90,70 @@ fn prepare_embeddings(self_: &OpenAIClient, data: &EmbeddingsData) -> Result<Req
     let mut request_data = RequestData::new(url, body);
 
     request_data.bearer_auth(api_key);
-    if let Some(organization_id) = &self_.config.organization_id {
-        request_data.header("OpenAI-Organization", organization_id);
-    }
+    set_openai_headers(self_, &mut request_data);
 
     Ok(request_data)
 }
 
+#[cfg(feature = "voice")]
+fn prepare_realtime(self_: &OpenAIClient) -> Result<RealtimeRequest> {
+    let api_key = self_.get_api_key()?;
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!(
+        "{}/realtime?model={}",
+        websocket_url(api_base.trim_end_matches('/')),
+        self_.model.name()
+    );
+
+    let mut request_data = RequestData::new(url, Value::Null);
+
+    request_data.bearer_auth(api_key);
+    request_data.header("OpenAI-Beta", "realtime=v1");
+    set_openai_headers(self_, &mut request_data);
+
+    Ok(RealtimeRequest {
+        provider: RealtimeProvider::OpenAI,
+        model: self_.model.name().to_string(),
+        data: request_data,
+    })
+}
+
+fn set_openai_headers(self_: &OpenAIClient, request_data: &mut RequestData) {
+    if let Ok(organization_id) = self_.get_organization_id() {
+        request_data.header("OpenAI-Organization", organization_id);
+    }
+    if let Ok(project_id) = self_.get_project_id() {
+        request_data.header("OpenAI-Project", project_id);
+    }
+}
+
 pub async fn openai_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
     let res = builder.send().await?;
     let status = res.status();
+    let header = |name: &str| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let provider = header("x-openrouter-provider");
+    let cost = header("x-openrouter-cost").and_then(|v| v.parse::<f64>().ok());
     let data: Value = res.json().await?;
     if !status.is_success() {
         catch_error(&data, status.as_u16())?;
     }
 
     debug!("non-stream-data: {data}");
-    openai_extract_chat_completions(&data)
+    let mut output = openai_extract_chat_completions(&data)?;
+    output.provider = output.provider.or(provider);
+    output.cost = output.cost.or(cost);
+    Ok(output)
 }
 
 pub async fn openai_chat_completions_streaming(
@@ -105,6 +165,7 @@ pub async fn openai_chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
+    let last_active = handler.last_active();
     let handle = |message: SseMmessage| -> Result<bool> {
         if message.data == "[DONE]" {
             if !function_name.is_empty() {
@@ -121,6 +182,15 @@ pub async fn openai_chat_completions_streaming(
         }
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
+        for citation in openai_extract_citations(&data, &data["choices"][0]["delta"]) {
+            handler.citation(citation);
+        }
+        handler.set_usage(
+            data["usage"]["prompt_tokens"].as_u64(),
+            data["usage"]["completion_tokens"].as_u64(),
+        );
+        handler.set_routing(data["provider"].as_str(), data["usage"]["cost"].as_f64());
+        handler.set_finish_reason(data["choices"][0]["finish_reason"].as_str());
         if let Some(text) = data["choices"][0]["delta"]["content"]
             .as_str()
             .filter(|v| !v.is_empty())
@@ -151,14 +221,19 @@ pub async fn openai_chat_completions_streaming(
                 call_id = maybe_call_id;
             }
             if let Some(name) = function.get("name").and_then(|v| v.as_str()) {
+                let started = function_name.is_empty() && !name.is_empty();
                 if name.starts_with(&function_name) {
                     function_name = name.to_string();
                 } else {
                     function_name.push_str(name);
                 }
+                if started {
+                    handler.tool_call_start(&function_name, id)?;
+                }
             }
             if let Some(arguments) = function.get("arguments").and_then(|v| v.as_str()) {
                 function_arguments.push_str(arguments);
+                handler.tool_call_delta(arguments)?;
             }
             if let Some(id) = id {
                 function_id = id.to_string();
@@ -167,7 +242,7 @@ pub async fn openai_chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    sse_stream(builder, last_active, handle).await
 }
 
 pub async fn openai_embeddings(
@@ -203,12 +278,15 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
         top_p,
         functions,
         stream,
+        stop,
+        response_format,
+        seed,
     } = data;
 
     let messages: Vec<Value> = messages
         .into_iter()
         .flat_map(|message| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::ToolCalls(MessageContentToolCalls {
                         tool_results,
@@ -289,6 +367,15 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
     if stream {
         body["stream"] = true.into();
     }
+    if let Some(v) = stop {
+        body["stop"] = v.into();
+    }
+    if let Some(v) = seed {
+        body["seed"] = v.into();
+    }
+    if response_format == Some(ResponseFormat::Json) {
+        body["response_format"] = json!({ "type": "json_object" });
+    }
     if let Some(functions) = functions {
         body["tools"] = functions
             .iter()
@@ -340,14 +427,43 @@ pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
     }
     let output = ChatCompletionsOutput {
         text: text.to_string(),
+        citations: openai_extract_citations(data, &data["choices"][0]["message"]),
         tool_calls,
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["prompt_tokens"].as_u64(),
         output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        provider: data["provider"].as_str().map(|v| v.to_string()),
+        cost: data["usage"]["cost"].as_f64(),
+        finish_reason: data["choices"][0]["finish_reason"]
+            .as_str()
+            .map(|v| v.to_string()),
+        ..Default::default()
     };
     Ok(output)
 }
 
+/// Collect `url_citation` annotations (OpenAI search models) and top-level `citations` (Perplexity).
+fn openai_extract_citations(data: &Value, message: &Value) -> Vec<Citation> {
+    let mut citations = vec![];
+    for annotation in message["annotations"].as_array().into_iter().flatten() {
+        let url_citation = &annotation["url_citation"];
+        if let Some(url) = url_citation["url"].as_str() {
+            let mut citation =
+                Citation::new(url, url_citation["title"].as_str().unwrap_or_default());
+            citation
+                .offsets
+                .extend(url_citation["end_index"].as_u64().map(|v| v as usize));
+            Citation::merge(&mut citations, citation);
+        }
+    }
+    for url in data["citations"].as_array().into_iter().flatten() {
+        if let Some(url) = url.as_str() {
+            Citation::merge(&mut citations, Citation::new(url, ""));
+        }
+    }
+    citations
+}
+
 fn normalize_function_id(value: &str) -> Option<String> {
     if value.is_empty() {
         None
@@ -1,10 +1,13 @@
+use super::access_token::get_access_token;
+use super::oauth::*;
 use super::openai::*;
 use super::*;
 
 use anyhow::{Context, Result};
-use reqwest::RequestBuilder;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
+use std::future::Future;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct OpenAICompatibleConfig {
@@ -13,6 +16,10 @@ pub struct OpenAICompatibleConfig {
     pub api_key: Option<String>,
     #[serde(default)]
     pub models: Vec<ModelData>,
+    pub oauth: Option<OAuthConfig>,
+    /// OpenRouter provider routing preferences (order, allow_fallbacks, max_price...)
+    pub provider: Option<Value>,
+    pub route: Option<String>,
     pub patch: Option<RequestPatch>,
     pub extra: Option<ExtraConfig>,
 }
@@ -35,27 +42,133 @@ impl OpenAICompatibleClient {
     ];
 }
 
-impl_client_trait!(
-    OpenAICompatibleClient,
-    (
-        prepare_chat_completions,
-        openai_chat_completions,
-        openai_chat_completions_streaming
-    ),
-    (prepare_embeddings, openai_embeddings),
-    (prepare_rerank, generic_rerank),
-);
+impl OpenAICompatibleClient {
+    async fn with_oauth<T, F, Fut>(&self, client: &ReqwestClient, run: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.prepare_oauth(client).await?;
+        match run().await {
+            Err(err) if self.reauthorize(client, &err).await? => run().await,
+            ret => ret,
+        }
+    }
+
+    async fn prepare_oauth(&self, client: &ReqwestClient) -> Result<()> {
+        if let Some(oauth) = &self.config.oauth {
+            prepare_oauth_access_token(client, self.name(), oauth).await?;
+        }
+        Ok(())
+    }
+
+    /// Get a new access token when the request was rejected as unauthorized, returns
+    /// whether the request is worth retrying.
+    async fn reauthorize(&self, client: &ReqwestClient, err: &anyhow::Error) -> Result<bool> {
+        match &self.config.oauth {
+            Some(oauth) if is_unauthorized_error(err) => {
+                invalidate_oauth_access_token(self.name());
+                prepare_oauth_access_token(client, self.name(), oauth).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn get_api_key_ext(&self) -> Result<Option<String>> {
+        match &self.config.oauth {
+            Some(_) => get_access_token(&oauth_access_token_key(self.name())).map(Some),
+            None => Ok(self.get_api_key().ok()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for OpenAICompatibleClient {
+    client_common_fns!();
+
+    async fn chat_completions_inner(
+        &self,
+        client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<ChatCompletionsOutput> {
+        self.with_oauth(client, || {
+            let data = data.clone();
+            async move {
+                let request_data = prepare_chat_completions(self, data)?;
+                let builder = self.request_builder(client, request_data)?;
+                openai_chat_completions(builder, self.model()).await
+            }
+        })
+        .await
+    }
+
+    async fn chat_completions_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut SseHandler,
+        data: ChatCompletionsData,
+    ) -> Result<()> {
+        // The handler is borrowed mutably, so `with_oauth` can't wrap the request
+        self.prepare_oauth(client).await?;
+        let request_data = prepare_chat_completions(self, data.clone())?;
+        let builder = self.request_builder(client, request_data)?;
+        match openai_chat_completions_streaming(builder, handler, self.model()).await {
+            Err(err) if self.reauthorize(client, &err).await? => {
+                let request_data = prepare_chat_completions(self, data)?;
+                let builder = self.request_builder(client, request_data)?;
+                openai_chat_completions_streaming(builder, handler, self.model()).await
+            }
+            ret => ret,
+        }
+    }
+
+    async fn embeddings_inner(
+        &self,
+        client: &ReqwestClient,
+        data: &EmbeddingsData,
+    ) -> Result<EmbeddingsOutput> {
+        self.with_oauth(client, || async {
+            let request_data = prepare_embeddings(self, data)?;
+            let builder = self.request_builder(client, request_data)?;
+            openai_embeddings(builder, self.model()).await
+        })
+        .await
+    }
+
+    async fn rerank_inner(
+        &self,
+        client: &ReqwestClient,
+        data: &RerankData,
+    ) -> Result<RerankOutput> {
+        self.with_oauth(client, || async {
+            let request_data = prepare_rerank(self, data)?;
+            let builder = self.request_builder(client, request_data)?;
+            generic_rerank(builder, self.model()).await
+        })
+        .await
+    }
+}
 
 fn prepare_chat_completions(
     self_: &OpenAICompatibleClient,
     data: ChatCompletionsData,
 ) -> Result<RequestData> {
-    let api_key = self_.get_api_key().ok();
+    let api_key = self_.get_api_key_ext()?;
     let api_base = get_api_base_ext(self_)?;
 
     let url = format!("{api_base}/chat/completions");
 
-    let body = openai_build_chat_completions_body(data, &self_.model);
+    let mut body = openai_build_chat_completions_body(data, &self_.model);
+    if let Some(provider) = &self_.config.provider {
+        body["provider"] = provider.clone();
+    }
+    if let Some(route) = &self_.config.route {
+        body["route"] = route.clone().into();
+    }
+    if self_.name().starts_with("openrouter") {
+        body["usage"] = json!({ "include": true });
+    }
 
     let mut request_data = RequestData::new(url, body);
 
@@ -70,7 +183,7 @@ fn prepare_embeddings(
     self_: &OpenAICompatibleClient,
     data: &EmbeddingsData,
 ) -> Result<RequestData> {
-    let api_key = self_.get_api_key().ok();
+    let api_key = self_.get_api_key_ext()?;
     let api_base = get_api_base_ext(self_)?;
 
     let url = format!("{api_base}/embeddings");
@@ -87,7 +200,7 @@ fn prepare_embeddings(
 }
 
 fn prepare_rerank(self_: &OpenAICompatibleClient, data: &RerankData) -> Result<RequestData> {
-    let api_key = self_.get_api_key().ok();
+    let api_key = self_.get_api_key_ext()?;
     let api_base = get_api_base_ext(self_)?;
 
     let url = format!("{api_base}/rerank");
@@ -103,6 +216,29 @@ fn prepare_rerank(self_: &OpenAICompatibleClient, data: &RerankData) -> Result<R
     Ok(request_data)
 }
 
+impl OpenAICompatibleClient {
+    pub async fn fetch_models(&self) -> Result<Vec<ModelData>> {
+        let api_base = get_api_base_ext(self)?;
+        let client = self.build_client()?;
+        if self.name().starts_with("ollama") {
+            let url = format!("{}/api/tags", api_base.trim_end_matches("/v1"));
+            let data = fetch_models_json(client.get(url)).await?;
+            return parse_ollama_models(&data);
+        }
+        let url = format!("{api_base}/models");
+        let data = self
+            .with_oauth(&client, || async {
+                let mut builder = client.get(&url);
+                if let Some(api_key) = self.get_api_key_ext()? {
+                    builder = builder.bearer_auth(api_key);
+                }
+                fetch_models_json(builder).await
+            })
+            .await?;
+        parse_openai_models(&data)
+    }
+}
+
 fn get_api_base_ext(self_: &OpenAICompatibleClient) -> Result<String> {
     let api_base = match self_.get_api_base() {
         Ok(v) => v,
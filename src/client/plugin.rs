@@ -0,0 +1,137 @@
+use super::exec::{parse_exec_line, ExecEvent};
+use super::openai::openai_build_chat_completions_body;
+use super::*;
+
+use crate::config::Config;
+use crate::plugin::{run_plugin, Plugin};
+
+use anyhow::{anyhow, Result};
+use reqwest::Client as ReqwestClient;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginConfig {
+    pub name: Option<String>,
+    pub plugin: String,
+    #[serde(default)]
+    pub models: Vec<ModelData>,
+    pub patch: Option<RequestPatch>,
+    pub extra: Option<ExtraConfig>,
+}
+
+impl PluginClient {
+    pub const PROMPTS: [PromptAction<'static>; 2] = [
+        ("plugin", "Plugin Name:", true, PromptKind::String),
+        ("models[].name", "Model Name:", true, PromptKind::String),
+    ];
+}
+
+/// The client entries registered by a plugin's manifest.
+pub fn plugin_client_configs(plugin: &Plugin) -> Vec<ClientConfig> {
+    plugin
+        .manifest
+        .clients
+        .iter()
+        .map(|v| {
+            ClientConfig::PluginConfig(PluginConfig {
+                name: Some(v.name.clone()),
+                plugin: plugin.name.clone(),
+                models: v.models.clone(),
+                patch: None,
+                extra: None,
+            })
+        })
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl Client for PluginClient {
+    client_common_fns!();
+
+    async fn chat_completions_inner(
+        &self,
+        _client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<ChatCompletionsOutput> {
+        let mut output = ChatCompletionsOutput::default();
+        self.run(data, false, |event| {
+            match event {
+                ExecEvent::Text(text) => output.text.push_str(&text),
+                ExecEvent::ToolCall(call) => output.tool_calls.push(call),
+                ExecEvent::Usage(input_tokens, output_tokens) => {
+                    output.input_tokens = input_tokens;
+                    output.output_tokens = output_tokens;
+                }
+                ExecEvent::FinishReason(reason) => output.finish_reason = Some(reason),
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(output)
+    }
+
+    async fn chat_completions_streaming_inner(
+        &self,
+        _client: &ReqwestClient,
+        handler: &mut SseHandler,
+        data: ChatCompletionsData,
+    ) -> Result<()> {
+        handler.connected();
+        self.run(data, true, |event| match event {
+            ExecEvent::Text(text) => handler.text(&text),
+            ExecEvent::ToolCall(call) => handler.tool_call(call),
+            ExecEvent::Usage(input_tokens, output_tokens) => {
+                handler.set_usage(input_tokens, output_tokens);
+                Ok(())
+            }
+            ExecEvent::FinishReason(reason) => {
+                handler.set_finish_reason(Some(&reason));
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+impl PluginClient {
+    /// Run the plugin's `chat` request, its stdout is parsed like the output of the `exec` client.
+    async fn run<F>(&self, data: ChatCompletionsData, stream: bool, mut on_event: F) -> Result<()>
+    where
+        F: FnMut(ExecEvent) -> Result<()>,
+    {
+        let mut body = openai_build_chat_completions_body(data, &self.model);
+        body["stream"] = stream.into();
+        let mut request_data = RequestData::new("", body);
+        self.patch_request_data(&mut request_data);
+        let request = json!({
+            "type": "chat",
+            "client": self.name(),
+            "body": request_data.body,
+        });
+        debug!("Plugin {} {request}", self.config.plugin);
+
+        let path = Config::plugins_dir().join(format!("{}.wasm", self.config.plugin));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::task::spawn_blocking(move || run_plugin(&path, &request, Some(tx)));
+
+        let mut buffer = vec![];
+        while let Some(chunk) = rx.recv().await {
+            buffer.extend(chunk);
+            while let Some(index) = buffer.iter().position(|v| *v == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=index).collect();
+                let line = String::from_utf8_lossy(&line[..index]);
+                if let Some(event) = parse_exec_line(&line)? {
+                    on_event(event)?;
+                }
+            }
+        }
+        task.await.map_err(|err| anyhow!("{err}"))??;
+        if !buffer.is_empty() {
+            if let Some(event) = parse_exec_line(&String::from_utf8_lossy(&buffer))? {
+                on_event(event)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use super::{connect_websocket, RequestData, WebSocket};
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+
+/// Replies of both providers are PCM16 mono at this rate
+pub const REALTIME_OUTPUT_SAMPLE_RATE: u32 = 24000;
+
+/// The WebSocket endpoint of a live voice session
+pub struct RealtimeRequest {
+    pub provider: RealtimeProvider,
+    pub model: String,
+    pub data: RequestData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RealtimeProvider {
+    OpenAI,
+    Gemini,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RealtimeEvent {
+    /// PCM16 mono audio of the reply
+    Audio(Vec<u8>),
+    Transcript(String),
+    InputTranscript(String),
+    /// The user started talking over the reply
+    Interrupted,
+    TurnDone,
+    Error(String),
+    /// The session is set up, audio can be sent from now on
+    Ready,
+}
+
+impl RealtimeProvider {
+    /// Gemini drops audio sent before it answers the setup message with `setupComplete`
+    pub fn ready_on_connect(&self) -> bool {
+        matches!(self, Self::OpenAI)
+    }
+
+    pub fn input_sample_rate(&self) -> u32 {
+        match self {
+            Self::OpenAI => 24000,
+            Self::Gemini => 16000,
+        }
+    }
+
+    pub fn setup_message(&self, model: &str, instructions: &str) -> Value {
+        match self {
+            Self::OpenAI => {
+                let mut session = json!({
+                    "modalities": ["text", "audio"],
+                    "input_audio_format": "pcm16",
+                    "output_audio_format": "pcm16",
+                    "input_audio_transcription": { "model": "whisper-1" },
+                    "turn_detection": { "type": "server_vad" },
+                });
+                if !instructions.is_empty() {
+                    session["instructions"] = instructions.into();
+                }
+                json!({ "type": "session.update", "session": session })
+            }
+            Self::Gemini => {
+                let mut setup = json!({
+                    "model": format!("models/{model}"),
+                    "generationConfig": { "responseModalities": ["AUDIO"] },
+                    "inputAudioTranscription": {},
+                    "outputAudioTranscription": {},
+                });
+                if !instructions.is_empty() {
+                    setup["systemInstruction"] = json!({ "parts": [{ "text": instructions }] });
+                }
+                json!({ "setup": setup })
+            }
+        }
+    }
+
+    pub fn audio_message(&self, pcm: &[u8]) -> Value {
+        let data = STANDARD.encode(pcm);
+        match self {
+            Self::OpenAI => json!({ "type": "input_audio_buffer.append", "audio": data }),
+            Self::Gemini => json!({
+                "realtimeInput": {
+                    "audio": {
+                        "data": data,
+                        "mimeType": format!("audio/pcm;rate={}", self.input_sample_rate()),
+                    }
+                }
+            }),
+        }
+    }
+
+    pub fn parse_event(&self, data: &Value) -> Vec<RealtimeEvent> {
+        match self {
+            Self::OpenAI => parse_openai_event(data),
+            Self::Gemini => parse_gemini_message(data),
+        }
+    }
+}
+
+pub async fn connect_realtime(request: &RealtimeRequest) -> Result<WebSocket> {
+    connect_websocket(&request.data).await
+}
+
+fn parse_openai_event(data: &Value) -> Vec<RealtimeEvent> {
+    let text = |key: &str| data[key].as_str().unwrap_or_default().to_string();
+    let event = match data["type"].as_str().unwrap_or_default() {
+        "response.audio.delta" => match STANDARD.decode(text("delta")) {
+            Ok(pcm) => RealtimeEvent::Audio(pcm),
+            Err(_) => return vec![],
+        },
+        "response.audio_transcript.delta" | "response.text.delta" => {
+            RealtimeEvent::Transcript(text("delta"))
+        }
+        "conversation.item.input_audio_transcription.completed" => {
+            RealtimeEvent::InputTranscript(text("transcript").trim().to_string())
+        }
+        "input_audio_buffer.speech_started" => RealtimeEvent::Interrupted,
+        "response.done" => RealtimeEvent::TurnDone,
+        "error" => RealtimeEvent::Error(
+            data["error"]["message"]
+                .as_str()
+                .unwrap_or("Unknown error")
+                .to_string(),
+        ),
+        _ => return vec![],
+    };
+    vec![event]
+}
+
+fn parse_gemini_message(data: &Value) -> Vec<RealtimeEvent> {
+    if data.get("setupComplete").is_some() {
+        return vec![RealtimeEvent::Ready];
+    }
+    let content = &data["serverContent"];
+    let mut events = vec![];
+    if let Some(text) = content["inputTranscription"]["text"].as_str() {
+        events.push(RealtimeEvent::InputTranscript(text.to_string()));
+    }
+    if content["interrupted"].as_bool() == Some(true) {
+        events.push(RealtimeEvent::Interrupted);
+    }
+    if let Some(parts) = content["modelTurn"]["parts"].as_array() {
+        for part in parts {
+            if let Some(pcm) = part["inlineData"]["data"]
+                .as_str()
+                .and_then(|v| STANDARD.decode(v).ok())
+            {
+                events.push(RealtimeEvent::Audio(pcm));
+            }
+        }
+    }
+    if let Some(text) = content["outputTranscription"]["text"].as_str() {
+        events.push(RealtimeEvent::Transcript(text.to_string()));
+    }
+    if content["turnComplete"].as_bool() == Some(true) {
+        events.push(RealtimeEvent::TurnDone);
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event() {
+        let openai = RealtimeProvider::OpenAI;
+        assert_eq!(
+            openai.parse_event(&json!({ "type": "response.audio.delta", "delta": "AAE=" })),
+            vec![RealtimeEvent::Audio(vec![0, 1])]
+        );
+        assert_eq!(
+            openai.parse_event(&json!({ "type": "error", "error": { "message": "Bad" } })),
+            vec![RealtimeEvent::Error("Bad".into())]
+        );
+        assert!(openai
+            .parse_event(&json!({ "type": "session.created" }))
+            .is_empty());
+
+        let gemini = RealtimeProvider::Gemini;
+        assert_eq!(
+            gemini.parse_event(&json!({
+                "serverContent": {
+                    "modelTurn": { "parts": [{ "inlineData": { "data": "AAE=" } }] },
+                    "outputTranscription": { "text": "Hi" },
+                    "turnComplete": true,
+                }
+            })),
+            vec![
+                RealtimeEvent::Audio(vec![0, 1]),
+                RealtimeEvent::Transcript("Hi".into()),
+                RealtimeEvent::TurnDone,
+            ]
+        );
+        assert_eq!(
+            gemini.parse_event(&json!({ "setupComplete": {} })),
+            vec![RealtimeEvent::Ready]
+        );
+    }
+}
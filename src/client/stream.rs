@@ -1,11 +1,16 @@
-use super::{catch_error, ToolCall};
+use super::{
+    is_websocket_request, transport_stream, Citation, SseTransport, ToolCall, WsTransport,
+};
 use crate::utils::AbortSignal;
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, Context, Result};
 use futures_util::{Stream, StreamExt};
+use parking_lot::Mutex;
 use reqwest::RequestBuilder;
-use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
-use serde_json::Value;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::UnboundedSender;
 
 pub struct SseHandler {
@@ -13,8 +18,16 @@ pub struct SseHandler {
     abort_signal: AbortSignal,
     buffer: String,
     tool_calls: Vec<ToolCall>,
+    citations: Vec<Citation>,
+    usage: (Option<u64>, Option<u64>),
+    routing: (Option<String>, Option<f64>),
+    finish_reason: Option<String>,
+    last_active: LastActive,
 }
 
+/// When the stream last produced output, `None` until the response headers arrive.
+pub type LastActive = Arc<Mutex<Option<Instant>>>;
+
 impl SseHandler {
     pub fn new(sender: UnboundedSender<SseEvent>, abort_signal: AbortSignal) -> Self {
         Self {
@@ -22,6 +35,11 @@ impl SseHandler {
             abort_signal,
             buffer: String::new(),
             tool_calls: Vec::new(),
+            citations: Vec::new(),
+            usage: (None, None),
+            routing: (None, None),
+            finish_reason: None,
+            last_active: Default::default(),
         }
     }
 
@@ -31,17 +49,7 @@ impl SseHandler {
             return Ok(());
         }
         self.buffer.push_str(text);
-        let ret = self
-            .sender
-            .send(SseEvent::Text(text.to_string()))
-            .with_context(|| "Failed to send SseEvent:Text");
-        if let Err(err) = ret {
-            if self.abort_signal.aborted() {
-                return Ok(());
-            }
-            return Err(err);
-        }
-        Ok(())
+        self.send(SseEvent::Text(text.to_string()))
     }
 
     pub fn done(&mut self) {
@@ -55,90 +63,173 @@ impl SseHandler {
         }
     }
 
+    pub fn tool_call_start(&mut self, name: &str, id: Option<&str>) -> Result<()> {
+        self.send(SseEvent::ToolCallStart {
+            name: name.to_string(),
+            id: id.map(|v| v.to_string()),
+        })
+    }
+
+    pub fn tool_call_delta(&mut self, arguments: &str) -> Result<()> {
+        if arguments.is_empty() {
+            return Ok(());
+        }
+        self.send(SseEvent::ToolCallDelta(arguments.to_string()))
+    }
+
     pub fn tool_call(&mut self, call: ToolCall) -> Result<()> {
         // debug!("HandleCall: {:?}", call);
-        self.tool_calls.push(call);
-        Ok(())
+        self.tool_calls.push(call.clone());
+        self.send(SseEvent::ToolCall(call))
+    }
+
+    pub fn citation(&mut self, citation: Citation) {
+        Citation::merge(&mut self.citations, citation);
+    }
+
+    /// Record token usage reported by the stream, later reports override earlier ones.
+    pub fn set_usage(&mut self, input_tokens: Option<u64>, output_tokens: Option<u64>) {
+        if input_tokens.is_some() {
+            self.usage.0 = input_tokens;
+        }
+        if output_tokens.is_some() {
+            self.usage.1 = output_tokens;
+        }
+    }
+
+    pub fn set_finish_reason(&mut self, finish_reason: Option<&str>) {
+        if let Some(v) = finish_reason {
+            self.finish_reason = Some(v.to_string());
+        }
+    }
+
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.finish_reason.as_deref()
+    }
+
+    pub fn usage(&self) -> (Option<u64>, Option<u64>) {
+        self.usage
+    }
+
+    /// Record the serving provider and cost reported by a routing API
+    pub fn set_routing(&mut self, provider: Option<&str>, cost: Option<f64>) {
+        if let Some(provider) = provider {
+            self.routing.0 = Some(provider.to_string());
+        }
+        if cost.is_some() {
+            self.routing.1 = cost;
+        }
+    }
+
+    pub fn routing(&self) -> (Option<String>, Option<f64>) {
+        self.routing.clone()
     }
 
     pub fn abort(&self) -> AbortSignal {
         self.abort_signal.clone()
     }
 
+    pub fn last_active(&self) -> LastActive {
+        self.last_active.clone()
+    }
+
+    /// The response has started, waiting longer for output counts as idle.
+    pub fn connected(&self) {
+        connected(&self.last_active);
+    }
+
     pub fn tool_calls(&self) -> &[ToolCall] {
         &self.tool_calls
     }
 
-    pub fn take(self) -> (String, Vec<ToolCall>) {
+    pub fn take(self) -> (String, Vec<ToolCall>, Vec<Citation>) {
         let Self {
-            buffer, tool_calls, ..
+            buffer,
+            tool_calls,
+            citations,
+            ..
         } = self;
-        (buffer, tool_calls)
+        (buffer, tool_calls, citations)
+    }
+
+    fn send(&mut self, event: SseEvent) -> Result<()> {
+        *self.last_active.lock() = Some(Instant::now());
+        let ret = self
+            .sender
+            .send(event)
+            .with_context(|| "Failed to send SseEvent");
+        if let Err(err) = ret {
+            if self.abort_signal.aborted() {
+                return Ok(());
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+pub fn connected(last_active: &LastActive) {
+    last_active.lock().get_or_insert_with(Instant::now);
+}
+
+/// Resolve once no output has been produced for `timeout` seconds since the response started,
+/// never if `timeout` is None.
+pub async fn wait_stream_idle(last_active: LastActive, timeout: Option<u64>) -> u64 {
+    let timeout = match timeout {
+        Some(v) if v > 0 => Duration::from_secs(v),
+        _ => return std::future::pending().await,
+    };
+    loop {
+        let Some(elapsed) = last_active.lock().map(|v| v.elapsed()) else {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        };
+        if elapsed >= timeout {
+            return timeout.as_secs();
+        }
+        tokio::time::sleep(timeout - elapsed).await;
     }
 }
 
 #[derive(Debug)]
 pub enum SseEvent {
     Text(String),
+    ToolCallStart {
+        name: String,
+        #[allow(unused)]
+        id: Option<String>,
+    },
+    ToolCallDelta(String),
+    #[allow(unused)]
+    ToolCall(ToolCall),
     Done,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SseMmessage {
     #[allow(unused)]
     pub event: String,
     pub data: String,
+    /// The last event id seen on the stream
+    pub id: Option<String>,
 }
 
-pub async fn sse_stream<F>(builder: RequestBuilder, mut handle: F) -> Result<()>
+pub async fn sse_stream<F>(
+    builder: RequestBuilder,
+    last_active: LastActive,
+    handle: F,
+) -> Result<()>
 where
     F: FnMut(SseMmessage) -> Result<bool>,
 {
-    let mut es = builder.eventsource()?;
-    while let Some(event) = es.next().await {
-        match event {
-            Ok(Event::Open) => {}
-            Ok(Event::Message(message)) => {
-                let message = SseMmessage {
-                    event: message.event,
-                    data: message.data,
-                };
-                if handle(message)? {
-                    break;
-                }
-            }
-            Err(err) => {
-                match err {
-                    EventSourceError::StreamEnded => {}
-                    EventSourceError::InvalidStatusCode(status, res) => {
-                        let text = res.text().await?;
-                        let data: Value = match text.parse() {
-                            Ok(data) => data,
-                            Err(_) => {
-                                bail!(
-                                    "Invalid response data: {text} (status: {})",
-                                    status.as_u16()
-                                );
-                            }
-                        };
-                        catch_error(&data, status.as_u16())?;
-                    }
-                    EventSourceError::InvalidContentType(header_value, res) => {
-                        let text = res.text().await?;
-                        bail!(
-                            "Invalid response event-stream. content-type: {}, data: {text}",
-                            header_value.to_str().unwrap_or_default()
-                        );
-                    }
-                    _ => {
-                        bail!("{}", err);
-                    }
-                }
-                es.close();
-            }
+    let request = builder.try_clone().and_then(|v| v.build().ok());
+    match request {
+        Some(request) if is_websocket_request(&request) => {
+            let transport = WsTransport::from_request(&request)?;
+            transport_stream(&transport, &last_active, handle).await
         }
+        _ => transport_stream(&SseTransport(builder), &last_active, handle).await,
     }
-    Ok(())
 }
 
 pub async fn json_stream<S, F, E>(mut stream: S, mut handle: F) -> Result<()>
@@ -212,10 +303,8 @@ impl JsonStreamParser {
                     }
                     self.balances.push(ch);
                 }
-                '[' => {
-                    if self.start.is_some() {
-                        self.balances.push(ch);
-                    }
+                '[' if self.start.is_some() => {
+                    self.balances.push(ch);
                 }
                 '}' => {
                     self.balances.pop();
@@ -237,6 +326,117 @@ impl JsonStreamParser {
     }
 }
 
+/// Incremental parser of `text/event-stream`, fed with chunks split anywhere.
+///
+/// Lines end with CRLF, LF or CR, `:` lines are comments (keep-alive heartbeats),
+/// multiple `data` lines are joined with `\n` and the last `id` sticks to later events.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    line: Vec<u8>,
+    pending_cr: bool,
+    started: bool,
+    event: String,
+    data: Vec<String>,
+    last_event_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseParser {
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseMmessage> {
+        let mut messages = vec![];
+        if chunk.is_empty() {
+            return messages;
+        }
+        let mut start = 0;
+        if std::mem::take(&mut self.pending_cr) && chunk.first() == Some(&b'\n') {
+            start = 1;
+        }
+        let mut i = start;
+        while i < chunk.len() {
+            let ch = chunk[i];
+            if ch == b'\n' || ch == b'\r' {
+                self.line.extend_from_slice(&chunk[start..i]);
+                let line = std::mem::take(&mut self.line);
+                if ch == b'\r' {
+                    match chunk.get(i + 1) {
+                        Some(b'\n') => i += 1,
+                        Some(_) => {}
+                        None => self.pending_cr = true,
+                    }
+                }
+                messages.extend(self.process_line(&line));
+                start = i + 1;
+            }
+            i += 1;
+        }
+        self.line.extend_from_slice(&chunk[start..]);
+        messages
+    }
+
+    /// Flush at the end of the stream, an event without the trailing blank line is still dispatched.
+    pub fn finish(&mut self) -> Option<SseMmessage> {
+        let line = std::mem::take(&mut self.line);
+        if !line.is_empty() {
+            self.process_line(&line);
+        }
+        self.dispatch()
+    }
+
+    /// The reconnection time in milliseconds the server asked for
+    #[allow(dead_code)]
+    pub fn retry(&self) -> Option<u64> {
+        self.retry
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<SseMmessage> {
+        let line = String::from_utf8_lossy(line);
+        let mut line = line.as_ref();
+        if !self.started {
+            self.started = true;
+            line = line.strip_prefix('\u{feff}').unwrap_or(line);
+        }
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => self.data.push(value.to_string()),
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseMmessage> {
+        let event = std::mem::take(&mut self.event);
+        if self.data.is_empty() {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data).join("\n");
+        Some(SseMmessage {
+            event: if event.is_empty() {
+                "message".into()
+            } else {
+                event
+            },
+            data,
+            id: self.last_event_id.clone(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -250,9 +450,9 @@ mod tests {
         let len = text.len();
         let cut1 = rng.gen_range(1..len - 1);
         let cut2 = rng.gen_range(cut1 + 1..len);
-        let chunk1 = text[..cut1].as_bytes().to_vec();
-        let chunk2 = text[cut1..cut2].as_bytes().to_vec();
-        let chunk3 = text[cut2..].as_bytes().to_vec();
+        let chunk1 = text.as_bytes()[..cut1].to_vec();
+        let chunk2 = text.as_bytes()[cut1..cut2].to_vec();
+        let chunk3 = text.as_bytes()[cut2..].to_vec();
         vec![chunk1, chunk2, chunk3]
     }
 
@@ -293,4 +493,100 @@ mod tests {
 {"key": "value3"}"#;
         assert_json_stream!(input, output);
     }
+
+    fn parse_sse(chunks: &[&[u8]]) -> Vec<SseMmessage> {
+        let mut parser = SseParser::default();
+        let mut messages = vec![];
+        for chunk in chunks {
+            messages.extend(parser.feed(chunk));
+        }
+        messages.extend(parser.finish());
+        messages
+    }
+
+    fn message(event: &str, data: &str, id: Option<&str>) -> SseMmessage {
+        SseMmessage {
+            event: event.into(),
+            data: data.into(),
+            id: id.map(|v| v.into()),
+        }
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let input = "\u{feff}: keep-alive\r\n\r\nid: 1\r\ndata: {\"a\":\r\ndata:1}\r\n\r\n:ping\n\nevent: done\rretry: 3000\rdata: [DONE]\r\r";
+        let expected = vec![
+            message("message", "{\"a\":\n1}", Some("1")),
+            message("done", "[DONE]", Some("1")),
+        ];
+        assert_eq!(parse_sse(&[input.as_bytes()]), expected);
+        let mut parser = SseParser::default();
+        parser.feed(input.as_bytes());
+        assert_eq!(parser.retry(), Some(3000));
+        // A CRLF split between chunks ends one line, not two
+        let bytes = input.as_bytes();
+        for i in 0..=bytes.len() {
+            assert_eq!(parse_sse(&[&bytes[..i], &bytes[i..]]), expected);
+        }
+        assert_eq!(
+            parse_sse(&[b"data: tail"]),
+            vec![message("message", "tail", None)]
+        );
+    }
+
+    #[test]
+    fn test_sse_parser_fuzz() {
+        let mut rng = thread_rng();
+        let line_endings = ["\n", "\r\n", "\r"];
+        let words = ["hello", "{\"k\": \"v\"}", "ü日本", "a:b", " spaced", ""];
+        for _ in 0..200 {
+            let mut input = String::new();
+            let mut expected = vec![];
+            for _ in 0..rng.gen_range(1..5) {
+                let eol = line_endings[rng.gen_range(0..line_endings.len())];
+                if rng.gen_bool(0.3) {
+                    input.push_str(&format!(": heartbeat{eol}"));
+                }
+                let lines: Vec<&str> = (0..rng.gen_range(1..4))
+                    .map(|_| words[rng.gen_range(0..words.len())])
+                    .collect();
+                for line in &lines {
+                    input.push_str(&format!("data: {line}{eol}"));
+                }
+                input.push_str(eol);
+                expected.push(message("message", &lines.join("\n"), None));
+            }
+            let bytes = input.as_bytes();
+            let mut cuts: Vec<usize> = (0..rng.gen_range(0..6))
+                .map(|_| rng.gen_range(0..=bytes.len()))
+                .collect();
+            cuts.sort_unstable();
+            let mut chunks = vec![];
+            let mut start = 0;
+            for cut in cuts {
+                chunks.push(&bytes[start..cut]);
+                start = cut;
+            }
+            chunks.push(&bytes[start..]);
+            assert_eq!(parse_sse(&chunks), expected, "input: {input:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_stream_idle() {
+        let last_active = LastActive::default();
+        // Waiting for the response headers doesn't count as idle
+        let wait = wait_stream_idle(last_active.clone(), Some(1));
+        assert!(tokio::time::timeout(Duration::from_millis(1500), wait)
+            .await
+            .is_err());
+        connected(&last_active);
+        let start = Instant::now();
+        assert_eq!(wait_stream_idle(last_active.clone(), Some(1)).await, 1);
+        assert!(start.elapsed() >= Duration::from_millis(900));
+        // Connecting again after a retry doesn't reset the clock
+        let started = *last_active.lock();
+        connected(&last_active);
+        assert_eq!(*last_active.lock(), started);
+    }
 }
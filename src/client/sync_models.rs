@@ -0,0 +1,269 @@
+use super::*;
+
+use crate::config::{Config, GlobalConfig};
+use crate::utils::check_offline;
+
+use anyhow::{bail, Context, Result};
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const MODELS_OVERRIDE_FILE_NAME: &str = "models-override.yaml";
+
+/// Model names containing these are not usable for chat or embeddings
+const SKIP_MODEL_KEYWORDS: [&str; 8] = [
+    "whisper",
+    "tts",
+    "dall-e",
+    "moderation",
+    "realtime",
+    "transcribe",
+    "audio",
+    "image",
+];
+
+pub fn models_override_file() -> PathBuf {
+    Config::local_path(MODELS_OVERRIDE_FILE_NAME)
+}
+
+/// Merge the synced models into the builtin ones, builtin metadata wins for models known to both.
+pub fn merge_models_override(
+    mut predefined: Vec<PredefinedModels>,
+    synced: Vec<PredefinedModels>,
+) -> Vec<PredefinedModels> {
+    for synced in synced.into_iter().rev() {
+        match predefined
+            .iter_mut()
+            .find(|v| v.platform == synced.platform)
+        {
+            Some(exist) => {
+                for model in synced.models {
+                    if !exist.models.iter().any(|v| v.name == model.name) {
+                        exist.models.push(model);
+                    }
+                }
+            }
+            None => predefined.insert(0, synced),
+        }
+    }
+    predefined
+}
+
+pub fn load_models_override() -> Vec<PredefinedModels> {
+    let path = models_override_file();
+    let Ok(content) = fs::read_to_string(&path) else {
+        return vec![];
+    };
+    serde_yaml::from_str(&content).unwrap_or_else(|err| {
+        warn!("Invalid models override at '{}', {err}", path.display());
+        vec![]
+    })
+}
+
+/// Whether `models_refresh_interval` hours have passed since the last sync.
+/// Never without an interval, so startup does not touch the network unless asked to.
+pub fn need_refresh_models(config: &Config) -> bool {
+    need_refresh_models_at(config.models_refresh_interval, &models_override_file())
+}
+
+fn need_refresh_models_at(interval: Option<u64>, path: &Path) -> bool {
+    let Some(interval) = interval else {
+        return false;
+    };
+    let modified = fs::metadata(path).and_then(|v| v.modified());
+    match modified.ok().and_then(|v| v.elapsed().ok()) {
+        Some(elapsed) => elapsed > Duration::from_secs(interval * 3600),
+        None => true,
+    }
+}
+
+/// Query the models endpoint of every client that has one and save the result to `models-override.yaml`.
+/// Returns the number of models fetched per client.
+pub async fn sync_models(config: &GlobalConfig) -> Result<Vec<(String, Result<usize>)>> {
+    let clients = config.read().clients.clone();
+    let mut synced = load_models_override();
+    let mut report = vec![];
+    for client_config in clients {
+        let (platform, ret) = match client_config {
+            ClientConfig::OpenAIConfig(c) => {
+                let name = OpenAIClient::name(&c).to_string();
+                let client = OpenAIClient {
+                    global_config: config.clone(),
+                    model: Model::new(&name, ""),
+                    config: c,
+                };
+                (OpenAIClient::NAME.to_string(), client.fetch_models().await)
+            }
+            ClientConfig::OpenAICompatibleConfig(c) => {
+                let name = OpenAICompatibleClient::name(&c).to_string();
+                let client = OpenAICompatibleClient {
+                    global_config: config.clone(),
+                    model: Model::new(&name, ""),
+                    config: c,
+                };
+                (name, client.fetch_models().await)
+            }
+            _ => continue,
+        };
+        let ret = ret.map(|models| {
+            let count = models.len();
+            synced.retain(|v| v.platform != platform);
+            synced.push(PredefinedModels {
+                platform: platform.clone(),
+                models,
+            });
+            count
+        });
+        report.push((platform, ret));
+    }
+    if report.is_empty() {
+        bail!("No client supports syncing models");
+    }
+    let path = models_override_file();
+    let content = serde_yaml::to_string(&synced)?;
+    fs::write(&path, content)
+        .with_context(|| format!("Failed to write models to '{}'", path.display()))?;
+    Ok(report)
+}
+
+pub async fn fetch_models_json(builder: RequestBuilder) -> Result<Value> {
+    let (client, request) = builder.build_split();
+    let request = request?;
+    check_offline(request.url().as_str())?;
+    let res = client.execute(request).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    catch_error(&data, status.as_u16())?;
+    Ok(data)
+}
+
+/// Parse an OpenAI-style `/models` listing, also picking up OpenRouter's context length and per-token pricing.
+pub fn parse_openai_models(data: &Value) -> Result<Vec<ModelData>> {
+    let Some(list) = data["data"].as_array() else {
+        bail!("Invalid response data: {data}");
+    };
+    let mut models = vec![];
+    for item in list {
+        let Some(id) = item["id"].as_str() else {
+            continue;
+        };
+        if SKIP_MODEL_KEYWORDS.iter().any(|v| id.contains(v)) {
+            continue;
+        }
+        let mut model = ModelData::new(id);
+        if id.contains("embed") {
+            model.model_type = "embedding".into();
+        }
+        model.max_input_tokens =
+            item["context_length"]
+                .as_u64()
+                .map(|v| v as usize)
+                .or_else(|| {
+                    item["top_provider"]["context_length"]
+                        .as_u64()
+                        .map(|v| v as usize)
+                });
+        model.max_output_tokens = item["top_provider"]["max_completion_tokens"]
+            .as_i64()
+            .map(|v| v as isize);
+        let per_million = |v: &Value| {
+            v.as_str()
+                .and_then(|v| v.parse::<f64>().ok())
+                .map(|v| (v * 1_000_000.0 * 1000.0).round() / 1000.0)
+        };
+        model.input_price = per_million(&item["pricing"]["prompt"]);
+        model.output_price = per_million(&item["pricing"]["completion"]);
+        if let Some(modality) = item["architecture"]["modality"].as_str() {
+            model.supports_vision = modality.contains("image");
+        }
+        if let Some(params) = item["supported_parameters"].as_array() {
+            model.supports_function_calling = params.iter().any(|v| v == "tools");
+        }
+        models.push(model);
+    }
+    Ok(models)
+}
+
+/// Parse Ollama's `/api/tags` listing
+pub fn parse_ollama_models(data: &Value) -> Result<Vec<ModelData>> {
+    let Some(list) = data["models"].as_array() else {
+        bail!("Invalid response data: {data}");
+    };
+    Ok(list
+        .iter()
+        .filter_map(|v| v["name"].as_str())
+        .map(|name| {
+            let mut model = ModelData::new(name.trim_end_matches(":latest"));
+            if name.contains("embed") {
+                model.model_type = "embedding".into();
+            }
+            model
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_need_refresh_models() {
+        let path = crate::utils::temp_file("-models-override-", ".yaml");
+        // Off by default, even when nothing was ever synced
+        assert!(!need_refresh_models_at(None, &path));
+        assert!(need_refresh_models_at(Some(24), &path));
+        fs::write(&path, "[]").unwrap();
+        assert!(!need_refresh_models_at(None, &path));
+        assert!(!need_refresh_models_at(Some(24), &path));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_openai_models() {
+        let data = json!({"data": [
+            {"id": "gpt-4o"},
+            {"id": "whisper-1"},
+            {"id": "text-embedding-3-small"},
+            {
+                "id": "anthropic/claude-3.5-sonnet",
+                "context_length": 200000,
+                "pricing": {"prompt": "0.000003", "completion": "0.000015"},
+                "architecture": {"modality": "text+image->text"},
+                "supported_parameters": ["tools", "temperature"]
+            }
+        ]});
+        let models = parse_openai_models(&data).unwrap();
+        assert_eq!(models.len(), 3);
+        assert_eq!(models[1].model_type, "embedding");
+        assert_eq!(models[2].max_input_tokens, Some(200000));
+        assert_eq!(models[2].input_price, Some(3.0));
+        assert_eq!(models[2].output_price, Some(15.0));
+        assert!(models[2].supports_vision && models[2].supports_function_calling);
+    }
+
+    #[test]
+    fn test_merge_models_override() {
+        let predefined = vec![PredefinedModels {
+            platform: "openai".into(),
+            models: vec![ModelData::new("gpt-4o")],
+        }];
+        let synced = vec![
+            PredefinedModels {
+                platform: "openai".into(),
+                models: vec![ModelData::new("gpt-4o"), ModelData::new("gpt-5")],
+            },
+            PredefinedModels {
+                platform: "ollama-local".into(),
+                models: vec![ModelData::new("qwen3")],
+            },
+        ];
+        let merged = merge_models_override(predefined, synced);
+        assert_eq!(merged[0].platform, "ollama-local");
+        assert_eq!(merged[1].models.len(), 2);
+    }
+}
@@ -0,0 +1,345 @@
+use super::{
+    catch_error, connected, ApiError, ErrorClass, LastActive, RequestData, SseMmessage, SseParser,
+};
+use crate::utils::check_offline;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE, UPGRADE},
+    Request, RequestBuilder, Response,
+};
+use serde_json::Value;
+use std::{collections::VecDeque, time::Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{HeaderName, HeaderValue},
+        Error as WsError, Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+/// Reconnect attempts when the connection fails before any message arrived
+const STREAM_RETRIES: usize = 2;
+const STREAM_RETRY_DELAY: Duration = Duration::from_millis(if cfg!(test) { 10 } else { 500 });
+
+pub type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A way to open the stream of a chat completion, `SseTransport` or `WsTransport`
+#[async_trait::async_trait]
+pub trait StreamTransport: Send + Sync {
+    async fn connect(&self) -> Result<Box<dyn MessageSource>>;
+}
+
+/// The messages of an open stream, `None` once it ends
+#[async_trait::async_trait]
+pub trait MessageSource: Send {
+    async fn next_message(&mut self) -> Result<Option<SseMmessage>>;
+}
+
+/// Feed the messages of the transport to `handle` until it returns true or the stream ends.
+///
+/// A transient failure (network, 502/503/504) is retried as long as nothing was received,
+/// so a reply is never handled twice.
+pub async fn transport_stream<F>(
+    transport: &dyn StreamTransport,
+    last_active: &LastActive,
+    mut handle: F,
+) -> Result<()>
+where
+    F: FnMut(SseMmessage) -> Result<bool>,
+{
+    let mut attempt = 0;
+    loop {
+        let mut received = false;
+        let ret = run_stream(transport, last_active, &mut handle, &mut received).await;
+        match ret {
+            Err(err) if !received && attempt < STREAM_RETRIES && is_transient(&err) => {
+                attempt += 1;
+                debug!("Reconnect stream ({attempt}/{STREAM_RETRIES}): {err}");
+                tokio::time::sleep(STREAM_RETRY_DELAY * attempt as u32).await;
+            }
+            ret => return ret,
+        }
+    }
+}
+
+async fn run_stream<F>(
+    transport: &dyn StreamTransport,
+    last_active: &LastActive,
+    handle: &mut F,
+    received: &mut bool,
+) -> Result<()>
+where
+    F: FnMut(SseMmessage) -> Result<bool>,
+{
+    let mut source = transport.connect().await?;
+    connected(last_active);
+    while let Some(message) = source.next_message().await? {
+        *received = true;
+        if handle(message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn is_transient(err: &anyhow::Error) -> bool {
+    ErrorClass::of(err) == ErrorClass::Network
+        || err
+            .chain()
+            .filter_map(|v| v.downcast_ref::<ApiError>())
+            .any(|v| matches!(v.status, 502..=504))
+}
+
+/// Server-sent events over HTTP
+pub struct SseTransport(pub RequestBuilder);
+
+#[async_trait::async_trait]
+impl StreamTransport for SseTransport {
+    async fn connect(&self) -> Result<Box<dyn MessageSource>> {
+        let builder = self
+            .0
+            .try_clone()
+            .ok_or_else(|| anyhow!("Unable to clone the stream request"))?;
+        let res = builder.header(ACCEPT, "text/event-stream").send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await?;
+            let data: Value = match text.parse() {
+                Ok(data) => data,
+                Err(_) => {
+                    bail!(
+                        "Invalid response data: {text} (status: {})",
+                        status.as_u16()
+                    );
+                }
+            };
+            catch_error(&data, status.as_u16())?;
+            bail!(
+                "Invalid response data: {data} (status: {})",
+                status.as_u16()
+            );
+        }
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !content_type.starts_with("text/event-stream") {
+            let text = res.text().await?;
+            bail!("Invalid response event-stream. content-type: {content_type}, data: {text}");
+        }
+        Ok(Box::new(SseSource {
+            res,
+            parser: SseParser::default(),
+            messages: VecDeque::new(),
+            ended: false,
+        }))
+    }
+}
+
+struct SseSource {
+    res: Response,
+    parser: SseParser,
+    messages: VecDeque<SseMmessage>,
+    ended: bool,
+}
+
+#[async_trait::async_trait]
+impl MessageSource for SseSource {
+    async fn next_message(&mut self) -> Result<Option<SseMmessage>> {
+        loop {
+            // Events with empty data are keep-alives
+            while let Some(message) = self.messages.pop_front() {
+                if !message.data.is_empty() {
+                    return Ok(Some(message));
+                }
+            }
+            if self.ended {
+                return Ok(None);
+            }
+            match self.res.chunk().await? {
+                Some(chunk) => self.messages.extend(self.parser.feed(&chunk)),
+                None => {
+                    self.ended = true;
+                    self.messages.extend(self.parser.finish());
+                }
+            }
+        }
+    }
+}
+
+/// A WebSocket sending the request body as the first frame, then reading each frame as the data of an event
+pub struct WsTransport(pub RequestData);
+
+impl WsTransport {
+    pub fn from_request(request: &Request) -> Result<Self> {
+        let body = request
+            .body()
+            .and_then(|v| v.as_bytes())
+            .map(serde_json::from_slice)
+            .transpose()
+            .with_context(|| "Invalid request body")?
+            .unwrap_or_default();
+        let mut request_data = RequestData::new(websocket_url(request.url().as_str()), body);
+        for (key, value) in request.headers() {
+            if key == UPGRADE {
+                continue;
+            }
+            if let Ok(value) = value.to_str() {
+                request_data.header(key, value);
+            }
+        }
+        Ok(Self(request_data))
+    }
+}
+
+#[async_trait::async_trait]
+impl StreamTransport for WsTransport {
+    async fn connect(&self) -> Result<Box<dyn MessageSource>> {
+        let mut socket = connect_websocket(&self.0).await?;
+        if !self.0.body.is_null() {
+            socket.send(Message::Text(self.0.body.to_string())).await?;
+        }
+        Ok(Box::new(WsSource(socket)))
+    }
+}
+
+struct WsSource(WebSocket);
+
+#[async_trait::async_trait]
+impl MessageSource for WsSource {
+    async fn next_message(&mut self) -> Result<Option<SseMmessage>> {
+        while let Some(message) = self.0.next().await {
+            let data = match message? {
+                Message::Text(text) => text,
+                Message::Binary(data) => String::from_utf8(data)?,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            return Ok(Some(SseMmessage {
+                event: "message".into(),
+                data,
+                id: None,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+/// Open a WebSocket with the url and headers of the request, the body isn't sent.
+pub async fn connect_websocket(request_data: &RequestData) -> Result<WebSocket> {
+    check_offline(&request_data.url)?;
+    let mut request = request_data
+        .url
+        .as_str()
+        .into_client_request()
+        .with_context(|| "Invalid websocket url")?;
+    for (key, value) in &request_data.headers {
+        request.headers_mut().insert(
+            HeaderName::from_bytes(key.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    match connect_async(request).await {
+        Ok((socket, _)) => Ok(socket),
+        Err(WsError::Http(res)) => {
+            let status = res.status().as_u16();
+            let data = res
+                .body()
+                .as_ref()
+                .and_then(|v| serde_json::from_slice(v).ok())
+                .unwrap_or_default();
+            catch_error(&data, status)?;
+            bail!("Unexpected websocket response (status: {status})")
+        }
+        Err(err) => Err(err).with_context(|| "Failed to connect to the websocket"),
+    }
+}
+
+/// An `api_base` of `ws://` or `wss://` streams over a WebSocket, the request is sent
+/// as `http(s)://` with `Upgrade: websocket` until `sse_stream` picks the transport.
+pub fn http_url(url: &str) -> Option<String> {
+    if let Some(rest) = url.strip_prefix("wss://") {
+        Some(format!("https://{rest}"))
+    } else {
+        url.strip_prefix("ws://")
+            .map(|rest| format!("http://{rest}"))
+    }
+}
+
+pub fn is_websocket_request(request: &Request) -> bool {
+    request
+        .headers()
+        .get(UPGRADE)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"websocket"))
+}
+
+/// `https://host/v1` to `wss://host/v1`
+pub fn websocket_url(url: &str) -> String {
+    if let Some(rest) = url.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else {
+        url.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    struct MockTransport {
+        failures: Mutex<Vec<u16>>,
+    }
+
+    struct MockSource(Vec<&'static str>);
+
+    #[async_trait::async_trait]
+    impl StreamTransport for MockTransport {
+        async fn connect(&self) -> Result<Box<dyn MessageSource>> {
+            if let Some(status) = self.failures.lock().pop() {
+                return Err(ApiError::new(status, "Unavailable", Value::Null).into());
+            }
+            Ok(Box::new(MockSource(vec!["b", "a"])))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MessageSource for MockSource {
+        async fn next_message(&mut self) -> Result<Option<SseMmessage>> {
+            Ok(self.0.pop().map(|data| SseMmessage {
+                event: "message".into(),
+                data: data.into(),
+                id: None,
+            }))
+        }
+    }
+
+    async fn collect(failures: Vec<u16>) -> Result<Vec<String>> {
+        let transport = MockTransport {
+            failures: Mutex::new(failures),
+        };
+        let mut output = vec![];
+        transport_stream(&transport, &Default::default(), |message| {
+            output.push(message.data);
+            Ok(false)
+        })
+        .await?;
+        Ok(output)
+    }
+
+    #[tokio::test]
+    async fn test_transport_stream_retry() {
+        assert_eq!(collect(vec![503, 502]).await.unwrap(), vec!["a", "b"]);
+        assert!(collect(vec![503, 503, 503]).await.is_err());
+        assert!(collect(vec![401]).await.is_err());
+    }
+}
@@ -3,12 +3,18 @@ use super::claude::*;
 use super::openai::*;
 use super::*;
 
+use crate::utils::{base64url_encode, check_offline, rsa_sha256_sign};
+
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::{Duration, Utc};
 use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::{path::PathBuf, str::FromStr};
+use std::{env, path::PathBuf, str::FromStr};
+
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const METADATA_HOST: &str = "metadata.google.internal";
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct VertexAIConfig {
@@ -16,6 +22,7 @@ pub struct VertexAIConfig {
     pub project_id: Option<String>,
     pub location: Option<String>,
     pub adc_file: Option<String>,
+    pub safety_settings: Option<Value>,
     #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
@@ -41,11 +48,11 @@ impl Client for VertexAIClient {
         client: &ReqwestClient,
         data: ChatCompletionsData,
     ) -> Result<ChatCompletionsOutput> {
-        prepare_gcloud_access_token(client, self.name(), &self.config.adc_file).await?;
+        prepare_gcloud_access_token(client, &self.config.adc_file).await?;
         let model = self.model();
         let model_category = ModelCategory::from_str(model.name())?;
         let request_data = prepare_chat_completions(self, data, &model_category)?;
-        let builder = self.request_builder(client, request_data);
+        let builder = self.request_builder(client, request_data)?;
         match model_category {
             ModelCategory::Gemini => gemini_chat_completions(builder, model).await,
             ModelCategory::Claude => claude_chat_completions(builder, model).await,
@@ -59,11 +66,11 @@ impl Client for VertexAIClient {
         handler: &mut SseHandler,
         data: ChatCompletionsData,
     ) -> Result<()> {
-        prepare_gcloud_access_token(client, self.name(), &self.config.adc_file).await?;
+        prepare_gcloud_access_token(client, &self.config.adc_file).await?;
         let model = self.model();
         let model_category = ModelCategory::from_str(model.name())?;
         let request_data = prepare_chat_completions(self, data, &model_category)?;
-        let builder = self.request_builder(client, request_data);
+        let builder = self.request_builder(client, request_data)?;
         match model_category {
             ModelCategory::Gemini => {
                 gemini_chat_completions_streaming(builder, handler, model).await
@@ -82,9 +89,9 @@ impl Client for VertexAIClient {
         client: &ReqwestClient,
         data: &EmbeddingsData,
     ) -> Result<Vec<Vec<f32>>> {
-        prepare_gcloud_access_token(client, self.name(), &self.config.adc_file).await?;
+        prepare_gcloud_access_token(client, &self.config.adc_file).await?;
         let request_data = prepare_embeddings(self, data)?;
-        let builder = self.request_builder(client, request_data);
+        let builder = self.request_builder(client, request_data)?;
         embeddings(builder, self.model()).await
     }
 }
@@ -96,7 +103,7 @@ fn prepare_chat_completions(
 ) -> Result<RequestData> {
     let project_id = self_.get_project_id()?;
     let location = self_.get_location()?;
-    let access_token = get_access_token(self_.name())?;
+    let access_token = get_access_token(&gcloud_access_token_key(&self_.config.adc_file))?;
 
     let base_url = format!("https://{location}-aiplatform.googleapis.com/v1/projects/{project_id}/locations/{location}/publishers");
 
@@ -123,7 +130,14 @@ fn prepare_chat_completions(
     };
 
     let body = match model_category {
-        ModelCategory::Gemini => gemini_build_chat_completions_body(data, &self_.model)?,
+        ModelCategory::Gemini => {
+            let mut body = gemini_build_chat_completions_body(data, &self_.model)?;
+            gemini_apply_safety_settings(&mut body, &self_.config.safety_settings)?;
+            if self_.global_config.read().web_search {
+                gemini_apply_web_search(&mut body);
+            }
+            body
+        }
         ModelCategory::Claude => {
             let mut body = claude_build_chat_completions_body(data, &self_.model)?;
             if let Some(body_obj) = body.as_object_mut() {
@@ -151,7 +165,7 @@ fn prepare_chat_completions(
 fn prepare_embeddings(self_: &VertexAIClient, data: &EmbeddingsData) -> Result<RequestData> {
     let project_id = self_.get_project_id()?;
     let location = self_.get_location()?;
-    let access_token = get_access_token(self_.name())?;
+    let access_token = get_access_token(&gcloud_access_token_key(&self_.config.adc_file))?;
 
     let base_url = format!("https://{location}-aiplatform.googleapis.com/v1/projects/{project_id}/locations/{location}/publishers");
     let url = format!("{base_url}/google/models/{}:predict", self_.model.name());
@@ -194,25 +208,34 @@ pub async fn gemini_chat_completions_streaming(
         let data: Value = res.json().await?;
         catch_error(&data, status.as_u16())?;
     } else {
+        handler.connected();
         let handle = |value: &str| -> Result<()> {
             let data: Value = serde_json::from_str(value)?;
             debug!("stream-data: {data}");
+            for citation in gemini_extract_citations(&data, None) {
+                handler.citation(citation);
+            }
+            handler.set_usage(
+                data["usageMetadata"]["promptTokenCount"].as_u64(),
+                data["usageMetadata"]["candidatesTokenCount"].as_u64(),
+            );
+            handler.set_finish_reason(data["candidates"][0]["finishReason"].as_str());
             if let Some(text) = data["candidates"][0]["content"]["parts"][0]["text"].as_str() {
                 if !text.is_empty() {
                     handler.text(text)?;
                 }
-            } else if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
-                .as_str()
-                .or_else(|| data["candidates"][0]["finishReason"].as_str())
-            {
-                bail!("Content Blocked")
+            } else if let Some(err) = gemini_blocked_error(&data) {
+                return Err(err);
             } else if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
                 for part in parts {
                     if let (Some(name), Some(args)) = (
                         part["functionCall"]["name"].as_str(),
                         part["functionCall"]["args"].as_object(),
                     ) {
-                        handler.tool_call(ToolCall::new(name.to_string(), json!(args), None))?;
+                        let args = json!(args);
+                        handler.tool_call_start(name, None)?;
+                        handler.tool_call_delta(&args.to_string())?;
+                        handler.tool_call(ToolCall::new(name.to_string(), args, None))?;
                     }
                 }
             }
@@ -278,25 +301,167 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
             .collect()
     }
     if text.is_empty() && tool_calls.is_empty() {
-        if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
-            .as_str()
-            .or_else(|| data["candidates"][0]["finishReason"].as_str())
-        {
-            bail!("Content Blocked")
-        } else {
-            bail!("Invalid response data: {data}");
+        match gemini_blocked_error(data) {
+            Some(err) => return Err(err),
+            None => bail!("Invalid response data: {data}"),
         }
     }
     let output = ChatCompletionsOutput {
         text: text.to_string(),
+        citations: gemini_extract_citations(data, Some(text)),
         tool_calls,
         id: None,
         input_tokens: data["usageMetadata"]["promptTokenCount"].as_u64(),
         output_tokens: data["usageMetadata"]["candidatesTokenCount"].as_u64(),
+        finish_reason: data["candidates"][0]["finishReason"]
+            .as_str()
+            .map(|v| v.to_string()),
+        ..Default::default()
     };
     Ok(output)
 }
 
+const HARM_CATEGORIES: [&str; 4] = [
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+];
+
+const BLOCKED_FINISH_REASONS: [&str; 5] = [
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+];
+
+/// Set `safetySettings` from the client config.
+///
+/// Accepts either a threshold such as `BLOCK_NONE` applied to every harm category,
+/// or the raw list of `{category, threshold}` objects.
+pub fn gemini_apply_safety_settings(
+    body: &mut Value,
+    safety_settings: &Option<Value>,
+) -> Result<()> {
+    let value = match safety_settings {
+        Some(Value::String(threshold)) => HARM_CATEGORIES
+            .iter()
+            .map(|category| json!({ "category": category, "threshold": threshold }))
+            .collect(),
+        Some(value @ Value::Array(_)) => value.clone(),
+        Some(value) => bail!("Invalid safety_settings: {value}"),
+        None => return Ok(()),
+    };
+    body["safetySettings"] = value;
+    Ok(())
+}
+
+fn gemini_blocked_error(data: &Value) -> Option<anyhow::Error> {
+    let candidate = &data["candidates"][0];
+    let (reason, ratings) = match data["promptFeedback"]["blockReason"].as_str() {
+        Some(reason) => (reason, &data["promptFeedback"]["safetyRatings"]),
+        None => {
+            let reason = candidate["finishReason"].as_str()?;
+            if !BLOCKED_FINISH_REASONS.contains(&reason) {
+                return None;
+            }
+            (reason, &candidate["safetyRatings"])
+        }
+    };
+    let flagged: Vec<String> = ratings
+        .as_array()
+        .map(|ratings| {
+            ratings
+                .iter()
+                .filter(|v| {
+                    v["blocked"].as_bool().unwrap_or_default()
+                        || matches!(v["probability"].as_str(), Some("MEDIUM" | "HIGH"))
+                })
+                .filter_map(|v| {
+                    let category = v["category"].as_str()?;
+                    let probability = v["probability"].as_str().unwrap_or("UNKNOWN");
+                    Some(format!("{category}={probability}"))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut message = format!("Content blocked (reason: {reason}");
+    if !flagged.is_empty() {
+        message.push_str(&format!(", {}", flagged.join(", ")));
+    }
+    message.push(')');
+    if reason == "SAFETY" {
+        message.push_str("; relax it with `safety_settings` in the client config");
+    }
+    Some(anyhow!("{message}"))
+}
+
+/// Collect sources from `citationMetadata` and `groundingMetadata`.
+///
+/// Marker offsets are only resolved when the full reply `text` is known.
+fn gemini_extract_citations(data: &Value, text: Option<&str>) -> Vec<Citation> {
+    let candidate = &data["candidates"][0];
+    let to_offset = |end_index: &Value| -> Option<usize> {
+        let end_index = end_index.as_u64()? as usize;
+        Some(text?.get(..end_index)?.chars().count())
+    };
+    let mut citations = vec![];
+    let sources = candidate["citationMetadata"]["citations"]
+        .as_array()
+        .or_else(|| candidate["citationMetadata"]["citationSources"].as_array());
+    for source in sources.into_iter().flatten() {
+        if let Some(uri) = source["uri"].as_str() {
+            let mut citation = Citation::new(uri, source["title"].as_str().unwrap_or_default());
+            citation.offsets.extend(to_offset(&source["endIndex"]));
+            Citation::merge(&mut citations, citation);
+        }
+    }
+    let grounding = &candidate["groundingMetadata"];
+    let chunks = grounding["groundingChunks"]
+        .as_array()
+        .map(|v| v.as_slice())
+        .unwrap_or_default();
+    let mut chunk_offsets: Vec<Vec<usize>> = vec![vec![]; chunks.len()];
+    for support in grounding["groundingSupports"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(offset) = to_offset(&support["segment"]["endIndex"]) {
+            for index in support["groundingChunkIndices"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                if let Some(offsets) = index
+                    .as_u64()
+                    .and_then(|i| chunk_offsets.get_mut(i as usize))
+                {
+                    offsets.push(offset);
+                }
+            }
+        }
+    }
+    for (chunk, offsets) in chunks.iter().zip(chunk_offsets) {
+        if let Some(uri) = chunk["web"]["uri"].as_str() {
+            let mut citation =
+                Citation::new(uri, chunk["web"]["title"].as_str().unwrap_or_default());
+            citation.offsets = offsets;
+            Citation::merge(&mut citations, citation);
+        }
+    }
+    citations
+}
+
+pub fn gemini_apply_web_search(body: &mut Value) {
+    let tool = json!({ "google_search": {} });
+    match body["tools"].as_array_mut() {
+        Some(tools) => tools.push(tool),
+        None => body["tools"] = json!([tool]),
+    }
+}
+
 pub fn gemini_build_chat_completions_body(
     data: ChatCompletionsData,
     model: &Model,
@@ -307,6 +472,9 @@ pub fn gemini_build_chat_completions_body(
         top_p,
         functions,
         stream: _,
+        stop,
+        response_format,
+        seed,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -315,7 +483,7 @@ pub fn gemini_build_chat_completions_body(
     let contents: Vec<Value> = messages
         .into_iter()
         .flat_map(|message| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             let role = match role {
                 MessageRole::User => "user",
                 _ => "model",
@@ -393,6 +561,15 @@ pub fn gemini_build_chat_completions_body(
     if let Some(v) = top_p {
         body["generationConfig"]["topP"] = v.into();
     }
+    if let Some(v) = stop {
+        body["generationConfig"]["stopSequences"] = v.into();
+    }
+    if let Some(v) = seed {
+        body["generationConfig"]["seed"] = v.into();
+    }
+    if response_format == Some(ResponseFormat::Json) {
+        body["generationConfig"]["responseMimeType"] = "application/json".into();
+    }
 
     if let Some(functions) = functions {
         // Gemini doesn't support functions with parameters that have empty properties, so we need to patch it.
@@ -440,34 +617,136 @@ impl FromStr for ModelCategory {
 
 pub async fn prepare_gcloud_access_token(
     client: &reqwest::Client,
-    client_name: &str,
     adc_file: &Option<String>,
 ) -> Result<()> {
-    if !is_valid_access_token(client_name) {
+    let key = gcloud_access_token_key(adc_file);
+    refresh_access_token(&key, || async {
         let (token, expires_in) = fetch_access_token(client, adc_file)
             .await
             .with_context(|| "Failed to fetch access token")?;
         let expires_at = Utc::now()
             + Duration::try_seconds(expires_in)
                 .ok_or_else(|| anyhow!("Failed to parse expires_in of access_token"))?;
-        set_access_token(client_name, token, expires_at.timestamp())
-    }
-    Ok(())
+        Ok((token, expires_at.timestamp()))
+    })
+    .await
+}
+
+pub fn gcloud_access_token_key(adc_file: &Option<String>) -> String {
+    format!("gcloud:{}", adc_file.as_deref().unwrap_or_default())
 }
 
 async fn fetch_access_token(
     client: &reqwest::Client,
     file: &Option<String>,
 ) -> Result<(String, i64)> {
-    let credentials = load_adc(file).await?;
+    let env_file = env::var("GOOGLE_APPLICATION_CREDENTIALS").ok();
+    let Some(adc_file) = resolve_adc_file(file, env_file, default_adc_file())? else {
+        return fetch_metadata_access_token(client).await;
+    };
+    let data = tokio::fs::read_to_string(&adc_file)
+        .await
+        .with_context(|| format!("Failed to read '{}'", adc_file.display()))?;
+    let data: Value = serde_json::from_str(&data)?;
+    let builder = match data["type"].as_str() {
+        Some("service_account") => {
+            let (token_uri, assertion) = create_service_account_assertion(&data)?;
+            client.post(token_uri).form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+        }
+        _ => {
+            if let (Some(client_id), Some(client_secret), Some(refresh_token)) = (
+                data["client_id"].as_str(),
+                data["client_secret"].as_str(),
+                data["refresh_token"].as_str(),
+            ) {
+                client.post(DEFAULT_TOKEN_URI).json(&json!({
+                    "client_id": client_id,
+                    "client_secret": client_secret,
+                    "refresh_token": refresh_token,
+                    "grant_type": "refresh_token",
+                }))
+            } else {
+                bail!("Invalid application_default_credentials.json")
+            }
+        }
+    };
+    let (client, request) = builder.build_split();
+    let request = request?;
+    check_offline(request.url().as_str())?;
+    let value: Value = client.execute(request).await?.json().await?;
+    extract_access_token(&value)
+}
+
+/// Pick the credentials file, `None` to fall back to the metadata server.
+///
+/// An explicit `adc_file` or `GOOGLE_APPLICATION_CREDENTIALS` must exist, only the
+/// gcloud default file is optional.
+fn resolve_adc_file(
+    file: &Option<String>,
+    env_file: Option<String>,
+    default_file: Option<PathBuf>,
+) -> Result<Option<PathBuf>> {
+    if let Some(path) = file.clone().or(env_file).filter(|v| !v.is_empty()) {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            bail!("Credentials file '{}' does not exist", path.display())
+        }
+        return Ok(Some(path));
+    }
+    Ok(default_file.filter(|v| v.exists()))
+}
+
+async fn fetch_metadata_access_token(client: &reqwest::Client) -> Result<(String, i64)> {
+    let host = env::var("GCE_METADATA_HOST").unwrap_or_else(|_| METADATA_HOST.into());
+    let url = format!("http://{host}/computeMetadata/v1/instance/service-accounts/default/token");
+    check_offline(&url)?;
     let value: Value = client
-        .post("https://oauth2.googleapis.com/token")
-        .json(&credentials)
+        .get(&url)
+        .header("Metadata-Flavor", "Google")
+        .timeout(std::time::Duration::from_secs(5))
         .send()
-        .await?
+        .await
+        .with_context(|| "No application_default_credentials.json and no metadata server")?
         .json()
         .await?;
+    extract_access_token(&value)
+}
 
+fn create_service_account_assertion(data: &Value) -> Result<(String, String)> {
+    let (Some(client_email), Some(private_key)) =
+        (data["client_email"].as_str(), data["private_key"].as_str())
+    else {
+        bail!("Invalid service account key file")
+    };
+    let token_uri = data["token_uri"]
+        .as_str()
+        .unwrap_or(DEFAULT_TOKEN_URI)
+        .to_string();
+    let now = Utc::now().timestamp();
+    let header = json!({ "alg": "RS256", "typ": "JWT" });
+    let claims = json!({
+        "iss": client_email,
+        "scope": CLOUD_PLATFORM_SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + 3600,
+    });
+    let message = format!(
+        "{}.{}",
+        base64url_encode(header.to_string()),
+        base64url_encode(claims.to_string())
+    );
+    let signature = rsa_sha256_sign(private_key, &message)?;
+    Ok((
+        token_uri,
+        format!("{message}.{}", base64url_encode(signature)),
+    ))
+}
+
+fn extract_access_token(value: &Value) -> Result<(String, i64)> {
     if let (Some(access_token), Some(expires_in)) =
         (value["access_token"].as_str(), value["expires_in"].as_i64())
     {
@@ -479,30 +758,6 @@ async fn fetch_access_token(
     }
 }
 
-async fn load_adc(file: &Option<String>) -> Result<Value> {
-    let adc_file = file
-        .as_ref()
-        .map(PathBuf::from)
-        .or_else(default_adc_file)
-        .ok_or_else(|| anyhow!("No application_default_credentials.json"))?;
-    let data = tokio::fs::read_to_string(adc_file).await?;
-    let data: Value = serde_json::from_str(&data)?;
-    if let (Some(client_id), Some(client_secret), Some(refresh_token)) = (
-        data["client_id"].as_str(),
-        data["client_secret"].as_str(),
-        data["refresh_token"].as_str(),
-    ) {
-        Ok(json!({
-            "client_id": client_id,
-            "client_secret": client_secret,
-            "refresh_token": refresh_token,
-            "grant_type": "refresh_token",
-        }))
-    } else {
-        bail!("Invalid application_default_credentials.json")
-    }
-}
-
 #[cfg(not(windows))]
 fn default_adc_file() -> Option<PathBuf> {
     let mut path = dirs::home_dir()?;
@@ -526,3 +781,37 @@ fn strip_model_version(name: &str) -> &str {
         None => name,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_file;
+
+    #[test]
+    fn test_resolve_adc_file() {
+        let existing = temp_file("-adc-", ".json");
+        std::fs::write(&existing, "{}").unwrap();
+        let missing = temp_file("-adc-", ".json");
+        let existing_str = existing.display().to_string();
+        let missing_str = missing.display().to_string();
+
+        let ret = resolve_adc_file(&Some(existing_str.clone()), Some(missing_str.clone()), None);
+        assert_eq!(ret.unwrap(), Some(existing.clone()));
+        let ret = resolve_adc_file(&None, Some(existing_str.clone()), None);
+        assert_eq!(ret.unwrap(), Some(existing.clone()));
+
+        // A missing explicit file is an error naming it, not a silent fallback
+        let err =
+            resolve_adc_file(&None, Some(missing_str.clone()), Some(existing.clone())).unwrap_err();
+        assert!(err.to_string().contains(&missing_str));
+        assert!(resolve_adc_file(&Some(missing_str), None, None).is_err());
+
+        // The gcloud default file is optional
+        let ret = resolve_adc_file(&None, None, Some(existing.clone()));
+        assert_eq!(ret.unwrap(), Some(existing.clone()));
+        assert_eq!(resolve_adc_file(&None, None, Some(missing)).unwrap(), None);
+        assert_eq!(resolve_adc_file(&None, None, None).unwrap(), None);
+
+        std::fs::remove_file(&existing).unwrap();
+    }
+}
@@ -219,6 +219,10 @@ impl Agent {
         &self.definition.conversation_starters
     }
 
+    pub fn greeting(&self) -> Option<&str> {
+        self.definition.greeting.as_deref()
+    }
+
     pub fn interpolated_instructions(&self) -> String {
         let mut output = self
             .session_dynamic_instructions
@@ -332,6 +336,7 @@ impl Agent {
             self.name().to_string(),
             vec!["_instructions".into(), "{}".into()],
             self.variable_envs(),
+            None,
         )?;
         match value {
             Some(v) => Ok(v),
@@ -460,6 +465,7 @@ pub struct AgentDefinition {
     pub variables: Vec<AgentVariable>,
     #[serde(default)]
     pub conversation_starters: Vec<String>,
+    pub greeting: Option<String>,
     #[serde(default)]
     pub documents: Vec<String>,
 }
@@ -0,0 +1,193 @@
+use super::*;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::Path};
+
+/// At most this many facts are injected into the system prompt
+const MEMORY_PROMPT_LIMIT: usize = 20;
+
+/// Long-term facts about the user, kept in `memory.yaml`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Memory {
+    #[serde(default)]
+    facts: Vec<MemoryFact>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MemoryFact {
+    pub content: String,
+    pub created_at: String,
+}
+
+impl Memory {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let err = || format!("Failed to load memory at '{}'", path.display());
+        let content = fs::read_to_string(path).with_context(err)?;
+        serde_yaml::from_str(&content).with_context(err)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        ensure_parent_exists(path)?;
+        let content = serde_yaml::to_string(self).with_context(|| "Failed to serde memory")?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to save memory to '{}'", path.display()))
+    }
+
+    pub fn facts(&self) -> &[MemoryFact] {
+        &self.facts
+    }
+
+    /// Returns false if the same fact is already remembered.
+    pub fn add(&mut self, content: &str) -> Result<bool> {
+        let content = content.trim();
+        if content.is_empty() {
+            bail!("Empty fact")
+        }
+        if self
+            .facts
+            .iter()
+            .any(|v| v.content.eq_ignore_ascii_case(content))
+        {
+            return Ok(false);
+        }
+        self.facts.push(MemoryFact {
+            content: content.to_string(),
+            created_at: now(),
+        });
+        Ok(true)
+    }
+
+    /// Forget the fact with the given 1-based number, or the facts containing the text.
+    pub fn forget(&mut self, target: &str) -> Result<Vec<MemoryFact>> {
+        let target = target.trim();
+        let removed: Vec<MemoryFact> = match target.parse::<usize>() {
+            Ok(index) if index >= 1 && index <= self.facts.len() => {
+                vec![self.facts.remove(index - 1)]
+            }
+            _ => {
+                let needle = target.to_lowercase();
+                let (removed, kept) = self
+                    .facts
+                    .drain(..)
+                    .partition(|v| v.content.to_lowercase().contains(&needle));
+                self.facts = kept;
+                removed
+            }
+        };
+        if removed.is_empty() {
+            bail!("No fact matches '{target}'")
+        }
+        Ok(removed)
+    }
+
+    /// Forget the fact with the given 1-based number or exactly this text, as the `memory` tool
+    /// does, a loose match could wipe most of the memory.
+    pub fn forget_exact(&mut self, target: &str) -> Result<MemoryFact> {
+        let target = target.trim();
+        let index = match target.parse::<usize>() {
+            Ok(index) if index >= 1 && index <= self.facts.len() => Some(index - 1),
+            _ => self
+                .facts
+                .iter()
+                .position(|v| v.content.eq_ignore_ascii_case(target)),
+        };
+        match index {
+            Some(index) => Ok(self.facts.remove(index)),
+            None => bail!("No fact is exactly '{target}'"),
+        }
+    }
+
+    /// The facts sharing words with `text`, the most first and newest first on ties.
+    pub fn search(&self, text: &str, limit: usize) -> Vec<&MemoryFact> {
+        self.rank(text)
+            .into_iter()
+            .filter(|(score, _)| *score > 0)
+            .take(limit)
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    /// All facts by the number of words shared with `text`, newest first on ties.
+    fn rank(&self, text: &str) -> Vec<(usize, &MemoryFact)> {
+        let query_words = words(text);
+        let mut scored: Vec<(usize, usize, &MemoryFact)> = self
+            .facts
+            .iter()
+            .enumerate()
+            .map(|(i, fact)| {
+                (
+                    query_words.intersection(&words(&fact.content)).count(),
+                    i,
+                    fact,
+                )
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+        scored.into_iter().map(|(score, _, v)| (score, v)).collect()
+    }
+
+    /// The system prompt section with the facts relevant to `text`.
+    pub fn render_prompt(&self, text: &str) -> Option<String> {
+        if self.facts.is_empty() {
+            return None;
+        }
+        // Unrelated facts still fill the prompt, the model may need them anyway
+        let facts: Vec<String> = self
+            .rank(text)
+            .into_iter()
+            .take(MEMORY_PROMPT_LIMIT)
+            .map(|(_, v)| format!("- {}", v.content))
+            .collect();
+        Some(format!(
+            "Facts you remember about the user (save new ones with the `memory` tool):\n{}",
+            facts.join("\n")
+        ))
+    }
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|v| v.chars().count() >= 3)
+        .map(|v| v.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory() {
+        let mut memory = Memory::default();
+        assert!(memory.add("Prefers Rust over Go").unwrap());
+        assert!(memory.add("Lives in Berlin").unwrap());
+        assert!(!memory.add("lives in berlin").unwrap());
+        let found = memory.search("what's the weather in berlin", 1);
+        assert_eq!(found[0].content, "Lives in Berlin");
+        let removed = memory.forget("rust").unwrap();
+        assert_eq!(removed[0].content, "Prefers Rust over Go");
+        assert_eq!(memory.forget("1").unwrap()[0].content, "Lives in Berlin");
+        assert!(memory.forget("nothing").is_err());
+    }
+
+    #[test]
+    fn test_memory_tool_paths() {
+        let mut memory = Memory::default();
+        memory.add("Prefers Rust over Go").unwrap();
+        memory.add("Has a cat named Pixel").unwrap();
+        assert!(memory.search("weather today", 10).is_empty());
+        assert_eq!(memory.search("my cat", 10).len(), 1);
+        assert!(memory.forget_exact("a").is_err());
+        assert_eq!(memory.facts().len(), 2);
+        let removed = memory.forget_exact("prefers rust over go").unwrap();
+        assert_eq!(removed.content, "Prefers Rust over Go");
+        assert_eq!(
+            memory.forget_exact("1").unwrap().content,
+            "Has a cat named Pixel"
+        );
+    }
+}
@@ -1,22 +1,41 @@
 mod agent;
 mod input;
+mod memory;
 mod role;
+mod role_import;
 mod session;
+mod usage;
 
 pub use self::agent::{list_agents, Agent, AgentVariables};
+use self::input::summarize_text;
 pub use self::input::Input;
+pub use self::memory::Memory;
 pub use self::role::{
-    Role, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE,
+    Role, RoleLike, RoleParams, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, RECAP_ROLE,
+    SHELL_ROLE, TRANSLATE_ROLE,
 };
+use self::role_import::list_imported_roles;
+pub use self::role_import::{import_roles, RolesImport};
 use self::session::Session;
+pub use self::session::{CompressStrategy, SessionCompression};
+use self::usage::append_usage;
+pub use self::usage::{load_usage, UsageEntry};
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, MessageContentToolCalls,
-    Model, ModelType, OPENAI_COMPATIBLE_PLATFORMS,
+    client_type, create_client_config, init_client, list_client_types, list_models,
+    plugin_client_configs, render_citations, split_content, supports_native_web_search,
+    ChatCompletionsOutput, Citation, ClientConfig, EmbeddingsData, MessageContentToolCalls,
+    MockConfig, Model, ModelType, SseEvent, OPENAI_COMPATIBLE_PLATFORMS,
 };
-use crate::function::{FunctionDeclaration, Functions, ToolResult};
+use crate::function::{
+    code_interpreter_declaration, fetch_url_declaration, fs_declarations, load_tool_audit,
+    memory_declaration, render_tool_audit, web_search_declaration, FunctionDeclaration, Functions,
+    ToolPolicy, ToolResult, CODE_INTERPRETER_FUNCTION_NAME, FETCH_URL_FUNCTION_NAME,
+    MEMORY_FUNCTION_NAME, WEB_SEARCH_FUNCTION_NAME,
+};
+use crate::plugin::{load_plugins, Plugin};
 use crate::rag::Rag;
-use crate::render::{MarkdownRender, RenderOptions};
+use crate::render::{render_stream, MarkdownRender, RenderOptions};
 use crate::utils::*;
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -35,13 +54,16 @@ use std::{
     io::Write,
     path::{Path, PathBuf},
     process,
-    sync::Arc,
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
 use syntect::highlighting::ThemeSet;
+use tokio::sync::mpsc::unbounded_channel;
 
 pub const TEMP_ROLE_NAME: &str = "%%";
 pub const TEMP_RAG_NAME: &str = "temp";
 pub const TEMP_SESSION_NAME: &str = "temp";
+pub const LAST_SESSION_NAME: &str = "last";
 
 /// Monokai Extended
 const DARK_THEME: &[u8] = include_bytes!("../../assets/monokai-extended.theme.bin");
@@ -49,6 +71,8 @@ const LIGHT_THEME: &[u8] = include_bytes!("../../assets/monokai-extended-light.t
 
 const CONFIG_FILE_NAME: &str = "config.yaml";
 const ROLES_DIR_NAME: &str = "roles";
+const IMPORTED_ROLES_DIR_NAME: &str = "roles.d";
+const ROLES_FILE_NAME: &str = "roles.yaml";
 const ENV_FILE_NAME: &str = ".env";
 const MESSAGES_FILE_NAME: &str = "messages.md";
 const SESSIONS_DIR_NAME: &str = "sessions";
@@ -57,11 +81,22 @@ const FUNCTIONS_DIR_NAME: &str = "functions";
 const FUNCTIONS_FILE_NAME: &str = "functions.json";
 const FUNCTIONS_BIN_DIR_NAME: &str = "bin";
 const AGENTS_DIR_NAME: &str = "agents";
+const PLUGINS_DIR_NAME: &str = "plugins";
+const MEMORY_FILE_NAME: &str = "memory.yaml";
+const SERVE_LOG_FILE_NAME: &str = "serve.log.jsonl";
+const TOOL_CALLS_FILE_NAME: &str = "tool-calls.jsonl";
+const USAGE_FILE_NAME: &str = "usage.jsonl";
 
 const CLIENTS_FIELD: &str = "clients";
 
 const SERVE_ADDR: &str = "127.0.0.1:8000";
 
+const REPLAY_CHUNK_DELAY_MS: f64 = 20.0;
+const REPLAY_MAX_DELAY_MS: f64 = 1000.0;
+
+/// Exchanges listed by `.find`
+const FIND_SESSION_LIMIT: usize = 5;
+
 const SUMMARIZE_PROMPT: &str =
     "Summarize the discussion briefly in 200 words or less to use as a prompt for future context.";
 const SUMMARY_PROMPT: &str = "This is a summary of the chat history as a recap: ";
@@ -86,7 +121,12 @@ __INPUT__
 </user_query>"#;
 
 const LEFT_PROMPT: &str = "{color.green}{?session {?agent {agent}>}{session}{?role /}}{!session {?agent {agent}>}}{role}{?rag @{rag}}{color.cyan}{?session )}{!session >}{color.reset} ";
-const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
+const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {?consume_percent>80 {color.red}}{consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
+
+lazy_static::lazy_static! {
+    /// Parsed on first use and again only once edited, all roles are listed on each completion
+    static ref ROLES_FILE: MtimeCache<Vec<Role>> = MtimeCache::default();
+}
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
@@ -94,27 +134,61 @@ pub struct Config {
     #[serde(rename(serialize = "model", deserialize = "model"))]
     #[serde(default)]
     pub model_id: String,
+    pub model_aliases: IndexMap<String, String>,
+    pub default_models: IndexMap<String, String>,
+    pub models_refresh_interval: Option<u64>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    pub seed: Option<u64>,
 
     pub dry_run: bool,
+    pub mock: Option<MockConfig>,
+    pub offline: bool,
     pub stream: bool,
+    pub auto_continue: usize,
     pub save: bool,
     pub keybindings: String,
     pub editor: Option<String>,
+    pub pager: Option<String>,
+    pub auto_page: bool,
+    pub footer: bool,
+    pub stream_status: bool,
+    pub output_pipe: Option<String>,
     pub wrap: Option<String>,
     pub wrap_code: bool,
 
     pub function_calling: bool,
     pub mapping_tools: IndexMap<String, String>,
     pub use_tools: Option<String>,
+    pub tool_policy: ToolPolicy,
+    pub tool_policies: IndexMap<String, ToolPolicy>,
+
+    pub redact: bool,
+    pub redact_rules: IndexMap<String, String>,
+
+    pub web_search: bool,
+    pub web_search_engine: Option<String>,
+    pub web_search_url: Option<String>,
+    pub web_search_api_key: Option<String>,
+    pub fetch_url: bool,
+
+    pub memory: bool,
+
+    pub fs_allowed_dirs: Vec<String>,
+
+    pub code_interpreter: bool,
+    pub code_interpreter_timeout: u64,
+    pub code_interpreter_memory_mb: Option<u64>,
 
     pub prelude: Option<String>,
     pub repl_prelude: Option<String>,
     pub agent_prelude: Option<String>,
 
     pub save_session: Option<bool>,
+    pub max_auto_sessions: Option<usize>,
     pub compress_threshold: usize,
+    pub compress_strategy: CompressStrategy,
+    pub compress_preview: bool,
     pub summarize_prompt: Option<String>,
     pub summary_prompt: Option<String>,
 
@@ -126,20 +200,40 @@ pub struct Config {
     pub rag_min_score_vector_search: f32,
     pub rag_min_score_keyword_search: f32,
     pub rag_template: Option<String>,
+    pub rag_injection_guard: Option<InjectionGuard>,
 
     #[serde(default)]
     pub document_loaders: HashMap<String, String>,
+    pub disabled_loaders: Vec<String>,
+    pub notebook_outputs: bool,
+    pub screenshot_command: Option<String>,
+    pub dedup_attachments: bool,
+    pub attachment_head_ratio: f64,
 
     pub highlight: bool,
     pub light_theme: bool,
     pub left_prompt: Option<String>,
     pub right_prompt: Option<String>,
 
+    pub listen_command: Option<String>,
+    pub stt_command: Option<String>,
+    pub speak: bool,
+    pub speak_command: Option<String>,
+    pub speak_voice: Option<String>,
+    pub realtime_mic_command: Option<String>,
+    pub realtime_player_command: Option<String>,
+
     pub serve_addr: Option<String>,
+    pub serve_log: Option<ServeLogMode>,
+    pub serve_admin_key: Option<String>,
     pub user_agent: Option<String>,
     pub save_shell_history: bool,
+    pub shell_sandbox: Option<String>,
+    pub shell_tools: Vec<String>,
+    pub shell_denylist: IndexMap<String, String>,
 
     pub clients: Vec<ClientConfig>,
+    pub schedules: Vec<Schedule>,
 
     #[serde(skip)]
     pub role: Option<Role>,
@@ -154,9 +248,26 @@ pub struct Config {
     #[serde(skip)]
     pub functions: Functions,
     #[serde(skip)]
+    pub plugins: Vec<Plugin>,
+    #[serde(skip)]
     pub working_mode: WorkingMode,
     #[serde(skip)]
-    pub last_message: Option<(Input, String)>,
+    pub last_message: Option<(Input, ChatCompletionsOutput)>,
+    /// The reply superseded by `.regenerate`, for `.diff`
+    #[serde(skip)]
+    pub previous_reply: Option<String>,
+    /// The parameters and hashes of the last request, for `.repro`
+    #[serde(skip)]
+    pub last_repro: Option<Vec<(&'static str, String)>>,
+    /// Tools trusted with `.trust`, until the session ends
+    #[serde(skip)]
+    pub trusted_tools: HashSet<String>,
+    /// Images pasted with `.paste-image`, attached to the next message
+    #[serde(skip)]
+    pub pending_files: Vec<String>,
+    /// `shell_denylist` compiled on first use
+    #[serde(skip)]
+    pub shell_guard: OnceLock<Arc<ShellGuard>>,
 
     #[serde(skip)]
     pub cli_info_flag: bool,
@@ -170,25 +281,59 @@ impl Default for Config {
             model_id: Default::default(),
             temperature: None,
             top_p: None,
+            seed: None,
 
             dry_run: false,
+            mock: None,
+            offline: false,
             stream: true,
+            auto_continue: 0,
             save: false,
             keybindings: "emacs".into(),
             editor: None,
+            pager: None,
+            auto_page: false,
+            footer: false,
+            stream_status: false,
+            output_pipe: None,
             wrap: None,
             wrap_code: false,
 
             function_calling: true,
             mapping_tools: Default::default(),
             use_tools: None,
+            tool_policy: ToolPolicy::Auto,
+            tool_policies: Default::default(),
+
+            redact: false,
+            redact_rules: Default::default(),
+            model_aliases: Default::default(),
+            default_models: Default::default(),
+            models_refresh_interval: None,
+
+            web_search: false,
+            web_search_engine: None,
+            web_search_url: None,
+            web_search_api_key: None,
+            fetch_url: false,
+
+            memory: false,
+
+            fs_allowed_dirs: vec![],
+
+            code_interpreter: false,
+            code_interpreter_timeout: 30,
+            code_interpreter_memory_mb: Some(1024),
 
             prelude: None,
             repl_prelude: None,
             agent_prelude: None,
 
             save_session: None,
+            max_auto_sessions: None,
             compress_threshold: 4000,
+            compress_strategy: CompressStrategy::Summarize,
+            compress_preview: false,
             summarize_prompt: None,
             summary_prompt: None,
 
@@ -200,19 +345,39 @@ impl Default for Config {
             rag_min_score_vector_search: 0.0,
             rag_min_score_keyword_search: 0.0,
             rag_template: None,
+            rag_injection_guard: None,
 
             document_loaders: Default::default(),
+            disabled_loaders: vec![],
+            notebook_outputs: false,
+            screenshot_command: None,
+            dedup_attachments: false,
+            attachment_head_ratio: 0.8,
 
             highlight: true,
             light_theme: false,
             left_prompt: None,
             right_prompt: None,
 
+            listen_command: None,
+            stt_command: None,
+            speak: false,
+            speak_command: None,
+            speak_voice: None,
+            realtime_mic_command: None,
+            realtime_player_command: None,
+
             serve_addr: None,
+            serve_log: None,
+            serve_admin_key: None,
             user_agent: None,
             save_shell_history: true,
+            shell_sandbox: None,
+            shell_tools: vec!["execute_command".into()],
+            shell_denylist: Default::default(),
 
             clients: vec![],
+            schedules: vec![],
 
             role: None,
             session: None,
@@ -220,8 +385,14 @@ impl Default for Config {
             agent: None,
             model: Default::default(),
             functions: Default::default(),
+            plugins: vec![],
             working_mode: WorkingMode::Cmd,
             last_message: None,
+            previous_reply: None,
+            last_repro: None,
+            trusted_tools: Default::default(),
+            pending_files: vec![],
+            shell_guard: OnceLock::new(),
 
             cli_info_flag: false,
             cli_agent_variables: None,
@@ -231,13 +402,24 @@ impl Default for Config {
 
 pub type GlobalConfig = Arc<RwLock<Config>>;
 
+/// Set by `Config::use_config_dir`
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
 impl Config {
     pub fn init(working_mode: WorkingMode) -> Result<Self> {
+        Self::init_with(working_mode, true)
+    }
+
+    /// Without `interactive`, a missing config file is an error instead of a prompt to create one.
+    pub fn init_with(working_mode: WorkingMode, interactive: bool) -> Result<Self> {
         let config_path = Self::config_file();
         let mut config = if !config_path.exists() {
             match env::var(get_env_name("platform")) {
                 Ok(v) => Self::load_dynamic(&v)?,
                 Err(_) => {
+                    if !interactive {
+                        bail!("No config file at '{}'", config_path.display());
+                    }
                     if *IS_STDOUT_TERMINAL {
                         create_config_file(&config_path)?;
                     }
@@ -257,6 +439,7 @@ impl Config {
         }
 
         config.load_functions()?;
+        config.load_plugins();
 
         config.setup_model()?;
         config.setup_document_loaders();
@@ -265,14 +448,28 @@ impl Config {
         Ok(config)
     }
 
+    /// Use `dir` instead of `$AICHAT_CONFIG_DIR` or the default one, for the whole process.
+    pub fn use_config_dir(dir: &Path) -> Result<()> {
+        let current = CONFIG_DIR.get_or_init(|| dir.to_path_buf());
+        if current != dir {
+            bail!(
+                "The config dir is already '{}', only one is supported per process",
+                current.display()
+            );
+        }
+        Ok(())
+    }
+
     pub fn config_dir() -> PathBuf {
-        if let Ok(v) = env::var(get_env_name("config_dir")) {
+        if let Some(dir) = CONFIG_DIR.get() {
+            dir.clone()
+        } else if let Ok(v) = env::var(get_env_name("config_dir")) {
             PathBuf::from(v)
         } else if let Ok(v) = env::var("XDG_CONFIG_HOME") {
-            PathBuf::from(v).join(env!("CARGO_CRATE_NAME"))
+            PathBuf::from(v).join(env!("CARGO_PKG_NAME"))
         } else {
             let dir = dirs::config_dir().expect("No user's config directory");
-            dir.join(env!("CARGO_CRATE_NAME"))
+            dir.join(env!("CARGO_PKG_NAME"))
         }
     }
 
@@ -294,8 +491,22 @@ impl Config {
         }
     }
 
+    pub fn roles_file() -> PathBuf {
+        match env::var(get_env_name("roles_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(ROLES_FILE_NAME),
+        }
+    }
+
+    pub fn imported_roles_dir() -> PathBuf {
+        match env::var(get_env_name("imported_roles_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(IMPORTED_ROLES_DIR_NAME),
+        }
+    }
+
     pub fn role_file(name: &str) -> PathBuf {
-        Self::roles_dir().join(format!("{name}.md"))
+        RolePaths::current().role_file(name)
     }
 
     pub fn env_file() -> PathBuf {
@@ -339,6 +550,109 @@ impl Config {
         }
     }
 
+    pub fn plugins_dir() -> PathBuf {
+        match env::var(get_env_name("plugins_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(PLUGINS_DIR_NAME),
+        }
+    }
+
+    pub fn memory_file() -> PathBuf {
+        match env::var(get_env_name("memory_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(MEMORY_FILE_NAME),
+        }
+    }
+
+    pub fn serve_log_file() -> PathBuf {
+        match env::var(get_env_name("serve_log_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(SERVE_LOG_FILE_NAME),
+        }
+    }
+
+    pub fn tool_calls_file() -> PathBuf {
+        match env::var(get_env_name("tool_calls_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(TOOL_CALLS_FILE_NAME),
+        }
+    }
+
+    pub fn usage_file() -> PathBuf {
+        match env::var(get_env_name("usage_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(USAGE_FILE_NAME),
+        }
+    }
+
+    /// The recent tool calls of the current session, or of all sessions without one.
+    pub fn tool_audit(&self, limit: usize) -> Result<String> {
+        let session = self.session.as_ref().map(|v| v.name());
+        let entries = load_tool_audit(&Self::tool_calls_file(), session, limit)?;
+        if entries.is_empty() {
+            return Ok("No tool calls recorded".into());
+        }
+        Ok(render_tool_audit(&entries))
+    }
+
+    pub fn repro_info(&self) -> Result<String> {
+        let Some(items) = &self.last_repro else {
+            bail!("No reply to reproduce")
+        };
+        let output = items
+            .iter()
+            .map(|(name, value)| format!("{name:<24}{value}\n"))
+            .collect::<Vec<String>>()
+            .join("");
+        Ok(output)
+    }
+
+    pub fn manage_memory(action: &str, value: &str) -> Result<()> {
+        let path = Self::memory_file();
+        let mut memory = Memory::load(&path)?;
+        match action {
+            "list" => {
+                if memory.facts().is_empty() {
+                    println!("No facts remembered");
+                }
+                for (i, fact) in memory.facts().iter().enumerate() {
+                    println!("{:>3}. {}", i + 1, fact.content);
+                }
+            }
+            "add" => {
+                if memory.add(value)? {
+                    memory.save(&path)?;
+                    println!("✓ Remembered '{}'", value.trim());
+                } else {
+                    println!("Already remembered");
+                }
+            }
+            "forget" => {
+                let removed = memory.forget(value)?;
+                memory.save(&path)?;
+                for fact in removed {
+                    println!("✓ Forgot '{}'", fact.content);
+                }
+            }
+            _ => println!("Usage: .memory [list|add <fact>|forget <number|text>]"),
+        }
+        Ok(())
+    }
+
+    /// The remembered facts relevant to `text`, for the system prompt.
+    pub fn memory_prompt(&self, text: &str) -> Option<String> {
+        if !self.memory {
+            return None;
+        }
+        match Memory::load(&Self::memory_file()) {
+            Ok(memory) => memory.render_prompt(text),
+            Err(err) => {
+                warn!("{err}");
+                None
+            }
+        }
+    }
+
     pub fn functions_file() -> PathBuf {
         Self::functions_dir().join(FUNCTIONS_FILE_NAME)
     }
@@ -444,7 +758,7 @@ impl Config {
                 true => None,
                 false => Some(Config::local_path(&format!(
                     "{}.log",
-                    env!("CARGO_CRATE_NAME")
+                    env!("CARGO_PKG_NAME")
                 ))),
             },
         };
@@ -552,31 +866,108 @@ impl Config {
             ),
             ("temperature", format_option_value(&role.temperature())),
             ("top_p", format_option_value(&role.top_p())),
+            ("seed", format_option_value(&self.seed)),
             ("dry_run", self.dry_run.to_string()),
+            ("offline", self.offline.to_string()),
             ("stream", self.stream.to_string()),
+            ("auto_continue", self.auto_continue.to_string()),
             ("save", self.save.to_string()),
             ("keybindings", self.keybindings.clone()),
             ("wrap", wrap),
             ("wrap_code", self.wrap_code.to_string()),
+            ("auto_page", self.auto_page.to_string()),
+            ("footer", self.footer.to_string()),
+            ("stream_status", self.stream_status.to_string()),
+            ("output_pipe", format_option_value(&self.output_pipe)),
             ("function_calling", self.function_calling.to_string()),
             ("use_tools", format_option_value(&role.use_tools())),
+            ("tool_policy", self.tool_policy.as_str().to_string()),
+            ("shell_sandbox", format_option_value(&self.shell_sandbox)),
+            (
+                "shell_tools",
+                format_option_value(&Some(self.shell_tools.join(",")).filter(|v| !v.is_empty())),
+            ),
+            ("redact", self.redact.to_string()),
+            ("web_search", self.web_search.to_string()),
+            ("fetch_url", self.fetch_url.to_string()),
+            ("memory", self.memory.to_string()),
+            ("code_interpreter", self.code_interpreter.to_string()),
+            (
+                "fs_allowed_dirs",
+                format_option_value(
+                    &Some(self.fs_allowed_dirs.join(",")).filter(|v| !v.is_empty()),
+                ),
+            ),
+            (
+                "web_search_engine",
+                format_option_value(&self.web_search_engine),
+            ),
             ("agent_prelude", format_option_value(&agent_prelude)),
             ("save_session", format_option_value(&self.save_session)),
+            (
+                "max_auto_sessions",
+                format_option_value(&self.max_auto_sessions),
+            ),
             ("compress_threshold", self.compress_threshold.to_string()),
+            (
+                "compress_strategy",
+                self.compress_strategy.as_str().to_string(),
+            ),
+            ("compress_preview", self.compress_preview.to_string()),
             (
                 "rag_reranker_model",
                 format_option_value(&rag_reranker_model),
             ),
             ("rag_top_k", rag_top_k.to_string()),
+            (
+                "rag_injection_guard",
+                format_option_value(&self.rag_injection_guard.map(|v| v.as_str())),
+            ),
+            (
+                "disabled_loaders",
+                format_option_value(
+                    &Some(self.disabled_loaders.join(",")).filter(|v| !v.is_empty()),
+                ),
+            ),
+            ("notebook_outputs", self.notebook_outputs.to_string()),
+            (
+                "screenshot_command",
+                format_option_value(&self.screenshot_command),
+            ),
+            ("dedup_attachments", self.dedup_attachments.to_string()),
+            (
+                "attachment_head_ratio",
+                self.attachment_head_ratio.to_string(),
+            ),
             ("highlight", self.highlight.to_string()),
             ("light_theme", self.light_theme.to_string()),
+            ("listen_command", format_option_value(&self.listen_command)),
+            ("stt_command", format_option_value(&self.stt_command)),
+            ("speak", self.speak.to_string()),
+            ("speak_command", format_option_value(&self.speak_command)),
+            ("speak_voice", format_option_value(&self.speak_voice)),
+            (
+                "realtime_mic_command",
+                format_option_value(&self.realtime_mic_command),
+            ),
+            (
+                "realtime_player_command",
+                format_option_value(&self.realtime_player_command),
+            ),
             ("config_file", display_path(&Self::config_file())),
             ("env_file", display_path(&Self::env_file())),
             ("roles_dir", display_path(&Self::roles_dir())),
+            ("roles_file", display_path(&Self::roles_file())),
+            (
+                "imported_roles_dir",
+                display_path(&Self::imported_roles_dir()),
+            ),
             ("sessions_dir", display_path(&self.sessions_dir())),
             ("rags_dir", display_path(&Self::rags_dir())),
             ("functions_dir", display_path(&Self::functions_dir())),
             ("messages_file", display_path(&self.messages_file())),
+            ("tool_calls_file", display_path(&Self::tool_calls_file())),
+            ("usage_file", display_path(&Self::usage_file())),
         ];
         if let Ok((_, Some(log_path))) = Self::log_config(self.working_mode.is_serve()) {
             items.push(("log_path", display_path(&log_path)));
@@ -609,6 +1000,10 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_top_p(value);
             }
+            "seed" => {
+                let value = parse_value(value)?;
+                config.write().seed = value;
+            }
             "dry_run" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().dry_run = value;
@@ -617,6 +1012,10 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().stream = value;
             }
+            "auto_continue" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().auto_continue = value;
+            }
             "save" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().save = value;
@@ -632,6 +1031,30 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_use_tools(value);
             }
+            "tool_policy" => {
+                let value = ToolPolicy::parse(value).ok_or_else(|| anyhow!("Invalid value"))?;
+                config.write().tool_policy = value;
+            }
+            "redact" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().redact = value;
+            }
+            "web_search" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().web_search = value;
+            }
+            "fetch_url" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().fetch_url = value;
+            }
+            "memory" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().memory = value;
+            }
+            "code_interpreter" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().code_interpreter = value;
+            }
             "agent_prelude" => {
                 let value = parse_value(value)?;
                 config.write().set_agent_prelude(value);
@@ -644,6 +1067,23 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_compress_threshold(value);
             }
+            "compress_strategy" => {
+                let value =
+                    CompressStrategy::parse(value).ok_or_else(|| anyhow!("Invalid value"))?;
+                config.write().compress_strategy = value;
+            }
+            "compress_preview" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().compress_preview = value;
+            }
+            "notebook_outputs" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().notebook_outputs = value;
+            }
+            "dedup_attachments" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().dedup_attachments = value;
+            }
             "rag_reranker_model" => {
                 let value = parse_value(value)?;
                 Self::set_rag_reranker_model(config, value)?;
@@ -652,16 +1092,48 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 Self::set_rag_top_k(config, value)?;
             }
+            "rag_injection_guard" => {
+                let value = match parse_value::<String>(value)? {
+                    Some(v) => {
+                        Some(InjectionGuard::parse(&v).ok_or_else(|| anyhow!("Invalid value"))?)
+                    }
+                    None => None,
+                };
+                config.write().rag_injection_guard = value;
+            }
             "highlight" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().highlight = value;
             }
+            "auto_page" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().auto_page = value;
+            }
+            "footer" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().footer = value;
+            }
+            "stream_status" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().stream_status = value;
+            }
+            "speak" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().speak = value;
+            }
             _ => bail!("Unknown key '{key}'"),
         }
         Ok(())
     }
 
-    pub fn delete(config: &GlobalConfig, kind: &str) -> Result<()> {
+    pub fn delete(config: &GlobalConfig, args: &str) -> Result<()> {
+        let (kind, name) = match args.split_once(' ') {
+            Some((kind, name)) => (kind, Some(name.trim())),
+            None => (args, None),
+        };
+        if let Some(name) = name.filter(|v| !v.is_empty()) {
+            return Self::delete_one(config, kind, name);
+        }
         let (dir, file_ext) = match kind {
             "role" => (Self::roles_dir(), Some(".md")),
             "session" => (config.read().sessions_dir(), Some(".yaml")),
@@ -729,6 +1201,48 @@ impl Config {
         Ok(())
     }
 
+    fn delete_one(config: &GlobalConfig, kind: &str, name: &str) -> Result<()> {
+        if !is_relative_name(name) {
+            bail!("Invalid {kind} name '{name}'");
+        }
+        let path = match kind {
+            "role" => Self::role_file(name),
+            "session" => {
+                let config = config.read();
+                if config.session.as_ref().map(|v| v.name()) == Some(name) {
+                    bail!("Cannot delete the session in use, please '.exit session' first");
+                }
+                config.session_file(name)
+            }
+            "rag" => Self::rags_dir().join(format!("{name}.yaml")),
+            "agent-data" => Self::agent_data_dir(name),
+            _ => bail!("Unknown kind '{kind}'"),
+        };
+        if !path.exists() {
+            if kind == "role" && Self::load_roles_file().iter().any(|v| v.name() == name) {
+                bail!(
+                    "Role '{name}' is defined in '{}', remove it from there",
+                    Self::roles_file().display()
+                );
+            }
+            bail!("No {kind} named '{name}'");
+        }
+        let ans = Confirm::new(&format!("Delete {kind} '{name}'?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            return Ok(());
+        }
+        if path.is_dir() {
+            remove_dir_all(&path)
+        } else {
+            remove_file(&path)
+        }
+        .with_context(|| format!("Failed to delete {kind} at '{}'", path.display()))?;
+        println!("✓ Successfully deleted {kind} '{name}'.");
+        Ok(())
+    }
+
     pub fn set_temperature(&mut self, value: Option<f64>) {
         match self.role_like_mut() {
             Some(role_like) => role_like.set_temperature(value),
@@ -832,6 +1346,32 @@ impl Config {
         Ok(())
     }
 
+    /// Pick a chat model from a fuzzy-searchable list showing its provider, context size and price.
+    pub fn select_model(config: &GlobalConfig) -> Result<()> {
+        let (models, current_id) = {
+            let config = config.read();
+            let models: Vec<Model> = list_models(&config, ModelType::Chat)
+                .into_iter()
+                .cloned()
+                .collect();
+            (models, config.current_model().id())
+        };
+        if models.is_empty() {
+            bail!("No chat models available");
+        }
+        let (header, options) = model_select_options(&models);
+        let cursor = models
+            .iter()
+            .position(|v| v.id() == current_id)
+            .unwrap_or_default();
+        let selected = Select::new("Select model:", options)
+            .with_help_message(&format!("{header}  (price per 1M tokens)"))
+            .with_starting_cursor(cursor)
+            .with_page_size(15)
+            .raw_prompt()?;
+        config.write().set_model(&models[selected.index].id())
+    }
+
     pub fn use_prompt(&mut self, prompt: &str) -> Result<()> {
         let mut role = Role::new(TEMP_ROLE_NAME, prompt);
         role.set_model(&self.model);
@@ -853,6 +1393,8 @@ impl Config {
         } else {
             self.role = Some(role);
         }
+        // Tools trusted for one role aren't for another
+        self.trusted_tools.clear();
         Ok(())
     }
 
@@ -878,19 +1420,31 @@ impl Config {
         } else if self.role.is_some() {
             self.role = None;
         }
+        self.trusted_tools.clear();
         Ok(())
     }
 
+    /// Load a role from the roles dir or builtin ones, without resolving its model
+    pub fn load_role(name: &str) -> Result<Role> {
+        RolePaths::current().load_role(name)
+    }
+
+    pub fn load_roles_file() -> Vec<Role> {
+        RolePaths::current().load_roles_file()
+    }
+
     pub fn retrieve_role(&self, name: &str) -> Result<Role> {
-        let names = Self::list_roles(false);
-        let mut role = if let Some(role_name) = Role::match_name(&names, name) {
-            let path = Self::role_file(&role_name);
-            let content = read_to_string(&path)?;
-            Role::new(name, &content)
-        } else {
-            Role::builtin(name)?
+        let mut role = Self::load_role(name)?;
+        let category = match name {
+            SHELL_ROLE | EXPLAIN_SHELL_ROLE => Some("shell"),
+            CODE_ROLE => Some("code"),
+            RECAP_ROLE => Some("summarize"),
+            _ => None,
         };
-        match role.model_id() {
+        let model_id = role
+            .model_id()
+            .or_else(|| category.and_then(|v| self.default_models.get(v).map(|v| v.as_str())));
+        match model_id {
             Some(model_id) => {
                 if self.model.id() != model_id {
                     let model = Model::retrieve_model(self, model_id, ModelType::Chat)?;
@@ -937,6 +1491,16 @@ impl Config {
         let role_name = Role::match_name(&names, name).unwrap_or_else(|| name.to_string());
         let role_path = Self::role_file(&role_name);
         ensure_parent_exists(&role_path)?;
+        if !role_path.exists() {
+            if let Some(role) = Self::load_roles_file()
+                .into_iter()
+                .find(|v| v.name() == role_name)
+            {
+                std::fs::write(&role_path, role.export()).with_context(|| {
+                    format!("Failed to write role to '{}'", role_path.display())
+                })?;
+            }
+        }
         let editor = self.editor()?;
         edit_file(&editor, &role_path)?;
         self.use_role(name)?;
@@ -985,8 +1549,7 @@ impl Config {
             .collect();
         let names = Self::list_roles(false);
         for name in names {
-            if let Ok(content) = read_to_string(Self::role_file(&name)) {
-                let role = Role::new(&name, &content);
+            if let Ok(role) = Self::load_role(&name) {
                 roles.insert(name, role);
             }
         }
@@ -996,24 +1559,7 @@ impl Config {
     }
 
     pub fn list_roles(with_builtin: bool) -> Vec<String> {
-        let mut names = HashSet::new();
-        if let Ok(rd) = read_dir(Self::roles_dir()) {
-            for entry in rd.flatten() {
-                if let Some(name) = entry
-                    .file_name()
-                    .to_str()
-                    .and_then(|v| v.strip_suffix(".md"))
-                {
-                    names.insert(name.to_string());
-                }
-            }
-        }
-        if with_builtin {
-            names.extend(Role::list_builtin_role_names());
-        }
-        let mut names: Vec<_> = names.into_iter().collect();
-        names.sort_unstable();
-        names
+        RolePaths::current().list_roles(with_builtin)
     }
 
     pub fn has_role(name: &str) -> bool {
@@ -1027,6 +1573,16 @@ impl Config {
                 "Already in a session, please run '.exit session' first to exit the current session."
             );
         }
+        let last_session_name;
+        let session_name = match session_name {
+            Some(LAST_SESSION_NAME) if !self.session_file(LAST_SESSION_NAME).exists() => {
+                last_session_name = self
+                    .last_session_name()
+                    .ok_or_else(|| anyhow!("No session to resume"))?;
+                Some(last_session_name.as_str())
+            }
+            v => v,
+        };
         let mut session;
         match session_name {
             None | Some(TEMP_SESSION_NAME) => {
@@ -1057,7 +1613,7 @@ impl Config {
                         .with_default(false)
                         .prompt()?;
                         if ans {
-                            session.add_message(input, output)?;
+                            session.add_message(input, output, self.seed)?;
                         }
                     }
                 }
@@ -1092,6 +1648,10 @@ impl Config {
             let sessions_dir = self.sessions_dir();
             session.exit(&sessions_dir, self.working_mode.is_repl())?;
             self.last_message = None;
+            self.trusted_tools.clear();
+            if let Err(err) = self.prune_auto_sessions() {
+                warn!("Failed to prune auto-saved sessions, {err}");
+            }
         }
         Ok(())
     }
@@ -1146,6 +1706,24 @@ impl Config {
         Ok(())
     }
 
+    pub fn undo_session(&mut self) -> Result<usize> {
+        let Some(session) = self.session.as_mut() else {
+            bail!("No session")
+        };
+        let dropped = session.undo()?;
+        self.last_message = None;
+        Ok(dropped)
+    }
+
+    pub fn rollback_session(&mut self, name: &str) -> Result<usize> {
+        let Some(session) = self.session.as_mut() else {
+            bail!("No session")
+        };
+        let dropped = session.rollback(name)?;
+        self.last_message = None;
+        Ok(dropped)
+    }
+
     pub fn set_save_session_this_time(&mut self) -> Result<()> {
         if let Some(session) = self.session.as_mut() {
             session.set_save_session_this_time();
@@ -1163,14 +1741,70 @@ impl Config {
         list_file_names(self.sessions_dir().join("_"), ".yaml")
     }
 
+    /// Remove the oldest auto-saved sessions beyond `max_auto_sessions`
+    fn prune_auto_sessions(&self) -> Result<()> {
+        let Some(max_auto_sessions) = self.max_auto_sessions else {
+            return Ok(());
+        };
+        let dir = self.sessions_dir().join("_");
+        let Ok(entries) = read_dir(&dir) else {
+            return Ok(());
+        };
+        let mut files = vec![];
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().map(|v| v == "yaml").unwrap_or_default() {
+                files.push((entry.metadata()?.modified()?, path));
+            }
+        }
+        if files.len() <= max_auto_sessions {
+            return Ok(());
+        }
+        files.sort_by_key(|v| std::cmp::Reverse(v.0));
+        for (_, path) in &files[max_auto_sessions..] {
+            remove_file(path).with_context(|| format!("Failed to remove '{}'", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// The most recently modified session, autonamed ones included and `temp` excluded
+    pub fn last_session_name(&self) -> Option<String> {
+        let sessions_dir = self.sessions_dir();
+        let mut last: Option<(std::time::SystemTime, String)> = None;
+        for (prefix, dir) in [("", sessions_dir.clone()), ("_/", sessions_dir.join("_"))] {
+            let Ok(entries) = read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let (Some(name), Ok(modified)) = (
+                    path.file_name()
+                        .and_then(|v| v.to_str())
+                        .and_then(|v| v.strip_suffix(".yaml")),
+                    entry.metadata().and_then(|v| v.modified()),
+                ) else {
+                    continue;
+                };
+                if prefix.is_empty() && name == TEMP_SESSION_NAME {
+                    continue;
+                }
+                if last.as_ref().map(|(t, _)| modified > *t).unwrap_or(true) {
+                    last = Some((modified, format!("{prefix}{name}")));
+                }
+            }
+        }
+        last.map(|(_, name)| name)
+    }
+
     pub fn maybe_compress_session(config: GlobalConfig) {
         let mut need_compress = false;
+        let compress_preview = config.read().compress_preview;
         {
             let mut config = config.write();
             let compress_threshold = config.compress_threshold;
             if let Some(session) = config.session.as_mut() {
                 if session.need_compress(compress_threshold) {
-                    session.set_compressing(true);
+                    session.set_compressing(!compress_preview);
                     need_compress = true;
                 }
             }
@@ -1183,6 +1817,15 @@ impl Config {
         } else {
             nu_ansi_term::Color::DarkGray
         };
+        if compress_preview {
+            print!(
+                "\n📢 {}\n",
+                color
+                    .italic()
+                    .paint("The session exceeds compress_threshold, run `.compress` to review it."),
+            );
+            return;
+        }
         print!(
             "\n📢 {}\n",
             color.italic().paint("Compressing the session."),
@@ -1191,40 +1834,84 @@ impl Config {
             if let Err(err) = Config::compress_session(&config).await {
                 warn!("Failed to compress the session: {err}");
             }
-            if let Some(session) = config.write().session.as_mut() {
-                session.set_compressing(false);
-            }
         });
     }
 
     pub async fn compress_session(config: &GlobalConfig) -> Result<()> {
-        match config.read().session.as_ref() {
-            Some(session) => {
-                if !session.has_user_messages() {
-                    bail!("No need to compress since there are no messages in the session")
-                }
+        match Self::prepare_session_compression(config, None).await {
+            Ok(compression) => {
+                Self::end_session_compression(config, Some(compression));
+                Ok(())
+            }
+            Err(err) => {
+                Self::end_session_compression(config, None);
+                Err(err)
             }
-            None => bail!("No session"),
         }
+    }
 
-        let prompt = config
-            .read()
-            .summarize_prompt
-            .clone()
-            .unwrap_or_else(|| SUMMARIZE_PROMPT.into());
-        let input = Input::from_str(config, &prompt, None);
-        let client = input.create_client()?;
-        let summary = client.chat_completions(input).await?.text;
-        let summary_prompt = config
-            .read()
-            .summary_prompt
-            .clone()
-            .unwrap_or_else(|| SUMMARY_PROMPT.into());
-        if let Some(session) = config.write().session.as_mut() {
-            session.compress(format!("{}{}", summary_prompt, summary));
+    /// Work out the messages to compress and summarize them unless the strategy prunes them.
+    /// The session stays marked as compressing until `end_session_compression`.
+    pub async fn prepare_session_compression(
+        config: &GlobalConfig,
+        strategy: Option<CompressStrategy>,
+    ) -> Result<SessionCompression> {
+        let (strategy, compress_from, compressed) = {
+            let mut config = config.write();
+            let strategy = strategy.unwrap_or(config.compress_strategy);
+            let compress_threshold = config.compress_threshold;
+            let Some(session) = config.session.as_mut() else {
+                bail!("No session")
+            };
+            if !session.has_user_messages() {
+                bail!("No need to compress since there are no messages in the session")
+            }
+            let compress_from = session.compress_from(strategy, compress_threshold);
+            let compressed = session.count_compressed(compress_from);
+            if compressed == 0 {
+                bail!("No messages to compress")
+            }
+            // Makes the session only include the messages to summarize
+            session.set_compressing(true);
+            session.set_compress_until(compress_from);
+            (strategy, compress_from, compressed)
+        };
+        let summary = match strategy {
+            CompressStrategy::Prune => None,
+            CompressStrategy::Summarize | CompressStrategy::Hybrid => {
+                let prompt = config
+                    .read()
+                    .summarize_prompt
+                    .clone()
+                    .unwrap_or_else(|| SUMMARIZE_PROMPT.into());
+                let input = Input::from_str(config, &prompt, None);
+                let client = input.create_client()?;
+                let summary = client.chat_completions(input).await?.text;
+                let summary_prompt = config
+                    .read()
+                    .summary_prompt
+                    .clone()
+                    .unwrap_or_else(|| SUMMARY_PROMPT.into());
+                Some(format!("{summary_prompt}{summary}"))
+            }
+        };
+        Ok(SessionCompression {
+            summary,
+            compress_from,
+            compressed,
+        })
+    }
+
+    /// Apply the compression if given, and let the session take new messages again.
+    pub fn end_session_compression(config: &GlobalConfig, compression: Option<SessionCompression>) {
+        let mut config = config.write();
+        if let Some(session) = config.session.as_mut() {
+            session.set_compressing(false);
+            if let Some(compression) = compression {
+                session.compress(compression.summary, compression.compress_from);
+                config.last_message = None;
+            }
         }
-        config.write().last_message = None;
-        Ok(())
     }
 
     pub fn is_compressing_session(&self) -> bool {
@@ -1261,6 +1948,103 @@ impl Config {
         });
     }
 
+    /// Bullet points of the session so far by the `summarize` default model, the session is left as it is.
+    pub async fn recap_session(config: &GlobalConfig) -> Result<String> {
+        let transcript = match config.read().session.as_ref() {
+            Some(session) if session.has_user_messages() => session.transcript(),
+            Some(_) => bail!("No messages to recap"),
+            None => bail!("No session"),
+        };
+        let role = config.read().retrieve_role(RECAP_ROLE)?;
+        let transcript = match role.model().max_input_tokens() {
+            // Keeps the latest messages of the transcript, leaving room for the prompt
+            Some(max_tokens) => truncate_tokens(&transcript, max_tokens * 3 / 4, 0.25).0,
+            None => transcript,
+        };
+        let input = Input::from_str(config, &transcript, Some(role));
+        let client = input.create_client()?;
+        Ok(client.chat_completions(input).await?.text)
+    }
+
+    /// The exchanges of the session closest in meaning to `query`, by the index of their user
+    /// message. Embeddings are kept with the session so that each exchange is embedded once.
+    pub async fn find_in_session(config: &GlobalConfig, query: &str) -> Result<String> {
+        let embedding_model = Rag::default_embedding_model(&config.read())?;
+        let model_id = embedding_model.id();
+        let max_tokens = embedding_model
+            .max_tokens_per_chunk()
+            .unwrap_or_else(|| embedding_model.default_chunk_size());
+        let (exchanges, mut embeddings) = {
+            let config = config.read();
+            let Some(session) = config.session.as_ref() else {
+                bail!("No session")
+            };
+            let exchanges: Vec<(usize, String, String)> = session
+                .exchanges()
+                .into_iter()
+                .map(|(index, text)| {
+                    let text = truncate_tokens(&text, max_tokens, 1.0).0;
+                    let key = sha256(&format!("{model_id}\n{text}"));
+                    (index, key, text)
+                })
+                .collect();
+            let embeddings: Vec<Option<Vec<f32>>> = exchanges
+                .iter()
+                .map(|(_, key, _)| session.cached_embedding(key))
+                .collect();
+            (exchanges, embeddings)
+        };
+        if exchanges.is_empty() {
+            bail!("No messages to search")
+        }
+        let client = init_client(config, Some(embedding_model.clone()))?;
+        let missing: Vec<usize> = (0..exchanges.len())
+            .filter(|i| embeddings[*i].is_none())
+            .collect();
+        let batch_size = embedding_model.max_batch_size().unwrap_or(1).max(1);
+        for batch in missing.chunks(batch_size) {
+            let texts = batch.iter().map(|i| exchanges[*i].2.clone()).collect();
+            let output = client
+                .embeddings(&EmbeddingsData::new(texts, false))
+                .await?;
+            for (i, embedding) in batch.iter().zip(output) {
+                embeddings[*i] = Some(embedding);
+            }
+        }
+        let query = client
+            .embeddings(&EmbeddingsData::new(vec![query.to_string()], true))
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("No embedding of the query"))?;
+
+        let mut scores = vec![];
+        {
+            let mut config = config.write();
+            for ((index, key, text), embedding) in exchanges.into_iter().zip(embeddings) {
+                let Some(embedding) = embedding else {
+                    continue;
+                };
+                scores.push((index, cosine_similarity(&query, &embedding), text));
+                if let Some(session) = config.session.as_mut() {
+                    session.cache_embedding(key, embedding);
+                }
+            }
+        }
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let rows: Vec<Vec<String>> = scores
+            .into_iter()
+            .take(FIND_SESSION_LIMIT)
+            .map(|(index, score, text)| {
+                vec![
+                    format!("#{index}"),
+                    format!("{score:.2}"),
+                    summarize_text(&text, 60),
+                ]
+            })
+            .collect();
+        Ok(render_table(&["#", "SCORE", "TEXT"], &rows))
+    }
+
     pub async fn autoname_session(config: &GlobalConfig) -> Result<()> {
         let text = match config
             .read()
@@ -1281,6 +2065,66 @@ impl Config {
         Ok(())
     }
 
+    /// Re-render a saved (or the current) session turn by turn, pacing each reply by its
+    /// recorded latency divided by `speed`.
+    pub async fn replay_session(
+        config: &GlobalConfig,
+        name: Option<&str>,
+        speed: f64,
+        abort_signal: AbortSignal,
+    ) -> Result<()> {
+        if !speed.is_finite() || speed <= 0.0 {
+            bail!("Invalid replay speed '{speed}'")
+        }
+        let session = match name {
+            Some(name) => {
+                let cfg = config.read();
+                let session_path = cfg.session_file(name);
+                if !session_path.exists() {
+                    bail!("Unknown session '{name}'")
+                }
+                Session::load(&cfg, name, &session_path)?
+            }
+            None => match config.read().session.clone() {
+                Some(session) => session,
+                None => bail!("No session"),
+            },
+        };
+        let turns = session.replay_turns();
+        if turns.is_empty() {
+            bail!("No messages in session '{}'", session.name())
+        }
+        for (role, text, latency_ms) in turns {
+            if abort_signal.aborted() {
+                break;
+            }
+            if role.is_user() {
+                println!("{}）{}", session.name(), text);
+                continue;
+            }
+            let chunks: Vec<String> = split_content(&text)
+                .into_iter()
+                .map(|v| v.to_string())
+                .collect();
+            let delay = replay_chunk_delay(latency_ms, chunks.len(), speed);
+            let (tx, rx) = unbounded_channel();
+            let producer_abort_signal = abort_signal.clone();
+            let producer = async move {
+                for chunk in chunks {
+                    if producer_abort_signal.aborted() || tx.send(SseEvent::Text(chunk)).is_err() {
+                        break;
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+                let _ = tx.send(SseEvent::Done);
+            };
+            let (_, ret) = tokio::join!(producer, render_stream(rx, config, abort_signal.clone()));
+            ret?;
+            println!();
+        }
+        Ok(())
+    }
+
     pub async fn use_rag(
         config: &GlobalConfig,
         rag: Option<&str>,
@@ -1402,7 +2246,7 @@ impl Config {
         rag: &Rag,
         text: &str,
         abort_signal: AbortSignal,
-    ) -> Result<String> {
+    ) -> Result<(String, Vec<Citation>)> {
         let (reranker_model, top_k) = rag.get_config();
         let (min_score_vector_search, min_score_keyword_search) = {
             let config = config.read();
@@ -1421,9 +2265,10 @@ impl Config {
                 abort_signal,
             )
             .await?;
+        let embeddings = config.read().guard_untrusted_content("RAG", &embeddings);
         let text = config.read().rag_template(&embeddings, text);
         rag.set_last_sources(&ids);
-        Ok(text)
+        Ok((text, rag.citations(&ids)))
     }
 
     pub fn list_rags() -> Vec<String> {
@@ -1454,6 +2299,26 @@ impl Config {
             .replace("__INPUT__", text)
     }
 
+    pub fn guard_untrusted_content(&self, source: &str, content: &str) -> String {
+        let guard = match self.rag_injection_guard {
+            Some(guard) if !content.is_empty() => guard,
+            _ => return content.to_string(),
+        };
+        let (output, hits) = guard.apply(content);
+        if !hits.is_empty() {
+            let action = match guard {
+                InjectionGuard::Flag => "Flagged",
+                InjectionGuard::Strip => "Stripped",
+            };
+            print_warning(&format!(
+                "{action} {} instruction-like phrase(s) in {source}: {}",
+                hits.len(),
+                hits.join(", ")
+            ));
+        }
+        output
+    }
+
     pub async fn use_agent(
         config: &GlobalConfig,
         agent_name: &str,
@@ -1470,8 +2335,12 @@ impl Config {
         let session = session_name
             .map(|v| v.to_string())
             .or_else(|| agent.agent_prelude().map(|v| v.to_string()));
-        config.write().rag = agent.rag();
-        config.write().agent = Some(agent);
+        {
+            let mut config = config.write();
+            config.rag = agent.rag();
+            config.agent = Some(agent);
+            config.trusted_tools.clear();
+        }
         if let Some(session) = session {
             config.write().use_session(Some(&session))?;
         } else {
@@ -1488,6 +2357,61 @@ impl Config {
         }
     }
 
+    pub fn conversation_starters(&self) -> Vec<String> {
+        match &self.agent {
+            Some(agent) => agent.conversation_staters().to_vec(),
+            None => self
+                .active_role()
+                .map(|v| v.conversation_starters().to_vec())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The starter numbered `value` if there is one, otherwise `value` itself
+    pub fn resolve_starter(&self, value: &str) -> String {
+        let starters = self.conversation_starters();
+        match value.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= starters.len() => starters[n - 1].clone(),
+            _ => value.to_string(),
+        }
+    }
+
+    /// The greeting and the numbered conversation starters of the agent or role
+    pub fn starters_banner(&self) -> Option<String> {
+        let greeting = match &self.agent {
+            Some(agent) => agent.greeting().map(|v| v.to_string()),
+            None => self
+                .active_role()
+                .and_then(|v| v.greeting().map(|v| v.to_string())),
+        };
+        let starters = self.conversation_starters();
+        if greeting.is_none() && starters.is_empty() {
+            return None;
+        }
+        let mut output = greeting.unwrap_or_default();
+        if !starters.is_empty() {
+            let starters: Vec<String> = starters
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("{}. {v}", i + 1))
+                .collect();
+            output = format!(
+                "{output}\n\nConversation starters (`.starter <n>` to use one):\n{}",
+                starters.join("\n")
+            );
+        }
+        Some(output.trim().to_string())
+    }
+
+    fn active_role(&self) -> Option<Role> {
+        match &self.session {
+            Some(session) => session
+                .role_name()
+                .and_then(|name| Self::load_role(name).ok()),
+            None => self.role.clone(),
+        }
+    }
+
     pub fn agent_banner(&self) -> Result<String> {
         if let Some(agent) = &self.agent {
             Ok(agent.banner())
@@ -1524,6 +2448,7 @@ impl Config {
             self.rag.take();
             self.last_message = None;
             self.cli_agent_variables = None;
+            self.trusted_tools.clear();
         }
         Ok(())
     }
@@ -1641,6 +2566,40 @@ impl Config {
                 functions = agent_functions;
             }
         };
+        if self.web_search
+            && role.model().data().supports_function_calling
+            && !supports_native_web_search(self, role.model())
+            && !functions.iter().any(|v| v.name == WEB_SEARCH_FUNCTION_NAME)
+        {
+            functions.push(web_search_declaration());
+        }
+        if self.fetch_url
+            && role.model().data().supports_function_calling
+            && !functions.iter().any(|v| v.name == FETCH_URL_FUNCTION_NAME)
+        {
+            functions.push(fetch_url_declaration());
+        }
+        if self.memory
+            && role.model().data().supports_function_calling
+            && !functions.iter().any(|v| v.name == MEMORY_FUNCTION_NAME)
+        {
+            functions.push(memory_declaration());
+        }
+        if self.code_interpreter
+            && role.model().data().supports_function_calling
+            && !functions
+                .iter()
+                .any(|v| v.name == CODE_INTERPRETER_FUNCTION_NAME)
+        {
+            functions.push(code_interpreter_declaration());
+        }
+        if !self.fs_allowed_dirs.is_empty() && role.model().data().supports_function_calling {
+            for declaration in fs_declarations() {
+                if !functions.iter().any(|v| v.name == declaration.name) {
+                    functions.push(declaration);
+                }
+            }
+        }
         if functions.is_empty() {
             None
         } else {
@@ -1648,6 +2607,36 @@ impl Config {
         }
     }
 
+    /// `.trust` only lifts `confirm`, a denied tool stays denied.
+    pub fn tool_policy_of(&self, name: &str) -> ToolPolicy {
+        let policy = self
+            .tool_policies
+            .get(name)
+            .copied()
+            .unwrap_or(self.tool_policy);
+        if policy == ToolPolicy::Confirm && self.trusted_tools.contains(name) {
+            ToolPolicy::Auto
+        } else {
+            policy
+        }
+    }
+
+    pub fn trust_tool(&mut self, name: &str) -> Result<()> {
+        if self.tool_policy_of(name) == ToolPolicy::Deny {
+            bail!("The tool '{name}' is denied by policy")
+        }
+        self.trusted_tools.insert(name.to_string());
+        Ok(())
+    }
+
+    pub fn shell_guard(&self) -> Result<Arc<ShellGuard>> {
+        if let Some(guard) = self.shell_guard.get() {
+            return Ok(guard.clone());
+        }
+        let guard = Arc::new(ShellGuard::new(&self.shell_denylist)?);
+        Ok(self.shell_guard.get_or_init(|| guard).clone())
+    }
+
     pub fn editor(&self) -> Result<String> {
         self.editor
             .clone()
@@ -1655,6 +2644,57 @@ impl Config {
             .ok_or_else(|| anyhow!("No editor, please configure `editor` or set $EDITOR/$VISUAL environment variable."))
     }
 
+    /// Word-level diff between the reply superseded by `.regenerate` and the current one.
+    pub fn diff_last_reply(&self) -> Result<String> {
+        let Some(previous_reply) = &self.previous_reply else {
+            bail!("No previous reply to compare, use `.regenerate` first")
+        };
+        Ok(render_word_diff(previous_reply, self.last_reply()))
+    }
+
+    pub fn pager(&self) -> String {
+        self.pager
+            .clone()
+            .or_else(|| env::var("PAGER").ok())
+            .unwrap_or_else(|| "less".into())
+    }
+
+    /// Show the rendered last reply in the pager.
+    pub fn page_last_reply(&self) -> Result<()> {
+        let Some((_, output)) = &self.last_message else {
+            bail!("No reply to page")
+        };
+        let text = render_citations(&output.text, &output.citations);
+        let text = if *IS_STDOUT_TERMINAL {
+            MarkdownRender::init(self.render_options()?)?.render(&text)
+        } else {
+            text
+        };
+        run_pager(&self.pager(), &text)
+    }
+
+    /// Whether to ask again for the rest of a reply cut off by `max_output_tokens`.
+    pub fn should_auto_continue(&self, input: &Input, output: &ChatCompletionsOutput) -> bool {
+        !self.dry_run
+            && output.is_truncated()
+            && output.tool_calls.is_empty()
+            && input.continue_count() < self.auto_continue
+    }
+
+    /// Page the last reply if `auto_page` is on and it doesn't fit in the terminal.
+    pub fn maybe_page_last_reply(&self) -> Result<()> {
+        if !self.auto_page || !*IS_STDOUT_TERMINAL {
+            return Ok(());
+        }
+        let Ok((_, rows)) = crossterm::terminal::size() else {
+            return Ok(());
+        };
+        if self.last_reply().lines().count() >= rows as usize {
+            self.page_last_reply()?;
+        }
+        Ok(())
+    }
+
     pub fn repl_complete(
         &self,
         cmd: &str,
@@ -1666,10 +2706,17 @@ impl Config {
         if args.len() == 1 {
             values = match cmd {
                 ".role" => map_completion_values(Self::list_roles(true)),
-                ".model" => list_models(self, ModelType::Chat)
-                    .into_iter()
-                    .map(|v| (v.id(), Some(v.description())))
+                ".model" => self
+                    .model_aliases
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Some(format!("alias of {v}"))))
+                    .chain(
+                        list_models(self, ModelType::Chat)
+                            .into_iter()
+                            .map(|v| (v.id(), Some(v.description()))),
+                    )
                     .collect(),
+                ".replay" => map_completion_values(self.list_sessions()),
                 ".session" => {
                     if args[0].starts_with("_/") {
                         map_completion_values(
@@ -1680,15 +2727,24 @@ impl Config {
                                 .collect::<Vec<String>>(),
                         )
                     } else {
-                        map_completion_values(self.list_sessions())
+                        let mut values = self.list_sessions();
+                        if !values.iter().any(|v| v == LAST_SESSION_NAME) {
+                            values.insert(0, LAST_SESSION_NAME.to_string());
+                        }
+                        map_completion_values(values)
                     }
                 }
                 ".rag" => map_completion_values(Self::list_rags()),
                 ".agent" => map_completion_values(list_agents()),
-                ".starter" => match &self.agent {
-                    Some(agent) => map_completion_values(agent.conversation_staters().to_vec()),
-                    None => vec![],
-                },
+                ".starter" => map_completion_values(self.conversation_starters()),
+                ".trust" => map_completion_values(
+                    self.select_functions(&self.extract_role())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|v| v.name)
+                        .filter(|v| !self.trusted_tools.contains(v))
+                        .collect(),
+                ),
                 ".variable" => match &self.agent {
                     Some(agent) => agent
                         .defined_variables()
@@ -1702,17 +2758,34 @@ impl Config {
                         "max_output_tokens",
                         "temperature",
                         "top_p",
+                        "seed",
                         "dry_run",
                         "stream",
+                        "auto_continue",
                         "save",
                         "function_calling",
                         "use_tools",
+                        "tool_policy",
+                        "redact",
+                        "web_search",
+                        "fetch_url",
+                        "memory",
+                        "code_interpreter",
                         "agent_prelude",
                         "save_session",
                         "compress_threshold",
+                        "compress_strategy",
+                        "compress_preview",
                         "rag_reranker_model",
                         "rag_top_k",
+                        "rag_injection_guard",
+                        "notebook_outputs",
+                        "dedup_attachments",
                         "highlight",
+                        "auto_page",
+                        "footer",
+                        "stream_status",
+                        "speak",
                     ];
                     values.sort_unstable();
                     values
@@ -1720,7 +2793,26 @@ impl Config {
                         .map(|v| (format!("{v} "), None))
                         .collect()
                 }
-                ".delete" => map_completion_values(vec!["role", "session", "rag", "agent-data"]),
+                ".delete" => ["role", "session", "rag", "agent-data"]
+                    .into_iter()
+                    .map(|v| (format!("{v} "), None))
+                    .collect(),
+                ".compress" => ["summarize", "prune", "hybrid"]
+                    .into_iter()
+                    .map(|v| (v.to_string(), None))
+                    .collect(),
+                ".rollback" => self
+                    .session
+                    .as_ref()
+                    .map(|v| v.checkpoint_names())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| (v, None))
+                    .collect(),
+                ".memory" => ["list", "add", "forget"]
+                    .into_iter()
+                    .map(|v| (format!("{v} "), None))
+                    .collect(),
                 _ => vec![],
             };
             filter = args[0]
@@ -1734,6 +2826,18 @@ impl Config {
                 "stream" => complete_bool(self.stream),
                 "save" => complete_bool(self.save),
                 "function_calling" => complete_bool(self.function_calling),
+                "tool_policy" => vec!["auto".into(), "confirm".into(), "deny".into()],
+                "compress_strategy" => {
+                    vec!["summarize".into(), "prune".into(), "hybrid".into()]
+                }
+                "compress_preview" => complete_bool(self.compress_preview),
+                "notebook_outputs" => complete_bool(self.notebook_outputs),
+                "dedup_attachments" => complete_bool(self.dedup_attachments),
+                "redact" => complete_bool(self.redact),
+                "web_search" => complete_bool(self.web_search),
+                "fetch_url" => complete_bool(self.fetch_url),
+                "memory" => complete_bool(self.memory),
+                "code_interpreter" => complete_bool(self.code_interpreter),
                 "use_tools" => {
                     let mut prefix = String::new();
                     let mut ignores = HashSet::new();
@@ -1765,11 +2869,35 @@ impl Config {
                     .iter()
                     .map(|v| v.id())
                     .collect(),
+                "rag_injection_guard" => vec!["flag".into(), "strip".into(), "null".into()],
                 "highlight" => complete_bool(self.highlight),
+                "auto_page" => complete_bool(self.auto_page),
+                "footer" => complete_bool(self.footer),
+                "stream_status" => complete_bool(self.stream_status),
+                "speak" => complete_bool(self.speak),
                 _ => vec![],
             };
             values = candidates.into_iter().map(|v| (v, None)).collect();
             filter = args[1];
+        } else if cmd == ".delete" && args.len() == 2 {
+            let names = match args[0] {
+                "role" => Self::list_roles(false),
+                "session" => {
+                    let mut names = self.list_sessions();
+                    names.extend(
+                        self.list_autoname_sessions()
+                            .into_iter()
+                            .rev()
+                            .map(|v| format!("_/{v}")),
+                    );
+                    names
+                }
+                "rag" => Self::list_rags(),
+                "agent-data" => list_file_names(Self::agents_data_dir(), ""),
+                _ => vec![],
+            };
+            values = map_completion_values(names);
+            filter = args[1];
         } else if cmd == ".agent" && args.len() >= 2 {
             let dir = Self::agent_data_dir(args[0]).join(SESSIONS_DIR_NAME);
             values = list_file_names(dir, ".yaml")
@@ -1777,13 +2905,11 @@ impl Config {
                 .map(|v| (v, None))
                 .collect();
         } else if cmd == ".starter" && args.len() >= 2 {
-            if let Some(agent) = &self.agent {
-                values = agent
-                    .conversation_staters()
-                    .iter()
-                    .filter_map(|v| v.strip_prefix(line).map(|x| (x.to_string(), None)))
-                    .collect()
-            }
+            values = self
+                .conversation_starters()
+                .iter()
+                .filter_map(|v| v.strip_prefix(line).map(|x| (x.to_string(), None)))
+                .collect()
         };
         values
             .into_iter()
@@ -1794,7 +2920,7 @@ impl Config {
     pub fn last_reply(&self) -> &str {
         self.last_message
             .as_ref()
-            .map(|(_, reply)| reply.as_str())
+            .map(|(_, reply)| reply.text.as_str())
             .unwrap_or_default()
     }
 
@@ -1830,18 +2956,52 @@ impl Config {
         Ok(RenderOptions::new(theme, wrap, self.wrap_code, truecolor))
     }
 
-    pub fn render_prompt_left(&self) -> String {
-        let variables = self.generate_prompt_context();
+    pub fn render_prompt_left(&self, keybinding_mode: &str) -> String {
         let left_prompt = self.left_prompt.as_deref().unwrap_or(LEFT_PROMPT);
+        let variables = self.generate_prompt_context(left_prompt, keybinding_mode);
         render_prompt(left_prompt, &variables)
     }
 
-    pub fn render_prompt_right(&self) -> String {
-        let variables = self.generate_prompt_context();
+    pub fn render_prompt_right(&self, keybinding_mode: &str) -> String {
         let right_prompt = self.right_prompt.as_deref().unwrap_or(RIGHT_PROMPT);
+        let variables = self.generate_prompt_context(right_prompt, keybinding_mode);
         render_prompt(right_prompt, &variables)
     }
 
+    /// With `footer: true`, a dim line about the reply: model, tokens, cost, latency and finish reason
+    pub fn print_footer(&self, input: &Input, output: &ChatCompletionsOutput) {
+        if !*IS_STDOUT_TERMINAL {
+            return;
+        }
+        if let Some(footer) = self.footer_text(input, output) {
+            println!("{}", dimmed_text(&footer));
+        }
+    }
+
+    /// The usage line under a reply, if `footer` is on.
+    pub fn footer_text(&self, input: &Input, output: &ChatCompletionsOutput) -> Option<String> {
+        if self.footer && !output.text.is_empty() {
+            Some(UsageEntry::new(input, output).footer(output))
+        } else {
+            None
+        }
+    }
+
+    /// Print a reply, through `output_pipe` if there is one.
+    pub fn print_reply(&self, text: &str) -> Result<()> {
+        match &self.output_pipe {
+            Some(command) => {
+                let output = run_output_pipe(command, text)?;
+                print!("{output}");
+                if !output.ends_with('\n') {
+                    println!();
+                }
+                Ok(())
+            }
+            None => self.print_markdown(text),
+        }
+    }
+
     pub fn print_markdown(&self, text: &str) -> Result<()> {
         if *IS_STDOUT_TERMINAL {
             let render_options = self.render_options()?;
@@ -1853,8 +3013,14 @@ impl Config {
         Ok(())
     }
 
-    fn generate_prompt_context(&self) -> HashMap<&str, String> {
+    /// The variables of a prompt template, those of the shell only when the template uses them
+    fn generate_prompt_context(
+        &self,
+        template: &str,
+        keybinding_mode: &str,
+    ) -> HashMap<&str, String> {
         let mut output = HashMap::new();
+        output.insert("keybinding_mode", keybinding_mode.to_string());
         let role = self.extract_role();
         output.insert("model", role.model().id());
         output.insert("client_name", role.model().client_name().to_string());
@@ -1899,7 +3065,7 @@ impl Config {
                 output.insert("session_autoname", autoname.to_string());
             }
             output.insert("dirty", session.dirty().to_string());
-            let (tokens, percent) = session.tokens_usage();
+            let (tokens, percent) = session.quick_tokens_usage();
             output.insert("consume_tokens", tokens.to_string());
             output.insert("consume_percent", percent.to_string());
             output.insert("user_messages_len", session.user_messages_len().to_string());
@@ -1910,6 +3076,19 @@ impl Config {
         if let Some(agent) = &self.agent {
             output.insert("agent", agent.name().to_string());
         }
+        if template.contains("cwd") {
+            if let Some(cwd) = display_cwd() {
+                output.insert("cwd", cwd);
+            }
+        }
+        if template.contains("git_branch") {
+            if let Some(branch) = git_branch() {
+                output.insert("git_branch", branch);
+            }
+        }
+        if template.contains("hostname") {
+            output.insert("hostname", hostname().to_string());
+        }
 
         if self.highlight {
             output.insert("color.reset", "\u{1b}[0m".to_string());
@@ -1937,30 +3116,47 @@ impl Config {
     }
 
     pub fn before_chat_completion(&mut self, input: &Input) -> Result<()> {
-        self.last_message = Some((input.clone(), String::new()));
+        if input.regenerate() {
+            if !self.last_reply().is_empty() {
+                self.previous_reply = Some(self.last_reply().to_string());
+            }
+        } else {
+            self.previous_reply = None;
+        }
+        self.last_message = Some((input.clone(), ChatCompletionsOutput::default()));
         Ok(())
     }
 
     pub fn after_chat_completion(
         &mut self,
         input: &Input,
-        output: &str,
+        output: &ChatCompletionsOutput,
         tool_results: &[ToolResult],
     ) -> Result<()> {
-        if self.dry_run || output.is_empty() || !tool_results.is_empty() {
+        if !self.dry_run {
+            let entry = UsageEntry::new(input, output);
+            if let Err(err) = append_usage(&Self::usage_file(), &entry) {
+                warn!("Failed to record the usage, {err}");
+            }
+        }
+        if self.dry_run || output.text.is_empty() || !tool_results.is_empty() {
             self.last_message = None;
             return Ok(());
         }
-        self.last_message = Some((input.clone(), output.to_string()));
-        self.save_message(input, output)?;
+        let mut output = output.clone();
+        if let Some(previous) = input.continue_output() {
+            output.text.drain(..overlap_len(previous, &output.text));
+        }
+        self.last_message = Some((input.clone(), output.clone()));
+        self.save_message(input, &output)?;
         Ok(())
     }
 
-    fn save_message(&mut self, input: &Input, output: &str) -> Result<()> {
+    fn save_message(&mut self, input: &Input, output: &ChatCompletionsOutput) -> Result<()> {
         let mut input = input.clone();
         input.clear_patch();
         if let Some(session) = input.session_mut(&mut self.session) {
-            session.add_message(&input, output)?;
+            session.add_message(&input, output, self.seed)?;
             return Ok(());
         }
 
@@ -1968,9 +3164,10 @@ impl Config {
             return Ok(());
         }
         let mut file = self.open_message_file()?;
-        if output.is_empty() || !self.save {
+        if output.text.is_empty() || !self.save {
             return Ok(());
         }
+        let output = render_citations(&output.text, &output.citations);
         let now = now();
         let summary = input.summary();
         let raw_input = input.raw();
@@ -2138,13 +3335,22 @@ impl Config {
         if let Some(v) = read_env_value::<f64>(&get_env_name("top_p")) {
             self.top_p = v;
         }
+        if let Some(v) = read_env_value::<u64>(&get_env_name("seed")) {
+            self.seed = v;
+        }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("dry_run")) {
             self.dry_run = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("offline")) {
+            self.offline = v;
+        }
         if let Some(Some(v)) = read_env_bool(&get_env_name("stream")) {
             self.stream = v;
         }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("auto_continue")) {
+            self.auto_continue = v;
+        }
         if let Some(Some(v)) = read_env_bool(&get_env_name("save")) {
             self.save = v;
         }
@@ -2159,6 +3365,21 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("wrap")) {
             self.wrap = v;
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("pager")) {
+            self.pager = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("auto_page")) {
+            self.auto_page = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("footer")) {
+            self.footer = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("stream_status")) {
+            self.stream_status = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("output_pipe")) {
+            self.output_pipe = v;
+        }
         if let Some(Some(v)) = read_env_bool(&get_env_name("wrap_code")) {
             self.wrap_code = v;
         }
@@ -2174,6 +3395,75 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("use_tools")) {
             self.use_tools = v;
         }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("tool_policy")) {
+            if let Some(v) = ToolPolicy::parse(&v) {
+                self.tool_policy = v;
+            }
+        }
+
+        if let Some(Some(v)) = read_env_bool(&get_env_name("redact")) {
+            self.redact = v;
+        }
+        if let Ok(v) = env::var(get_env_name("mock")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.mock = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("model_aliases")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.model_aliases = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("default_models")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.default_models = v;
+            }
+        }
+        // AICHAT_MODEL, like `--model`, wins over every default, `model` in the file is only the fallback
+        if env::var(get_env_name("model")).is_ok() {
+            self.default_models.clear();
+        }
+        if let Some(v) = read_env_value::<u64>(&get_env_name("models_refresh_interval")) {
+            self.models_refresh_interval = v;
+        }
+        if let Ok(v) = env::var(get_env_name("redact_rules")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.redact_rules = v;
+            }
+        }
+
+        if let Some(Some(v)) = read_env_bool(&get_env_name("web_search")) {
+            self.web_search = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("web_search_engine")) {
+            self.web_search_engine = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("web_search_url")) {
+            self.web_search_url = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("web_search_api_key")) {
+            self.web_search_api_key = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("fetch_url")) {
+            self.fetch_url = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("memory")) {
+            self.memory = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("code_interpreter")) {
+            self.code_interpreter = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("code_interpreter_timeout")) {
+            self.code_interpreter_timeout = v;
+        }
+        if let Some(v) = read_env_value::<u64>(&get_env_name("code_interpreter_memory_mb")) {
+            self.code_interpreter_memory_mb = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("fs_allowed_dirs")) {
+            self.fs_allowed_dirs = v
+                .map(|v| v.split(',').map(|v| v.trim().to_string()).collect())
+                .unwrap_or_default();
+        }
 
         if let Some(v) = read_env_value::<String>(&get_env_name("prelude")) {
             self.prelude = v;
@@ -2188,9 +3478,20 @@ impl Config {
         if let Some(v) = read_env_bool(&get_env_name("save_session")) {
             self.save_session = v;
         }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("max_auto_sessions")) {
+            self.max_auto_sessions = v;
+        }
         if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_threshold")) {
             self.compress_threshold = v;
         }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("compress_strategy")) {
+            if let Some(v) = CompressStrategy::parse(&v) {
+                self.compress_strategy = v;
+            }
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("compress_preview")) {
+            self.compress_preview = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("summarize_prompt")) {
             self.summarize_prompt = v;
         }
@@ -2223,12 +3524,32 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("rag_template")) {
             self.rag_template = v;
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_injection_guard")) {
+            self.rag_injection_guard = v.and_then(|v| InjectionGuard::parse(&v));
+        }
 
         if let Ok(v) = env::var(get_env_name("document_loaders")) {
             if let Ok(v) = serde_json::from_str(&v) {
                 self.document_loaders = v;
             }
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("disabled_loaders")) {
+            self.disabled_loaders = v
+                .map(|v| v.split(',').map(|v| v.trim().to_string()).collect())
+                .unwrap_or_default();
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("notebook_outputs")) {
+            self.notebook_outputs = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("dedup_attachments")) {
+            self.dedup_attachments = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("screenshot_command")) {
+            self.screenshot_command = v;
+        }
+        if let Some(Some(v)) = read_env_value::<f64>(&get_env_name("attachment_head_ratio")) {
+            self.attachment_head_ratio = v;
+        }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("highlight")) {
             self.highlight = v;
@@ -2252,15 +3573,56 @@ impl Config {
             self.right_prompt = v;
         }
 
+        if let Some(v) = read_env_value::<String>(&get_env_name("listen_command")) {
+            self.listen_command = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("stt_command")) {
+            self.stt_command = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("speak")) {
+            self.speak = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("speak_command")) {
+            self.speak_command = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("speak_voice")) {
+            self.speak_voice = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("realtime_mic_command")) {
+            self.realtime_mic_command = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("realtime_player_command")) {
+            self.realtime_player_command = v;
+        }
+
         if let Some(v) = read_env_value::<String>(&get_env_name("serve_addr")) {
             self.serve_addr = v;
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_log")) {
+            self.serve_log = v.and_then(|v| ServeLogMode::parse(&v));
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_admin_key")) {
+            self.serve_admin_key = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("user_agent")) {
             self.user_agent = v;
         }
         if let Some(Some(v)) = read_env_bool(&get_env_name("save_shell_history")) {
             self.save_shell_history = v;
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("shell_sandbox")) {
+            self.shell_sandbox = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("shell_tools")) {
+            self.shell_tools = v
+                .map(|v| v.split(',').map(|v| v.trim().to_string()).collect())
+                .unwrap_or_default();
+        }
+        if let Ok(v) = env::var(get_env_name("shell_denylist")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.shell_denylist = v;
+            }
+        }
     }
 
     fn load_functions(&mut self) -> Result<()> {
@@ -2268,8 +3630,28 @@ impl Config {
         Ok(())
     }
 
+    fn load_plugins(&mut self) {
+        self.plugins = load_plugins(&Self::plugins_dir());
+        for plugin in self.plugins.clone() {
+            for (client, client_config) in plugin
+                .manifest
+                .clients
+                .iter()
+                .zip(plugin_client_configs(&plugin))
+            {
+                if client_type(self, &client.name).is_none() {
+                    self.clients.push(client_config);
+                }
+            }
+            self.functions.extend(plugin.manifest.tools);
+        }
+    }
+
     fn setup_model(&mut self) -> Result<()> {
-        let mut model_id = self.model_id.clone();
+        let mut model_id = match self.default_models.get(self.working_mode.name()) {
+            Some(v) => v.clone(),
+            None => self.model_id.clone(),
+        };
         if model_id.is_empty() {
             let models = list_models(self, ModelType::Chat);
             if models.is_empty() {
@@ -2295,7 +3677,7 @@ impl Config {
         if let Some("auto") = self.user_agent.as_deref() {
             self.user_agent = Some(format!(
                 "{}/{}",
-                env!("CARGO_CRATE_NAME"),
+                env!("CARGO_PKG_NAME"),
                 env!("CARGO_PKG_VERSION")
             ));
         }
@@ -2321,6 +3703,126 @@ pub fn load_env_file() -> Result<()> {
     Ok(())
 }
 
+/// Where roles are looked up: the role files, `roles.yaml` and the imported roles
+#[derive(Debug, Clone)]
+pub struct RolePaths {
+    pub roles_dir: PathBuf,
+    pub roles_file: PathBuf,
+    pub imported_roles_dir: PathBuf,
+}
+
+impl RolePaths {
+    pub fn current() -> Self {
+        Self {
+            roles_dir: Config::roles_dir(),
+            roles_file: Config::roles_file(),
+            imported_roles_dir: Config::imported_roles_dir(),
+        }
+    }
+
+    /// Roles in the roles dir take precedence, `<namespace>/<role>` falls back to the imported ones.
+    pub fn role_file(&self, name: &str) -> PathBuf {
+        let path = self.roles_dir.join(format!("{name}.md"));
+        if name.contains('/') && !path.exists() {
+            let imported_path = self.imported_roles_dir.join(format!("{name}.md"));
+            if imported_path.exists() {
+                return imported_path;
+            }
+        }
+        path
+    }
+
+    pub fn load_role(&self, name: &str) -> Result<Role> {
+        let path = self.role_file(name);
+        if path.exists() {
+            let content = read_to_string(&path)?;
+            return Ok(Role::new(name, &content));
+        }
+        let names = self.list_roles(false);
+        if let Some(role_name) = Role::match_name(&names, name) {
+            let path = self.role_file(&role_name);
+            if path.exists() {
+                let content = read_to_string(&path)?;
+                return Ok(Role::new(name, &content));
+            }
+            if let Some(role) = self
+                .load_roles_file()
+                .into_iter()
+                .find(|v| v.name() == role_name)
+            {
+                return Ok(Role::new(name, &role.export()));
+            }
+        }
+        Role::builtin(name)
+    }
+
+    /// The roles of `roles.yaml`, the role files of the same name take precedence.
+    pub fn load_roles_file(&self) -> Vec<Role> {
+        let path = &self.roles_file;
+        ROLES_FILE.get_or_load(path, || {
+            let Ok(content) = read_to_string(path) else {
+                return vec![];
+            };
+            serde_yaml::from_str(&content).unwrap_or_else(|err| {
+                warn!("Invalid roles file at '{}', {err}", path.display());
+                vec![]
+            })
+        })
+    }
+
+    pub fn list_roles(&self, with_builtin: bool) -> Vec<String> {
+        let mut names: HashSet<String> = list_file_names(&self.roles_dir, ".md")
+            .into_iter()
+            .collect();
+        names.extend(
+            self.load_roles_file()
+                .into_iter()
+                .map(|v| v.name().to_string()),
+        );
+        names.extend(list_imported_roles(&self.imported_roles_dir));
+        if with_builtin {
+            names.extend(Role::list_builtin_role_names());
+        }
+        let mut names: Vec<_> = names.into_iter().collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// A prompt run by `--daemon` whenever its cron expression matches
+#[derive(Debug, Clone, Deserialize)]
+pub struct Schedule {
+    pub name: String,
+    pub cron: String,
+    pub prompt: String,
+    pub role: Option<String>,
+    pub model: Option<String>,
+    /// Append the replies to this file
+    pub output: Option<String>,
+    /// POST the replies as json to this url
+    pub webhook: Option<String>,
+}
+
+/// How much of the requests and replies the serve request log keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServeLogMode {
+    Metadata,
+    Truncated,
+    Full,
+}
+
+impl ServeLogMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "metadata" => Some(Self::Metadata),
+            "truncated" => Some(Self::Truncated),
+            "full" => Some(Self::Full),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum WorkingMode {
     Cmd,
@@ -2329,6 +3831,13 @@ pub enum WorkingMode {
 }
 
 impl WorkingMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkingMode::Cmd => "cmd",
+            WorkingMode::Repl => "repl",
+            WorkingMode::Serve => "serve",
+        }
+    }
     pub fn is_cmd(&self) -> bool {
         *self == WorkingMode::Cmd
     }
@@ -2415,7 +3924,7 @@ fn create_config_file(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn ensure_parent_exists(path: &Path) -> Result<()> {
+pub fn ensure_parent_exists(path: &Path) -> Result<()> {
     if path.exists() {
         return Ok(());
     }
@@ -2433,6 +3942,51 @@ pub(crate) fn ensure_parent_exists(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// How long to wait between the chunks of a replayed reply
+fn replay_chunk_delay(latency_ms: Option<u64>, chunks: usize, speed: f64) -> Duration {
+    let delay = match latency_ms {
+        Some(latency_ms) => latency_ms as f64 / chunks.max(1) as f64,
+        None => REPLAY_CHUNK_DELAY_MS,
+    };
+    Duration::from_secs_f64((delay / speed).min(REPLAY_MAX_DELAY_MS) / 1000.0)
+}
+
+/// The header and one aligned line per model for the model selector
+fn model_select_options(models: &[Model]) -> (String, Vec<String>) {
+    let rows: Vec<Vec<String>> = models
+        .iter()
+        .map(|model| {
+            let data = model.data();
+            let price = match (data.input_price, data.output_price) {
+                (None, None) => "-".to_string(),
+                (input, output) => format!(
+                    "{}/{}",
+                    format_option_value(&input),
+                    format_option_value(&output)
+                ),
+            };
+            vec![
+                model.client_name().to_string(),
+                model.name().to_string(),
+                format_option_value(&data.max_input_tokens),
+                price,
+            ]
+        })
+        .collect();
+    let table = render_table(&["PROVIDER", "MODEL", "CONTEXT", "PRICE"], &rows);
+    let mut lines = table.lines();
+    let header = lines.next().unwrap_or_default().to_string();
+    (header, lines.map(|v| v.to_string()).collect())
+}
+
+/// Whether `name` stays inside the directory it is joined to, `pack/name` is fine but `../name` isn't.
+fn is_relative_name(name: &str) -> bool {
+    !name.contains('\\')
+        && name
+            .split('/')
+            .all(|v| !v.is_empty() && v != "." && v != ".." && !v.contains(':'))
+}
+
 fn read_env_value<T>(key: &str) -> Option<Option<T>>
 where
     T: std::str::FromStr,
@@ -2491,3 +4045,213 @@ where
     config.write().rag = Some(Arc::new(rag));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_policy_of() {
+        let mut config = Config {
+            tool_policy: ToolPolicy::Confirm,
+            ..Default::default()
+        };
+        config
+            .tool_policies
+            .insert("fs_read".into(), ToolPolicy::Auto);
+        config
+            .tool_policies
+            .insert("execute_command".into(), ToolPolicy::Deny);
+        assert_eq!(config.tool_policy_of("fs_read"), ToolPolicy::Auto);
+        assert_eq!(config.tool_policy_of("fs_write"), ToolPolicy::Confirm);
+        assert_eq!(config.tool_policy_of("execute_command"), ToolPolicy::Deny);
+    }
+
+    #[test]
+    fn test_trust_tool() {
+        let mut config = Config {
+            tool_policy: ToolPolicy::Confirm,
+            ..Default::default()
+        };
+        config
+            .tool_policies
+            .insert("execute_command".into(), ToolPolicy::Deny);
+        config.trust_tool("fs_write").unwrap();
+        assert_eq!(config.tool_policy_of("fs_write"), ToolPolicy::Auto);
+        assert!(config.trust_tool("execute_command").is_err());
+        assert_eq!(config.tool_policy_of("execute_command"), ToolPolicy::Deny);
+
+        // Trust doesn't carry over to another role
+        config
+            .use_role_obj(Role::new("coder", "You are a coder"))
+            .unwrap();
+        assert_eq!(config.tool_policy_of("fs_write"), ToolPolicy::Confirm);
+        config.trust_tool("fs_write").unwrap();
+        config.exit_role().unwrap();
+        assert_eq!(config.tool_policy_of("fs_write"), ToolPolicy::Confirm);
+    }
+
+    #[test]
+    fn test_replay_chunk_delay() {
+        let ms = |v: Duration| v.as_millis();
+        // The recorded latency spread over the chunks
+        assert_eq!(ms(replay_chunk_delay(Some(2000), 100, 1.0)), 20);
+        assert_eq!(ms(replay_chunk_delay(Some(2000), 100, 2.0)), 10);
+        assert_eq!(ms(replay_chunk_delay(Some(500), 0, 1.0)), 500);
+        assert_eq!(ms(replay_chunk_delay(None, 100, 1.0)), 20);
+        assert_eq!(ms(replay_chunk_delay(None, 100, 0.5)), 40);
+        // Long pauses are capped
+        assert_eq!(ms(replay_chunk_delay(Some(60000), 2, 1.0)), 1000);
+    }
+
+    #[test]
+    fn test_model_select_options() {
+        let mut gpt = Model::new("openai", "gpt-4o");
+        gpt.data_mut().max_input_tokens = Some(128000);
+        gpt.data_mut().input_price = Some(2.5);
+        gpt.data_mut().output_price = Some(10.0);
+        let mut local = Model::new("ollama", "llama3.2");
+        local.data_mut().output_price = Some(0.0);
+        let bare = Model::new("ollama", "qwen");
+        let (header, options) = model_select_options(&[gpt, local, bare]);
+        assert_eq!(header, "PROVIDER  MODEL     CONTEXT  PRICE");
+        assert_eq!(
+            options,
+            vec![
+                "openai    gpt-4o    128000   2.5/10",
+                "ollama    llama3.2  -        -/0",
+                "ollama    qwen      -        -",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_roles_file_merge() {
+        let dir = temp_file("-roles-", "");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("coder.md"),
+            "---\ntemperature: 0.2\n---\nYou are a coder from a file",
+        )
+        .unwrap();
+        let roles_file = dir.join("roles.yaml");
+        std::fs::write(
+            &roles_file,
+            "- name: coder\n  prompt: You are a coder from roles.yaml\n- name: writer\n  prompt: You are a writer\n  temperature: 0.7\n",
+        )
+        .unwrap();
+        let paths = RolePaths {
+            roles_dir: dir.clone(),
+            roles_file,
+            imported_roles_dir: dir.join("roles.d"),
+        };
+        let names = paths.list_roles(false);
+        let coder = paths.load_role("coder");
+        let writer = paths.load_role("writer");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names, ["coder", "writer"]);
+        // The role file wins over the roles.yaml entry of the same name
+        let coder = coder.unwrap();
+        assert_eq!(coder.prompt(), "You are a coder from a file");
+        assert_eq!(coder.temperature(), Some(0.2));
+        let writer = writer.unwrap();
+        assert_eq!(writer.prompt(), "You are a writer");
+        assert_eq!(writer.temperature(), Some(0.7));
+    }
+
+    #[test]
+    fn test_starters_banner() {
+        let mut config = Config::default();
+        assert_eq!(config.starters_banner(), None);
+        config
+            .use_role_obj(Role::new("reviewer", "---\ngreeting: Hi\n---\nYou review"))
+            .unwrap();
+        assert_eq!(config.starters_banner().unwrap(), "Hi");
+        let content =
+            "---\nconversation_starters:\n- Review my diff\n- Explain this error\n---\nYou review";
+        config.use_role_obj(Role::new("reviewer", content)).unwrap();
+        assert_eq!(
+            config.starters_banner().unwrap(),
+            "Conversation starters (`.starter <n>` to use one):\n1. Review my diff\n2. Explain this error"
+        );
+        assert_eq!(config.resolve_starter("2"), "Explain this error");
+        assert_eq!(config.resolve_starter("3"), "3");
+        assert_eq!(config.resolve_starter("0"), "0");
+        assert_eq!(config.resolve_starter("Review it"), "Review it");
+    }
+
+    #[test]
+    fn test_is_relative_name() {
+        assert!(is_relative_name("coder"));
+        assert!(is_relative_name("pack/coder"));
+        assert!(!is_relative_name(""));
+        assert!(!is_relative_name("../config"));
+        assert!(!is_relative_name("pack/../../config"));
+        assert!(!is_relative_name("/etc/passwd"));
+        assert!(!is_relative_name("pack//coder"));
+        assert!(!is_relative_name("..\\config"));
+        assert!(!is_relative_name("C:config"));
+    }
+
+    #[test]
+    fn test_setup_model() {
+        let yaml = r#"
+default_models:
+  repl: openai:gpt-4o
+  shell: openai:gpt-4o-mini
+clients:
+- type: openai
+  api_key: sk-test
+"#;
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.working_mode = WorkingMode::Repl;
+        config.setup_model().unwrap();
+        assert_eq!(config.model_id, "openai:gpt-4o");
+        let role = config.retrieve_role(SHELL_ROLE).unwrap();
+        assert_eq!(role.model().id(), "openai:gpt-4o-mini");
+
+        // `model` is only the fallback of the working modes without a default
+        let yaml = format!("model: openai:o3-mini\n{yaml}");
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.working_mode = WorkingMode::Repl;
+        config.setup_model().unwrap();
+        assert_eq!(config.model_id, "openai:gpt-4o");
+        let role = config.retrieve_role(SHELL_ROLE).unwrap();
+        assert_eq!(role.model().id(), "openai:gpt-4o-mini");
+
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.working_mode = WorkingMode::Cmd;
+        config.setup_model().unwrap();
+        assert_eq!(config.model_id, "openai:o3-mini");
+        let role = config.retrieve_role(SHELL_ROLE).unwrap();
+        assert_eq!(role.model().id(), "openai:gpt-4o-mini");
+    }
+
+    #[tokio::test]
+    async fn test_recap_session() {
+        let config = Config {
+            dry_run: true,
+            model: Model::new("openai", "gpt-4o-mini"),
+            clients: vec![ClientConfig::default()],
+            ..Default::default()
+        };
+        let config: GlobalConfig = Arc::new(RwLock::new(config));
+        let err = Config::recap_session(&config).await.unwrap_err();
+        assert_eq!(err.to_string(), "No session");
+        config.write().session = Some(Session::default());
+        let err = Config::recap_session(&config).await.unwrap_err();
+        assert_eq!(err.to_string(), "No messages to recap");
+
+        let session =
+            "model: openai:gpt-4o-mini\nmessages:\n- role: user\n  content: hi\n- role: assistant\n  content: hello\n";
+        config.write().session = Some(serde_yaml::from_str(session).unwrap());
+        // The dry run echoes the recap prompt and the transcript
+        let recap = Config::recap_session(&config).await.unwrap();
+        assert!(recap.contains("Recap the conversation transcript"));
+        assert!(recap.contains("user: hi\n\nassistant: hello"));
+        // The session is left as it is
+        let session = config.read().session.clone().unwrap();
+        assert_eq!(session.transcript(), "user: hi\n\nassistant: hello");
+    }
+}
@@ -1,17 +1,19 @@
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole, Model};
+use crate::client::{Message, MessageContent, MessageRole, Model, ResponseFormat};
 
 use anyhow::Result;
 use fancy_regex::Regex;
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 
 pub const SHELL_ROLE: &str = "%shell%";
 pub const EXPLAIN_SHELL_ROLE: &str = "%explain-shell%";
 pub const CODE_ROLE: &str = "%code%";
 pub const CREATE_TITLE_ROLE: &str = "%create-title%";
+pub const RECAP_ROLE: &str = "%recap%";
+pub const TRANSLATE_ROLE: &str = "%translate%";
 
 pub const INPUT_PLACEHOLDER: &str = "__INPUT__";
 
@@ -52,11 +54,37 @@ pub struct Role {
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    greeting: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    conversation_starters: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    voice: Option<String>,
+    #[serde(flatten)]
+    params: RoleParams,
 
     #[serde(skip)]
     model: Model,
 }
 
+/// Request parameters applied while the role is active
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct RoleParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<isize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Merged into the request body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<Value>,
+}
+
 impl Role {
     pub fn new(name: &str, content: &str) -> Self {
         let mut metadata = "";
@@ -83,6 +111,30 @@ impl Role {
                             "temperature" => role.temperature = value.as_f64(),
                             "top_p" => role.top_p = value.as_f64(),
                             "use_tools" => role.use_tools = value.as_str().map(|v| v.to_string()),
+                            "description" => {
+                                role.description = value.as_str().map(|v| v.to_string())
+                            }
+                            "greeting" => role.greeting = value.as_str().map(|v| v.to_string()),
+                            "conversation_starters" => {
+                                role.conversation_starters =
+                                    serde_json::from_value(value.clone()).unwrap_or_default()
+                            }
+                            "voice" => role.voice = value.as_str().map(|v| v.to_string()),
+                            "max_output_tokens" => {
+                                role.params.max_output_tokens = value.as_i64().map(|v| v as isize)
+                            }
+                            "stop" => {
+                                role.params.stop = match value {
+                                    Value::String(v) => Some(vec![v.clone()]),
+                                    _ => serde_json::from_value(value.clone()).ok(),
+                                }
+                            }
+                            "response_format" => {
+                                role.params.response_format =
+                                    value.as_str().and_then(ResponseFormat::parse)
+                            }
+                            "seed" => role.params.seed = value.as_u64(),
+                            "patch" => role.params.patch = Some(value.clone()),
                             _ => (),
                         }
                     }
@@ -93,7 +145,9 @@ impl Role {
     }
 
     pub fn builtin(name: &str) -> Result<Self> {
-        let content = RolesAsset::get(&format!("{name}.md"))
+        let file_name = Self::match_name(&Self::list_builtin_role_names(), name)
+            .unwrap_or_else(|| name.to_string());
+        let content = RolesAsset::get(&format!("{file_name}.md"))
             .ok_or_else(|| anyhow!("Unknown role `{name}`"))?;
         let content = unsafe { std::str::from_utf8_unchecked(&content.data) };
         Ok(Role::new(name, content))
@@ -146,6 +200,41 @@ impl Role {
         if let Some(use_tools) = self.use_tools() {
             metadata.push(format!("use_tools: {}", use_tools));
         }
+        if let Some(description) = &self.description {
+            metadata.push(format!("description: {}", description));
+        }
+        if let Some(greeting) = &self.greeting {
+            metadata.push(format!("greeting: {}", json!(greeting)));
+        }
+        if !self.conversation_starters.is_empty() {
+            let starters = serde_yaml::to_string(&self.conversation_starters).unwrap_or_default();
+            metadata.push(format!("conversation_starters:\n{}", starters.trim_end()));
+        }
+        if let Some(voice) = &self.voice {
+            metadata.push(format!("voice: {}", voice));
+        }
+        let RoleParams {
+            max_output_tokens,
+            stop,
+            response_format,
+            seed,
+            patch,
+        } = &self.params;
+        if let Some(max_output_tokens) = max_output_tokens {
+            metadata.push(format!("max_output_tokens: {}", max_output_tokens));
+        }
+        if let Some(stop) = stop {
+            metadata.push(format!("stop: {}", json!(stop)));
+        }
+        if let Some(response_format) = response_format {
+            metadata.push(format!("response_format: {}", response_format.as_str()));
+        }
+        if let Some(seed) = seed {
+            metadata.push(format!("seed: {}", seed));
+        }
+        if let Some(patch) = patch {
+            metadata.push(format!("patch: {}", patch));
+        }
         if metadata.is_empty() {
             format!("{}\n", self.prompt)
         } else if self.prompt.is_empty() {
@@ -221,6 +310,44 @@ impl Role {
         &self.prompt
     }
 
+    pub fn greeting(&self) -> Option<&str> {
+        self.greeting.as_deref()
+    }
+
+    pub fn voice(&self) -> Option<&str> {
+        self.voice.as_deref()
+    }
+
+    pub fn conversation_starters(&self) -> &[String] {
+        &self.conversation_starters
+    }
+
+    pub fn params(&self) -> &RoleParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: RoleParams) {
+        self.params = params;
+        self.apply_params();
+    }
+
+    fn apply_params(&mut self) {
+        if let Some(max_output_tokens) = self.params.max_output_tokens {
+            self.model.set_max_tokens(Some(max_output_tokens), true);
+        }
+        if self.params.patch.is_some() {
+            self.model.set_patch(self.params.patch.clone());
+        }
+    }
+
+    /// The `description` metadata, falling back to the first line of the prompt
+    pub fn description(&self) -> String {
+        match &self.description {
+            Some(v) => v.clone(),
+            None => self.prompt.lines().next().unwrap_or_default().to_string(),
+        }
+    }
+
     pub fn is_empty_prompt(&self) -> bool {
         self.prompt.is_empty()
     }
@@ -253,14 +380,14 @@ impl Role {
             if !system.is_empty() {
                 messages.push(Message::new(
                     MessageRole::System,
-                    MessageContent::Text(system.to_string()),
+                    MessageContent::Text(system.into()),
                 ));
             }
             if !cases.is_empty() {
                 messages.extend(cases.into_iter().flat_map(|(i, o)| {
                     vec![
-                        Message::new(MessageRole::User, MessageContent::Text(i.to_string())),
-                        Message::new(MessageRole::Assistant, MessageContent::Text(o.to_string())),
+                        Message::new(MessageRole::User, MessageContent::Text(i.into())),
+                        Message::new(MessageRole::Assistant, MessageContent::Text(o.into())),
                     ]
                 }));
             }
@@ -307,6 +434,7 @@ impl RoleLike for Role {
             self.model_id = Some(model.id().to_string());
         }
         self.model = model.clone();
+        self.apply_params();
     }
 
     fn set_temperature(&mut self, value: Option<f64>) {
@@ -394,6 +522,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_conversation_starters() {
+        let content = "---\ngreeting: \"Hi: what shall we review?\"\nconversation_starters:\n- Review my diff\n- Explain this error\n---\nYou are a reviewer";
+        let role = Role::new("reviewer", content);
+        assert_eq!(role.greeting(), Some("Hi: what shall we review?"));
+        assert_eq!(
+            role.conversation_starters(),
+            ["Review my diff", "Explain this error"]
+        );
+        assert_eq!(role.prompt(), "You are a reviewer");
+        // Survives an export, as `.save role` does
+        let role = Role::new("reviewer", &role.export());
+        assert_eq!(role.greeting(), Some("Hi: what shall we review?"));
+        assert_eq!(
+            role.conversation_starters(),
+            ["Review my diff", "Explain this error"]
+        );
+    }
+
     #[test]
     fn test_match_name() {
         let names = vec![
@@ -462,4 +609,21 @@ Input 1
 "#;
         assert_eq!(parse_structure_prompt(prompt), (prompt, vec![]));
     }
+
+    #[test]
+    fn test_role_params() {
+        let content = "---\nmax_output_tokens: 100\nstop: END\nresponse_format: json\nseed: 42\npatch: {\"seed\": 1}\n---\nReply in json";
+        let role = Role::new("js", content);
+        assert_eq!(
+            role.params(),
+            &RoleParams {
+                max_output_tokens: Some(100),
+                stop: Some(vec!["END".into()]),
+                response_format: Some(ResponseFormat::Json),
+                seed: Some(42),
+                patch: Some(json!({ "seed": 1 })),
+            }
+        );
+        assert_eq!(Role::new("js", &role.export()).params(), role.params());
+    }
 }
@@ -0,0 +1,365 @@
+use super::*;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const SOURCE_FILE_NAME: &str = ".source.yaml";
+const ROLES_FILE_NAMES: [&str; 2] = ["roles.yaml", "roles.yml"];
+const SKIP_FILE_NAMES: [&str; 3] = ["README.md", "CHANGELOG.md", "LICENSE.md"];
+
+/// Where the roles of a namespace came from, with the hashes of the imported files to tell
+/// upstream updates from local edits.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct RoleSource {
+    source: String,
+    imported_at: String,
+    #[serde(default)]
+    roles: IndexMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct RolesImport {
+    pub namespace: String,
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub removed: Vec<String>,
+    pub conflicts: Vec<String>,
+}
+
+/// Import the roles of a roles.yaml, a role file, a directory or a git repo into
+/// `roles.d/<namespace>/`, where they are available as `<namespace>/<role>`.
+pub async fn import_roles(source: &str) -> Result<RolesImport> {
+    let namespace = role_namespace(source)?;
+    let files = fetch_role_files(source).await?;
+    if files.is_empty() {
+        bail!("No roles found in '{source}'");
+    }
+
+    let dir = Config::imported_roles_dir().join(&namespace);
+    let source_path = dir.join(SOURCE_FILE_NAME);
+    let mut role_source = match fs::read_to_string(&source_path) {
+        Ok(content) => serde_yaml::from_str::<RoleSource>(&content)
+            .with_context(|| format!("Invalid '{}'", source_path.display()))?,
+        Err(_) => RoleSource::default(),
+    };
+    if !role_source.source.is_empty() && role_source.source != source {
+        bail!(
+            "The namespace '{namespace}' is already imported from '{}'",
+            role_source.source
+        );
+    }
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+
+    let local_names = Config::list_roles(true);
+    let mut report = RolesImport {
+        namespace: namespace.clone(),
+        ..Default::default()
+    };
+    let mut roles = IndexMap::new();
+    for (name, content) in &files {
+        let full_name = format!("{namespace}/{name}");
+        let path = dir.join(format!("{name}.md"));
+        let hash = sha256(content);
+        let recorded = role_source.roles.get(name);
+        let current = fs::read_to_string(&path).ok().map(|v| sha256(&v));
+        match (current, recorded) {
+            (Some(current), Some(recorded)) if &current != recorded => {
+                report
+                    .conflicts
+                    .push(format!("{full_name} has local changes, kept them"));
+                roles.insert(name.clone(), recorded.clone());
+                continue;
+            }
+            (Some(_), Some(recorded)) if recorded == &hash => {
+                report.unchanged.push(full_name.clone());
+            }
+            (Some(_), _) => report.updated.push(full_name.clone()),
+            (None, _) => report.added.push(full_name.clone()),
+        }
+        if local_names.contains(name) {
+            report.conflicts.push(format!(
+                "{full_name} has the same name as the role '{name}', which still takes precedence"
+            ));
+        }
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write '{}'", path.display()))?;
+        roles.insert(name.clone(), hash);
+    }
+    for (name, recorded) in &role_source.roles {
+        if roles.contains_key(name) {
+            continue;
+        }
+        let path = dir.join(format!("{name}.md"));
+        let full_name = format!("{namespace}/{name}");
+        match fs::read_to_string(&path) {
+            Ok(content) if &sha256(&content) != recorded => {
+                report.conflicts.push(format!(
+                    "{full_name} was removed upstream but has local changes, kept it"
+                ));
+            }
+            _ => {
+                let _ = fs::remove_file(&path);
+                report.removed.push(full_name);
+            }
+        }
+    }
+
+    role_source.source = source.to_string();
+    role_source.imported_at = now();
+    role_source.roles = roles;
+    let content = serde_yaml::to_string(&role_source)?;
+    fs::write(&source_path, content)
+        .with_context(|| format!("Failed to write '{}'", source_path.display()))?;
+    Ok(report)
+}
+
+/// The imported role names, `<namespace>/<role>`.
+pub fn list_imported_roles(dir: &Path) -> Vec<String> {
+    let mut names = vec![];
+    let Ok(rd) = fs::read_dir(dir) else {
+        return names;
+    };
+    for entry in rd.flatten() {
+        let namespace = entry.file_name().to_string_lossy().to_string();
+        let Ok(rd) = fs::read_dir(entry.path()) else {
+            continue;
+        };
+        for entry in rd.flatten() {
+            if let Some(name) = entry
+                .file_name()
+                .to_str()
+                .and_then(|v| v.strip_suffix(".md"))
+            {
+                names.push(format!("{namespace}/{name}"));
+            }
+        }
+    }
+    names
+}
+
+async fn fetch_role_files(source: &str) -> Result<Vec<(String, String)>> {
+    if is_git_source(source) {
+        if !Path::new(source).exists() {
+            check_offline(source)?;
+        }
+        let dir = temp_file("-roles-", "");
+        let output = Command::new("git")
+            .args(["clone", "--depth", "1", "--quiet", "--", source])
+            .arg(&dir)
+            .output()
+            .with_context(|| "Failed to run git")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to clone '{source}', {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let files = read_role_dir(&dir, false);
+        let _ = fs::remove_dir_all(&dir);
+        return files;
+    }
+    if is_url(source) {
+        let content = fetch_text(source).await?;
+        return parse_role_file(url_file_name(source), &content);
+    }
+    let path = Path::new(source);
+    if path.is_dir() {
+        read_role_dir(path, true)
+    } else {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        let file_name = path
+            .file_name()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default();
+        parse_role_file(file_name, &content)
+    }
+}
+
+/// A `roles.yaml` at the top, plus the markdown roles in `roles/` or else at the top.
+/// Unless `explicit`, as for git repos, markdown files at the top need a front matter to count as roles.
+fn read_role_dir(dir: &Path, explicit: bool) -> Result<Vec<(String, String)>> {
+    let mut files = vec![];
+    for file_name in ROLES_FILE_NAMES {
+        let path = dir.join(file_name);
+        if let Ok(content) = fs::read_to_string(&path) {
+            files.extend(parse_role_file(file_name, &content)?);
+        }
+    }
+    let roles_dir = dir.join("roles");
+    let (roles_dir, need_front_matter) = if roles_dir.is_dir() {
+        (roles_dir, false)
+    } else {
+        (dir.to_path_buf(), !explicit)
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(&roles_dir)
+        .with_context(|| format!("Failed to read '{}'", roles_dir.display()))?
+        .flatten()
+        .map(|v| v.path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let Some(file_name) = path.file_name().and_then(|v| v.to_str()) else {
+            continue;
+        };
+        if !file_name.ends_with(".md") || SKIP_FILE_NAMES.contains(&file_name) {
+            continue;
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        if need_front_matter && !content.starts_with("---") {
+            continue;
+        }
+        files.extend(parse_role_file(file_name, &content)?);
+    }
+    Ok(files)
+}
+
+fn parse_role_file(file_name: &str, content: &str) -> Result<Vec<(String, String)>> {
+    let files = if let Some(name) = file_name.strip_suffix(".md") {
+        vec![(name.to_string(), content.to_string())]
+    } else if file_name.ends_with(".yaml") || file_name.ends_with(".yml") {
+        let roles: Vec<Role> = serde_yaml::from_str(content)
+            .with_context(|| format!("Invalid roles file '{file_name}'"))?;
+        roles
+            .iter()
+            .map(|role| (role.name().to_string(), role.export()))
+            .collect()
+    } else {
+        bail!("Unsupported roles file '{file_name}', expect a .yaml or .md file")
+    };
+    for (name, _) in &files {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            bail!("Invalid role name '{name}' in '{file_name}'");
+        }
+    }
+    Ok(files)
+}
+
+fn is_git_source(source: &str) -> bool {
+    if source.starts_with("git@") || source.ends_with(".git") {
+        return true;
+    }
+    [
+        "https://github.com/",
+        "https://gitlab.com/",
+        "https://codeberg.org/",
+    ]
+    .iter()
+    .any(|prefix| {
+        source
+            .strip_prefix(prefix)
+            .map(|v| v.trim_end_matches('/').split('/').count() == 2)
+            .unwrap_or_default()
+    })
+}
+
+fn url_file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    path.rsplit('/').next().unwrap_or_default()
+}
+
+/// The repo name for git sources, otherwise the directory holding the roles.
+fn role_namespace(source: &str) -> Result<String> {
+    let source = source.split(['?', '#']).next().unwrap_or_default();
+    let segments: Vec<&str> = source
+        .trim_end_matches('/')
+        .split(['/', '\\', ':'])
+        .filter(|v| !v.is_empty())
+        .collect();
+    let name = if is_git_source(source) {
+        segments.last().map(|v| v.trim_end_matches(".git"))
+    } else if let Some(rest) = source.strip_prefix("https://raw.githubusercontent.com/") {
+        rest.split('/').nth(1)
+    } else if Path::new(source).is_dir() {
+        segments.last().copied()
+    } else {
+        segments.iter().rev().nth(1).copied()
+    };
+    let name: String = name
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('-');
+    if name.is_empty() || name == "." {
+        bail!("Unable to name the roles of '{source}'");
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_namespace() {
+        assert_eq!(
+            role_namespace("https://github.com/acme/prompts").unwrap(),
+            "prompts"
+        );
+        assert_eq!(
+            role_namespace("git@github.com:acme/My.Prompts.git").unwrap(),
+            "my-prompts"
+        );
+        assert_eq!(
+            role_namespace("https://raw.githubusercontent.com/acme/prompts/main/roles.yaml")
+                .unwrap(),
+            "prompts"
+        );
+        assert_eq!(
+            role_namespace("https://example.com/team/roles.yaml").unwrap(),
+            "team"
+        );
+        assert!(!is_git_source(
+            "https://github.com/acme/prompts/blob/main/a.md"
+        ));
+    }
+
+    #[test]
+    fn test_parse_role_file() {
+        let content = "- name: critic\n  prompt: Be harsh\n  temperature: 0.2\n";
+        let files = parse_role_file("roles.yaml", content).unwrap();
+        assert_eq!(files[0].0, "critic");
+        assert_eq!(files[0].1, "---\ntemperature: 0.2\n---\n\nBe harsh\n");
+        assert!(parse_role_file("roles.yaml", "- name: ../x\n  prompt: y\n").is_err());
+    }
+
+    #[test]
+    fn test_read_role_dir() {
+        let dir = temp_file("-roles-test-", "");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("README.md"), "# Prompts").unwrap();
+        fs::write(dir.join("NOTES.md"), "Some notes").unwrap();
+        fs::write(
+            dir.join("critic.md"),
+            "---\ntemperature: 0.2\n---\nBe harsh",
+        )
+        .unwrap();
+        let names = |explicit| -> Vec<String> {
+            read_role_dir(&dir, explicit)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+        assert_eq!(names(false), vec!["critic"]);
+        assert_eq!(names(true), vec!["NOTES", "critic"]);
+        fs::create_dir_all(dir.join("roles")).unwrap();
+        fs::write(dir.join("roles").join("poet.md"), "Write poems").unwrap();
+        assert_eq!(names(false), vec!["poet"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
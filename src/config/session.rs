@@ -1,7 +1,10 @@
 use super::input::*;
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole};
+use crate::client::{
+    render_citations, ChatCompletionsOutput, Citation, Message, MessageContent, MessageMetadata,
+    MessageRole,
+};
 use crate::render::MarkdownRender;
 
 use anyhow::{bail, Context, Result};
@@ -10,15 +13,27 @@ use inquire::{validator::Validation, Confirm, Text};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
-use std::fs::{read_to_string, write};
+use std::fs::{read_dir, read_to_string, write};
 use std::path::Path;
 
+/// The session file format version, bump it together with a new entry in `SESSION_MIGRATIONS`
+pub const SESSION_VERSION: u64 = 1;
+
+/// Migrations on the raw yaml, the `i`-th one upgrades version `i` to `i + 1`
+const SESSION_MIGRATIONS: [fn(&mut serde_yaml::Mapping) -> Result<()>; SESSION_VERSION as usize] = [
+    // v0 -> v1: per-message `citations` and `metadata` are optional, nothing to rewrite
+    |_| Ok(()),
+];
+
 lazy_static::lazy_static! {
-    static ref RE_AUTONAME_PREFIX: Regex = Regex::new(r"\d{8}T\d{6}-").unwrap();
+    static ref RE_AUTONAME_PREFIX: Regex =
+        Regex::new(r"^(?:\d{8}T\d{6}|\d{4}-\d{2}-\d{2}-\d{4}(?:_\d+)?)-").unwrap();
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Session {
+    #[serde(default)]
+    version: u64,
     #[serde(rename(serialize = "model", deserialize = "model"))]
     model_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -44,11 +59,15 @@ pub struct Session {
     messages: Vec<Message>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     data_urls: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    checkpoints: IndexMap<String, Checkpoint>,
 
     #[serde(skip)]
     model: Model,
     #[serde(skip)]
     role_prompt: String,
+    #[serde(default, flatten)]
+    role_params: RoleParams,
     #[serde(skip)]
     name: String,
     #[serde(skip)]
@@ -60,13 +79,19 @@ pub struct Session {
     #[serde(skip)]
     compressing: bool,
     #[serde(skip)]
+    compress_until: usize,
+    #[serde(skip)]
     autoname: Option<AutoName>,
+    /// Embeddings of the exchanges searched by `.find`, by the hash of the model and the text
+    #[serde(skip)]
+    embeddings: HashMap<String, Vec<f32>>,
 }
 
 impl Session {
     pub fn new(config: &Config, name: &str) -> Self {
         let role = config.extract_role();
         let mut session = Self {
+            version: SESSION_VERSION,
             name: name.to_string(),
             save_session: config.save_session,
             ..Default::default()
@@ -79,16 +104,20 @@ impl Session {
     pub fn load(config: &Config, name: &str, path: &Path) -> Result<Self> {
         let content = read_to_string(path)
             .with_context(|| format!("Failed to load session {} at {}", name, path.display()))?;
-        let mut session: Self =
+        let mut value: serde_yaml::Value =
             serde_yaml::from_str(&content).with_context(|| format!("Invalid session {}", name))?;
+        let old_version = migrate_session(&mut value)
+            .with_context(|| format!("Failed to migrate session {}", name))?;
+        let mut session: Self =
+            serde_yaml::from_value(value).with_context(|| format!("Invalid session {}", name))?;
 
         session.model = Model::retrieve_model(config, &session.model_id, ModelType::Chat)?;
 
         if let Some(autoname) = name.strip_prefix("_/") {
             session.name = TEMP_SESSION_NAME.to_string();
             session.path = None;
-            if let Ok(true) = RE_AUTONAME_PREFIX.is_match(autoname) {
-                session.autoname = Some(AutoName::new(autoname[16..].to_string()));
+            if let Ok(Some(m)) = RE_AUTONAME_PREFIX.find(autoname) {
+                session.autoname = Some(AutoName::new(autoname[m.end()..].to_string()));
             }
         } else {
             session.name = name.to_string();
@@ -98,9 +127,23 @@ impl Session {
         if let Some(role_name) = &session.role_name {
             if let Ok(role) = config.retrieve_role(role_name) {
                 session.role_prompt = role.prompt().to_string();
+                // Sessions saved before the params were persisted
+                if session.role_params == RoleParams::default() {
+                    session.role_params = role.params().clone();
+                }
             }
         }
 
+        if let Some(old_version) = old_version {
+            let backup_path = format!("{}.v{old_version}.bak", path.display());
+            write(&backup_path, &content)
+                .with_context(|| format!("Failed to backup session to '{backup_path}'"))?;
+            let content = serde_yaml::to_string(&session)
+                .with_context(|| format!("Failed to serde session '{}'", name))?;
+            write(path, content)
+                .with_context(|| format!("Failed to write session to '{}'", path.display()))?;
+        }
+
         Ok(session)
     }
 
@@ -124,14 +167,59 @@ impl Session {
         self.save_session
     }
 
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
     pub fn tokens(&self) -> usize {
         self.model().total_tokens(&self.messages)
     }
 
+    pub fn quick_tokens(&self) -> usize {
+        self.model().quick_total_tokens(&self.messages)
+    }
+
     pub fn has_user_messages(&self) -> bool {
         self.messages.iter().any(|v| v.role.is_user())
     }
 
+    /// The contents of the file in its last full copy attached to the messages
+    pub fn attachment_contents(&self, path: &str) -> Option<String> {
+        self.messages
+            .iter()
+            .rev()
+            .filter(|v| v.role.is_user())
+            .find_map(|v| {
+                let text = v.content.to_text();
+                parse_attachments(&text)
+                    .into_iter()
+                    .rev()
+                    .find(|(v, contents)| *v == path && !is_dedup_marker(contents))
+                    .map(|(_, contents)| contents.to_string())
+            })
+    }
+
+    /// The messages as `user: ...` paragraphs, with the system prompt only when it holds
+    /// the summary of compressed messages
+    pub fn transcript(&self) -> String {
+        let compressed = !self.compressed_messages.is_empty();
+        self.messages
+            .iter()
+            .filter(|v| compressed || !v.role.is_system())
+            .filter_map(|v| {
+                let role = match v.role {
+                    MessageRole::System => "system",
+                    MessageRole::Assistant => "assistant",
+                    MessageRole::User => "user",
+                    MessageRole::Tool => return None,
+                };
+                let text = v.content.to_text();
+                (!text.trim().is_empty()).then(|| format!("{role}: {}", text.trim()))
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
     pub fn user_messages_len(&self) -> usize {
         self.messages.iter().filter(|v| v.role.is_user()).count()
     }
@@ -216,7 +304,7 @@ impl Session {
         lines.push(String::new());
 
         if !self.is_empty() {
-            let resolve_url_fn = |url: &str| resolve_data_url(&self.data_urls, url.to_string());
+            let resolve_url_fn = |url: &str| resolve_data_url(&self.data_urls, url);
 
             for message in &self.messages {
                 match message.role {
@@ -228,7 +316,7 @@ impl Session {
                     }
                     MessageRole::Assistant => {
                         if let MessageContent::Text(text) = &message.content {
-                            lines.push(render.render(text));
+                            lines.push(render.render(&render_citations(text, &message.citations)));
                         }
                         lines.push("".into());
                     }
@@ -249,8 +337,40 @@ impl Session {
         Ok(lines.join("\n"))
     }
 
+    /// The user inputs and replies in order, each reply with its original latency.
+    pub fn replay_turns(&self) -> Vec<(MessageRole, String, Option<u64>)> {
+        let resolve_url_fn = |url: &str| resolve_data_url(&self.data_urls, url);
+        self.messages
+            .iter()
+            .filter_map(|message| match message.role {
+                MessageRole::User => Some((
+                    MessageRole::User,
+                    message.content.render_input(resolve_url_fn, &None),
+                    None,
+                )),
+                MessageRole::Assistant => match &message.content {
+                    MessageContent::Text(text) => Some((
+                        MessageRole::Assistant,
+                        render_citations(text, &message.citations),
+                        message.metadata.as_ref().and_then(|v| v.latency_ms),
+                    )),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
     pub fn tokens_usage(&self) -> (usize, f32) {
-        let tokens = self.tokens();
+        self.usage_percent(self.tokens())
+    }
+
+    /// `tokens_usage` from the quick estimate, cheap enough for every prompt
+    pub fn quick_tokens_usage(&self) -> (usize, f32) {
+        self.usage_percent(self.quick_tokens())
+    }
+
+    fn usage_percent(&self, tokens: usize) -> (usize, f32) {
         let max_input_tokens = self.model().max_input_tokens().unwrap_or_default();
         let percent = if max_input_tokens == 0 {
             0.0
@@ -269,12 +389,14 @@ impl Session {
         self.model = role.model().clone();
         self.role_name = convert_option_string(role.name());
         self.role_prompt = role.prompt().to_string();
+        self.role_params = role.params().clone();
         self.dirty = true;
     }
 
     pub fn clear_role(&mut self) {
         self.role_name = None;
         self.role_prompt.clear();
+        self.role_params = RoleParams::default();
     }
 
     pub fn sync_agent(&mut self, agent: &Agent) {
@@ -315,7 +437,7 @@ impl Session {
             return false;
         }
         let threshold = self.compress_threshold.unwrap_or(global_compress_threshold);
-        if threshold < 1 {
+        if threshold < 1 || is_clearly_within(self.quick_tokens(), threshold) {
             return false;
         }
         self.tokens() > threshold
@@ -329,24 +451,236 @@ impl Session {
         self.compressing = compressing;
     }
 
-    pub fn compress(&mut self, mut prompt: String) {
-        if let Some(system_prompt) = self.messages.first().and_then(|v| {
-            if MessageRole::System == v.role {
-                let content = v.content.to_text();
-                if !content.is_empty() {
-                    return Some(content);
+    /// The index of the first message `strategy` keeps verbatim, the ones before it are compressed.
+    pub fn compress_from(
+        &self,
+        strategy: CompressStrategy,
+        global_compress_threshold: usize,
+    ) -> usize {
+        let len = self.messages.len();
+        if strategy == CompressStrategy::Summarize {
+            return len;
+        }
+        // Keep the recent messages within half of the threshold
+        let budget = self.compress_threshold.unwrap_or(global_compress_threshold) / 2;
+        let mut tokens = 0;
+        let mut index = len;
+        while index > 0 {
+            tokens += self
+                .model
+                .messages_tokens(std::slice::from_ref(&self.messages[index - 1]));
+            if tokens > budget {
+                break;
+            }
+            index -= 1;
+        }
+        while index < len && !self.messages[index].role.is_user() {
+            index += 1;
+        }
+        if index == len {
+            index = self
+                .messages
+                .iter()
+                .rposition(|v| v.role.is_user())
+                .unwrap_or(len);
+        }
+        index
+    }
+
+    /// Replace the messages before `compress_from` with `summary`, or drop them without one.
+    /// The role prompt and pinned messages are kept.
+    pub fn compress(&mut self, summary: Option<String>, compress_from: usize) {
+        let kept = self
+            .messages
+            .split_off(compress_from.min(self.messages.len()));
+        let mut system_prompt = None;
+        let mut pinned = vec![];
+        for (i, message) in self.messages.drain(..).enumerate() {
+            if i == 0 && message.role.is_system() {
+                if summary.is_some() {
+                    self.compressed_messages.push(message.clone());
+                }
+                system_prompt = Some(message);
+            } else if message.pinned {
+                pinned.push(message);
+            } else {
+                self.compressed_messages.push(message);
+            }
+        }
+        match summary {
+            Some(mut prompt) => {
+                if let Some(content) = system_prompt
+                    .map(|v| v.content.to_text())
+                    .filter(|v| !v.is_empty())
+                {
+                    prompt = format!("{content}\n\n{prompt}");
+                }
+                self.messages.push(Message::new(
+                    MessageRole::System,
+                    MessageContent::Text(prompt.into()),
+                ));
+            }
+            None => self.messages.extend(system_prompt),
+        }
+        self.messages.extend(pinned);
+        self.messages.extend(kept);
+        self.dirty = true;
+    }
+
+    pub fn set_compress_until(&mut self, compress_from: usize) {
+        self.compress_until = compress_from;
+    }
+
+    /// How many messages compressing the ones before `compress_from` removes
+    pub fn count_compressed(&self, compress_from: usize) -> usize {
+        self.messages
+            .iter()
+            .take(compress_from)
+            .enumerate()
+            .filter(|(i, v)| !(v.pinned || *i == 0 && v.role.is_system()))
+            .count()
+    }
+
+    /// Pin or unpin the message at `index`, returns whether it is pinned now.
+    pub fn toggle_pin(&mut self, index: usize) -> Result<bool> {
+        let Some(message) = self.messages.get_mut(index) else {
+            bail!("No message #{index}, see `.pin` for the messages")
+        };
+        message.pinned = !message.pinned;
+        self.dirty = true;
+        Ok(message.pinned)
+    }
+
+    /// Each user message with its reply, by the index of the user message
+    pub fn exchanges(&self) -> Vec<(usize, String)> {
+        let mut output: Vec<(usize, String)> = vec![];
+        for (i, message) in self.messages.iter().enumerate() {
+            let text = message.content.to_text();
+            match message.role {
+                MessageRole::User => output.push((i, text)),
+                MessageRole::Assistant if !text.is_empty() => {
+                    if let Some((_, exchange)) = output.last_mut() {
+                        exchange.push_str("\n\n");
+                        exchange.push_str(&text);
+                    }
                 }
+                _ => {}
             }
-            None
-        }) {
-            prompt = format!("{system_prompt}\n\n{prompt}",);
-        }
-        self.compressed_messages.append(&mut self.messages);
-        self.messages.push(Message::new(
-            MessageRole::System,
-            MessageContent::Text(prompt),
-        ));
+        }
+        output
+    }
+
+    pub fn cached_embedding(&self, key: &str) -> Option<Vec<f32>> {
+        self.embeddings.get(key).cloned()
+    }
+
+    pub fn cache_embedding(&mut self, key: String, embedding: Vec<f32>) {
+        self.embeddings.insert(key, embedding);
+    }
+
+    /// The message, with its reply for a user message, at an index of `messages_info`
+    pub fn message_info(&self, index: usize) -> Result<String> {
+        let Some(message) = self.messages.get(index) else {
+            bail!("No message #{index}, see `.history` for the messages")
+        };
+        let end = match message.role.is_user() {
+            true => self.messages[index + 1..]
+                .iter()
+                .position(|v| v.role.is_user() || v.role.is_system())
+                .map(|v| index + 1 + v)
+                .unwrap_or(self.messages.len()),
+            false => index + 1,
+        };
+        let output: Vec<String> = (index..end)
+            .filter_map(|i| {
+                let message = &self.messages[i];
+                let text = message.content.to_text();
+                let role = serde_json::to_value(message.role).ok()?;
+                let role = role.as_str()?;
+                (!text.is_empty()).then(|| format!("**#{i} {role}**\n\n{text}"))
+            })
+            .collect();
+        Ok(output.join("\n\n"))
+    }
+
+    pub fn messages_info(&self) -> String {
+        let rows: Vec<Vec<String>> = self
+            .messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let role = serde_json::to_value(message.role).unwrap_or_default();
+                vec![
+                    format!("#{i}"),
+                    role.as_str().unwrap_or_default().to_string(),
+                    if message.pinned { "📌" } else { "" }.to_string(),
+                    summarize_text(&message.content.to_text(), 60),
+                ]
+            })
+            .collect();
+        render_table(&["#", "ROLE", "PIN", "TEXT"], &rows)
+    }
+
+    pub fn set_checkpoint(&mut self, name: &str) {
+        let checkpoint = Checkpoint {
+            messages: self.messages.clone(),
+            compressed_messages: self.compressed_messages.clone(),
+            time: now(),
+        };
+        self.checkpoints.insert(name.to_string(), checkpoint);
+        self.dirty = true;
+    }
+
+    /// Restore the messages saved by `.checkpoint`, returns how many messages were dropped.
+    pub fn rollback(&mut self, name: &str) -> Result<usize> {
+        let Some(checkpoint) = self.checkpoints.get(name) else {
+            bail!("No checkpoint '{name}', see `.checkpoint` for the checkpoints")
+        };
+        let dropped = self
+            .messages
+            .len()
+            .saturating_sub(checkpoint.messages.len());
+        self.messages = checkpoint.messages.clone();
+        self.compressed_messages = checkpoint.compressed_messages.clone();
+        self.dirty = true;
+        Ok(dropped)
+    }
+
+    /// Drop the last user message and the replies after it, returns how many messages were dropped.
+    pub fn undo(&mut self) -> Result<usize> {
+        let Some(index) = self.messages.iter().rposition(|v| v.role.is_user()) else {
+            bail!("No exchange to undo")
+        };
+        let dropped = self.messages.len() - index;
+        self.messages.truncate(index);
         self.dirty = true;
+        Ok(dropped)
+    }
+
+    pub fn checkpoint_names(&self) -> Vec<String> {
+        self.checkpoints.keys().cloned().collect()
+    }
+
+    pub fn checkpoints_info(&self) -> String {
+        let rows: Vec<Vec<String>> = self
+            .checkpoints
+            .iter()
+            .map(|(name, checkpoint)| {
+                let last_user = checkpoint
+                    .messages
+                    .iter()
+                    .rfind(|v| v.role.is_user())
+                    .map(|v| summarize_text(&v.content.to_text(), 50))
+                    .unwrap_or_default();
+                vec![
+                    name.clone(),
+                    checkpoint.time.clone(),
+                    checkpoint.messages.len().to_string(),
+                    last_user,
+                ]
+            })
+            .collect();
+        render_table(&["NAME", "TIME", "MESSAGES", "LAST QUESTION"], &rows)
     }
 
     pub fn need_autoname(&self) -> bool {
@@ -411,11 +745,7 @@ impl Session {
                     format!("Failed to create directory '{}'", session_dir.display())
                 })?;
 
-                let now = chrono::Local::now();
-                session_name = now.format("%Y%m%dT%H%M%S").to_string();
-                if let Some(autoname) = self.autoname() {
-                    session_name = format!("{session_name}-{autoname}")
-                }
+                session_name = auto_session_name(&session_dir, self.autoname());
             }
             let session_path = session_dir.join(format!("{session_name}.yaml"));
             self.save(&session_name, &session_path, is_repl)?;
@@ -458,17 +788,51 @@ impl Session {
         Ok(())
     }
 
-    pub fn add_message(&mut self, input: &Input, output: &str) -> Result<()> {
+    pub fn add_message(
+        &mut self,
+        input: &Input,
+        output: &ChatCompletionsOutput,
+        config_seed: Option<u64>,
+    ) -> Result<()> {
+        let metadata = MessageMetadata {
+            model: input.role().model().id(),
+            temperature: input.role().temperature(),
+            top_p: input.role().top_p(),
+            seed: input.seed(config_seed),
+            input_tokens: output.input_tokens,
+            output_tokens: output.output_tokens,
+            latency_ms: output.latency_ms,
+            provider: output.provider.clone(),
+            cost: output.cost,
+            timestamp: now(),
+        };
+        let ChatCompletionsOutput {
+            text: output,
+            citations,
+            ..
+        } = output;
         if input.continue_output().is_some() {
             if let Some(message) = self.messages.last_mut() {
                 if let MessageContent::Text(text) = &mut message.content {
-                    *text = format!("{text}{output}");
+                    let offset = text.chars().count();
+                    *text = format!("{text}{output}").into();
+                    for citation in citations {
+                        let mut citation = citation.clone();
+                        citation.offsets.iter_mut().for_each(|v| *v += offset);
+                        Citation::merge(&mut message.citations, citation);
+                    }
+                    match message.metadata.as_mut() {
+                        Some(exist) => exist.merge(metadata),
+                        None => message.metadata = Some(metadata),
+                    }
                 }
             }
         } else if input.regenerate() {
             if let Some(message) = self.messages.last_mut() {
                 if let MessageContent::Text(text) = &mut message.content {
-                    *text = output.to_string();
+                    *text = output.as_str().into();
+                    message.citations = citations.clone();
+                    message.metadata = Some(metadata);
                 }
             }
         } else {
@@ -490,10 +854,13 @@ impl Session {
                     MessageContent::ToolCalls(tool_calls.clone()),
                 ))
             }
-            self.messages.push(Message::new(
+            let mut message = Message::new(
                 MessageRole::Assistant,
-                MessageContent::Text(output.to_string()),
-            ));
+                MessageContent::Text(output.as_str().into()),
+            );
+            message.citations = citations.clone();
+            message.metadata = Some(metadata);
+            self.messages.push(message);
         }
         self.dirty = true;
         Ok(())
@@ -513,7 +880,18 @@ impl Session {
     }
 
     pub fn build_messages(&self, input: &Input) -> Vec<Message> {
-        let mut messages = self.messages.clone();
+        let mut messages: Vec<Message> = if self.compressing {
+            // Only the compressed messages are summarized, pinned ones are kept as they are
+            self.messages
+                .iter()
+                .take(self.compress_until)
+                .enumerate()
+                .filter(|(i, v)| *i == 0 || !v.pinned)
+                .map(|(_, v)| v.clone())
+                .collect()
+        } else {
+            self.messages.clone()
+        };
         if input.continue_output().is_some() {
             return messages;
         } else if input.regenerate() {
@@ -525,7 +903,10 @@ impl Session {
         if len == 0 {
             messages = input.role().build_messages(input);
             need_add_msg = false;
-        } else if len == 1 && self.compressed_messages.len() >= 2 {
+        } else if self.compressed_messages.len() >= 2
+            && messages[0].role.is_system()
+            && messages[1..].iter().all(|v| v.pinned)
+        {
             if let Some(index) = self
                 .compressed_messages
                 .iter()
@@ -545,6 +926,7 @@ impl RoleLike for Session {
     fn to_role(&self) -> Role {
         let role_name = self.role_name.as_deref().unwrap_or_default();
         let mut role = Role::new(role_name, &self.role_prompt);
+        role.set_params(self.role_params.clone());
         role.sync(self);
         role
     }
@@ -623,3 +1005,334 @@ impl AutoName {
         !self.naming && self.chat_history.is_some() && self.name.is_none()
     }
 }
+
+/// Timestamped name for an auto-saved session, e.g. `2025-06-01-1432-rust-lifetimes`.
+/// Sessions saved within the same minute get a `_2`, `_3`... suffix instead of overwriting each other.
+fn auto_session_name(session_dir: &Path, autoname: Option<&str>) -> String {
+    let timestamp = chrono::Local::now().format("%Y-%m-%d-%H%M").to_string();
+    auto_session_name_at(session_dir, autoname, &timestamp)
+}
+
+fn auto_session_name_at(session_dir: &Path, autoname: Option<&str>, timestamp: &str) -> String {
+    let mut index = 1;
+    loop {
+        let prefix = match index {
+            1 => timestamp.to_string(),
+            _ => format!("{timestamp}_{index}"),
+        };
+        let taken = read_dir(session_dir)
+            .map(|entries| {
+                entries.flatten().any(|entry| {
+                    let file_name = entry.file_name();
+                    let file_name = file_name.to_string_lossy();
+                    file_name == format!("{prefix}.yaml")
+                        || file_name.starts_with(&format!("{prefix}-"))
+                })
+            })
+            .unwrap_or_default();
+        if !taken {
+            return match autoname {
+                Some(autoname) => format!("{prefix}-{autoname}"),
+                None => prefix,
+            };
+        }
+        index += 1;
+    }
+}
+
+/// Upgrade a session yaml in place, returning the original version if anything changed.
+fn migrate_session(value: &mut serde_yaml::Value) -> Result<Option<u64>> {
+    let data = match value.as_mapping_mut() {
+        Some(v) => v,
+        None => bail!("Not a mapping"),
+    };
+    let version = data.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version > SESSION_VERSION {
+        bail!("Unsupported version {version}, please upgrade aichat");
+    }
+    if version == SESSION_VERSION {
+        return Ok(None);
+    }
+    for migration in &SESSION_MIGRATIONS[version as usize..] {
+        migration(data)?;
+    }
+    data.insert("version".into(), SESSION_VERSION.into());
+    Ok(Some(version))
+}
+
+/// How compression shortens the history of a session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressStrategy {
+    /// Replace all messages with a summary
+    #[default]
+    Summarize,
+    /// Drop the oldest messages
+    Prune,
+    /// Summarize the oldest messages and keep the recent ones
+    Hybrid,
+}
+
+impl CompressStrategy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "summarize" => Some(Self::Summarize),
+            "prune" => Some(Self::Prune),
+            "hybrid" => Some(Self::Hybrid),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Summarize => "summarize",
+            Self::Prune => "prune",
+            Self::Hybrid => "hybrid",
+        }
+    }
+}
+
+/// What `Config::prepare_session_compression` worked out, applied by `Session::compress`
+#[derive(Debug, Clone)]
+pub struct SessionCompression {
+    pub summary: Option<String>,
+    pub compress_from: usize,
+    pub compressed: usize,
+}
+
+impl SessionCompression {
+    /// Show the summary, or what gets dropped, and ask whether to go on.
+    pub fn confirm(&self) -> Result<bool> {
+        let message = match &self.summary {
+            Some(summary) => {
+                println!("{summary}\n");
+                format!(
+                    "Replace {} messages with the summary above?",
+                    self.compressed
+                )
+            }
+            None => format!("Drop the {} oldest messages?", self.compressed),
+        };
+        let ans = Confirm::new(&message).with_default(true).prompt()?;
+        Ok(ans)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct Checkpoint {
+    messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    compressed_messages: Vec<Message>,
+    time: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_session() {
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str("model: openai:gpt-4o\nmessages: []").unwrap();
+        assert_eq!(migrate_session(&mut value).unwrap(), Some(0));
+        assert_eq!(value["version"].as_u64(), Some(SESSION_VERSION));
+        assert_eq!(migrate_session(&mut value).unwrap(), None);
+
+        let mut value: serde_yaml::Value = serde_yaml::from_str("version: 999").unwrap();
+        assert!(migrate_session(&mut value).is_err());
+    }
+
+    #[test]
+    fn test_role_params_persisted() {
+        let session = Session {
+            role_params: RoleParams {
+                max_output_tokens: Some(100),
+                stop: Some(vec!["END".into()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let content = serde_yaml::to_string(&session).unwrap();
+        assert!(content.contains("max_output_tokens: 100"));
+        let session: Session = serde_yaml::from_str(&content).unwrap();
+        assert_eq!(session.role_params.max_output_tokens, Some(100));
+        assert_eq!(session.role_params.stop, Some(vec!["END".to_string()]));
+    }
+
+    #[test]
+    fn test_auto_session_name() {
+        let dir = crate::utils::temp_file("-sessions-", "");
+        std::fs::create_dir_all(&dir).unwrap();
+        let timestamp = "2024-06-01-1432";
+        let first = auto_session_name_at(&dir, Some("hello"), timestamp);
+        std::fs::write(dir.join(format!("{first}.yaml")), "").unwrap();
+        let second = auto_session_name_at(&dir, Some("world"), timestamp);
+        std::fs::write(dir.join(format!("{second}.yaml")), "").unwrap();
+        let third = auto_session_name_at(&dir, None, timestamp);
+        let next_minute = auto_session_name_at(&dir, Some("world"), "2024-06-01-1433");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first, "2024-06-01-1432-hello");
+        assert_eq!(second, "2024-06-01-1432_2-world");
+        assert_eq!(third, "2024-06-01-1432_3");
+        assert_eq!(next_minute, "2024-06-01-1433-world");
+        let m = RE_AUTONAME_PREFIX.find(&first).unwrap().unwrap();
+        assert_eq!(&first[m.end()..], "hello");
+        let m = RE_AUTONAME_PREFIX
+            .find("20240601T143210-old")
+            .unwrap()
+            .unwrap();
+        assert_eq!(m.end(), 16);
+    }
+
+    #[test]
+    fn test_replay_turns() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let mut reply = text(MessageRole::Assistant, "hello");
+        reply.metadata = Some(MessageMetadata {
+            latency_ms: Some(1200),
+            ..Default::default()
+        });
+        let session = Session {
+            messages: vec![
+                text(MessageRole::System, "be brief"),
+                text(MessageRole::User, "hi"),
+                reply,
+                text(MessageRole::User, "bye"),
+                text(MessageRole::Assistant, "see you"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            session.replay_turns(),
+            vec![
+                (MessageRole::User, "hi".into(), None),
+                (MessageRole::Assistant, "hello".into(), Some(1200)),
+                (MessageRole::User, "bye".into(), None),
+                (MessageRole::Assistant, "see you".into(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transcript() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let mut session = Session {
+            messages: vec![
+                text(MessageRole::System, "be brief"),
+                text(MessageRole::User, " hi\n"),
+                text(MessageRole::Assistant, "hello"),
+                text(MessageRole::Tool, "42"),
+                text(MessageRole::User, "  "),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(session.transcript(), "user: hi\n\nassistant: hello");
+        // The system prompt holds the summary of the compressed messages
+        session.compressed_messages = vec![text(MessageRole::User, "old")];
+        assert_eq!(
+            session.transcript(),
+            "system: be brief\n\nuser: hi\n\nassistant: hello"
+        );
+    }
+
+    #[test]
+    fn test_attachment_contents() {
+        let text = |text: &str| Message::new(MessageRole::User, MessageContent::Text(text.into()));
+        let session = Session {
+            messages: vec![
+                text("hi\n\n============ PATH: a.rs ============\n\nfn a() {}\n\n\n============ PATH: b.rs ============\n\nb\n\n"),
+                text("\n============ PATH: a.rs ============\n\n[Same as attached earlier, sha256 0123]\n"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(session.attachment_contents("a.rs").unwrap(), "fn a() {}\n");
+        assert_eq!(session.attachment_contents("b.rs").unwrap(), "b\n");
+        assert!(session.attachment_contents("c.rs").is_none());
+    }
+
+    #[test]
+    fn test_compress_keeps_pinned() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let mut session = Session {
+            messages: vec![
+                text(MessageRole::User, "remember the code word"),
+                text(MessageRole::Assistant, "ok"),
+                text(MessageRole::User, "hi"),
+            ],
+            ..Default::default()
+        };
+        assert!(session.toggle_pin(0).unwrap());
+        assert!(session.toggle_pin(3).is_err());
+        session.compress(Some("summary".into()), 3);
+        let texts: Vec<String> = session
+            .messages
+            .iter()
+            .map(|v| v.content.to_text())
+            .collect();
+        assert_eq!(texts, ["summary", "remember the code word"]);
+        assert_eq!(session.compressed_messages.len(), 2);
+    }
+
+    #[test]
+    fn test_checkpoint_rollback() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let mut session = Session {
+            messages: vec![
+                text(MessageRole::User, "hi"),
+                text(MessageRole::Assistant, "hello"),
+            ],
+            ..Default::default()
+        };
+        session.set_checkpoint("good");
+        session.messages.push(text(MessageRole::User, "risky"));
+        session.messages.push(text(MessageRole::Assistant, "oops"));
+        assert_eq!(session.rollback("good").unwrap(), 2);
+        assert_eq!(session.messages.len(), 2);
+        assert!(session.rollback("missing").is_err());
+        assert_eq!(session.checkpoint_names(), ["good"]);
+    }
+
+    #[test]
+    fn test_undo() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let mut session = Session {
+            messages: vec![
+                text(MessageRole::System, "be brief"),
+                text(MessageRole::User, "hi"),
+                text(MessageRole::Assistant, "hello"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(session.undo().unwrap(), 2);
+        assert_eq!(session.messages.len(), 1);
+        assert!(session.undo().is_err());
+    }
+
+    #[test]
+    fn test_compress_prune() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let mut session = Session {
+            messages: vec![
+                text(MessageRole::System, "be brief"),
+                text(MessageRole::User, &"old question ".repeat(20)),
+                text(MessageRole::Assistant, &"old answer ".repeat(20)),
+                text(MessageRole::User, "new question"),
+                text(MessageRole::Assistant, "new answer"),
+            ],
+            compress_threshold: Some(40),
+            ..Default::default()
+        };
+        assert_eq!(session.compress_from(CompressStrategy::Summarize, 0), 5);
+        let compress_from = session.compress_from(CompressStrategy::Prune, 0);
+        assert_eq!(compress_from, 3);
+        assert_eq!(session.count_compressed(compress_from), 2);
+        session.compress(None, compress_from);
+        let texts: Vec<String> = session
+            .messages
+            .iter()
+            .map(|v| v.content.to_text())
+            .collect();
+        assert_eq!(texts, ["be brief", "new question", "new answer"]);
+    }
+}
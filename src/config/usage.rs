@@ -0,0 +1,115 @@
+use super::{ensure_parent_exists, Input, RoleLike};
+
+use crate::client::ChatCompletionsOutput;
+use crate::utils::{estimate_token_length, now};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+/// One chat completion in `usage.jsonl`, the ledger read by `--stats`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct UsageEntry {
+    pub time: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    /// Reported by the API, or worked out from the prices of the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl UsageEntry {
+    /// Tokens the API doesn't report are estimated.
+    pub fn new(input: &Input, output: &ChatCompletionsOutput) -> Self {
+        let role = input.role();
+        let model = role.model();
+        let input_tokens = output
+            .input_tokens
+            .unwrap_or_else(|| estimate_token_length(&input.text()) as u64);
+        let output_tokens = output
+            .output_tokens
+            .unwrap_or_else(|| estimate_token_length(&output.text) as u64);
+        let cost = output.cost.or_else(|| {
+            let data = model.data();
+            match (data.input_price, data.output_price) {
+                (None, None) => None,
+                (input_price, output_price) => Some(
+                    (input_tokens as f64 * input_price.unwrap_or_default()
+                        + output_tokens as f64 * output_price.unwrap_or_default())
+                        / 1_000_000.0,
+                ),
+            }
+        });
+        Self {
+            time: now(),
+            model: model.id(),
+            role: (!role.is_derived() && !role.name().is_empty()).then(|| role.name().to_string()),
+            input_tokens,
+            output_tokens,
+            cost,
+        }
+    }
+
+    /// The line printed after a reply with `footer: true`, `~` marks estimated tokens
+    pub fn footer(&self, output: &ChatCompletionsOutput) -> String {
+        let estimated = |v: Option<u64>| if v.is_some() { "" } else { "~" };
+        let mut parts = vec![
+            self.model.clone(),
+            format!(
+                "{}{} in / {}{} out",
+                estimated(output.input_tokens),
+                self.input_tokens,
+                estimated(output.output_tokens),
+                self.output_tokens
+            ),
+        ];
+        if let Some(cost) = self.cost {
+            parts.push(format!("${cost:.4}"));
+        }
+        if let Some(latency_ms) = output.latency_ms {
+            parts.push(format!("{:.1}s", latency_ms as f64 / 1000.0));
+        }
+        if let Some(finish_reason) = &output.finish_reason {
+            parts.push(finish_reason.clone());
+        }
+        parts.join(" · ")
+    }
+
+    pub fn time(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(&self.time).ok()
+    }
+}
+
+pub fn append_usage(path: &Path, entry: &UsageEntry) -> Result<()> {
+    ensure_parent_exists(path)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open '{}'", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+pub fn load_usage(path: &Path) -> Result<Vec<UsageEntry>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let entries = content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<UsageEntry>(line).ok())
+        .collect();
+    Ok(entries)
+}
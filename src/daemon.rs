@@ -0,0 +1,135 @@
+use crate::batch::{create_batch_input, run_batch, DEFAULT_BATCH_CONCURRENCY};
+use crate::client::{Model, ModelType};
+use crate::config::{GlobalConfig, Input, RoleLike, Schedule};
+use crate::utils::{dimmed_text, now, post_webhook, warning_text, Cron};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, Timelike};
+use serde_json::json;
+use std::{fs::OpenOptions, io::Write, time::Duration};
+
+/// Run the configured `schedules` until Ctrl+C, checking the cron expressions every minute.
+pub async fn run(config: &GlobalConfig) -> Result<()> {
+    let schedules = config.read().schedules.clone();
+    if schedules.is_empty() {
+        bail!("No schedules, add them to `schedules` in the config file");
+    }
+    let crons = schedules
+        .iter()
+        .map(|v| Cron::parse(&v.cron).with_context(|| format!("Invalid schedule '{}'", v.name)))
+        .collect::<Result<Vec<_>>>()?;
+    println!(
+        "{}",
+        dimmed_text(&format!(
+            "Running {} schedule(s), press Ctrl+C to stop",
+            schedules.len()
+        ))
+    );
+    loop {
+        let time = Local::now();
+        let wait = Duration::from_secs(60 - time.second() as u64)
+            - Duration::from_nanos(time.nanosecond().min(999_999_999) as u64);
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        let time = Local::now();
+        let due: Vec<Schedule> = schedules
+            .iter()
+            .zip(&crons)
+            .filter(|(_, cron)| cron.matches(&time))
+            .map(|(schedule, _)| schedule.clone())
+            .collect();
+        if !due.is_empty() {
+            // Runs outlasting a minute must not hold up the schedules of the next ones
+            tokio::spawn(run_schedules(config.clone(), due));
+        }
+    }
+    Ok(())
+}
+
+async fn run_schedules(config: GlobalConfig, schedules: Vec<Schedule>) {
+    let mut inputs = vec![];
+    let mut ready = vec![];
+    for schedule in &schedules {
+        match create_input(&config, schedule) {
+            Ok(input) => {
+                inputs.push(input);
+                ready.push(schedule);
+            }
+            Err(err) => eprintln!("{}", warning_text(&format!("✗ {}: {err:#}", schedule.name))),
+        }
+    }
+    let outputs = run_batch(inputs, DEFAULT_BATCH_CONCURRENCY).await;
+    for (schedule, output) in ready.into_iter().zip(outputs) {
+        let ret = match output {
+            Ok(output) => deliver(schedule, Ok(&output.text)).await,
+            Err(err) => {
+                let err = format!("{err:#}");
+                let _ = deliver(schedule, Err(&err)).await;
+                Err(anyhow::anyhow!(err))
+            }
+        };
+        match ret {
+            Ok(()) => println!(
+                "{}",
+                dimmed_text(&format!("✓ {} at {}", schedule.name, now()))
+            ),
+            Err(err) => eprintln!("{}", warning_text(&format!("✗ {}: {err:#}", schedule.name))),
+        }
+    }
+}
+
+fn create_input(config: &GlobalConfig, schedule: &Schedule) -> Result<Input> {
+    match &schedule.role {
+        Some(role) => {
+            let mut role = config.read().retrieve_role(role)?;
+            if let Some(model_id) = &schedule.model {
+                role.set_model(&Model::retrieve_model(
+                    &config.read(),
+                    model_id,
+                    ModelType::Chat,
+                )?);
+            }
+            Ok(Input::from_str(config, &schedule.prompt, Some(role)))
+        }
+        None => create_batch_input(
+            config,
+            schedule.model.as_deref(),
+            &schedule.name,
+            "",
+            &schedule.prompt,
+        ),
+    }
+}
+
+/// Append the reply to the output file and send it (or the error) to the webhook, print it
+/// if the schedule has neither.
+async fn deliver(schedule: &Schedule, reply: Result<&str, &str>) -> Result<()> {
+    let time = now();
+    if let Ok(text) = reply {
+        match &schedule.output {
+            Some(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open '{path}'"))?;
+                writeln!(file, "## {} ({time})\n\n{}\n", schedule.name, text.trim())
+                    .with_context(|| format!("Failed to write '{path}'"))?;
+            }
+            None if schedule.webhook.is_none() => {
+                println!("## {} ({time})\n\n{}\n", schedule.name, text.trim());
+            }
+            None => {}
+        }
+    }
+    if let Some(url) = &schedule.webhook {
+        let body = match reply {
+            Ok(text) => json!({ "name": schedule.name, "time": time, "text": text }),
+            Err(err) => json!({ "name": schedule.name, "time": time, "error": err }),
+        };
+        post_webhook(url, &body).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use crate::app::print_list;
+use crate::batch::{create_batch_input, grade_reply, run_batch, DEFAULT_BATCH_CONCURRENCY};
+use crate::config::GlobalConfig;
+use crate::utils::{color_text, extract_block, render_table};
+
+use anyhow::{bail, Context, Result};
+use fancy_regex::Regex;
+use nu_ansi_term::Color;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+
+/// A passing grader score is at least this
+const GRADER_PASS_SCORE: f64 = 7.0;
+
+/// The `--eval` file
+#[derive(Debug, Deserialize)]
+struct EvalFile {
+    #[serde(default)]
+    models: Vec<String>,
+    grader: Option<String>,
+    #[serde(default)]
+    prompt: String,
+    cases: Vec<EvalCase>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EvalCase {
+    name: Option<String>,
+    input: String,
+    expect: Expect,
+}
+
+#[derive(Debug, Deserialize)]
+struct Expect {
+    regex: Option<String>,
+    json_schema: Option<Value>,
+    /// Criteria for the grader model
+    grader: Option<String>,
+}
+
+pub async fn run(config: &GlobalConfig, path: &str, json: bool) -> Result<()> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read cases at '{path}'"))?;
+    let file: EvalFile =
+        serde_yaml::from_str(&content).with_context(|| format!("Invalid cases at '{path}'"))?;
+    if file.cases.is_empty() {
+        bail!("No cases in '{path}'");
+    }
+    let models = match file.models.is_empty() {
+        true => vec![config.read().current_model().id()],
+        false => file.models.clone(),
+    };
+
+    let mut rows = vec![];
+    let mut total_failed = 0;
+    for model_id in &models {
+        let inputs = file
+            .cases
+            .iter()
+            .enumerate()
+            .map(|(i, case)| {
+                let name = case_name(case, i);
+                create_batch_input(config, Some(model_id), &name, &file.prompt, &case.input)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let outputs = run_batch(inputs, DEFAULT_BATCH_CONCURRENCY).await;
+        let mut passed = 0;
+        for (i, (case, output)) in file.cases.iter().zip(outputs).enumerate() {
+            let ret = match output {
+                Ok(output) => check_case(config, file.grader.as_deref(), case, &output.text).await,
+                Err(err) => Err(err),
+            };
+            match ret {
+                Ok(()) => passed += 1,
+                Err(err) if !json => println!(
+                    "{} {model_id} {}: {err:#}",
+                    color_text("FAIL", Color::Red),
+                    case_name(case, i)
+                ),
+                Err(_) => {}
+            }
+        }
+        let failed = file.cases.len() - passed;
+        total_failed += failed;
+        rows.push(vec![
+            model_id.clone(),
+            file.cases.len().to_string(),
+            passed.to_string(),
+            failed.to_string(),
+            format!("{:.1}", passed as f64 * 100.0 / file.cases.len() as f64),
+        ]);
+    }
+
+    let headers = ["MODEL", "CASES", "PASSED", "FAILED", "PASS_RATE"];
+    if json {
+        print_list(true, &headers, rows);
+    } else {
+        println!("{}", render_table(&headers, &rows));
+    }
+    if total_failed > 0 {
+        bail!("{total_failed} case(s) failed");
+    }
+    Ok(())
+}
+
+fn case_name(case: &EvalCase, index: usize) -> String {
+    case.name
+        .clone()
+        .unwrap_or_else(|| format!("case-{}", index + 1))
+}
+
+async fn check_case(
+    config: &GlobalConfig,
+    grader: Option<&str>,
+    case: &EvalCase,
+    reply: &str,
+) -> Result<()> {
+    let expect = &case.expect;
+    if let Some(regex) = &expect.regex {
+        let re = Regex::new(regex).with_context(|| format!("Invalid regex '{regex}'"))?;
+        if !re.is_match(reply)? {
+            bail!("reply does not match /{regex}/");
+        }
+    }
+    if let Some(schema) = &expect.json_schema {
+        let value: Value = serde_json::from_str(&extract_block(reply))
+            .with_context(|| "reply is not valid JSON")?;
+        validate_json(&value, schema, "$")?;
+    }
+    if let Some(criteria) = &expect.grader {
+        let Some(grader) = grader else {
+            bail!("`grader` model is not set");
+        };
+        let score = grade_reply(config, grader, criteria, &case.input, reply).await?;
+        if score < GRADER_PASS_SCORE {
+            bail!("grader scored {score}");
+        }
+    }
+    Ok(())
+}
+
+/// Check the common JSON schema keywords: `type`, `enum`, `required`, `properties` and `items`.
+fn validate_json(value: &Value, schema: &Value, path: &str) -> Result<()> {
+    if let Some(expected) = schema["type"].as_str() {
+        let matched = match expected {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !matched {
+            bail!("{path} is not of type '{expected}'");
+        }
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        if !values.contains(value) {
+            bail!("{path} is not one of {}", schema["enum"]);
+        }
+    }
+    if let Some(required) = schema["required"].as_array() {
+        for key in required.iter().filter_map(|v| v.as_str()) {
+            if value.get(key).is_none() {
+                bail!("{path}.{key} is required");
+            }
+        }
+    }
+    if let (Some(properties), Some(object)) = (schema["properties"].as_object(), value.as_object())
+    {
+        for (key, property_schema) in properties {
+            if let Some(property) = object.get(key) {
+                validate_json(property, property_schema, &format!("{path}.{key}"))?;
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            validate_json(item, items, &format!("{path}[{i}]"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_json() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": { "type": "string" },
+                "tags": { "type": "array", "items": { "enum": ["a", "b"] } }
+            }
+        });
+        assert!(validate_json(&json!({"name": "x", "tags": ["a"]}), &schema, "$").is_ok());
+        assert_eq!(
+            validate_json(&json!({"tags": []}), &schema, "$")
+                .unwrap_err()
+                .to_string(),
+            "$.name is required"
+        );
+        assert_eq!(
+            validate_json(&json!({"name": "x", "tags": ["c"]}), &schema, "$")
+                .unwrap_err()
+                .to_string(),
+            r#"$.tags[0] is not one of ["a","b"]"#
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! A minimal C API over [`Aichat`], see `scripts/bindings/aichat.h`.
+//!
+//! Functions returning a pointer return NULL on failure, and an `int` return of `-1` means failure;
+//! `aichat_last_error` then describes the error of the calling thread. A panic is caught at the
+//! boundary and reported as a failure, it never unwinds into the caller.
+
+use crate::Aichat;
+
+use anyhow::{anyhow, Result};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+use tokio::runtime::Runtime;
+
+pub struct AichatHandle {
+    runtime: Runtime,
+    aichat: Aichat,
+}
+
+pub type AichatStreamCallback = extern "C" fn(text: *const c_char, user_data: *mut c_void);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(err: anyhow::Error) {
+    let message = CString::new(format!("{err:#}").replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|v| *v.borrow_mut() = Some(message));
+}
+
+/// Run `f`, turning a panic into an error
+fn guard<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|err| {
+        let message = err
+            .downcast_ref::<&str>()
+            .map(|v| v.to_string())
+            .or_else(|| err.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(anyhow!("Panicked: {message}"))
+    })
+}
+
+unsafe fn read_str<'a>(value: *const c_char, name: &str) -> Result<&'a str> {
+    if value.is_null() {
+        return Err(anyhow!("'{name}' is null"));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| anyhow!("'{name}' is not valid utf-8"))
+}
+
+unsafe fn read_opt_str<'a>(value: *const c_char, name: &str) -> Result<Option<&'a str>> {
+    if value.is_null() {
+        Ok(None)
+    } else {
+        read_str(value, name).map(Some)
+    }
+}
+
+/// Create a client from a config directory (NULL for the default one) and an optional model id.
+/// Fails if there is no config file.
+///
+/// # Safety
+///
+/// `config_dir` and `model` must be NULL or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn aichat_init(
+    config_dir: *const c_char,
+    model: *const c_char,
+) -> *mut AichatHandle {
+    let ret = guard(|| {
+        let mut builder = Aichat::builder();
+        if let Some(config_dir) = read_opt_str(config_dir, "config_dir")? {
+            builder = builder.config_dir(config_dir);
+        }
+        if let Some(model) = read_opt_str(model, "model")? {
+            builder = builder.model(model);
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let aichat = runtime.block_on(async { builder.build() })?;
+        Ok(AichatHandle { runtime, aichat })
+    });
+    match ret {
+        Ok(handle) => Box::into_raw(Box::new(handle)),
+        Err(err) => {
+            set_last_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Send a prompt and return the whole reply, free it with `aichat_string_free`.
+///
+/// # Safety
+///
+/// `handle` must come from `aichat_init`, and `prompt` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn aichat_send(
+    handle: *mut AichatHandle,
+    prompt: *const c_char,
+) -> *mut c_char {
+    let ret = guard(|| {
+        let handle = handle.as_ref().ok_or_else(|| anyhow!("'handle' is null"))?;
+        let prompt = read_str(prompt, "prompt")?;
+        let text = handle.runtime.block_on(handle.aichat.send(prompt))?;
+        Ok(CString::new(text.replace('\0', ""))?)
+    });
+    match ret {
+        Ok(text) => text.into_raw(),
+        Err(err) => {
+            set_last_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Send a prompt and call `callback` with each chunk of the reply. Returns 0 on success.
+///
+/// # Safety
+///
+/// `handle` must come from `aichat_init`, and `prompt` must be a valid NUL-terminated string.
+/// The text passed to `callback` is only valid during the call.
+#[no_mangle]
+pub unsafe extern "C" fn aichat_stream(
+    handle: *mut AichatHandle,
+    prompt: *const c_char,
+    callback: AichatStreamCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    let ret = guard(|| {
+        let handle = handle.as_ref().ok_or_else(|| anyhow!("'handle' is null"))?;
+        let prompt = read_str(prompt, "prompt")?;
+        handle
+            .runtime
+            .block_on(handle.aichat.stream(prompt, |text| {
+                if let Ok(text) = CString::new(text.replace('\0', "")) {
+                    callback(text.as_ptr(), user_data);
+                }
+            }))?;
+        Ok(())
+    });
+    match ret {
+        Ok(()) => 0,
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// The last error of the calling thread, or NULL. Valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn aichat_last_error() -> *const c_char {
+    guard(|| {
+        Ok(LAST_ERROR.with(|v| {
+            v.borrow()
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(ptr::null())
+        }))
+    })
+    .unwrap_or(ptr::null())
+}
+
+/// # Safety
+///
+/// `text` must be NULL or a string returned by this library, freed only once.
+#[no_mangle]
+pub unsafe extern "C" fn aichat_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        let _ = guard(|| {
+            drop(CString::from_raw(text));
+            Ok(())
+        });
+    }
+}
+
+/// # Safety
+///
+/// `handle` must be NULL or come from `aichat_init`, freed only once.
+#[no_mangle]
+pub unsafe extern "C" fn aichat_free(handle: *mut AichatHandle) {
+    if !handle.is_null() {
+        let _ = guard(|| {
+            drop(Box::from_raw(handle));
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_config_dir;
+
+    extern "C" fn collect(text: *const c_char, user_data: *mut c_void) {
+        let output = unsafe { &mut *(user_data as *mut String) };
+        output.push_str(unsafe { CStr::from_ptr(text) }.to_str().unwrap());
+    }
+
+    #[test]
+    fn test_c_api() {
+        unsafe {
+            let config_dir = CString::new(test_config_dir().display().to_string()).unwrap();
+            let handle = aichat_init(config_dir.as_ptr(), ptr::null());
+            assert!(!handle.is_null());
+
+            let prompt = CString::new("hi").unwrap();
+            let text = aichat_send(handle, prompt.as_ptr());
+            assert_eq!(CStr::from_ptr(text).to_str().unwrap(), "You said: hi");
+            aichat_string_free(text);
+
+            let mut output = String::new();
+            let ret = aichat_stream(
+                handle,
+                prompt.as_ptr(),
+                collect,
+                &mut output as *mut String as *mut c_void,
+            );
+            assert_eq!(ret, 0);
+            assert_eq!(output, "You said: hi");
+
+            assert!(aichat_send(handle, ptr::null()).is_null());
+            let error = CStr::from_ptr(aichat_last_error()).to_str().unwrap();
+            assert_eq!(error, "'prompt' is null");
+            aichat_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_guard_catches_panic() {
+        let err = guard(|| -> Result<()> { panic!("boom") }).unwrap_err();
+        assert_eq!(err.to_string(), "Panicked: boom");
+    }
+}
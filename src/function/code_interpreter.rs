@@ -0,0 +1,268 @@
+use super::{FunctionDeclaration, JsonSchema, ToolCall};
+
+use crate::config::GlobalConfig;
+use crate::utils::*;
+
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use serde_json::{json, Value};
+use std::{
+    env, fs,
+    path::Path,
+    process::Stdio,
+    time::{Duration, SystemTime},
+};
+
+pub const CODE_INTERPRETER_FUNCTION_NAME: &str = "code_interpreter";
+
+/// stdout and stderr are each cut to this many characters
+const OUTPUT_LIMIT: usize = 20_000;
+
+/// The only environment variables the program sees, so API keys don't leak into it
+const ENV_ALLOWLIST: [&str; 4] = ["PATH", "HOME", "LANG", "SYSTEMROOT"];
+
+/// Work dirs holding files created by the program are kept this long
+const WORK_DIR_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The builtin `code_interpreter` function, running Python or JavaScript snippets.
+pub fn code_interpreter_declaration() -> FunctionDeclaration {
+    let string_schema = |description: &str, enum_value: Option<Vec<String>>| JsonSchema {
+        type_value: "string".into(),
+        description: Some(description.into()),
+        properties: None,
+        items: None,
+        enum_value,
+        required: None,
+    };
+    FunctionDeclaration {
+        name: CODE_INTERPRETER_FUNCTION_NAME.into(),
+        description: "Run a Python or JavaScript (Node.js) program and return its stdout, stderr and the files it creates in the working directory. Print the results you need.".into(),
+        parameters: JsonSchema {
+            type_value: "object".into(),
+            description: None,
+            properties: Some(IndexMap::from([
+                (
+                    "language".to_string(),
+                    string_schema(
+                        "The language of the code",
+                        Some(vec!["python".into(), "javascript".into()]),
+                    ),
+                ),
+                (
+                    "code".to_string(),
+                    string_schema("The complete program to run", None),
+                ),
+            ])),
+            items: None,
+            enum_value: None,
+            required: Some(vec!["language".into(), "code".into()]),
+        },
+        agent: false,
+    }
+}
+
+impl ToolCall {
+    pub(super) fn eval_code_interpreter(&self, config: &GlobalConfig) -> Result<Value> {
+        let arg = |name: &str| self.arguments.get(name).and_then(|v| v.as_str());
+        let (script_name, program) = match arg("language") {
+            Some("python") => ("main.py", "python3"),
+            Some("javascript") => ("main.js", "node"),
+            _ => bail!(
+                "The call '{}' has invalid arguments: {}",
+                self.name,
+                self.arguments
+            ),
+        };
+        let Some(code) = arg("code") else {
+            bail!(
+                "The call '{}' has invalid arguments: {}",
+                self.name,
+                self.arguments
+            )
+        };
+        let (timeout, memory_mb, sandbox) = {
+            let config = config.read();
+            (
+                config.code_interpreter_timeout,
+                config.code_interpreter_memory_mb,
+                config.shell_sandbox.clone(),
+            )
+        };
+        remove_stale_work_dirs();
+        let work_dir = temp_file("-code-", "");
+        fs::create_dir_all(&work_dir)
+            .with_context(|| format!("Failed to create '{}'", work_dir.display()))?;
+        fs::write(work_dir.join(script_name), code)?;
+
+        let (cmd, args) = build_command(program, script_name, memory_mb);
+        let (cmd, args) = match sandbox {
+            Some(sandbox) => wrap_in_sandbox(&sandbox, &cmd, &args)?,
+            None => (cmd, args),
+        };
+        if *IS_STDOUT_TERMINAL {
+            println!(
+                "{}",
+                dimmed_text(&format!("Run {script_name} in {}", work_dir.display()))
+            );
+        }
+        let output = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(run_code(&cmd, &args, &work_dir, timeout))
+        })?;
+        let files = list_created_files(&work_dir, script_name);
+        if files.is_empty() {
+            let _ = fs::remove_dir_all(&work_dir);
+        }
+        let Some(output) = output else {
+            return Ok(json!({ "error": format!("Timed out after {timeout}s") }));
+        };
+        Ok(json!({
+            "exit_code": output.status.code(),
+            "stdout": truncate_output(&String::from_utf8_lossy(&output.stdout)),
+            "stderr": truncate_output(&String::from_utf8_lossy(&output.stderr)),
+            "files": files,
+        }))
+    }
+}
+
+/// Node reserves far more virtual memory than it uses, so it gets a heap limit instead of `ulimit -v`
+fn build_command(
+    program: &str,
+    script_name: &str,
+    memory_mb: Option<u64>,
+) -> (String, Vec<String>) {
+    if cfg!(windows) {
+        return (program.to_string(), vec![script_name.to_string()]);
+    }
+    let command = match (program, memory_mb) {
+        ("node", Some(mb)) => format!("exec node --max-old-space-size={mb} {script_name}"),
+        (_, Some(mb)) => format!("ulimit -v {} && exec {program} {script_name}", mb * 1024),
+        (_, None) => format!("exec {program} {script_name}"),
+    };
+    ("sh".to_string(), vec!["-c".to_string(), command])
+}
+
+fn code_envs() -> Vec<(String, String)> {
+    ENV_ALLOWLIST
+        .iter()
+        .filter_map(|name| Some((name.to_string(), env::var(name).ok()?)))
+        .collect()
+}
+
+/// Remove the work dirs of earlier runs that outlived `WORK_DIR_TTL`
+fn remove_stale_work_dirs() {
+    let prefix = format!("{}-", env!("CARGO_PKG_NAME").to_lowercase());
+    let Ok(entries) = fs::read_dir(env::temp_dir()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(&prefix) || !name.contains("-code-") {
+            continue;
+        }
+        let expired = entry
+            .metadata()
+            .and_then(|v| v.modified())
+            .ok()
+            .and_then(|v| SystemTime::now().duration_since(v).ok())
+            .is_some_and(|v| v > WORK_DIR_TTL);
+        if expired && entry.path().is_dir() {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+async fn run_code(
+    cmd: &str,
+    args: &[String],
+    work_dir: &Path,
+    timeout: u64,
+) -> Result<Option<std::process::Output>> {
+    let child = tokio::process::Command::new(cmd)
+        .args(args)
+        .current_dir(work_dir)
+        .env_clear()
+        .envs(code_envs())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Unable to run {cmd}"))?;
+    match tokio::time::timeout(Duration::from_secs(timeout), child.wait_with_output()).await {
+        Ok(output) => Ok(Some(output?)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn list_created_files(work_dir: &Path, script_name: &str) -> Vec<String> {
+    let mut files = vec![];
+    let mut dirs = vec![work_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path != work_dir.join(script_name) {
+                files.push(path.display().to_string());
+            }
+        }
+    }
+    files.sort_unstable();
+    files
+}
+
+fn truncate_output(text: &str) -> String {
+    if text.chars().count() <= OUTPUT_LIMIT {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(OUTPUT_LIMIT).collect();
+    format!("{head}\n... (truncated)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_command() {
+        if cfg!(windows) {
+            return;
+        }
+        let (_, args) = build_command("python3", "main.py", Some(1024));
+        assert_eq!(args[1], "ulimit -v 1048576 && exec python3 main.py");
+        let (_, args) = build_command("node", "main.js", Some(1024));
+        assert_eq!(args[1], "exec node --max-old-space-size=1024 main.js");
+        let (_, args) = build_command("node", "main.js", None);
+        assert_eq!(args[1], "exec node main.js");
+    }
+
+    #[test]
+    fn test_code_envs() {
+        let names: Vec<String> = code_envs().into_iter().map(|(k, _)| k).collect();
+        assert!(names.iter().all(|v| ENV_ALLOWLIST.contains(&v.as_str())));
+    }
+
+    #[test]
+    fn test_list_created_files() {
+        let dir = temp_file("-code-test-", "");
+        fs::create_dir_all(dir.join("out")).unwrap();
+        fs::write(dir.join("main.py"), "").unwrap();
+        fs::write(dir.join("out").join("plot.png"), "").unwrap();
+        let files = list_created_files(&dir, "main.py");
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(
+            files,
+            vec![dir.join("out").join("plot.png").display().to_string()]
+        );
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        assert_eq!(truncate_output("abc"), "abc");
+        let text = "a".repeat(OUTPUT_LIMIT + 1);
+        assert!(truncate_output(&text).ends_with("\n... (truncated)"));
+    }
+}
@@ -0,0 +1,341 @@
+use super::{FunctionDeclaration, JsonSchema, ToolCall};
+
+use crate::config::{ensure_parent_exists, GlobalConfig};
+use crate::utils::*;
+
+use anyhow::{anyhow, bail, Context, Result};
+use indexmap::IndexMap;
+use inquire::Confirm;
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+pub const FS_READ_FUNCTION_NAME: &str = "fs_read";
+pub const FS_WRITE_FUNCTION_NAME: &str = "fs_write";
+pub const FS_LIST_FUNCTION_NAME: &str = "fs_list";
+pub const FS_FUNCTION_NAMES: [&str; 3] = [
+    FS_READ_FUNCTION_NAME,
+    FS_WRITE_FUNCTION_NAME,
+    FS_LIST_FUNCTION_NAME,
+];
+
+/// `fs_read` returns at most this many bytes of a file
+const FS_READ_LIMIT: usize = 100 * 1024;
+/// `fs_list` returns at most this many entries
+const FS_LIST_LIMIT: usize = 500;
+
+/// The builtin file tools, restricted to `fs_allowed_dirs`.
+pub fn fs_declarations() -> Vec<FunctionDeclaration> {
+    let string_schema = |description: &str| JsonSchema {
+        type_value: "string".into(),
+        description: Some(description.into()),
+        properties: None,
+        items: None,
+        enum_value: None,
+        required: None,
+    };
+    let declaration =
+        |name: &str, description: &str, params: Vec<(&str, &str)>| FunctionDeclaration {
+            name: name.into(),
+            description: description.into(),
+            parameters: JsonSchema {
+                type_value: "object".into(),
+                description: None,
+                properties: Some(
+                    params
+                        .iter()
+                        .map(|(name, description)| (name.to_string(), string_schema(description)))
+                        .collect::<IndexMap<_, _>>(),
+                ),
+                items: None,
+                enum_value: None,
+                required: Some(params.iter().map(|(name, _)| name.to_string()).collect()),
+            },
+            agent: false,
+        };
+    vec![
+        declaration(
+            FS_READ_FUNCTION_NAME,
+            "Read the contents of a text file.",
+            vec![("path", "The path of the file")],
+        ),
+        declaration(
+            FS_WRITE_FUNCTION_NAME,
+            "Create or overwrite a text file with the given contents. The user reviews the diff before it is written.",
+            vec![
+                ("path", "The path of the file"),
+                ("contents", "The full new contents of the file"),
+            ],
+        ),
+        declaration(
+            FS_LIST_FUNCTION_NAME,
+            "List the entries of a directory, directories end with '/'.",
+            vec![("path", "The path of the directory")],
+        ),
+    ]
+}
+
+impl ToolCall {
+    pub(super) fn eval_fs(&self, config: &GlobalConfig) -> Result<Value> {
+        let arg = |name: &str| self.arguments.get(name).and_then(|v| v.as_str());
+        let Some(path) = arg("path") else {
+            bail!(
+                "The call '{}' has invalid arguments: {}",
+                self.name,
+                self.arguments
+            )
+        };
+        let allowed_dirs = config.read().fs_allowed_dirs.clone();
+        let path = match resolve_allowed_path(&allowed_dirs, path) {
+            Ok(v) => v,
+            Err(err) => return Ok(json!({ "error": err.to_string() })),
+        };
+        let output = match self.name.as_str() {
+            FS_READ_FUNCTION_NAME => fs_read(&path),
+            FS_LIST_FUNCTION_NAME => fs_list(&path),
+            _ => match arg("contents") {
+                Some(contents) => fs_write(config, &path, contents),
+                None => bail!(
+                    "The call '{}' has invalid arguments: {}",
+                    self.name,
+                    self.arguments
+                ),
+            },
+        };
+        Ok(output.unwrap_or_else(|err| json!({ "error": err.to_string() })))
+    }
+}
+
+fn fs_read(path: &Path) -> Result<Value> {
+    if *IS_STDOUT_TERMINAL {
+        println!("{}", dimmed_text(&format!("Read {}", path.display())));
+    }
+    let data = fs::read(path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let truncated = data.len() > FS_READ_LIMIT;
+    let data = &data[..data.len().min(FS_READ_LIMIT)];
+    let contents = match std::str::from_utf8(data) {
+        Ok(v) => v.to_string(),
+        Err(err) if truncated && err.error_len().is_none() => {
+            String::from_utf8_lossy(&data[..err.valid_up_to()]).to_string()
+        }
+        Err(_) => bail!("'{}' is not a text file", path.display()),
+    };
+    Ok(json!({ "contents": contents, "truncated": truncated }))
+}
+
+fn fs_list(path: &Path) -> Result<Value> {
+    if *IS_STDOUT_TERMINAL {
+        println!("{}", dimmed_text(&format!("List {}", path.display())));
+    }
+    let mut entries = vec![];
+    for entry in
+        fs::read_dir(path).with_context(|| format!("Failed to list '{}'", path.display()))?
+    {
+        let entry = entry?;
+        let mut name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().map(|v| v.is_dir()).unwrap_or_default() {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort_unstable();
+    let truncated = entries.len() > FS_LIST_LIMIT;
+    entries.truncate(FS_LIST_LIMIT);
+    Ok(json!({ "entries": entries, "truncated": truncated }))
+}
+
+fn fs_write(config: &GlobalConfig, path: &Path, contents: &str) -> Result<Value> {
+    let old_contents = if path.exists() {
+        fs::read_to_string(path).with_context(|| format!("Failed to read '{}'", path.display()))?
+    } else {
+        String::new()
+    };
+    if old_contents == contents {
+        return Ok(json!({ "written": false, "reason": "The file is unchanged" }));
+    }
+    let action = if path.exists() { "Update" } else { "Create" };
+    if !config.read().trusted_tools.contains(FS_WRITE_FUNCTION_NAME) {
+        if !*IS_STDIN_TERMINAL {
+            bail!("Writing files needs confirmation but there is no terminal, do not retry it");
+        }
+        // Keep piped stdout clean, the prompt itself is on stderr too
+        eprintln!("{}", warning_text(&format!("{action} {}", path.display())));
+        eprint!("{}", render_line_diff(&old_contents, contents, 2));
+        let ans = Confirm::new("Write this file?")
+            .with_default(false)
+            .with_help_message("Use `.trust fs_write` to stop asking in this session")
+            .prompt()?;
+        if !ans {
+            bail!("The user declined to write '{}'", path.display());
+        }
+    } else if *IS_STDOUT_TERMINAL {
+        println!("{}", dimmed_text(&format!("{action} {}", path.display())));
+    }
+    ensure_parent_exists(path)?;
+    fs::write(path, contents).with_context(|| format!("Failed to write '{}'", path.display()))?;
+    Ok(json!({ "written": true, "bytes": contents.len() }))
+}
+
+/// Resolve `path` against the working directory, following symlinks, and make sure it is
+/// inside one of the allowed directories.
+fn resolve_allowed_path(allowed_dirs: &[String], path: &str) -> Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let path = canonicalize_lossy(&cwd.join(expand_tilde(path)))?;
+    let mut allowed = false;
+    for dir in allowed_dirs {
+        if path.starts_with(canonicalize_lossy(&cwd.join(expand_tilde(dir)))?) {
+            allowed = true;
+            break;
+        }
+    }
+    if !allowed {
+        return Err(anyhow!(
+            "'{}' is outside the allowed directories: {}",
+            path.display(),
+            allowed_dirs.join(", ")
+        ));
+    }
+    Ok(path)
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Canonicalize the longest existing ancestor, then append the rest with `..` resolved.
+/// A symlink to a missing target is an error, writing through it would create the target.
+fn canonicalize_lossy(path: &Path) -> Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            _ => normalized.push(component),
+        }
+    }
+    let mut existing = normalized.as_path();
+    let mut rest = vec![];
+    while fs::symlink_metadata(existing).is_err() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let mut output = match existing.canonicalize() {
+        Ok(v) => v,
+        Err(_) if existing.is_symlink() => {
+            bail!("'{}' is a link to a missing target", existing.display())
+        }
+        Err(_) => existing.to_path_buf(),
+    };
+    output.extend(rest.iter().rev());
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let dir = temp_file("-fs-test-", "");
+            fs::create_dir_all(dir.join("sub")).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_resolve_allowed_path() {
+        let dir = TestDir::new();
+        let dir = &dir.0;
+        let allowed = vec![dir.display().to_string()];
+        let file = dir.join("sub/new.txt").display().to_string();
+        assert_eq!(
+            resolve_allowed_path(&allowed, &file).unwrap(),
+            dir.canonicalize().unwrap().join("sub/new.txt")
+        );
+        let escape = dir.join("sub/../../etc/passwd").display().to_string();
+        assert!(resolve_allowed_path(&allowed, &escape).is_err());
+        assert!(resolve_allowed_path(&allowed, "/etc/passwd").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_allowed_path_symlink() {
+        let allowed_dir = TestDir::new();
+        let outside_dir = TestDir::new();
+        let (dir, outside) = (&allowed_dir.0, &outside_dir.0);
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside, dir.join("link")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), dir.join("secret.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.join("sub"), dir.join("inner")).unwrap();
+        let allowed = vec![dir.display().to_string()];
+        let path = |v: &str| dir.join(v).display().to_string();
+        // Links that lead outside are rejected, for existing and new files alike
+        assert!(resolve_allowed_path(&allowed, &path("secret.txt")).is_err());
+        assert!(resolve_allowed_path(&allowed, &path("link/secret.txt")).is_err());
+        assert!(resolve_allowed_path(&allowed, &path("link/new.txt")).is_err());
+        // Dangling links are rejected, writing through them would create their target
+        std::os::unix::fs::symlink(outside.join("new.txt"), dir.join("dangling.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing.txt"), dir.join("dangling2.txt")).unwrap();
+        assert!(resolve_allowed_path(&allowed, &path("dangling.txt")).is_err());
+        assert!(resolve_allowed_path(&allowed, &path("dangling2.txt")).is_err());
+        assert!(!outside.join("new.txt").exists());
+        // Links that stay inside are fine
+        assert_eq!(
+            resolve_allowed_path(&allowed, &path("inner/new.txt")).unwrap(),
+            dir.canonicalize().unwrap().join("sub/new.txt")
+        );
+    }
+
+    #[test]
+    fn test_fs_read() {
+        let dir = TestDir::new();
+        let file = dir.0.join("a.txt");
+        fs::write(&file, "hello").unwrap();
+        assert_eq!(
+            fs_read(&file).unwrap(),
+            json!({ "contents": "hello", "truncated": false })
+        );
+        // Cut at the limit without splitting a character
+        fs::write(&file, format!("{}é", "a".repeat(FS_READ_LIMIT - 1))).unwrap();
+        let output = fs_read(&file).unwrap();
+        assert_eq!(output["truncated"], true);
+        assert_eq!(
+            output["contents"].as_str().unwrap().len(),
+            FS_READ_LIMIT - 1
+        );
+        fs::write(&file, [0xff, 0xfe, 0x00]).unwrap();
+        assert!(fs_read(&file).is_err());
+    }
+
+    #[test]
+    fn test_fs_list() {
+        let dir = TestDir::new();
+        fs::write(dir.0.join("b.txt"), "").unwrap();
+        fs::write(dir.0.join("a.txt"), "").unwrap();
+        assert_eq!(
+            fs_list(&dir.0).unwrap(),
+            json!({ "entries": ["a.txt", "b.txt", "sub/"], "truncated": false })
+        );
+        assert!(fs_list(&dir.0.join("missing")).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! The core of aichat: config, clients, sessions and streaming.
+//!
+//! The `aichat` binary is a thin CLI over this library. To embed the provider abstraction in
+//! another application, build an [`Aichat`] from the usual config directory:
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! let aichat = aichat_core::Aichat::builder().model("openai:gpt-4o-mini").build()?;
+//! let reply = aichat.send("Hello").await?;
+//! aichat.stream("Tell me a joke", |text| print!("{text}")).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The public API is [`Aichat`] with its [`AichatBuilder`], the C API in [`ffi`] and
+//! [`run_cli`], the entry of the `aichat` binary. Everything else is internal.
+
+mod ab;
+mod app;
+mod batch;
+mod chain;
+mod cli;
+mod client;
+mod config;
+mod daemon;
+mod eval;
+pub mod ffi;
+mod function;
+mod plugin;
+mod rag;
+#[cfg(feature = "voice")]
+mod realtime;
+mod render;
+mod repl;
+mod serve;
+mod speak;
+mod stats;
+mod stream_stdin;
+mod summarize;
+mod translate;
+mod tui;
+#[macro_use]
+mod utils;
+
+#[macro_use]
+extern crate log;
+
+use crate::client::{ChatCompletionsOutput, SseEvent, SseHandler};
+use crate::config::{Config, GlobalConfig, Input, WorkingMode};
+use crate::utils::create_abort_signal;
+
+use anyhow::{bail, Result};
+use parking_lot::RwLock;
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::mpsc::unbounded_channel;
+
+/// Run the `aichat` command line with the arguments of the process.
+///
+/// On failure it reports the error and exits the process with the exit code of its class.
+pub async fn run_cli() -> Result<()> {
+    app::main().await
+}
+
+/// Builds an [`Aichat`] from a config directory.
+#[derive(Debug, Default)]
+pub struct AichatBuilder {
+    config_dir: Option<PathBuf>,
+    model: Option<String>,
+    role: Option<String>,
+}
+
+impl AichatBuilder {
+    /// Use this config directory instead of the default one (or `$AICHAT_CONFIG_DIR`).
+    /// It applies to the whole process, building with another one fails.
+    pub fn config_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config_dir = Some(dir.into());
+        self
+    }
+
+    /// Override the configured model, e.g. `openai:gpt-4o` or a model alias.
+    pub fn model(mut self, model_id: impl Into<String>) -> Self {
+        self.model = Some(model_id.into());
+        self
+    }
+
+    /// Use a role for every request.
+    pub fn role(mut self, name: impl Into<String>) -> Self {
+        self.role = Some(name.into());
+        self
+    }
+
+    /// Fails if the config file is missing, it's never created interactively.
+    pub fn build(self) -> Result<Aichat> {
+        if let Some(dir) = &self.config_dir {
+            Config::use_config_dir(dir)?;
+        }
+        let config = Arc::new(RwLock::new(Config::init_with(WorkingMode::Cmd, false)?));
+        if let Some(model_id) = &self.model {
+            config.write().set_model(model_id)?;
+        }
+        if let Some(name) = &self.role {
+            config
+                .write()
+                .use_role_obj(Config::retrieve_role(&config.read(), name)?)?;
+        }
+        Ok(Aichat { config })
+    }
+}
+
+/// A configured client, see the crate docs for an example.
+#[derive(Debug, Clone)]
+pub struct Aichat {
+    config: GlobalConfig,
+}
+
+impl Aichat {
+    pub fn builder() -> AichatBuilder {
+        AichatBuilder::default()
+    }
+
+    /// Send a prompt and wait for the whole reply.
+    pub async fn send(&self, prompt: &str) -> Result<String> {
+        let input = self.input(prompt);
+        let client = input.create_client()?;
+        Ok(client.chat_completions(input).await?.text)
+    }
+
+    /// Send a prompt and call `on_text` with each chunk as it streams, returns the whole reply.
+    pub async fn stream<F>(&self, prompt: &str, on_text: F) -> Result<String>
+    where
+        F: FnMut(&str),
+    {
+        Ok(self.stream_input(&self.input(prompt), on_text).await?.text)
+    }
+
+    async fn stream_input<F>(&self, input: &Input, mut on_text: F) -> Result<ChatCompletionsOutput>
+    where
+        F: FnMut(&str),
+    {
+        let client = input.create_client()?;
+        let abort_signal = create_abort_signal();
+        let (tx, mut rx) = unbounded_channel();
+        let mut handler = SseHandler::new(tx, abort_signal);
+        let forward = async {
+            while let Some(event) = rx.recv().await {
+                match event {
+                    SseEvent::Text(text) => on_text(&text),
+                    SseEvent::Done => break,
+                    _ => {}
+                }
+            }
+        };
+        let (ret, _) = tokio::join!(
+            client.chat_completions_streaming(input, &mut handler),
+            forward
+        );
+        ret?;
+        if handler.abort().aborted() {
+            bail!("Aborted.");
+        }
+        let (input_tokens, output_tokens) = handler.usage();
+        let (provider, cost) = handler.routing();
+        let (text, tool_calls, citations) = handler.take();
+        Ok(ChatCompletionsOutput {
+            text,
+            tool_calls,
+            input_tokens,
+            output_tokens,
+            citations,
+            provider,
+            cost,
+            ..Default::default()
+        })
+    }
+
+    fn input(&self, prompt: &str) -> Input {
+        Input::from_str(&self.config, prompt, None)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{fs, path::Path, sync::OnceLock};
+
+    /// The config dir is per process, so the tests share one with a canned reply
+    pub(crate) fn test_config_dir() -> &'static Path {
+        static DIR: OnceLock<PathBuf> = OnceLock::new();
+        DIR.get_or_init(|| {
+            let dir = crate::utils::temp_file("-lib-test-", "");
+            fs::create_dir_all(&dir).unwrap();
+            let config = "model: openai:gpt-4o-mini\ndry_run: true\nmock:\n  text: 'You said: {{input}}'\nclients:\n  - type: openai\n    api_key: sk-test\n";
+            fs::write(dir.join("config.yaml"), config).unwrap();
+            dir
+        })
+    }
+
+    #[tokio::test]
+    async fn test_send_and_stream() {
+        let aichat = Aichat::builder()
+            .config_dir(test_config_dir())
+            .build()
+            .unwrap();
+        assert_eq!(aichat.send("hi").await.unwrap(), "You said: hi");
+        let mut chunks = String::new();
+        let reply = aichat
+            .stream("hello", |text| chunks.push_str(text))
+            .await
+            .unwrap();
+        assert_eq!(reply, "You said: hello");
+        assert_eq!(chunks, reply);
+    }
+
+    #[test]
+    fn test_build_with_another_config_dir() {
+        Config::use_config_dir(test_config_dir()).unwrap();
+        let err = Aichat::builder()
+            .config_dir(std::env::temp_dir())
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("only one is supported"));
+    }
+}
@@ -0,0 +1,496 @@
+//! WASI plugins dropped into `<config-dir>/plugins/*.wasm`.
+//!
+//! A plugin is a WASI command (`_start`). Every invocation gets one JSON request on stdin and
+//! writes its reply to stdout:
+//!
+//! - `{"type": "manifest"}`: print `{"clients": [{"name", "models": [...]}], "tools": [<function declaration>...]}`
+//! - `{"type": "chat", "client": "<name>", "body": <OpenAI chat completions body>}`: print one event per line,
+//!   the same events as the `exec` client (`{"text"}`, `{"tool_call"}`, `{"usage"}`, `{"error"}` or plain text)
+//! - `{"type": "tool", "name": "<name>", "arguments": {...}}`: print the JSON result
+//!
+//! Only stdio, args/environ (empty), clocks, random and `proc_exit` are provided, there is no filesystem or network access.
+//! An invocation is stopped once it runs out of fuel. The manifest is cached in `<name>.manifest.json` until the plugin changes.
+
+use crate::client::ModelData;
+use crate::function::FunctionDeclaration;
+use crate::utils::random_bytes;
+
+use anyhow::{anyhow, bail, Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::sync::mpsc::UnboundedSender;
+use wasmi::{Caller, CompilationMode, Engine, Linker, Memory, Module, Store};
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_FAULT: i32 = 21;
+const ERRNO_NOSYS: i32 = 52;
+const ERRNO_SPIPE: i32 = 70;
+const ERRNO_INVAL: i32 = 28;
+
+/// Fuel of one invocation, roughly the number of executed instructions
+const PLUGIN_FUEL: u64 = 20_000_000_000;
+
+lazy_static::lazy_static! {
+    static ref ENGINE: Engine = {
+        let mut config = wasmi::Config::default();
+        config
+            .consume_fuel(true)
+            .compilation_mode(CompilationMode::Lazy);
+        Engine::new(&config)
+    };
+    static ref MODULES: Mutex<HashMap<PathBuf, (SystemTime, Arc<Module>)>> = Default::default();
+}
+
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+    pub manifest: PluginManifest,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PluginManifest {
+    #[serde(default)]
+    pub clients: Vec<PluginClientManifest>,
+    #[serde(default)]
+    pub tools: Vec<FunctionDeclaration>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PluginClientManifest {
+    pub name: String,
+    #[serde(default)]
+    pub models: Vec<ModelData>,
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .and_then(|v| v.to_str())
+            .ok_or_else(|| anyhow!("Invalid plugin path '{}'", path.display()))?
+            .to_string();
+        let modified = fs::metadata(path).and_then(|v| v.modified()).ok();
+        let cache_path = path.with_extension("manifest.json");
+        let manifest = match modified.and_then(|v| read_cached_manifest(&cache_path, v)) {
+            Some(manifest) => manifest,
+            None => {
+                let output = run_plugin(path, &json!({ "type": "manifest" }), None)?;
+                let manifest: PluginManifest = serde_json::from_slice(&output)
+                    .with_context(|| format!("Invalid manifest of plugin '{name}'"))?;
+                if let Some(modified) = modified {
+                    write_cached_manifest(&cache_path, modified, &manifest);
+                }
+                manifest
+            }
+        };
+        Ok(Self {
+            name,
+            path: path.to_path_buf(),
+            manifest,
+        })
+    }
+
+    pub fn has_tool(&self, name: &str) -> bool {
+        self.manifest.tools.iter().any(|v| v.name == name)
+    }
+
+    pub fn call_tool(&self, name: &str, arguments: &Value) -> Result<Value> {
+        let request = json!({ "type": "tool", "name": name, "arguments": arguments });
+        let output = run_plugin(&self.path, &request, None)?;
+        let output = String::from_utf8_lossy(&output);
+        Ok(serde_json::from_str(&output).unwrap_or_else(|_| json!({ "output": output })))
+    }
+}
+
+/// The manifest is kept in `<name>.manifest.json` so startup doesn't run every plugin
+#[derive(Deserialize, Serialize)]
+struct CachedManifest {
+    modified: SystemTime,
+    manifest: PluginManifest,
+}
+
+fn read_cached_manifest(path: &Path, modified: SystemTime) -> Option<PluginManifest> {
+    let cached: CachedManifest = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+    (cached.modified == modified).then_some(cached.manifest)
+}
+
+fn write_cached_manifest(path: &Path, modified: SystemTime, manifest: &PluginManifest) {
+    let cached = CachedManifest {
+        modified,
+        manifest: manifest.clone(),
+    };
+    if let Ok(data) = serde_json::to_vec(&cached) {
+        let _ = fs::write(path, data);
+    }
+}
+
+/// Compile the plugin once per modification, function bodies are compiled on first call
+fn load_module(path: &Path) -> Result<Arc<Module>> {
+    let modified = fs::metadata(path)
+        .and_then(|v| v.modified())
+        .with_context(|| format!("Failed to read plugin '{}'", path.display()))?;
+    if let Some((time, module)) = MODULES.lock().get(path) {
+        if *time == modified {
+            return Ok(module.clone());
+        }
+    }
+    let wasm =
+        fs::read(path).with_context(|| format!("Failed to read plugin '{}'", path.display()))?;
+    let module = Module::new(&ENGINE, &wasm[..])
+        .map(Arc::new)
+        .map_err(|err| anyhow!("Invalid plugin '{}', {err}", path.display()))?;
+    MODULES
+        .lock()
+        .insert(path.to_path_buf(), (modified, module.clone()));
+    Ok(module)
+}
+
+pub fn load_plugins(dir: &Path) -> Vec<Plugin> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|v| v.path())
+        .filter(|v| v.extension().map(|v| v == "wasm").unwrap_or_default())
+        .collect();
+    paths.sort_unstable();
+    paths
+        .into_iter()
+        .filter_map(|path| match Plugin::load(&path) {
+            Ok(plugin) => Some(plugin),
+            Err(err) => {
+                warn!("Failed to load plugin '{}', {err:#}", path.display());
+                None
+            }
+        })
+        .collect()
+}
+
+struct WasiCtx {
+    stdin: Vec<u8>,
+    stdin_pos: usize,
+    stdout: Vec<u8>,
+    stdout_sender: Option<UnboundedSender<Vec<u8>>>,
+    stderr: Vec<u8>,
+}
+
+/// Run the plugin with `request` on stdin.
+///
+/// Stdout is forwarded chunk by chunk to `stdout_sender` if given, otherwise it's returned once the plugin exits.
+pub fn run_plugin(
+    path: &Path,
+    request: &Value,
+    stdout_sender: Option<UnboundedSender<Vec<u8>>>,
+) -> Result<Vec<u8>> {
+    run_plugin_with_fuel(path, request, stdout_sender, PLUGIN_FUEL)
+}
+
+fn run_plugin_with_fuel(
+    path: &Path,
+    request: &Value,
+    stdout_sender: Option<UnboundedSender<Vec<u8>>>,
+    fuel: u64,
+) -> Result<Vec<u8>> {
+    let module = load_module(path)?;
+    let mut store = Store::new(
+        &ENGINE,
+        WasiCtx {
+            stdin: request.to_string().into_bytes(),
+            stdin_pos: 0,
+            stdout: vec![],
+            stdout_sender,
+            stderr: vec![],
+        },
+    );
+    store.set_fuel(fuel).map_err(|err| anyhow!("{err}"))?;
+    let mut linker = <Linker<WasiCtx>>::new(&ENGINE);
+    define_wasi(&mut linker).map_err(|err| anyhow!("{err}"))?;
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|v| v.start(&mut store))
+        .map_err(|err| anyhow!("Failed to instantiate plugin '{}', {err}", path.display()))?;
+    let start = instance
+        .get_typed_func::<(), ()>(&store, "_start")
+        .map_err(|_| anyhow!("Plugin '{}' has no '_start' export", path.display()))?;
+    if let Err(err) = start.call(&mut store, ()) {
+        match err.i32_exit_status() {
+            Some(0) => {}
+            Some(code) => {
+                let stderr = String::from_utf8_lossy(&store.data().stderr);
+                bail!("Plugin exited with code {code}: {}", stderr.trim())
+            }
+            None if err.as_trap_code() == Some(wasmi::core::TrapCode::OutOfFuel) => {
+                bail!("Plugin '{}' ran too long and was stopped", path.display())
+            }
+            None => bail!("Plugin '{}' trapped, {err}", path.display()),
+        }
+    }
+    let stderr = &store.data().stderr;
+    if !stderr.is_empty() {
+        debug!("plugin stderr: {}", String::from_utf8_lossy(stderr));
+    }
+    Ok(std::mem::take(&mut store.data_mut().stdout))
+}
+
+fn memory(caller: &Caller<'_, WasiCtx>) -> Option<Memory> {
+    caller.get_export("memory").and_then(|v| v.into_memory())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+fn write_bytes(caller: &mut Caller<'_, WasiCtx>, offset: i32, bytes: &[u8]) -> i32 {
+    let Ok(offset) = usize::try_from(offset) else {
+        return ERRNO_FAULT;
+    };
+    match memory(caller) {
+        Some(memory) => match memory.write(caller, offset, bytes) {
+            Ok(_) => ERRNO_SUCCESS,
+            Err(_) => ERRNO_FAULT,
+        },
+        None => ERRNO_FAULT,
+    }
+}
+
+/// Collect the `(ptr, len)` pairs of an iovec array, every buffer must lie within `data`
+fn read_iovecs(data: &[u8], iovs: i32, iovs_len: i32) -> Option<Vec<(usize, usize)>> {
+    let iovs = usize::try_from(iovs).ok()?;
+    let iovs_len = usize::try_from(iovs_len).ok()?;
+    if iovs_len.checked_mul(8)?.checked_add(iovs)? > data.len() {
+        return None;
+    }
+    (0..iovs_len)
+        .map(|i| {
+            let offset = iovs + i * 8;
+            let ptr = read_u32(data, offset)? as usize;
+            let len = read_u32(data, offset + 4)? as usize;
+            (ptr.checked_add(len)? <= data.len()).then_some((ptr, len))
+        })
+        .collect()
+}
+
+fn define_wasi(linker: &mut Linker<WasiCtx>) -> Result<(), wasmi::errors::LinkerError> {
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_write",
+        |mut caller: Caller<'_, WasiCtx>, fd: i32, iovs: i32, iovs_len: i32, nwritten: i32| {
+            let Some(memory) = memory(&caller) else {
+                return ERRNO_FAULT;
+            };
+            let data = memory.data(&caller);
+            let Some(iovecs) = read_iovecs(data, iovs, iovs_len) else {
+                return ERRNO_FAULT;
+            };
+            let mut bytes = vec![];
+            for (ptr, len) in iovecs {
+                bytes.extend_from_slice(&data[ptr..ptr + len]);
+            }
+            let written = (bytes.len() as u32).to_le_bytes();
+            let ctx = caller.data_mut();
+            match fd {
+                1 => match &ctx.stdout_sender {
+                    Some(sender) => {
+                        let _ = sender.send(bytes);
+                    }
+                    None => ctx.stdout.extend(bytes),
+                },
+                2 => ctx.stderr.extend(bytes),
+                _ => return ERRNO_BADF,
+            }
+            write_bytes(&mut caller, nwritten, &written)
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_read",
+        |mut caller: Caller<'_, WasiCtx>, fd: i32, iovs: i32, iovs_len: i32, nread: i32| {
+            if fd != 0 {
+                return ERRNO_BADF;
+            }
+            let Some(memory) = memory(&caller) else {
+                return ERRNO_FAULT;
+            };
+            let Some(iovecs) = read_iovecs(memory.data(&caller), iovs, iovs_len) else {
+                return ERRNO_FAULT;
+            };
+            let mut total = 0;
+            for (ptr, len) in iovecs {
+                let ctx = caller.data();
+                let remaining = &ctx.stdin[ctx.stdin_pos..];
+                let chunk = remaining[..len.min(remaining.len())].to_vec();
+                if chunk.is_empty() {
+                    break;
+                }
+                if memory.write(&mut caller, ptr, &chunk).is_err() {
+                    return ERRNO_FAULT;
+                }
+                caller.data_mut().stdin_pos += chunk.len();
+                total += chunk.len();
+            }
+            write_bytes(&mut caller, nread, &(total as u32).to_le_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_fdstat_get",
+        |mut caller: Caller<'_, WasiCtx>, fd: i32, stat: i32| {
+            if !(0..=2).contains(&fd) {
+                return ERRNO_BADF;
+            }
+            // filetype = character device, no flags, all rights
+            let mut bytes = [0u8; 24];
+            bytes[0] = 2;
+            bytes[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+            write_bytes(&mut caller, stat, &bytes)
+        },
+    )?;
+    linker.func_wrap(WASI_MODULE, "fd_close", |_: Caller<'_, WasiCtx>, _: i32| {
+        ERRNO_SUCCESS
+    })?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_seek",
+        |_: Caller<'_, WasiCtx>, _: i32, _: i64, _: i32, _: i32| ERRNO_SPIPE,
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_prestat_get",
+        |_: Caller<'_, WasiCtx>, _: i32, _: i32| ERRNO_BADF,
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_prestat_dir_name",
+        |_: Caller<'_, WasiCtx>, _: i32, _: i32, _: i32| ERRNO_BADF,
+    )?;
+    for name in ["environ_sizes_get", "args_sizes_get"] {
+        linker.func_wrap(
+            WASI_MODULE,
+            name,
+            |mut caller: Caller<'_, WasiCtx>, count: i32, size: i32| {
+                let ret = write_bytes(&mut caller, count, &0u32.to_le_bytes());
+                if ret != ERRNO_SUCCESS {
+                    return ret;
+                }
+                write_bytes(&mut caller, size, &0u32.to_le_bytes())
+            },
+        )?;
+    }
+    for name in ["environ_get", "args_get"] {
+        linker.func_wrap(
+            WASI_MODULE,
+            name,
+            |_: Caller<'_, WasiCtx>, _: i32, _: i32| ERRNO_SUCCESS,
+        )?;
+    }
+    linker.func_wrap(
+        WASI_MODULE,
+        "random_get",
+        |mut caller: Caller<'_, WasiCtx>, buf: i32, len: i32| {
+            let (Ok(offset), Ok(len)) = (usize::try_from(buf), usize::try_from(len)) else {
+                return ERRNO_INVAL;
+            };
+            let Some(memory) = memory(&caller) else {
+                return ERRNO_FAULT;
+            };
+            match offset.checked_add(len) {
+                Some(end) if end <= memory.data(&caller).len() => {}
+                _ => return ERRNO_FAULT,
+            }
+            match random_bytes(len) {
+                Ok(bytes) => write_bytes(&mut caller, buf, &bytes),
+                Err(_) => ERRNO_NOSYS,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "clock_time_get",
+        |mut caller: Caller<'_, WasiCtx>, _: i32, _: i64, time: i32| {
+            let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+            write_bytes(&mut caller, time, &nanos.to_le_bytes())
+        },
+    )?;
+    linker.func_wrap(WASI_MODULE, "sched_yield", |_: Caller<'_, WasiCtx>| {
+        ERRNO_SUCCESS
+    })?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "poll_oneoff",
+        |_: Caller<'_, WasiCtx>, _: i32, _: i32, _: i32, _: i32| ERRNO_NOSYS,
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "proc_exit",
+        |_: Caller<'_, WasiCtx>, code: i32| -> Result<(), wasmi::Error> {
+            Err(wasmi::Error::i32_exit(code))
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_iovecs() {
+        let mut data = vec![0u8; 256];
+        data[0..4].copy_from_slice(&64u32.to_le_bytes());
+        data[4..8].copy_from_slice(&10u32.to_le_bytes());
+        data[8..12].copy_from_slice(&128u32.to_le_bytes());
+        data[12..16].copy_from_slice(&5u32.to_le_bytes());
+        assert_eq!(read_iovecs(&data, 0, 2), Some(vec![(64, 10), (128, 5)]));
+        assert_eq!(read_iovecs(&data, 8, 2), Some(vec![(128, 5), (0, 0)]));
+        assert_eq!(read_iovecs(&data, 252, 1), None);
+    }
+
+    #[test]
+    fn test_read_iovecs_rejects_out_of_bounds() {
+        let mut data = vec![0u8; 16];
+        data[0..4].copy_from_slice(&8u32.to_le_bytes());
+        data[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_iovecs(&data, 0, -1), None);
+        assert_eq!(read_iovecs(&data, -8, 1), None);
+        assert_eq!(read_iovecs(&data, 0, i32::MAX), None);
+        assert_eq!(read_iovecs(&data, 0, 1), None);
+    }
+
+    #[test]
+    fn test_run_plugin_out_of_fuel() {
+        // (module (func (export "_start") (loop (br 0))))
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x07, 0x0a, 0x01, 0x06, b'_', b's', b't', b'a', b'r', b't',
+            0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b,
+        ];
+        let path = crate::utils::temp_file("-plugin-", ".wasm");
+        fs::write(&path, wasm).unwrap();
+        let ret = run_plugin_with_fuel(&path, &json!({}), None, 1_000_000);
+        let _ = fs::remove_file(&path);
+        assert!(ret.unwrap_err().to_string().contains("ran too long"));
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest: PluginManifest = serde_json::from_str(
+            r#"{"clients":[{"name":"echo","models":[{"name":"echo-1"}]}],"tools":[{"name":"add","description":"Add numbers","parameters":{"type":"object","properties":{}}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.clients[0].models[0].name, "echo-1");
+        assert_eq!(manifest.tools[0].name, "add");
+    }
+}
@@ -1,5 +1,7 @@
 use self::splitter::*;
 
+pub use self::splitter::{get_separators, RecursiveCharacterTextSplitter};
+
 use crate::client::*;
 use crate::config::*;
 use crate::utils::*;
@@ -100,6 +102,59 @@ impl Rag {
         Ok(rag)
     }
 
+    /// `rag_embedding_model`, or else the first embedding model available
+    pub fn default_embedding_model(config: &Config) -> Result<Model> {
+        let embedding_model_id = match config.rag_embedding_model.clone() {
+            Some(value) => value,
+            None => match list_models(config, ModelType::Embedding).first() {
+                Some(model) => model.id(),
+                None => bail!("No available embedding model"),
+            },
+        };
+        Model::retrieve_model(config, &embedding_model_id, ModelType::Embedding)
+    }
+
+    /// An unsaved RAG over `doc_paths`, configured without prompting, for documents too large
+    /// to send whole.
+    pub async fn init_in_memory(
+        config: &GlobalConfig,
+        doc_paths: &[String],
+        abort_signal: AbortSignal,
+    ) -> Result<Self> {
+        let embedding_model = Self::default_embedding_model(&config.read())?;
+        let (chunk_size, chunk_overlap, reranker_model, top_k, save_path, loaders) = {
+            let config = config.read();
+            let chunk_size = config
+                .rag_chunk_size
+                .unwrap_or_else(|| embedding_model.default_chunk_size());
+            (
+                chunk_size,
+                config.rag_chunk_overlap.unwrap_or(chunk_size / 20),
+                config.rag_reranker_model.clone(),
+                config.rag_top_k,
+                config.rag_file(TEMP_RAG_NAME),
+                config.document_loaders.clone(),
+            )
+        };
+        let data = RagData::new(
+            embedding_model.id(),
+            chunk_size,
+            chunk_overlap,
+            reranker_model,
+            top_k,
+            embedding_model.max_batch_size(),
+        );
+        let mut rag = Self::create(config, TEMP_RAG_NAME, &save_path, data)?;
+        let (spinner, spinner_rx) = Spinner::create("");
+        abortable_run_with_spinner_rx(
+            rag.sync_documents(doc_paths, true, loaders, Some(spinner)),
+            spinner_rx,
+            abort_signal,
+        )
+        .await?;
+        Ok(rag)
+    }
+
     pub fn load(config: &GlobalConfig, name: &str, path: &Path) -> Result<Self> {
         let err = || format!("Failed to load rag '{name}' at '{}'", path.display());
         let content = fs::read_to_string(path).with_context(err)?;
@@ -204,6 +259,17 @@ impl Rag {
         self.last_sources.read().clone()
     }
 
+    pub fn citations(&self, ids: &[DocumentId]) -> Vec<Citation> {
+        let mut citations = vec![];
+        for id in ids {
+            let (file_index, _) = id.split();
+            if let Some(file) = self.data.files.get(&file_index) {
+                Citation::merge(&mut citations, Citation::new(&file.path, ""));
+            }
+        }
+        citations
+    }
+
     pub fn set_last_sources(&self, ids: &[DocumentId]) {
         let mut sources: IndexMap<String, Vec<String>> = IndexMap::new();
         for id in ids {
@@ -955,10 +1021,7 @@ fn reciprocal_rank_fusion(
 ) -> Vec<DocumentId> {
     let rrf_k = top_k * 2;
     let mut map: IndexMap<DocumentId, f32> = IndexMap::new();
-    for (document_ids, weight) in list_of_document_ids
-        .into_iter()
-        .zip(list_of_weights.into_iter())
-    {
+    for (document_ids, weight) in list_of_document_ids.into_iter().zip(list_of_weights) {
         for (index, &item) in document_ids.iter().enumerate() {
             *map.entry(item).or_default() += (1.0 / ((rrf_k + index + 1) as f32)) * weight;
         }
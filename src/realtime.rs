@@ -0,0 +1,227 @@
+use crate::client::{connect_realtime, RealtimeEvent, REALTIME_OUTPUT_SAMPLE_RATE};
+use crate::config::{GlobalConfig, Input};
+use crate::render::render_error;
+use crate::utils::{
+    dimmed_text, spawn_audio_stream, wait_abort_signal, AbortSignal, DEFAULT_MIC_COMMAND,
+    DEFAULT_PLAYER_COMMAND,
+};
+
+use anyhow::{anyhow, bail, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::io::{stdout, Write};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Child,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Speaker {
+    User,
+    Assistant,
+}
+
+/// Run `--realtime`, a live voice conversation with the current model.
+///
+/// The microphone is streamed up as the user talks, audio replies are played as they arrive
+/// and both sides are transcribed in the terminal.
+pub async fn run(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
+    let input = Input::from_str(config, "", None);
+    let client = input.create_client()?;
+    let request = client.prepare_realtime()?;
+    let provider = request.provider;
+    let (mic_command, player_command) = {
+        let config = config.read();
+        (
+            config.realtime_mic_command.clone(),
+            config.realtime_player_command.clone(),
+        )
+    };
+    let mic_command = mic_command.unwrap_or_else(|| DEFAULT_MIC_COMMAND.to_string());
+    let player_command = player_command.unwrap_or_else(|| DEFAULT_PLAYER_COMMAND.to_string());
+
+    let stream = connect_realtime(&request).await?;
+    let (mut sink, mut source) = stream.split();
+    let setup = provider.setup_message(&request.model, input.role().prompt());
+    sink.send(Message::Text(setup.to_string())).await?;
+
+    let mut mic = spawn_audio_stream(&mic_command, provider.input_sample_rate(), true)?;
+    let mut mic_output = mic
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("No microphone output"))?;
+    let (audio_sender, audio_receiver) = mpsc::unbounded_channel();
+    let (generation_sender, generation_receiver) = watch::channel(0u64);
+    let mut player = spawn_player(player_command, audio_receiver, generation_receiver);
+    let mut generation = 0;
+    let mut ready = provider.ready_on_connect();
+
+    println!(
+        "{}",
+        dimmed_text(&format!(
+            "Realtime session with {} (experimental), start talking, Ctrl+C to quit",
+            client.model().id()
+        ))
+    );
+
+    // Send the microphone audio in chunks of 100ms
+    let mut buf = vec![0u8; provider.input_sample_rate() as usize / 10 * 2];
+    let mut speaker: Option<Speaker> = None;
+    loop {
+        tokio::select! {
+            size = mic_output.read(&mut buf), if ready => {
+                let size = size?;
+                if size == 0 {
+                    let status = mic.wait().await?;
+                    if status.code().is_some_and(|v| v != 0) {
+                        bail!("The microphone command `{mic_command}` exited with {status}");
+                    }
+                    break;
+                }
+                let message = provider.audio_message(&buf[..size]);
+                sink.send(Message::Text(message.to_string())).await?;
+            }
+            message = source.next() => {
+                let data: Value = match message {
+                    None => break,
+                    Some(message) => match message? {
+                        Message::Text(text) => serde_json::from_str(&text)?,
+                        Message::Binary(data) => serde_json::from_slice(&data)?,
+                        Message::Close(frame) => {
+                            match frame {
+                                Some(frame) if !frame.reason.is_empty() => bail!("{}", frame.reason),
+                                _ => break,
+                            }
+                        }
+                        _ => continue,
+                    },
+                };
+                for event in provider.parse_event(&data) {
+                    match event {
+                        RealtimeEvent::Audio(pcm) => {
+                            let _ = audio_sender.send((generation, pcm));
+                        }
+                        RealtimeEvent::Transcript(text) => {
+                            switch_speaker(&mut speaker, Some(Speaker::Assistant));
+                            print!("{text}");
+                        }
+                        RealtimeEvent::InputTranscript(text) => {
+                            switch_speaker(&mut speaker, Some(Speaker::User));
+                            print!("{}", dimmed_text(&text));
+                        }
+                        RealtimeEvent::Interrupted => {
+                            // Drop the audio of the reply still queued for the player
+                            generation += 1;
+                            let _ = generation_sender.send(generation);
+                        }
+                        RealtimeEvent::Ready => ready = true,
+                        RealtimeEvent::TurnDone => switch_speaker(&mut speaker, None),
+                        RealtimeEvent::Error(message) => {
+                            switch_speaker(&mut speaker, None);
+                            render_error(anyhow!(message));
+                        }
+                    }
+                }
+                stdout().flush()?;
+            }
+            ret = &mut player => {
+                ret??;
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = wait_abort_signal(&abort_signal) => break,
+        }
+    }
+    player.abort();
+    switch_speaker(&mut speaker, None);
+    Ok(())
+}
+
+/// Play the reply audio from its own task, the player only consumes it in real time and
+/// a full pipe must not hold up the microphone.
+///
+/// Audio is tagged with the generation it belongs to, a new generation kills the player
+/// at once and the audio of older ones is dropped.
+fn spawn_player(
+    command: String,
+    mut audio: mpsc::UnboundedReceiver<(u64, Vec<u8>)>,
+    mut generation: watch::Receiver<u64>,
+) -> JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        let mut current = *generation.borrow_and_update();
+        let mut player = spawn_audio_stream(&command, REALTIME_OUTPUT_SAMPLE_RATE, false)?;
+        loop {
+            let interrupted = tokio::select! {
+                chunk = audio.recv() => {
+                    let Some((chunk_generation, pcm)) = chunk else {
+                        break;
+                    };
+                    if chunk_generation != current {
+                        continue;
+                    }
+                    let stdin = player
+                        .stdin
+                        .as_mut()
+                        .ok_or_else(|| anyhow!("No player input"))?;
+                    tokio::select! {
+                        ret = stdin.write_all(&pcm) => {
+                            ret.map_err(|err| anyhow!("The player `{command}` failed, {err}"))?;
+                            false
+                        }
+                        ret = generation.changed() => ret.is_ok(),
+                    }
+                }
+                ret = generation.changed() => ret.is_ok(),
+            };
+            if interrupted {
+                current = *generation.borrow_and_update();
+                player = restart_player(player, &command).await?;
+            }
+        }
+        Ok(())
+    })
+}
+
+async fn restart_player(mut player: Child, command: &str) -> Result<Child> {
+    let _ = player.kill().await;
+    spawn_audio_stream(command, REALTIME_OUTPUT_SAMPLE_RATE, false)
+}
+
+fn switch_speaker(speaker: &mut Option<Speaker>, next: Option<Speaker>) {
+    if *speaker == next {
+        return;
+    }
+    if speaker.is_some() {
+        println!();
+    }
+    if next == Some(Speaker::User) {
+        print!("{}", dimmed_text("> "));
+    }
+    *speaker = next;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_player_interrupt() {
+        // A player that never reads, writing the reply blocks once the pipe is full
+        let (audio_sender, audio_receiver) = mpsc::unbounded_channel();
+        let (generation_sender, generation_receiver) = watch::channel(0u64);
+        let player = spawn_player("sleep 30".into(), audio_receiver, generation_receiver);
+        audio_sender.send((0, vec![0; 1024 * 1024])).unwrap();
+        audio_sender.send((0, vec![0; 1024])).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        generation_sender.send(1).unwrap();
+        audio_sender.send((1, vec![0; 1024])).unwrap();
+        drop(audio_sender);
+        let ret = tokio::time::timeout(Duration::from_secs(5), player).await;
+        assert!(ret.unwrap().unwrap().is_ok());
+    }
+}
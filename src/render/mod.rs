@@ -16,9 +16,12 @@ pub async fn render_stream(
     abort_signal: AbortSignal,
 ) -> Result<()> {
     let ret = if *IS_STDOUT_TERMINAL {
-        let render_options = config.read().render_options()?;
+        let (render_options, stream_status) = {
+            let config = config.read();
+            (config.render_options()?, config.stream_status)
+        };
         let mut render = MarkdownRender::init(render_options)?;
-        markdown_stream(rx, &mut render, &abort_signal).await
+        markdown_stream(rx, &mut render, &abort_signal, stream_status).await
     } else {
         raw_stream(rx, &abort_signal).await
     };
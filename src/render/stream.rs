@@ -1,6 +1,8 @@
 use super::{MarkdownRender, SseEvent};
 
-use crate::utils::{poll_abort_signal, spawn_spinner, AbortSignal};
+use crate::utils::{
+    dimmed_text, estimate_token_length, poll_abort_signal, spawn_spinner, AbortSignal,
+};
 
 use anyhow::Result;
 use crossterm::{
@@ -9,7 +11,7 @@ use crossterm::{
 };
 use std::{
     io::{self, stdout, Stdout, Write},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use textwrap::core::display_width;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -18,11 +20,13 @@ pub async fn markdown_stream(
     rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
+    stream_status: bool,
 ) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
 
-    let ret = markdown_stream_inner(rx, render, abort_signal, &mut stdout).await;
+    let status = stream_status.then(StatusLine::new);
+    let ret = markdown_stream_inner(rx, render, abort_signal, status, &mut stdout).await;
 
     disable_raw_mode()?;
 
@@ -49,20 +53,29 @@ pub async fn raw_stream(
                 SseEvent::Done => {
                     break;
                 }
+                _ => {}
             }
         }
     }
     Ok(())
 }
 
+/// Tokens arriving within this interval are rendered together
+const COALESCE_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Tokens of the current line are printed as they come, a line is only highlighted (and
+/// the cursor position only queried) once it is complete.
 async fn markdown_stream_inner(
     mut rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
+    mut status: Option<StatusLine>,
     writer: &mut Stdout,
 ) -> Result<()> {
+    // The current line, printed as is
     let mut buffer = String::new();
-    let mut buffer_rows = 1;
+    // The tool call being streamed, shown dimmed on its own line until it completes
+    let mut tool_preview: Option<(String, String)> = None;
 
     let columns = terminal::size()?.0;
 
@@ -70,71 +83,59 @@ async fn markdown_stream_inner(
 
     'outer: loop {
         if abort_signal.aborted() {
-            return Ok(());
+            break;
         }
         for reply_event in gather_events(&mut rx).await {
             if let Some(spinner) = spinner.take() {
                 spinner.stop();
             }
+            if let Some(status) = status.as_mut() {
+                status.clear(writer)?;
+            }
 
             match reply_event {
-                SseEvent::Text(mut text) => {
+                SseEvent::Text(text) => {
+                    if let Some(status) = status.as_mut() {
+                        status.tokens += estimate_token_length(&text);
+                    }
                     // tab width hacking
-                    text = text.replace('\t', "    ");
-
-                    let mut attempts = 0;
-                    let (col, mut row) = loop {
-                        match cursor::position() {
-                            Ok(pos) => break pos,
-                            Err(_) if attempts < 3 => attempts += 1,
-                            Err(e) => return Err(e.into()),
+                    let text = text.replace('\t', "    ");
+                    let printed_len = buffer.len();
+                    match complete_lines(&mut buffer, &text) {
+                        Some(lines) => {
+                            rewind_line(writer, &lines[..printed_len], columns)?;
+                            let output = render.render(&lines);
+                            print_block(writer, &output, columns)?;
+                            queue!(writer, style::Print(&buffer))?;
                         }
-                    };
-
-                    // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
-                    if col == 0 && row > 0 && display_width(&buffer) == columns as usize {
-                        row -= 1;
+                        None => queue!(writer, style::Print(&text))?,
                     }
-
-                    if row + 1 >= buffer_rows {
-                        queue!(writer, cursor::MoveTo(0, row + 1 - buffer_rows),)?;
-                    } else {
-                        let scroll_rows = buffer_rows - row - 1;
+                    writer.flush()?;
+                }
+                SseEvent::ToolCallStart { name, .. } => {
+                    if !buffer.is_empty() {
+                        finish_line(writer, render, &buffer, columns)?;
+                        queue!(writer, style::Print("\r\n"))?;
+                        buffer.clear();
+                    }
+                    print_tool_preview(writer, &name, "", columns)?;
+                    tool_preview = Some((name, String::new()));
+                }
+                SseEvent::ToolCallDelta(text) => {
+                    if let Some((name, arguments)) = tool_preview.as_mut() {
+                        arguments.push_str(&text);
+                        print_tool_preview(writer, name, arguments, columns)?;
+                    }
+                }
+                SseEvent::ToolCall(_) => {
+                    if tool_preview.take().is_some() {
                         queue!(
                             writer,
-                            terminal::ScrollUp(scroll_rows),
-                            cursor::MoveTo(0, 0),
+                            cursor::MoveToColumn(0),
+                            terminal::Clear(terminal::ClearType::CurrentLine)
                         )?;
+                        writer.flush()?;
                     }
-
-                    // No guarantee that text returned by render will not be re-layouted, so it is better to clear it.
-                    queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
-
-                    if text.contains('\n') {
-                        let text = format!("{buffer}{text}");
-                        let (head, tail) = split_line_tail(&text);
-                        let output = render.render(head);
-                        print_block(writer, &output, columns)?;
-                        buffer = tail.to_string();
-                    } else {
-                        buffer = format!("{buffer}{text}");
-                    }
-
-                    let output = render.render_line(&buffer);
-                    if output.contains('\n') {
-                        let (head, tail) = split_line_tail(&output);
-                        buffer_rows = print_block(writer, head, columns)?;
-                        queue!(writer, style::Print(&tail),)?;
-
-                        // No guarantee the buffer width of the buffer will not exceed the number of columns.
-                        // So we calculate the number of rows needed, rather than setting it directly to 1.
-                        buffer_rows += need_rows(tail, columns);
-                    } else {
-                        queue!(writer, style::Print(&output))?;
-                        buffer_rows = need_rows(&output, columns);
-                    }
-
-                    writer.flush()?;
                 }
                 SseEvent::Done => {
                     break 'outer;
@@ -142,6 +143,10 @@ async fn markdown_stream_inner(
             }
         }
 
+        if let Some(status) = status.as_mut().filter(|_| spinner.is_none()) {
+            status.draw(writer, &buffer, columns)?;
+        }
+
         if poll_abort_signal(abort_signal)? {
             break;
         }
@@ -150,36 +155,204 @@ async fn markdown_stream_inner(
     if let Some(spinner) = spinner.take() {
         spinner.stop();
     }
+    if let Some(status) = status.as_mut() {
+        status.clear(writer)?;
+    }
+    if !buffer.is_empty() {
+        finish_line(writer, render, &buffer, columns)?;
+    }
+    Ok(())
+}
+
+/// With `stream_status`, a dim line under the reply with the time and the tokens so far,
+/// cleared before anything is printed and drawn again after.
+struct StatusLine {
+    start: Instant,
+    tokens: usize,
+    shown: Option<String>,
+}
+
+impl StatusLine {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            tokens: 0,
+            shown: None,
+        }
+    }
+
+    fn text(&self, elapsed: Duration) -> String {
+        format!("{:.1}s · ~{} tokens", elapsed.as_secs_f64(), self.tokens)
+    }
+
+    fn draw(&mut self, writer: &mut impl Write, buffer: &str, columns: u16) -> Result<()> {
+        let text = self.text(self.start.elapsed());
+        if self.shown.as_ref() == Some(&text) {
+            return Ok(());
+        }
+        self.clear(writer)?;
+        if at_row_end(buffer, columns) {
+            return Ok(());
+        }
+        let (col, _) = cursor::position()?;
+        // Printed below the reply, scrolling if needed, then back up to where the reply goes on
+        queue!(
+            writer,
+            style::Print("\r\n"),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::Print(dimmed_text(&text)),
+            cursor::MoveUp(1),
+            cursor::MoveToColumn(col),
+        )?;
+        writer.flush()?;
+        self.shown = Some(text);
+        Ok(())
+    }
+
+    fn clear(&mut self, writer: &mut impl Write) -> Result<()> {
+        if self.shown.take().is_some() {
+            queue!(
+                writer,
+                cursor::SavePosition,
+                cursor::MoveToNextLine(1),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                cursor::RestorePosition,
+            )?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Append streamed text to the current line, returning the lines it completes, if any.
+fn complete_lines(buffer: &mut String, text: &str) -> Option<String> {
+    match text.rsplit_once('\n') {
+        Some((head, tail)) => {
+            let lines = format!("{buffer}{head}");
+            *buffer = tail.to_string();
+            Some(lines)
+        }
+        None => {
+            buffer.push_str(text);
+            None
+        }
+    }
+}
+
+/// At the end of a full row the cursor waits to wrap, it can't be put back there.
+fn at_row_end(buffer: &str, columns: u16) -> bool {
+    let width = display_width(buffer);
+    width > 0 && width.is_multiple_of(columns as usize)
+}
+
+/// Replace the raw current line with its highlighted version.
+fn finish_line(
+    writer: &mut Stdout,
+    render: &MarkdownRender,
+    buffer: &str,
+    columns: u16,
+) -> Result<()> {
+    rewind_line(writer, buffer, columns)?;
+    let output = render.render_line(buffer);
+    match output.rsplit_once('\n') {
+        Some((head, tail)) => {
+            print_block(writer, head, columns)?;
+            queue!(writer, style::Print(tail))?;
+        }
+        None => queue!(writer, style::Print(&output))?,
+    }
+    writer.flush()?;
     Ok(())
 }
 
+/// Move the cursor to the start of the current line, which may span several rows, and clear it.
+fn rewind_line(writer: &mut Stdout, buffer: &str, columns: u16) -> Result<()> {
+    let buffer_rows = need_rows(buffer, columns);
+    let mut attempts = 0;
+    let (col, mut row) = loop {
+        match cursor::position() {
+            Ok(pos) => break pos,
+            Err(_) if attempts < 3 => attempts += 1,
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
+    if col == 0 && row > 0 && display_width(buffer) == columns as usize {
+        row -= 1;
+    }
+
+    if row + 1 >= buffer_rows {
+        queue!(writer, cursor::MoveTo(0, row + 1 - buffer_rows))?;
+    } else {
+        let scroll_rows = buffer_rows - row - 1;
+        queue!(
+            writer,
+            terminal::ScrollUp(scroll_rows),
+            cursor::MoveTo(0, 0),
+        )?;
+    }
+    queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+    Ok(())
+}
+
+/// Gather the events of the next coalescing interval in order, merging adjacent texts and argument deltas.
 async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
-    let mut texts = vec![];
-    let mut done = false;
+    let mut events = vec![];
     tokio::select! {
         _ = async {
             while let Some(reply_event) = rx.recv().await {
-                match reply_event {
-                    SseEvent::Text(v) => texts.push(v),
-                    SseEvent::Done => {
-                        done = true;
+                match (events.last_mut(), reply_event) {
+                    (Some(SseEvent::Text(text)), SseEvent::Text(v)) => text.push_str(&v),
+                    (Some(SseEvent::ToolCallDelta(text)), SseEvent::ToolCallDelta(v)) => {
+                        text.push_str(&v)
+                    }
+                    (_, SseEvent::Done) => {
+                        events.push(SseEvent::Done);
                         break;
                     }
+                    (_, event) => events.push(event),
                 }
             }
         } => {}
-        _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+        _ = tokio::time::sleep(COALESCE_INTERVAL) => {}
     };
-    let mut events = vec![];
-    if !texts.is_empty() {
-        events.push(SseEvent::Text(texts.join("")))
-    }
-    if done {
-        events.push(SseEvent::Done)
-    }
     events
 }
 
+/// Show the tail of the arguments when the call doesn't fit in one line.
+fn print_tool_preview(
+    writer: &mut Stdout,
+    name: &str,
+    arguments: &str,
+    columns: u16,
+) -> Result<()> {
+    let arguments = arguments.replace(['\n', '\r'], " ");
+    let max_width = (columns as usize).saturating_sub(1);
+    let mut text = format!("⚙ {name} {arguments}");
+    if display_width(&text) > max_width {
+        let head = format!("⚙ {name} …");
+        let mut width = display_width(&head);
+        let mut tail = vec![];
+        for c in arguments.chars().rev() {
+            width += display_width(c.encode_utf8(&mut [0; 4]));
+            if width > max_width {
+                break;
+            }
+            tail.push(c);
+        }
+        text = format!("{head}{}", tail.into_iter().rev().collect::<String>());
+    }
+    queue!(
+        writer,
+        cursor::MoveToColumn(0),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        style::Print(dimmed_text(&text)),
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
 fn print_block(writer: &mut Stdout, text: &str, columns: u16) -> Result<u16> {
     let mut num = 0;
     for line in text.split('\n') {
@@ -194,15 +367,117 @@ fn print_block(writer: &mut Stdout, text: &str, columns: u16) -> Result<u16> {
     Ok(num)
 }
 
-fn split_line_tail(text: &str) -> (&str, &str) {
-    if let Some((head, tail)) = text.rsplit_once('\n') {
-        (head, tail)
-    } else {
-        ("", text)
-    }
-}
-
 fn need_rows(text: &str, columns: u16) -> u16 {
     let buffer_width = display_width(text).max(1) as u16;
     buffer_width.div_ceil(columns)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::RenderOptions;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[tokio::test]
+    async fn test_gather_events() {
+        let (tx, mut rx) = unbounded_channel();
+        for event in [
+            SseEvent::Text("Let me ".into()),
+            SseEvent::Text("check.".into()),
+            SseEvent::ToolCallStart {
+                name: "get_weather".into(),
+                id: None,
+            },
+            SseEvent::ToolCallDelta("{\"city\":".into()),
+            SseEvent::ToolCallDelta("\"Paris\"}".into()),
+            SseEvent::Done,
+        ] {
+            tx.send(event).unwrap();
+        }
+        let events = gather_events(&mut rx).await;
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0], SseEvent::Text(v) if v == "Let me check."));
+        assert!(
+            matches!(&events[1], SseEvent::ToolCallStart { name, .. } if name == "get_weather")
+        );
+        assert!(matches!(&events[2], SseEvent::ToolCallDelta(v) if v == "{\"city\":\"Paris\"}"));
+        assert!(matches!(&events[3], SseEvent::Done));
+    }
+
+    #[test]
+    fn test_status_line() {
+        let mut status = StatusLine::new();
+        status.tokens = 12;
+        assert_eq!(
+            status.text(Duration::from_millis(1540)),
+            "1.5s · ~12 tokens"
+        );
+
+        let mut output = vec![];
+        status.clear(&mut output).unwrap();
+        assert!(output.is_empty());
+        // Cleared once after being drawn
+        status.shown = Some(status.text(Duration::ZERO));
+        status.clear(&mut output).unwrap();
+        assert!(!output.is_empty());
+        assert!(status.shown.is_none());
+        output.clear();
+        status.clear(&mut output).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_at_row_end() {
+        assert!(!at_row_end("", 10));
+        assert!(!at_row_end("hello", 10));
+        assert!(at_row_end(&"a".repeat(10), 10));
+        assert!(!at_row_end(&"a".repeat(11), 10));
+        assert!(at_row_end(&"中".repeat(10), 10));
+    }
+
+    #[test]
+    fn test_complete_lines() {
+        let mut buffer = String::new();
+        assert_eq!(complete_lines(&mut buffer, "Hello"), None);
+        assert_eq!(buffer, "Hello");
+        assert_eq!(
+            complete_lines(&mut buffer, " world\nSecond"),
+            Some("Hello world".into())
+        );
+        assert_eq!(buffer, "Second");
+        assert_eq!(
+            complete_lines(&mut buffer, " line\n\nThird\n"),
+            Some("Second line\n\nThird".into())
+        );
+        assert_eq!(buffer, "");
+    }
+
+    #[test]
+    fn test_stream_highlights_completed_lines() {
+        let text = "Some code:\n\n```rust\nfn main() {\n    println!(\"hi\");\n}\n```\nDone.";
+        let options = RenderOptions {
+            theme: bincode::deserialize_from(
+                &include_bytes!("../../assets/monokai-extended.theme.bin")[..],
+            )
+            .ok(),
+            ..Default::default()
+        };
+        let mut expect_render = MarkdownRender::init(options.clone()).unwrap();
+        let expect = expect_render.render(text);
+
+        let mut render = MarkdownRender::init(options).unwrap();
+        let mut buffer = String::new();
+        let mut outputs = vec![];
+        let chars: Vec<char> = text.chars().collect();
+        for chunk in chars.chunks(3) {
+            let chunk: String = chunk.iter().collect();
+            if let Some(lines) = complete_lines(&mut buffer, &chunk) {
+                outputs.push(render.render(&lines));
+            }
+        }
+        outputs.push(render.render_line(&buffer));
+        // Highlighted once per completed line, not once per chunk
+        assert!(outputs.len() <= text.lines().count());
+        assert_eq!(outputs.join("\n"), expect);
+    }
+}
@@ -91,7 +91,7 @@ pub struct ReplCompleter {
 
 impl ReplCompleter {
     pub fn new(config: &GlobalConfig) -> Self {
-        let mut groups = HashMap::new();
+        let mut groups: HashMap<&str, usize> = HashMap::new();
 
         let commands: Vec<ReplCommand> = REPL_COMMANDS.to_vec();
 
@@ -4,21 +4,27 @@ mod prompt;
 
 use self::completer::ReplCompleter;
 use self::highlighter::ReplHighlighter;
-use self::prompt::ReplPrompt;
+use self::prompt::{KeybindingMode, ReplPrompt};
 
-use crate::client::{call_chat_completions, call_chat_completions_streaming};
-use crate::config::{AssertState, Config, GlobalConfig, Input, StateFlags};
+use crate::client::{
+    call_chat_completions, call_chat_completions_streaming, ChatCompletionsOutput,
+};
+use crate::config::{AssertState, CompressStrategy, Config, GlobalConfig, Input, StateFlags};
+use crate::function::ToolResult;
 use crate::render::render_error;
+use crate::speak::maybe_speak;
 use crate::utils::{
-    abortable_run_with_spinner, create_abort_signal, set_text, temp_file, AbortSignal,
+    abortable_run_with_spinner, create_abort_signal, dimmed_text, get_image_png, set_text,
+    temp_file, AbortSignal,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use crossterm::cursor::SetCursorStyle;
 use fancy_regex::Regex;
 use reedline::{
     default_emacs_keybindings, default_vi_insert_keybindings, default_vi_normal_keybindings,
-    ColumnarMenu, EditCommand, EditMode, Emacs, KeyCode, KeyModifiers, Keybindings, Reedline,
-    ReedlineEvent, ReedlineMenu, ValidationResult, Validator, Vi,
+    ColumnarMenu, CursorConfig, EditCommand, EditMode, Emacs, KeyCode, KeyModifiers, Keybindings,
+    Reedline, ReedlineEvent, ReedlineMenu, ValidationResult, Validator, Vi,
 };
 use reedline::{MenuBuilder, Signal};
 use std::{env, process};
@@ -31,7 +37,7 @@ lazy_static::lazy_static! {
 const MENU_NAME: &str = "completion_menu";
 
 lazy_static::lazy_static! {
-    static ref REPL_COMMANDS: [ReplCommand; 34] = [
+    static ref REPL_COMMANDS: [ReplCommand; 51] = [
         ReplCommand::new(".help", "Show this help message", AssertState::pass()),
         ReplCommand::new(".info", "View system info", AssertState::pass()),
         ReplCommand::new(".model", "Change the current LLM", AssertState::pass()),
@@ -80,6 +86,41 @@ lazy_static::lazy_static! {
             "Compress messages in the current session",
             AssertState::True(StateFlags::SESSION)
         ),
+        ReplCommand::new(
+            ".recap",
+            "Summarize the session so far",
+            AssertState::True(StateFlags::SESSION)
+        ),
+        ReplCommand::new(
+            ".find",
+            "Search the session for the most relevant exchanges",
+            AssertState::True(StateFlags::SESSION)
+        ),
+        ReplCommand::new(
+            ".history",
+            "View the session messages or a message by its index",
+            AssertState::True(StateFlags::SESSION)
+        ),
+        ReplCommand::new(
+            ".pin",
+            "Keep a session message when compressing",
+            AssertState::True(StateFlags::SESSION)
+        ),
+        ReplCommand::new(
+            ".undo",
+            "Remove the last question and answer from the session",
+            AssertState::True(StateFlags::SESSION)
+        ),
+        ReplCommand::new(
+            ".checkpoint",
+            "Save the session messages under a name",
+            AssertState::True(StateFlags::SESSION)
+        ),
+        ReplCommand::new(
+            ".rollback",
+            "Restore the session messages of a checkpoint",
+            AssertState::True(StateFlags::SESSION)
+        ),
         ReplCommand::new(
             ".info session",
             "View session info",
@@ -100,11 +141,16 @@ lazy_static::lazy_static! {
             "End the session",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION)
         ),
+        ReplCommand::new(
+            ".replay",
+            "Replay a session with its original pacing",
+            AssertState::pass()
+        ),
         ReplCommand::new(".agent", "Use a agent", AssertState::bare()),
         ReplCommand::new(
             ".starter",
             "Use the conversation starter",
-            AssertState::True(StateFlags::AGENT)
+            AssertState::True(StateFlags::AGENT | StateFlags::ROLE)
         ),
         ReplCommand::new(
             ".variable",
@@ -156,14 +202,35 @@ lazy_static::lazy_static! {
             "Include files with the message",
             AssertState::pass()
         ),
+        ReplCommand::new(
+            ".paste-image",
+            "Attach the image in the clipboard to the next message",
+            AssertState::pass()
+        ),
+        ReplCommand::new(
+            ".listen",
+            "Speak the message, transcribed with stt_command",
+            AssertState::pass()
+        ),
+        ReplCommand::new(".tokens", "Show the token usage of the next request", AssertState::pass()),
         ReplCommand::new(".continue", "Continue the response", AssertState::pass()),
         ReplCommand::new(
             ".regenerate",
             "Regenerate the last response",
             AssertState::pass()
         ),
+        ReplCommand::new(
+            ".diff",
+            "Compare the last response with the regenerated one",
+            AssertState::pass()
+        ),
+        ReplCommand::new(".repro", "Show how to reproduce the last response", AssertState::pass()),
         ReplCommand::new(".copy", "Copy the last response", AssertState::pass()),
+        ReplCommand::new(".page", "View the last response in the pager", AssertState::pass()),
         ReplCommand::new(".set", "Adjust runtime configuration", AssertState::pass()),
+        ReplCommand::new(".memory", "List, add or forget remembered facts", AssertState::pass()),
+        ReplCommand::new(".trust", "Run a tool without confirmation in this session", AssertState::pass()),
+        ReplCommand::new(".audit", "View the recent tool calls", AssertState::pass()),
         ReplCommand::new(".delete", "Delete roles/sessions/RAGs/agents", AssertState::pass()),
         ReplCommand::new(".exit", "Exit the REPL", AssertState::pass()),
     ];
@@ -180,9 +247,11 @@ pub struct Repl {
 
 impl Repl {
     pub fn init(config: &GlobalConfig) -> Result<Self> {
-        let editor = Self::create_editor(config)?;
+        let edit_mode = Self::create_edit_mode(config);
+        let keybinding_mode = KeybindingMode::new(edit_mode.as_ref());
+        let editor = Self::create_editor(config, keybinding_mode.track(edit_mode))?;
 
-        let prompt = ReplPrompt::new(config);
+        let prompt = ReplPrompt::new(config, &keybinding_mode);
         let abort_signal = create_abort_signal();
 
         Ok(Self {
@@ -208,6 +277,7 @@ impl Repl {
             match sig {
                 Ok(Signal::Success(line)) => {
                     self.abort_signal.reset();
+                    let line = normalize_newlines(&line);
                     match self.handle(&line).await {
                         Ok(exit) => {
                             if exit {
@@ -235,271 +305,12 @@ impl Repl {
         Ok(())
     }
 
-    async fn handle(&self, mut line: &str) -> Result<bool> {
-        if let Ok(Some(captures)) = MULTILINE_RE.captures(line) {
-            if let Some(text_match) = captures.get(1) {
-                line = text_match.as_str();
-            }
-        }
-        match parse_command(line) {
-            Some((cmd, args)) => match cmd {
-                ".help" => {
-                    dump_repl_help();
-                }
-                ".info" => match args {
-                    Some("role") => {
-                        let info = self.config.read().role_info()?;
-                        print!("{}", info);
-                    }
-                    Some("session") => {
-                        let info = self.config.read().session_info()?;
-                        print!("{}", info);
-                    }
-                    Some("rag") => {
-                        let info = self.config.read().rag_info()?;
-                        print!("{}", info);
-                    }
-                    Some("agent") => {
-                        let info = self.config.read().agent_info()?;
-                        print!("{}", info);
-                    }
-                    Some(_) => unknown_command()?,
-                    None => {
-                        let output = self.config.read().sysinfo()?;
-                        print!("{}", output);
-                    }
-                },
-                ".model" => match args {
-                    Some(name) => {
-                        self.config.write().set_model(name)?;
-                    }
-                    None => println!("Usage: .model <name>"),
-                },
-                ".prompt" => match args {
-                    Some(text) => {
-                        self.config.write().use_prompt(text)?;
-                    }
-                    None => println!("Usage: .prompt <text>..."),
-                },
-                ".role" => match args {
-                    Some(args) => match args.split_once(['\n', ' ']) {
-                        Some((name, text)) => {
-                            let role = self.config.read().retrieve_role(name.trim())?;
-                            let input = Input::from_str(&self.config, text.trim(), Some(role));
-                            ask(&self.config, self.abort_signal.clone(), input, false).await?;
-                        }
-                        None => {
-                            let name = args;
-                            if Config::has_role(name) {
-                                self.config.write().use_role(name)?;
-                            } else {
-                                self.config.write().new_role(name)?;
-                            }
-                        }
-                    },
-                    None => println!(
-                        r#"Usage:
-    .role <name>                    # If the role exists, switch to it; otherwise, create a new role
-    .role <name> [text]...          # Temporarily switch to the role, send the text, and switch back"#
-                    ),
-                },
-                ".session" => {
-                    self.config.write().use_session(args)?;
-                    Config::maybe_autoname_session(self.config.clone());
-                }
-                ".rag" => {
-                    Config::use_rag(&self.config, args, self.abort_signal.clone()).await?;
-                }
-                ".agent" => match split_args(args) {
-                    Some((agent_name, session_name)) => {
-                        Config::use_agent(
-                            &self.config,
-                            agent_name,
-                            session_name,
-                            self.abort_signal.clone(),
-                        )
-                        .await?;
-                    }
-                    None => println!(r#"Usage: .agent <agent-name> [session-name]"#),
-                },
-                ".starter" => match args {
-                    Some(value) => {
-                        let input = Input::from_str(&self.config, value, None);
-                        ask(&self.config, self.abort_signal.clone(), input, true).await?;
-                    }
-                    None => {
-                        let banner = self.config.read().agent_banner()?;
-                        self.config.read().print_markdown(&banner)?;
-                    }
-                },
-                ".variable" => match args {
-                    Some(args) => {
-                        self.config.write().set_agent_variable(args)?;
-                    }
-                    _ => {
-                        println!("Usage: .variable <key> <value>")
-                    }
-                },
-                ".save" => match split_args(args) {
-                    Some(("role", name)) => {
-                        self.config.write().save_role(name)?;
-                    }
-                    Some(("session", name)) => {
-                        self.config.write().save_session(name)?;
-                    }
-                    _ => {
-                        println!(r#"Usage: .save <role|session> [name]"#)
-                    }
-                },
-                ".edit" => match args {
-                    Some("role") => {
-                        self.config.write().edit_role()?;
-                    }
-                    Some("session") => {
-                        self.config.write().edit_session()?;
-                    }
-                    Some("rag-docs") => {
-                        Config::edit_rag_docs(&self.config, self.abort_signal.clone()).await?;
-                    }
-                    _ => {
-                        println!(r#"Usage: .edit <role|session|rag-docs>"#)
-                    }
-                },
-                ".compress" => match args {
-                    Some("session") => {
-                        abortable_run_with_spinner(
-                            Config::compress_session(&self.config),
-                            "Compressing",
-                            self.abort_signal.clone(),
-                        )
-                        .await?;
-                        println!("✓ Successfully compressed the session.");
-                    }
-                    _ => {
-                        println!(r#"Usage: .compress session"#)
-                    }
-                },
-                ".empty" => match args {
-                    Some("session") => {
-                        self.config.write().empty_session()?;
-                    }
-                    _ => {
-                        println!(r#"Usage: .empty session"#)
-                    }
-                },
-                ".rebuild" => match args {
-                    Some("rag") => {
-                        Config::rebuild_rag(&self.config, self.abort_signal.clone()).await?;
-                    }
-                    _ => {
-                        println!(r#"Usage: .rebuild rag"#)
-                    }
-                },
-                ".sources" => match args {
-                    Some("rag") => {
-                        let output = Config::rag_sources(&self.config)?;
-                        println!("{}", output);
-                    }
-                    _ => {
-                        println!(r#"Usage: .sources rag"#)
-                    }
-                },
-                ".file" => match args {
-                    Some(args) => {
-                        let (files, text) = split_files_text(args);
-                        let files = shell_words::split(files).with_context(|| "Invalid args")?;
-                        let input = Input::from_files_with_spinner(
-                            &self.config,
-                            text,
-                            files,
-                            None,
-                            self.abort_signal.clone(),
-                        )
-                        .await?;
-                        ask(&self.config, self.abort_signal.clone(), input, true).await?;
-                    }
-                    None => println!("Usage: .file <files>... [-- <text>...]"),
-                },
-                ".continue" => {
-                    let (mut input, output) = match self.config.read().last_message.clone() {
-                        Some(v) => v,
-                        None => bail!("Unable to continue response"),
-                    };
-                    input.set_continue_output(&output);
-                    ask(&self.config, self.abort_signal.clone(), input, true).await?;
-                }
-                ".regenerate" => {
-                    let (mut input, _) = match self.config.read().last_message.clone() {
-                        Some(v) => v,
-                        None => bail!("Unable to regenerate the last response"),
-                    };
-                    input.set_regenerate();
-                    ask(&self.config, self.abort_signal.clone(), input, true).await?;
-                }
-                ".set" => match args {
-                    Some(args) => {
-                        Config::update(&self.config, args)?;
-                    }
-                    _ => {
-                        println!("Usage: .set <key> <value>...")
-                    }
-                },
-                ".delete" => match args {
-                    Some(args) => {
-                        Config::delete(&self.config, args)?;
-                    }
-                    _ => {
-                        println!("Usage: .delete <role|session|rag|agent-data>")
-                    }
-                },
-                ".copy" => {
-                    let config = self.config.read();
-                    self.copy(config.last_reply())
-                        .with_context(|| "Failed to copy the last response")?;
-                }
-                ".exit" => match args {
-                    Some("role") => {
-                        self.config.write().exit_role()?;
-                    }
-                    Some("session") => {
-                        if self.config.read().agent.is_some() {
-                            self.config.write().exit_agent_session()?;
-                        } else {
-                            self.config.write().exit_session()?;
-                        }
-                    }
-                    Some("rag") => {
-                        self.config.write().exit_rag()?;
-                    }
-                    Some("agent") => {
-                        self.config.write().exit_agent()?;
-                    }
-                    Some(_) => unknown_command()?,
-                    None => {
-                        return Ok(true);
-                    }
-                },
-                ".clear" => match args {
-                    Some("messages") => {
-                        bail!("Use '.empty session' instead");
-                    }
-                    _ => unknown_command()?,
-                },
-                _ => unknown_command()?,
-            },
-            None => {
-                let input = Input::from_str(&self.config, line, None);
-                ask(&self.config, self.abort_signal.clone(), input, true).await?;
-            }
-        }
-
-        println!();
-
-        Ok(false)
+    async fn handle(&self, line: &str) -> Result<bool> {
+        run_repl_command(&self.config, self.abort_signal.clone(), line).await
     }
 
     fn banner(&self) {
-        let name = env!("CARGO_CRATE_NAME");
+        let name = env!("CARGO_PKG_NAME");
         let version = env!("CARGO_PKG_VERSION");
         print!(
             r#"Welcome to {name} {version}
@@ -508,11 +319,10 @@ Type ".help" for additional help.
         )
     }
 
-    fn create_editor(config: &GlobalConfig) -> Result<Reedline> {
+    fn create_editor(config: &GlobalConfig, edit_mode: Box<dyn EditMode>) -> Result<Reedline> {
         let completer = ReplCompleter::new(config);
         let highlighter = ReplHighlighter::new(config);
         let menu = Self::create_menu();
-        let edit_mode = Self::create_edit_mode(config);
         let mut editor = Reedline::create()
             .with_completer(Box::new(completer))
             .with_highlighter(Box::new(highlighter))
@@ -524,6 +334,14 @@ Type ".help" for additional help.
             .with_validator(Box::new(ReplValidator))
             .with_ansi_colors(true);
 
+        if config.read().keybindings == "vi" {
+            editor = editor.with_cursor_config(CursorConfig {
+                vi_insert: Some(SetCursorStyle::BlinkingBar),
+                vi_normal: Some(SetCursorStyle::SteadyBlock),
+                emacs: None,
+            });
+        }
+
         if let Ok(cmd) = config.read().editor() {
             let temp_file = temp_file("-repl-", ".txt");
             let command = process::Command::new(cmd);
@@ -583,14 +401,6 @@ Type ".help" for additional help.
         let completion_menu = ColumnarMenu::default().with_name(MENU_NAME);
         ReedlineMenu::EngineCompleter(Box::new(completion_menu))
     }
-
-    fn copy(&self, text: &str) -> Result<()> {
-        if text.is_empty() {
-            bail!("No text to copy")
-        }
-        set_text(text)?;
-        Ok(())
-    }
 }
 
 #[derive(Debug, Clone)]
@@ -628,22 +438,539 @@ impl Validator for ReplValidator {
     }
 }
 
+/// Handle a line of the REPL, either a dot command or a message. Returns `true` to exit.
+pub async fn run_repl_command(
+    config: &GlobalConfig,
+    abort_signal: AbortSignal,
+    line: &str,
+) -> Result<bool> {
+    match handle_repl_line(config, abort_signal.clone(), line).await? {
+        ReplAction::Exit => return Ok(true),
+        ReplAction::Ask(input, with_embeddings) => {
+            ask(config, abort_signal, *input, with_embeddings).await?
+        }
+        ReplAction::Done => {}
+    }
+    println!();
+    Ok(false)
+}
+
+/// What is left to do after a REPL line is handled, the chat is up to the caller so that
+/// the TUI renders it in its own pane.
+pub enum ReplAction {
+    Exit,
+    Done,
+    Ask(Box<Input>, bool),
+}
+
+/// Run a dot command, or build the input of a message.
+pub async fn handle_repl_line(
+    config: &GlobalConfig,
+    abort_signal: AbortSignal,
+    mut line: &str,
+) -> Result<ReplAction> {
+    if let Ok(Some(captures)) = MULTILINE_RE.captures(line) {
+        if let Some(text_match) = captures.get(1) {
+            line = text_match.as_str();
+        }
+    }
+    match parse_command(line) {
+        Some((cmd, args)) => match cmd {
+            ".help" => {
+                dump_repl_help();
+            }
+            ".info" => match args {
+                Some("role") => {
+                    let info = config.read().role_info()?;
+                    print!("{}", info);
+                }
+                Some("session") => {
+                    let info = config.read().session_info()?;
+                    print!("{}", info);
+                }
+                Some("rag") => {
+                    let info = config.read().rag_info()?;
+                    print!("{}", info);
+                }
+                Some("agent") => {
+                    let info = config.read().agent_info()?;
+                    print!("{}", info);
+                }
+                Some(_) => unknown_command()?,
+                None => {
+                    let output = config.read().sysinfo()?;
+                    print!("{}", output);
+                }
+            },
+            ".model" => match args {
+                Some(name) => {
+                    config.write().set_model(name)?;
+                }
+                None => Config::select_model(config)?,
+            },
+            ".prompt" => match args {
+                Some(text) => {
+                    config.write().use_prompt(text)?;
+                }
+                None => println!("Usage: .prompt <text>..."),
+            },
+            ".role" => match args {
+                Some(args) => match args.split_once(['\n', ' ']) {
+                    Some((name, text)) => {
+                        let role = config.read().retrieve_role(name.trim())?;
+                        let input = Input::from_str(config, text.trim(), Some(role));
+                        return Ok(ReplAction::Ask(Box::new(input), false));
+                    }
+                    None => {
+                        let name = args;
+                        if Config::has_role(name) {
+                            config.write().use_role(name)?;
+                            print_starters_banner(config)?;
+                        } else {
+                            config.write().new_role(name)?;
+                        }
+                    }
+                },
+                None => println!(
+                    r#"Usage:
+.role <name>                    # If the role exists, switch to it; otherwise, create a new role
+.role <name> [text]...          # Temporarily switch to the role, send the text, and switch back"#
+                ),
+            },
+            ".session" => {
+                config.write().use_session(args)?;
+                Config::maybe_autoname_session(config.clone());
+            }
+            ".rag" => {
+                Config::use_rag(config, args, abort_signal.clone()).await?;
+            }
+            ".agent" => match split_args(args) {
+                Some((agent_name, session_name)) => {
+                    Config::use_agent(config, agent_name, session_name, abort_signal.clone())
+                        .await?;
+                    print_starters_banner(config)?;
+                }
+                None => println!(r#"Usage: .agent <agent-name> [session-name]"#),
+            },
+            ".starter" => match args {
+                Some(value) => {
+                    let text = config.read().resolve_starter(value);
+                    let input = Input::from_str(config, &text, None);
+                    return Ok(ReplAction::Ask(Box::new(input), true));
+                }
+                None => {
+                    let banner = if config.read().agent.is_some() {
+                        Some(config.read().agent_banner()?)
+                    } else {
+                        config.read().starters_banner()
+                    };
+                    match banner {
+                        Some(banner) => config.read().print_markdown(&banner)?,
+                        None => println!("No conversation starters"),
+                    }
+                }
+            },
+            ".variable" => match args {
+                Some(args) => {
+                    config.write().set_agent_variable(args)?;
+                }
+                _ => {
+                    println!("Usage: .variable <key> <value>")
+                }
+            },
+            ".save" => match split_args(args) {
+                Some(("role", name)) => {
+                    config.write().save_role(name)?;
+                }
+                Some(("session", name)) => {
+                    config.write().save_session(name)?;
+                }
+                _ => {
+                    println!(r#"Usage: .save <role|session> [name]"#)
+                }
+            },
+            ".edit" => match args {
+                Some("role") => {
+                    config.write().edit_role()?;
+                }
+                Some("session") => {
+                    config.write().edit_session()?;
+                }
+                Some("rag-docs") => {
+                    Config::edit_rag_docs(config, abort_signal.clone()).await?;
+                }
+                _ => {
+                    println!(r#"Usage: .edit <role|session|rag-docs>"#)
+                }
+            },
+            ".compress" => {
+                let strategy = match args {
+                    None | Some("session") => None,
+                    Some(v) => Some(
+                        CompressStrategy::parse(v)
+                            .ok_or_else(|| anyhow!("Usage: .compress [summarize|prune|hybrid]"))?,
+                    ),
+                };
+                let compression = abortable_run_with_spinner(
+                    Config::prepare_session_compression(config, strategy),
+                    "Compressing",
+                    abort_signal.clone(),
+                )
+                .await
+                .and_then(|v| {
+                    if config.read().compress_preview && !v.confirm()? {
+                        return Ok(None);
+                    }
+                    Ok(Some(v))
+                });
+                match compression {
+                    Ok(Some(compression)) => {
+                        Config::end_session_compression(config, Some(compression));
+                        println!("✓ Successfully compressed the session.");
+                    }
+                    Ok(None) => Config::end_session_compression(config, None),
+                    Err(err) => {
+                        Config::end_session_compression(config, None);
+                        return Err(err);
+                    }
+                }
+            }
+            ".recap" => {
+                let recap = abortable_run_with_spinner(
+                    Config::recap_session(config),
+                    "Recapping",
+                    abort_signal.clone(),
+                )
+                .await?;
+                config.read().print_markdown(&recap)?;
+            }
+            ".find" => match args {
+                Some(query) => {
+                    let output = abortable_run_with_spinner(
+                        Config::find_in_session(config, query),
+                        "Searching",
+                        abort_signal.clone(),
+                    )
+                    .await?;
+                    println!("{output}");
+                }
+                None => println!("Usage: .find <query>"),
+            },
+            ".history" => {
+                let config = config.read();
+                let Some(session) = config.session.as_ref() else {
+                    bail!("No session")
+                };
+                match args {
+                    Some(index) => {
+                        let index = index.parse().with_context(|| "Usage: .history [index]")?;
+                        config.print_markdown(&session.message_info(index)?)?;
+                    }
+                    None => println!("{}", session.messages_info()),
+                }
+            }
+            ".pin" => {
+                let mut config = config.write();
+                let Some(session) = config.session.as_mut() else {
+                    bail!("No session")
+                };
+                match args {
+                    Some(index) => {
+                        let index = index.parse().with_context(|| "Usage: .pin [index]")?;
+                        if session.toggle_pin(index)? {
+                            println!("✓ Pinned message #{index}");
+                        } else {
+                            println!("✓ Unpinned message #{index}");
+                        }
+                    }
+                    None => println!("{}", session.messages_info()),
+                }
+            }
+            ".undo" => {
+                let dropped = config.write().undo_session()?;
+                println!("✓ Removed the last exchange ({dropped} messages)");
+            }
+            ".checkpoint" => {
+                let mut config = config.write();
+                let Some(session) = config.session.as_mut() else {
+                    bail!("No session")
+                };
+                match args {
+                    Some(name) => {
+                        session.set_checkpoint(name);
+                        println!("✓ Saved checkpoint '{name}'");
+                    }
+                    None => println!("{}", session.checkpoints_info()),
+                }
+            }
+            ".rollback" => match args {
+                Some(name) => {
+                    let dropped = config.write().rollback_session(name)?;
+                    println!("✓ Rolled back to '{name}', dropped {dropped} message(s)");
+                }
+                None => println!("Usage: .rollback <name>"),
+            },
+            ".empty" => match args {
+                Some("session") => {
+                    config.write().empty_session()?;
+                }
+                _ => {
+                    println!(r#"Usage: .empty session"#)
+                }
+            },
+            ".rebuild" => match args {
+                Some("rag") => {
+                    Config::rebuild_rag(config, abort_signal.clone()).await?;
+                }
+                _ => {
+                    println!(r#"Usage: .rebuild rag"#)
+                }
+            },
+            ".sources" => match args {
+                Some("rag") => {
+                    let output = Config::rag_sources(config)?;
+                    println!("{}", output);
+                }
+                _ => {
+                    println!(r#"Usage: .sources rag"#)
+                }
+            },
+            ".file" => match args {
+                Some(args) => {
+                    let (files, text) = split_files_text(args);
+                    let mut files = shell_words::split(files).with_context(|| "Invalid args")?;
+                    let full = files.iter().any(|v| v == "--full");
+                    files.retain(|v| v != "--full");
+                    let pending_files = std::mem::take(&mut config.write().pending_files);
+                    let input = Input::builder(config)
+                        .text(text)
+                        .files(pending_files)
+                        .files(files)
+                        .full(full)
+                        .spinner(abort_signal.clone())
+                        .build()
+                        .await?;
+                    return Ok(ReplAction::Ask(Box::new(input), true));
+                }
+                None => println!("Usage: .file [--full] <files>... [-- <text>...]"),
+            },
+            ".paste-image" => {
+                let path = temp_file("-paste-", ".png");
+                std::fs::write(&path, get_image_png()?)?;
+                let path = path.display().to_string();
+                println!("Pasted '{path}', attached to the next message");
+                config.write().pending_files.push(path);
+            }
+            ".listen" => {
+                let text = listen(config, abort_signal.clone()).await?;
+                if !text.trim().is_empty() {
+                    let input = Input::from_str(config, &text, None);
+                    return Ok(ReplAction::Ask(Box::new(input), true));
+                }
+            }
+            ".tokens" => {
+                let input = match args {
+                    Some(args) if SPLIT_FILES_TEXT_ARGS_RE.is_match(args).unwrap_or_default() => {
+                        let (files, text) = split_files_text(args);
+                        let files = shell_words::split(files).with_context(|| "Invalid args")?;
+                        Input::builder(config)
+                            .text(text)
+                            .files(files)
+                            .spinner(abort_signal.clone())
+                            .build()
+                            .await?
+                    }
+                    _ => Input::from_str(config, args.unwrap_or_default(), None),
+                };
+                println!("{}", input.tokens_breakdown()?);
+            }
+            ".continue" => {
+                let (mut input, output) = match config.read().last_message.clone() {
+                    Some(v) => v,
+                    None => bail!("Unable to continue response"),
+                };
+                input.set_continue_output(&output.text);
+                return Ok(ReplAction::Ask(Box::new(input), true));
+            }
+            ".regenerate" => {
+                let (mut input, _) = match config.read().last_message.clone() {
+                    Some(v) => v,
+                    None => bail!("Unable to regenerate the last response"),
+                };
+                input.set_regenerate();
+                return Ok(ReplAction::Ask(Box::new(input), true));
+            }
+            ".set" => match args {
+                Some(args) => {
+                    Config::update(config, args)?;
+                }
+                _ => {
+                    println!("Usage: .set <key> <value>...")
+                }
+            },
+            ".delete" => match args {
+                Some(args) => {
+                    Config::delete(config, args)?;
+                }
+                _ => {
+                    println!("Usage: .delete <role|session|rag|agent-data> [name]")
+                }
+            },
+            ".replay" => {
+                let (name, speed) = parse_replay_args(args)?;
+                Config::replay_session(config, name, speed, abort_signal.clone()).await?;
+            }
+            ".diff" => {
+                let output = config.read().diff_last_reply()?;
+                println!("{output}");
+            }
+            ".page" => {
+                config.read().page_last_reply()?;
+            }
+            ".repro" => {
+                print!("{}", config.read().repro_info()?);
+            }
+            ".memory" => {
+                let args = args.unwrap_or("list");
+                let (action, value) = args.split_once(' ').unwrap_or((args, ""));
+                Config::manage_memory(action, value)?;
+            }
+            ".audit" => {
+                let limit = match args {
+                    Some(v) => v.parse().with_context(|| "Usage: .audit [limit]")?,
+                    None => 20,
+                };
+                println!("{}", config.read().tool_audit(limit)?);
+            }
+            ".trust" => match args {
+                Some(name) => {
+                    config.write().trust_tool(name)?;
+                    println!("✓ Trusted '{name}' until the session ends");
+                }
+                None => {
+                    let config = config.read();
+                    if config.trusted_tools.is_empty() {
+                        println!("Usage: .trust <tool>")
+                    } else {
+                        let mut names: Vec<&str> =
+                            config.trusted_tools.iter().map(|v| v.as_str()).collect();
+                        names.sort_unstable();
+                        println!("Trusted tools: {}", names.join(", "));
+                    }
+                }
+            },
+            ".copy" => {
+                let config = config.read();
+                copy_text(config.last_reply())
+                    .with_context(|| "Failed to copy the last response")?;
+            }
+            ".exit" => match args {
+                Some("role") => {
+                    config.write().exit_role()?;
+                }
+                Some("session") => {
+                    if config.read().agent.is_some() {
+                        config.write().exit_agent_session()?;
+                    } else {
+                        config.write().exit_session()?;
+                    }
+                }
+                Some("rag") => {
+                    config.write().exit_rag()?;
+                }
+                Some("agent") => {
+                    config.write().exit_agent()?;
+                }
+                Some(_) => unknown_command()?,
+                None => {
+                    return Ok(ReplAction::Exit);
+                }
+            },
+            ".clear" => match args {
+                Some("messages") => {
+                    bail!("Use '.empty session' instead");
+                }
+                _ => unknown_command()?,
+            },
+            _ => unknown_command()?,
+        },
+        None => {
+            let pending_files = std::mem::take(&mut config.write().pending_files);
+            let input = Input::builder(config)
+                .text(line)
+                .files(pending_files)
+                .spinner(abort_signal.clone())
+                .build()
+                .await?;
+            return Ok(ReplAction::Ask(Box::new(input), true));
+        }
+    }
+
+    Ok(ReplAction::Done)
+}
+
+fn copy_text(text: &str) -> Result<()> {
+    if text.is_empty() {
+        bail!("No text to copy")
+    }
+    set_text(text)?;
+    Ok(())
+}
+
+/// Record a voice message and let the user edit the transcript before sending it.
+#[cfg(feature = "voice")]
+async fn listen(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<String> {
+    use crate::utils::{record_audio, transcribe_audio, DEFAULT_LISTEN_COMMAND};
+
+    let (listen_command, stt_command) = {
+        let config = config.read();
+        (config.listen_command.clone(), config.stt_command.clone())
+    };
+    let Some(stt_command) = stt_command else {
+        bail!("No stt_command configured, it is required by .listen");
+    };
+    let listen_command = listen_command.unwrap_or_else(|| DEFAULT_LISTEN_COMMAND.to_string());
+    let path = temp_file("-listen-", ".wav");
+    record_audio(&listen_command, &path).await?;
+    let transcript = {
+        let path = path.clone();
+        abortable_run_with_spinner(
+            async move {
+                tokio::task::spawn_blocking(move || transcribe_audio(&stt_command, &path)).await?
+            },
+            "Transcribing",
+            abort_signal,
+        )
+        .await
+    };
+    let _ = std::fs::remove_file(&path);
+    let transcript = transcript?;
+    if transcript.is_empty() {
+        bail!("Nothing was transcribed");
+    }
+    let text = inquire::Text::new("Transcript:")
+        .with_initial_value(&transcript)
+        .with_help_message("Edit and press Enter to send, Esc to discard")
+        .prompt_skippable()?;
+    Ok(text.unwrap_or_default())
+}
+
+#[cfg(not(feature = "voice"))]
+async fn listen(_config: &GlobalConfig, _abort_signal: AbortSignal) -> Result<String> {
+    bail!("Voice input is unavailable, aichat was built without the `voice` feature")
+}
+
 #[async_recursion::async_recursion]
 async fn ask(
     config: &GlobalConfig,
     abort_signal: AbortSignal,
-    mut input: Input,
+    input: Input,
     with_embeddings: bool,
 ) -> Result<()> {
-    if input.is_empty() {
+    let Some(input) = prepare_input(config, abort_signal.clone(), input, with_embeddings).await?
+    else {
         return Ok(());
-    }
-    if with_embeddings {
-        input.use_embeddings(abort_signal.clone()).await?;
-    }
-    while config.read().is_compressing_session() {
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-    }
+    };
 
     let client = input.create_client()?;
     config.write().before_chat_completion(&input)?;
@@ -655,18 +982,72 @@ async fn ask(
     config
         .write()
         .after_chat_completion(&input, &output, &tool_results)?;
+    match follow_up(config, &input, &output, tool_results) {
+        FollowUp::Send(input) => return ask(config, abort_signal, *input, false).await,
+        FollowUp::Truncated => println!("{}", dimmed_text(TRUNCATED_NOTICE)),
+        FollowUp::Done => {}
+    }
+    config.read().maybe_page_last_reply()?;
+    maybe_speak(config, &input, &output.text).await?;
+    Config::maybe_autoname_session(config.clone());
+    Config::maybe_compress_session(config.clone());
+    Ok(())
+}
+
+pub const TRUNCATED_NOTICE: &str =
+    "The reply was cut off by max_output_tokens, type `.continue` for the rest";
+
+/// Get the input of `ask` ready to send, `None` if there is nothing to send.
+pub async fn prepare_input(
+    config: &GlobalConfig,
+    abort_signal: AbortSignal,
+    mut input: Input,
+    with_embeddings: bool,
+) -> Result<Option<Input>> {
+    if input.is_empty() {
+        return Ok(None);
+    }
+    if with_embeddings {
+        input.use_embeddings(abort_signal).await?;
+    }
+    while config.read().is_compressing_session() {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    Ok(Some(input))
+}
+
+/// What comes after a chat completion has been saved
+pub enum FollowUp {
+    /// Send the tool results, or ask for the rest of a truncated reply
+    Send(Box<Input>),
+    /// The reply was cut off and `auto_continue` is used up
+    Truncated,
+    Done,
+}
+
+pub fn follow_up(
+    config: &GlobalConfig,
+    input: &Input,
+    output: &ChatCompletionsOutput,
+    tool_results: Vec<ToolResult>,
+) -> FollowUp {
     if !tool_results.is_empty() {
-        ask(
-            config,
-            abort_signal,
-            input.merge_tool_results(output, tool_results),
-            false,
-        )
-        .await
-    } else {
-        Config::maybe_autoname_session(config.clone());
-        Config::maybe_compress_session(config.clone());
-        Ok(())
+        return FollowUp::Send(Box::new(
+            input
+                .clone()
+                .merge_tool_results(output.text.clone(), tool_results),
+        ));
+    }
+    if !output.is_truncated() || !output.tool_calls.is_empty() {
+        return FollowUp::Done;
+    }
+    let last_message = config.read().last_message.clone();
+    match last_message {
+        Some((mut input, output)) if config.read().should_auto_continue(&input, &output) => {
+            input.set_continue_output(&output.text);
+            FollowUp::Send(Box::new(input))
+        }
+        _ => FollowUp::Truncated,
     }
 }
 
@@ -674,6 +1055,14 @@ fn unknown_command() -> Result<()> {
     bail!(r#"Unknown command. Type ".help" for additional help."#);
 }
 
+fn print_starters_banner(config: &GlobalConfig) -> Result<()> {
+    let banner = config.read().starters_banner();
+    if let Some(banner) = banner {
+        config.read().print_markdown(&banner)?;
+    }
+    Ok(())
+}
+
 fn dump_repl_help() {
     let head = REPL_COMMANDS
         .iter()
@@ -701,6 +1090,26 @@ fn parse_command(line: &str) -> Option<(&str, Option<&str>)> {
     }
 }
 
+/// `.replay [session] [<speed>x]`, a bare number is a session name
+fn parse_replay_args(args: Option<&str>) -> Result<(Option<&str>, f64)> {
+    let mut args: Vec<&str> = args.unwrap_or_default().split_whitespace().collect();
+    let mut speed = 1.0;
+    if let Some(value) = args.last().and_then(|v| v.strip_suffix('x')) {
+        if let Ok(value) = value.parse::<f64>() {
+            if !value.is_finite() || value <= 0.0 {
+                bail!("Invalid replay speed '{value}x'")
+            }
+            speed = value;
+            args.pop();
+        }
+    }
+    match args[..] {
+        [] => Ok((None, speed)),
+        [name] => Ok((Some(name), speed)),
+        _ => bail!("Usage: .replay [session] [<speed>x]"),
+    }
+}
+
 fn split_args(args: Option<&str>) -> Option<(&str, Option<&str>)> {
     args.map(|v| match v.split_once(' ') {
         Some((subcmd, args)) => (subcmd, Some(args.trim())),
@@ -708,6 +1117,11 @@ fn split_args(args: Option<&str>) -> Option<(&str, Option<&str>)> {
     })
 }
 
+/// Pastes from Windows terminals carry CRLF line endings
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
 fn split_files_text(args: &str) -> (&str, &str) {
     match SPLIT_FILES_TEXT_ARGS_RE.find(args).ok().flatten() {
         Some(mat) => {
@@ -727,6 +1141,15 @@ fn split_files_text(args: &str) -> (&str, &str) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_normalize_newlines() {
+        assert_eq!(normalize_newlines("a\r\nb\rc\nd"), "a\nb\nc\nd");
+        assert_eq!(
+            parse_command(&normalize_newlines(".file a.txt --\r\nhello")),
+            Some((".file", Some("a.txt --\nhello")))
+        );
+    }
+
     #[test]
     fn test_process_command_line() {
         assert_eq!(parse_command(" ."), Some((".", None)));
@@ -746,6 +1169,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_replay_args() {
+        assert_eq!(parse_replay_args(None).unwrap(), (None, 1.0));
+        assert_eq!(
+            parse_replay_args(Some("2024")).unwrap(),
+            (Some("2024"), 1.0)
+        );
+        assert_eq!(parse_replay_args(Some("1 2x")).unwrap(), (Some("1"), 2.0));
+        assert_eq!(parse_replay_args(Some("0.5x")).unwrap(), (None, 0.5));
+        assert_eq!(
+            parse_replay_args(Some("chat 1.5x")).unwrap(),
+            (Some("chat"), 1.5)
+        );
+        assert!(parse_replay_args(Some("nanx")).is_err());
+        assert!(parse_replay_args(Some("chat infx")).is_err());
+        assert!(parse_replay_args(Some("chat 0x")).is_err());
+        assert!(parse_replay_args(Some("chat 2")).is_err());
+    }
+
     #[test]
     fn test_split_files_text() {
         assert_eq!(split_files_text("file.txt"), ("file.txt", ""));
@@ -764,4 +1206,29 @@ mod tests {
             ("file.txt", "hello")
         );
     }
+
+    #[tokio::test]
+    async fn test_pending_files() {
+        let config: GlobalConfig = std::sync::Arc::new(parking_lot::RwLock::new(Config::default()));
+        let path = temp_file("-paste-", ".png");
+        std::fs::write(&path, b"pasted").unwrap();
+        let path = path.display().to_string();
+        config.write().pending_files.push(path.clone());
+        let ret = handle_repl_line(&config, create_abort_signal(), "what is this?").await;
+        std::fs::remove_file(&path).unwrap();
+        let ReplAction::Ask(input, _) = ret.unwrap() else {
+            panic!("expected an input");
+        };
+        assert_eq!(input.render(), format!(".file {path} -- what is this?"));
+        // Attached to the next message only
+        assert!(config.read().pending_files.is_empty());
+        let ReplAction::Ask(input, _) =
+            handle_repl_line(&config, create_abort_signal(), "and now?")
+                .await
+                .unwrap()
+        else {
+            panic!("expected an input");
+        };
+        assert_eq!(input.render(), "and now?");
+    }
 }
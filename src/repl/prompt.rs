@@ -1,42 +1,50 @@
 use crate::config::GlobalConfig;
 
-use reedline::{Prompt, PromptHistorySearch, PromptHistorySearchStatus};
-use std::borrow::Cow;
+use parking_lot::RwLock;
+use reedline::{
+    EditMode, Prompt, PromptEditMode, PromptHistorySearch, PromptHistorySearchStatus, PromptViMode,
+    ReedlineEvent, ReedlineRawEvent,
+};
+use std::{borrow::Cow, sync::Arc};
 
 #[derive(Clone)]
 pub struct ReplPrompt {
     config: GlobalConfig,
+    keybinding_mode: KeybindingMode,
 }
 
 impl ReplPrompt {
-    pub fn new(config: &GlobalConfig) -> Self {
+    pub fn new(config: &GlobalConfig, keybinding_mode: &KeybindingMode) -> Self {
         Self {
             config: config.clone(),
+            keybinding_mode: keybinding_mode.clone(),
         }
     }
 }
 
 impl Prompt for ReplPrompt {
-    fn render_prompt_left(&self) -> Cow<str> {
-        Cow::Owned(self.config.read().render_prompt_left())
+    fn render_prompt_left(&self) -> Cow<'_, str> {
+        let mode = self.keybinding_mode.get();
+        Cow::Owned(self.config.read().render_prompt_left(mode))
     }
 
-    fn render_prompt_right(&self) -> Cow<str> {
-        Cow::Owned(self.config.read().render_prompt_right())
+    fn render_prompt_right(&self) -> Cow<'_, str> {
+        let mode = self.keybinding_mode.get();
+        Cow::Owned(self.config.read().render_prompt_right(mode))
     }
 
-    fn render_prompt_indicator(&self, _prompt_mode: reedline::PromptEditMode) -> Cow<str> {
+    fn render_prompt_indicator(&self, _prompt_mode: reedline::PromptEditMode) -> Cow<'_, str> {
         Cow::Borrowed("")
     }
 
-    fn render_prompt_multiline_indicator(&self) -> Cow<str> {
+    fn render_prompt_multiline_indicator(&self) -> Cow<'_, str> {
         Cow::Borrowed("... ")
     }
 
     fn render_prompt_history_search_indicator(
         &self,
         history_search: PromptHistorySearch,
-    ) -> Cow<str> {
+    ) -> Cow<'_, str> {
         let prefix = match history_search.status {
             PromptHistorySearchStatus::Passing => "",
             PromptHistorySearchStatus::Failing => "failing ",
@@ -49,3 +57,107 @@ impl Prompt for ReplPrompt {
         ))
     }
 }
+
+/// `{keybinding_mode}` of the prompt: `insert` or `normal` with vi keybindings, `emacs` otherwise.
+///
+/// Reedline only tells the prompt indicator about the mode, which is rendered after the left and
+/// right prompts, so the mode is recorded as the keys are parsed instead.
+#[derive(Clone)]
+pub struct KeybindingMode(Arc<RwLock<&'static str>>);
+
+impl KeybindingMode {
+    pub fn new(edit_mode: &dyn EditMode) -> Self {
+        Self(Arc::new(RwLock::new(mode_name(edit_mode.edit_mode()))))
+    }
+
+    pub fn get(&self) -> &'static str {
+        *self.0.read()
+    }
+
+    /// Wrap the edit mode of the editor to keep track of its mode
+    pub fn track(&self, edit_mode: Box<dyn EditMode>) -> Box<dyn EditMode> {
+        Box::new(TrackedEditMode {
+            inner: edit_mode,
+            mode: self.clone(),
+        })
+    }
+}
+
+struct TrackedEditMode {
+    inner: Box<dyn EditMode>,
+    mode: KeybindingMode,
+}
+
+impl EditMode for TrackedEditMode {
+    fn parse_event(&mut self, event: ReedlineRawEvent) -> ReedlineEvent {
+        let event = self.inner.parse_event(event);
+        *self.mode.0.write() = mode_name(self.inner.edit_mode());
+        event
+    }
+
+    fn edit_mode(&self) -> PromptEditMode {
+        self.inner.edit_mode()
+    }
+}
+
+fn mode_name(mode: PromptEditMode) -> &'static str {
+    match mode {
+        PromptEditMode::Vi(PromptViMode::Insert) => "insert",
+        PromptEditMode::Vi(PromptViMode::Normal) => "normal",
+        _ => "emacs",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use reedline::{default_vi_insert_keybindings, default_vi_normal_keybindings, Emacs, Vi};
+
+    fn press(edit_mode: &mut dyn EditMode, code: KeyCode) {
+        let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        edit_mode.parse_event(ReedlineRawEvent::try_from(event).unwrap());
+    }
+
+    #[test]
+    fn test_keybinding_mode() {
+        let vi = Box::new(Vi::new(
+            default_vi_insert_keybindings(),
+            default_vi_normal_keybindings(),
+        ));
+        let mode = KeybindingMode::new(vi.as_ref());
+        let mut edit_mode = mode.track(vi);
+        assert_eq!(mode.get(), "insert");
+        press(edit_mode.as_mut(), KeyCode::Esc);
+        assert_eq!(mode.get(), "normal");
+        press(edit_mode.as_mut(), KeyCode::Char('i'));
+        assert_eq!(mode.get(), "insert");
+
+        let emacs = Box::<Emacs>::default();
+        let mode = KeybindingMode::new(emacs.as_ref());
+        let mut edit_mode = mode.track(emacs);
+        press(edit_mode.as_mut(), KeyCode::Esc);
+        assert_eq!(mode.get(), "emacs");
+    }
+
+    #[test]
+    fn test_prompt_keybinding_mode() {
+        let config = Config {
+            left_prompt: Some("[{keybinding_mode}] ".into()),
+            right_prompt: Some("{keybinding_mode}".into()),
+            ..Default::default()
+        };
+        let config: GlobalConfig = Arc::new(RwLock::new(config));
+        let vi = Box::new(Vi::default());
+        let mode = KeybindingMode::new(vi.as_ref());
+        let mut edit_mode = mode.track(vi);
+        let prompt = ReplPrompt::new(&config, &mode);
+        assert_eq!(prompt.render_prompt_left(), "[insert] ");
+        press(edit_mode.as_mut(), KeyCode::Esc);
+        // Rendered again with the new mode
+        assert_eq!(prompt.render_prompt_left(), "[normal] ");
+        assert_eq!(prompt.render_prompt_right(), "normal");
+    }
+}
@@ -0,0 +1,1624 @@
+mod request_log;
+
+use self::request_log::{LogEntry, RequestLog};
+
+use crate::{client::*, config::*, function::*, rag::*, utils::*};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use chrono::{Timelike, Utc};
+use futures_util::StreamExt;
+use http::{HeaderMap, Method, Response, StatusCode};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::{
+    body::{Frame, Incoming},
+    service::service_fn,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    convert::Infallible,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+use tokio_graceful::Shutdown;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+const DEFAULT_MODEL_NAME: &str = "default";
+const PLAYGROUND_HTML: &[u8] = include_bytes!("../../assets/playground.html");
+const ARENA_HTML: &[u8] = include_bytes!("../../assets/arena.html");
+const DEFAULT_RECENT_LOGS: usize = 50;
+const MAX_RECENT_LOGS: usize = 1000;
+
+type AppResponse = Response<BoxBody<Bytes, Infallible>>;
+
+pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
+    let addr = match addr {
+        Some(addr) => {
+            if let Ok(port) = addr.parse::<u16>() {
+                format!("127.0.0.1:{port}")
+            } else if let Ok(ip) = addr.parse::<IpAddr>() {
+                format!("{ip}:8000")
+            } else {
+                addr
+            }
+        }
+        None => config.read().serve_addr(),
+    };
+    let server = Arc::new(Server::new(&config));
+    let listener = TcpListener::bind(&addr).await?;
+    let stop_server = server.clone().run(listener).await?;
+    println!("Chat Completions API: http://{addr}/v1/chat/completions");
+    println!("Messages API:         http://{addr}/v1/messages");
+    println!("Embeddings API:       http://{addr}/v1/embeddings");
+    println!("Rerank API:           http://{addr}/v1/rerank");
+    println!("LLM Playground:       http://{addr}/playground");
+    println!("LLM Arena:            http://{addr}/arena?num=2");
+    if let Some(log) = &server.log {
+        println!("Request Log:          {}", log.path().display());
+    }
+    shutdown_signal().await;
+    let _ = stop_server.send(());
+    Ok(())
+}
+
+struct Server {
+    config: Config,
+    models: Vec<Value>,
+    roles: Vec<Role>,
+    rags: Vec<String>,
+    log: Option<Arc<RequestLog>>,
+    admin_key: Option<String>,
+}
+
+impl Server {
+    fn new(config: &GlobalConfig) -> Self {
+        let mut config = config.read().clone();
+        config.functions = Functions::default();
+        let mut models = list_all_models(&config);
+        let mut default_model = config.model.clone();
+        default_model.data_mut().name = DEFAULT_MODEL_NAME.into();
+        models.insert(0, &default_model);
+        let models: Vec<Value> = models
+            .into_iter()
+            .enumerate()
+            .map(|(i, model)| {
+                let id = if i == 0 {
+                    DEFAULT_MODEL_NAME.into()
+                } else {
+                    model.id()
+                };
+                let mut value = json!(model.data());
+                if let Some(value_obj) = value.as_object_mut() {
+                    value_obj.insert("id".into(), id.into());
+                    value_obj.insert("object".into(), "model".into());
+                    value_obj.insert("owned_by".into(), model.client_name().into());
+                    value_obj.remove("name");
+                }
+                value
+            })
+            .collect();
+        let log = config
+            .serve_log
+            .map(|mode| Arc::new(RequestLog::new(mode, Config::serve_log_file())));
+        let admin_key = config.serve_admin_key.clone();
+        Self {
+            config,
+            models,
+            roles: Config::all_roles(),
+            rags: Config::list_rags(),
+            log,
+            admin_key,
+        }
+    }
+
+    async fn run(self: Arc<Self>, listener: TcpListener) -> Result<oneshot::Sender<()>> {
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let shutdown = Shutdown::new(async { rx.await.unwrap_or_default() });
+            let guard = shutdown.guard_weak();
+
+            loop {
+                tokio::select! {
+                    res = listener.accept() => {
+                        let Ok((cnx, _)) = res else {
+                            continue;
+                        };
+
+                        let stream = TokioIo::new(cnx);
+                        let server = self.clone();
+                        shutdown.spawn_task(async move {
+                            let hyper_service = service_fn(move |request: hyper::Request<Incoming>| {
+                                server.clone().handle(request)
+                            });
+                            let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                                .serve_connection_with_upgrades(stream, hyper_service)
+                                .await;
+                        });
+                    }
+                    _ = guard.cancelled() => {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(tx)
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        req: hyper::Request<Incoming>,
+    ) -> std::result::Result<AppResponse, hyper::Error> {
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+        let path = uri.path();
+
+        if method == Method::OPTIONS {
+            let mut res = Response::default();
+            *res.status_mut() = StatusCode::NO_CONTENT;
+            set_cors_header(&mut res);
+            return Ok(res);
+        }
+
+        let (parts, body) = req.into_parts();
+        let req_body = body.collect().await?.to_bytes();
+        let mut entry = LogEntry::new(method.as_str(), path, &req_body);
+
+        let mut status = StatusCode::OK;
+        let res = if path == "/v1/chat/completions" {
+            self.chat_completions(req_body, &mut entry).await
+        } else if path == "/v1/messages" {
+            self.messages(req_body, &mut entry).await
+        } else if path == "/v1/embeddings" {
+            self.embeddings(req_body).await
+        } else if path == "/v1/rerank" {
+            self.rerank(req_body).await
+        } else if path == "/v1/models" {
+            self.list_models()
+        } else if path == "/v1/roles" {
+            self.list_roles()
+        } else if path == "/v1/rags" {
+            self.list_rags()
+        } else if path == "/v1/rags/search" {
+            self.search_rag(req_body).await
+        } else if path == "/logs/recent" {
+            match self.check_admin_key(&parts.headers) {
+                Ok(()) => self.recent_logs(uri.query()).await,
+                Err(err) => {
+                    status = StatusCode::UNAUTHORIZED;
+                    Err(err)
+                }
+            }
+        } else if path == "/playground" || path == "/playground.html" {
+            self.playground_page()
+        } else if path == "/arena" || path == "/arena.html" {
+            self.arena_page()
+        } else {
+            status = StatusCode::NOT_FOUND;
+            Err(anyhow!("Not Found"))
+        };
+        let mut res = match res {
+            Ok(res) => {
+                info!("{method} {uri} {}", status.as_u16());
+                res
+            }
+            Err(err) => {
+                if status == StatusCode::OK {
+                    status = StatusCode::BAD_REQUEST;
+                }
+                error!("{method} {uri} {} {err}", status.as_u16());
+                entry.error = Some(err.to_string());
+                if path == "/v1/messages" {
+                    ret_messages_err(err)
+                } else {
+                    ret_err(err)
+                }
+            }
+        };
+        *res.status_mut() = status;
+        set_cors_header(&mut res);
+        if let Some(log) = &self.log {
+            if !entry.deferred && !path.starts_with("/logs/") {
+                entry.status = status.as_u16();
+                write_log(log, entry);
+            }
+        }
+        Ok(res)
+    }
+
+    fn playground_page(&self) -> Result<AppResponse> {
+        let res = Response::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(PLAYGROUND_HTML)).boxed())?;
+        Ok(res)
+    }
+
+    fn arena_page(&self) -> Result<AppResponse> {
+        let res = Response::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(ARENA_HTML)).boxed())?;
+        Ok(res)
+    }
+
+    fn list_models(&self) -> Result<AppResponse> {
+        let data = json!({ "data": self.models });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    fn list_roles(&self) -> Result<AppResponse> {
+        let data = json!({ "data": self.roles });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    fn list_rags(&self) -> Result<AppResponse> {
+        let data = json!({ "data": self.rags });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    async fn search_rag(&self, req_body: Bytes) -> Result<AppResponse> {
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("search rag request: {req_body}");
+        let SearchRagReqBody { name, input } = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let config = Arc::new(RwLock::new(self.config.clone()));
+
+        let abort_signal = create_abort_signal();
+
+        let rag_path = config.read().rag_file(&name);
+        let rag = Rag::load(&config, &name, &rag_path)?;
+
+        let (rag_result, _) = Config::search_rag(&config, &rag, &input, abort_signal).await?;
+
+        let data = json!({ "data": rag_result });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    async fn chat_completions(&self, req_body: Bytes, entry: &mut LogEntry) -> Result<AppResponse> {
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("chat completions request: {req_body}");
+        let req_body = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let ChatRequest {
+            client,
+            http_client,
+            data,
+            model_name,
+        } = self.prepare_chat(req_body)?;
+
+        let completion_id = generate_completion_id();
+        let created = Utc::now().timestamp();
+        entry.model = Some(model_name.clone());
+
+        if data.stream {
+            let rx = start_chat_stream(client, http_client, data).await?;
+            let rx = self.log_stream(rx, entry);
+
+            let shared: Arc<(String, String, i64, AtomicBool)> =
+                Arc::new((completion_id, model_name, created, AtomicBool::new(false)));
+            let stream = UnboundedReceiverStream::new(rx);
+            let stream = stream.filter_map(move |res_event| {
+                let shared = shared.clone();
+                async move {
+                    let (completion_id, model, created, has_tool_calls) = shared.as_ref();
+                    match res_event {
+                        ResEvent::Text(text) => {
+                            Some(Ok(create_text_frame(completion_id, model, *created, &text)))
+                        }
+                        ResEvent::ToolCalls(tool_calls) => {
+                            has_tool_calls.store(true, Ordering::SeqCst);
+                            Some(Ok(create_tool_calls_frame(
+                                completion_id,
+                                model,
+                                *created,
+                                &tool_calls,
+                            )))
+                        }
+                        ResEvent::Done(_) => Some(Ok(create_done_frame(
+                            completion_id,
+                            model,
+                            *created,
+                            has_tool_calls.load(Ordering::SeqCst),
+                        ))),
+                        _ => None,
+                    }
+                }
+            });
+            let res = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/event-stream")
+                .header("Cache-Control", "no-cache")
+                .header("Connection", "keep-alive")
+                .body(BodyExt::boxed(StreamBody::new(stream)))?;
+            Ok(res)
+        } else {
+            let output = {
+                let _slot = client.acquire_slot().await;
+                client.chat_completions_inner(&http_client, data).await?
+            };
+            log_output(entry, &output);
+            let res = Response::builder()
+                .header("Content-Type", "application/json")
+                .body(
+                    Full::new(ret_non_stream(
+                        &completion_id,
+                        &model_name,
+                        created,
+                        &output,
+                    ))
+                    .boxed(),
+                )?;
+            Ok(res)
+        }
+    }
+
+    async fn messages(&self, req_body: Bytes, entry: &mut LogEntry) -> Result<AppResponse> {
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("messages request: {req_body}");
+        let req_body: MessagesReqBody = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+        let req_body = req_body
+            .into_chat_completions()
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let ChatRequest {
+            client,
+            http_client,
+            data,
+            model_name,
+        } = self.prepare_chat(req_body)?;
+
+        let message_id = generate_message_id();
+        entry.model = Some(model_name.clone());
+
+        if data.stream {
+            let rx = start_chat_stream(client, http_client, data).await?;
+            let rx = self.log_stream(rx, entry);
+
+            let state = MessagesStreamState::new(message_id, model_name);
+            let stream = UnboundedReceiverStream::new(rx)
+                .scan(state, |state, res_event| {
+                    futures_util::future::ready(Some(state.frame(res_event)))
+                })
+                .filter_map(|frame| futures_util::future::ready(frame.map(Ok)));
+            let res = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/event-stream")
+                .header("Cache-Control", "no-cache")
+                .header("Connection", "keep-alive")
+                .body(BodyExt::boxed(StreamBody::new(stream)))?;
+            Ok(res)
+        } else {
+            let output = {
+                let _slot = client.acquire_slot().await;
+                client.chat_completions_inner(&http_client, data).await?
+            };
+            log_output(entry, &output);
+            let res = Response::builder()
+                .header("Content-Type", "application/json")
+                .body(
+                    Full::new(ret_messages_non_stream(&message_id, &model_name, &output)).boxed(),
+                )?;
+            Ok(res)
+        }
+    }
+
+    /// Record the streamed reply, the entry is written once the stream ends.
+    fn log_stream(
+        &self,
+        mut rx: UnboundedReceiver<ResEvent>,
+        entry: &mut LogEntry,
+    ) -> UnboundedReceiver<ResEvent> {
+        let Some(log) = self.log.clone() else {
+            return rx;
+        };
+        entry.deferred = true;
+        let mut entry = entry.clone();
+        entry.status = StatusCode::OK.as_u16();
+        let (tx, output_rx) = unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(res_event) = rx.recv().await {
+                match &res_event {
+                    ResEvent::Text(text) => entry.push_response(text),
+                    ResEvent::ToolCalls(tool_calls) => {
+                        for call in tool_calls {
+                            entry.push_response(&format!("\n{}({})", call.name, call.arguments));
+                        }
+                    }
+                    ResEvent::Done(end) => {
+                        entry.input_tokens = end.input_tokens;
+                        entry.output_tokens = end.output_tokens;
+                    }
+                    _ => {}
+                }
+                let _ = tx.send(res_event);
+            }
+            write_log(&log, entry);
+        });
+        output_rx
+    }
+
+    fn check_admin_key(&self, headers: &HeaderMap) -> Result<()> {
+        let Some(admin_key) = &self.admin_key else {
+            bail!("Admin endpoints are disabled, set `serve_admin_key` to enable them");
+        };
+        let key = headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !constant_time_eq(key.as_bytes(), admin_key.as_bytes()) {
+            bail!("Invalid admin key");
+        }
+        Ok(())
+    }
+
+    async fn recent_logs(&self, query: Option<&str>) -> Result<AppResponse> {
+        let Some(log) = self.log.clone() else {
+            bail!("Request logging is disabled, set `serve_log` to enable it");
+        };
+        let limit = query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|v| v.strip_prefix("limit="))
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_RECENT_LOGS)
+            .min(MAX_RECENT_LOGS);
+        let entries = tokio::task::spawn_blocking(move || log.recent(limit)).await??;
+        let data = json!({ "data": entries });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    fn prepare_chat(&self, req_body: ChatCompletionsReqBody) -> Result<ChatRequest> {
+        let ChatCompletionsReqBody {
+            model,
+            messages,
+            temperature,
+            top_p,
+            max_tokens,
+            stream,
+            tools,
+            seed,
+        } = req_body;
+
+        let mut messages =
+            parse_messages(messages).map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let functions = parse_tools(tools).map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let config = self.config.clone();
+
+        let default_model = config.model.clone();
+
+        let config = Arc::new(RwLock::new(config));
+
+        let (model_name, change) = if model == DEFAULT_MODEL_NAME {
+            (default_model.id(), true)
+        } else if default_model.id() == model {
+            (model, false)
+        } else {
+            (model, true)
+        };
+
+        if change {
+            config.write().set_model(&model_name)?;
+        }
+
+        let mut client = init_client(&config, None)?;
+        if max_tokens.is_some() {
+            client.model_mut().set_max_tokens(max_tokens, true);
+        }
+        let http_client = client.build_client()?;
+
+        if client.model().no_system_message() {
+            patch_system_message(&mut messages);
+        }
+        let data: ChatCompletionsData = ChatCompletionsData {
+            messages,
+            temperature,
+            top_p,
+            functions,
+            stream,
+            stop: None,
+            response_format: None,
+            seed,
+        };
+        Ok(ChatRequest {
+            client,
+            http_client,
+            data,
+            model_name,
+        })
+    }
+
+    async fn embeddings(&self, req_body: Bytes) -> Result<AppResponse> {
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("embeddings request: {req_body}");
+        let req_body = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let EmbeddingsReqBody {
+            input,
+            model: embedding_model_id,
+        } = req_body;
+
+        let config = Arc::new(RwLock::new(self.config.clone()));
+
+        let embedding_model =
+            Model::retrieve_model(&config.read(), &embedding_model_id, ModelType::Embedding)?;
+
+        let texts = match input {
+            EmbeddingsReqBodyInput::Single(v) => vec![v],
+            EmbeddingsReqBodyInput::Multiple(v) => v,
+        };
+        let client = init_client(&config, Some(embedding_model))?;
+        let data = client
+            .embeddings(&EmbeddingsData {
+                query: false,
+                texts,
+            })
+            .await?;
+        let data: Vec<_> = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| {
+                json!({
+                        "object": "embedding",
+                        "embedding": v,
+                        "index": i,
+                })
+            })
+            .collect();
+        let output = json!({
+            "object": "list",
+            "data": data,
+            "model": embedding_model_id,
+            "usage": {
+                "prompt_tokens": 0,
+                "total_tokens": 0,
+            }
+        });
+        let res = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(output.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    async fn rerank(&self, req_body: Bytes) -> Result<AppResponse> {
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("rerank request: {req_body}");
+        let req_body = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let RerankReqBody {
+            model: reranker_model_id,
+            documents,
+            query,
+            top_n,
+        } = req_body;
+
+        let top_n = top_n.unwrap_or(documents.len());
+
+        let config = Arc::new(RwLock::new(self.config.clone()));
+
+        let reranker_model =
+            Model::retrieve_model(&config.read(), &reranker_model_id, ModelType::Reranker)?;
+
+        let client = init_client(&config, Some(reranker_model))?;
+        let data = client
+            .rerank(&RerankData {
+                query,
+                documents: documents.clone(),
+                top_n,
+            })
+            .await?;
+
+        let results: Vec<_> = data
+            .into_iter()
+            .map(|v| {
+                json!({
+                    "index": v.index,
+                    "relevance_score": v.relevance_score,
+                    "document": documents.get(v.index).map(|v| json!(v)).unwrap_or_default(),
+                })
+            })
+            .collect();
+        let output = json!({
+            "id": uuid::Uuid::new_v4().to_string(),
+            "results": results,
+        });
+        let res = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(output.to_string())).boxed())?;
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRagReqBody {
+    name: String,
+    input: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionsReqBody {
+    model: String,
+    messages: Vec<Value>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    max_tokens: Option<isize>,
+    #[serde(default)]
+    stream: bool,
+    tools: Option<Vec<Value>>,
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesReqBody {
+    model: String,
+    system: Option<Value>,
+    messages: Vec<Value>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    max_tokens: Option<isize>,
+    #[serde(default)]
+    stream: bool,
+    tools: Option<Vec<Value>>,
+}
+
+impl MessagesReqBody {
+    fn into_chat_completions(self) -> Result<ChatCompletionsReqBody> {
+        let tools = self.tools.map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool["name"],
+                            "description": tool["description"].as_str().unwrap_or_default(),
+                            "parameters": tool["input_schema"],
+                        }
+                    })
+                })
+                .collect()
+        });
+        Ok(ChatCompletionsReqBody {
+            model: self.model,
+            messages: convert_anthropic_messages(self.system, self.messages)?,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            stream: self.stream,
+            tools,
+            seed: None,
+        })
+    }
+}
+
+struct ChatRequest {
+    client: Box<dyn Client>,
+    http_client: reqwest::Client,
+    data: ChatCompletionsData,
+    model_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsReqBody {
+    input: EmbeddingsReqBodyInput,
+    model: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EmbeddingsReqBodyInput {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct RerankReqBody {
+    documents: Vec<String>,
+    query: String,
+    model: String,
+    top_n: Option<usize>,
+}
+
+#[derive(Debug)]
+enum ResEvent {
+    First(Option<String>),
+    Text(String),
+    ToolCalls(Vec<ToolCall>),
+    Done(ReplyEnd),
+}
+
+/// Why the reply stopped and what it used, as the API reports them
+#[derive(Debug, Clone, Default)]
+struct ReplyEnd {
+    finish_reason: Option<String>,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+}
+
+/// The Anthropic `stop_reason` of a reply
+fn messages_stop_reason(finish_reason: Option<&str>, has_tool_calls: bool) -> &'static str {
+    if has_tool_calls {
+        return "tool_use";
+    }
+    match finish_reason {
+        Some("length" | "max_tokens" | "MAX_TOKENS") => "max_tokens",
+        Some("stop_sequence") => "stop_sequence",
+        _ => "end_turn",
+    }
+}
+
+/// Translates the Anthropic Messages events out of the chat events
+struct MessagesStreamState {
+    id: String,
+    model: String,
+    started: bool,
+    text_block: Option<usize>,
+    next_index: usize,
+    has_tool_calls: bool,
+}
+
+impl MessagesStreamState {
+    fn new(id: String, model: String) -> Self {
+        Self {
+            id,
+            model,
+            started: false,
+            text_block: None,
+            next_index: 0,
+            has_tool_calls: false,
+        }
+    }
+
+    fn frame(&mut self, res_event: ResEvent) -> Option<Frame<Bytes>> {
+        let mut events = vec![];
+        if !self.started {
+            self.started = true;
+            events.push(json!({
+                "type": "message_start",
+                "message": {
+                    "id": self.id,
+                    "type": "message",
+                    "role": "assistant",
+                    "model": self.model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": { "input_tokens": 0, "output_tokens": 0 },
+                },
+            }));
+        }
+        match res_event {
+            ResEvent::Text(text) => {
+                if !text.is_empty() {
+                    let index = match self.text_block {
+                        Some(index) => index,
+                        None => {
+                            let index =
+                                self.open_block(&mut events, json!({ "type": "text", "text": "" }));
+                            self.text_block = Some(index);
+                            index
+                        }
+                    };
+                    events.push(json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": { "type": "text_delta", "text": text },
+                    }));
+                }
+            }
+            ResEvent::ToolCalls(tool_calls) => {
+                self.close_text_block(&mut events);
+                self.has_tool_calls = true;
+                for call in tool_calls {
+                    let index = self.open_block(
+                        &mut events,
+                        json!({ "type": "tool_use", "id": call.id, "name": call.name, "input": {} }),
+                    );
+                    events.push(json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": { "type": "input_json_delta", "partial_json": call.arguments.to_string() },
+                    }));
+                    events.push(json!({ "type": "content_block_stop", "index": index }));
+                }
+            }
+            ResEvent::Done(end) => {
+                self.close_text_block(&mut events);
+                let stop_reason =
+                    messages_stop_reason(end.finish_reason.as_deref(), self.has_tool_calls);
+                events.push(json!({
+                    "type": "message_delta",
+                    "delta": { "stop_reason": stop_reason, "stop_sequence": null },
+                    "usage": {
+                        "input_tokens": end.input_tokens.unwrap_or_default(),
+                        "output_tokens": end.output_tokens.unwrap_or_default(),
+                    },
+                }));
+                events.push(json!({ "type": "message_stop" }));
+            }
+            ResEvent::First(_) => {}
+        }
+        if events.is_empty() {
+            return None;
+        }
+        let data: String = events
+            .into_iter()
+            .map(|v| {
+                format!(
+                    "event: {}\ndata: {v}\n\n",
+                    v["type"].as_str().unwrap_or_default()
+                )
+            })
+            .collect();
+        Some(Frame::data(Bytes::from(data)))
+    }
+
+    fn open_block(&mut self, events: &mut Vec<Value>, content_block: Value) -> usize {
+        let index = self.next_index;
+        self.next_index += 1;
+        events.push(json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": content_block,
+        }));
+        index
+    }
+
+    fn close_text_block(&mut self, events: &mut Vec<Value>) {
+        if let Some(index) = self.text_block.take() {
+            events.push(json!({ "type": "content_block_stop", "index": index }));
+        }
+    }
+}
+
+/// Run a streaming chat in the background, failing early if the request is rejected.
+async fn start_chat_stream(
+    client: Box<dyn Client>,
+    http_client: reqwest::Client,
+    data: ChatCompletionsData,
+) -> Result<UnboundedReceiver<ResEvent>> {
+    let abort_signal = create_abort_signal();
+    let (tx, mut rx) = unbounded_channel();
+    tokio::spawn(async move {
+        let is_first = Arc::new(AtomicBool::new(true));
+        let (sse_tx, sse_rx) = unbounded_channel();
+        let mut handler = SseHandler::new(sse_tx, abort_signal);
+        let end = Arc::new(Mutex::new(ReplyEnd::default()));
+        async fn map_event(
+            mut sse_rx: UnboundedReceiver<SseEvent>,
+            tx: &UnboundedSender<ResEvent>,
+            is_first: Arc<AtomicBool>,
+            end: Arc<Mutex<ReplyEnd>>,
+        ) {
+            while let Some(reply_event) = sse_rx.recv().await {
+                if is_first.load(Ordering::SeqCst) {
+                    let _ = tx.send(ResEvent::First(None));
+                    is_first.store(false, Ordering::SeqCst)
+                }
+                match reply_event {
+                    SseEvent::Text(text) => {
+                        let _ = tx.send(ResEvent::Text(text));
+                    }
+                    SseEvent::Done => {
+                        let _ = tx.send(ResEvent::Done(end.lock().clone()));
+                        sse_rx.close();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        async fn chat_completions(
+            client: &dyn Client,
+            http_client: &reqwest::Client,
+            handler: &mut SseHandler,
+            mut data: ChatCompletionsData,
+            tx: &UnboundedSender<ResEvent>,
+            is_first: Arc<AtomicBool>,
+            end: Arc<Mutex<ReplyEnd>>,
+        ) {
+            let _slot = client.acquire_slot().await;
+            if client.model().no_stream() {
+                data.stream = false;
+                let ret = client.chat_completions_inner(http_client, data).await;
+                match ret {
+                    Ok(output) => {
+                        let ChatCompletionsOutput {
+                            text,
+                            tool_calls,
+                            input_tokens,
+                            output_tokens,
+                            finish_reason,
+                            ..
+                        } = output;
+                        *end.lock() = ReplyEnd {
+                            finish_reason,
+                            input_tokens,
+                            output_tokens,
+                        };
+                        let _ = tx.send(ResEvent::First(None));
+                        is_first.store(false, Ordering::SeqCst);
+                        let _ = tx.send(ResEvent::Text(text));
+                        if !tool_calls.is_empty() {
+                            let _ = tx.send(ResEvent::ToolCalls(tool_calls));
+                        }
+                    }
+                    Err(err) => {
+                        let _ = tx.send(ResEvent::First(Some(format!("{err:?}"))));
+                        is_first.store(false, Ordering::SeqCst)
+                    }
+                };
+            } else {
+                let ret = client
+                    .chat_completions_streaming_inner(http_client, handler, data)
+                    .await;
+                let first = match ret {
+                    Ok(()) => None,
+                    Err(err) => Some(format!("{err:?}")),
+                };
+                if is_first.load(Ordering::SeqCst) {
+                    let _ = tx.send(ResEvent::First(first));
+                    is_first.store(false, Ordering::SeqCst)
+                }
+                let tool_calls = handler.tool_calls().to_vec();
+                if !tool_calls.is_empty() {
+                    let _ = tx.send(ResEvent::ToolCalls(tool_calls));
+                }
+                let (input_tokens, output_tokens) = handler.usage();
+                *end.lock() = ReplyEnd {
+                    finish_reason: handler.finish_reason().map(|v| v.to_string()),
+                    input_tokens,
+                    output_tokens,
+                };
+            }
+            handler.done();
+        }
+        tokio::join!(
+            map_event(sse_rx, &tx, is_first.clone(), end.clone()),
+            chat_completions(
+                client.as_ref(),
+                &http_client,
+                &mut handler,
+                data,
+                &tx,
+                is_first,
+                end
+            ),
+        );
+    });
+
+    let first_event = rx.recv().await;
+
+    if let Some(ResEvent::First(Some(err))) = first_event {
+        bail!("{err}");
+    }
+
+    Ok(rx)
+}
+
+fn log_output(entry: &mut LogEntry, output: &ChatCompletionsOutput) {
+    entry.push_response(&output.text);
+    for call in &output.tool_calls {
+        entry.push_response(&format!("\n{}({})", call.name, call.arguments));
+    }
+    entry.input_tokens = output.input_tokens;
+    entry.output_tokens = output.output_tokens;
+}
+
+/// Written off the async runtime, the file may be rotated on the way
+fn write_log(log: &Arc<RequestLog>, entry: LogEntry) {
+    let log = log.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = log.write(entry) {
+            warn!("Failed to write the request log, {err}");
+        }
+    });
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler")
+}
+
+pub(crate) fn generate_completion_id() -> String {
+    let random_id = chrono::Utc::now().nanosecond();
+    format!("chatcmpl-{}", random_id)
+}
+
+fn generate_message_id() -> String {
+    let random_id = chrono::Utc::now().nanosecond();
+    format!("msg_{}", random_id)
+}
+
+fn set_cors_header(res: &mut AppResponse) {
+    res.headers_mut().insert(
+        hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        hyper::header::HeaderValue::from_static("*"),
+    );
+    res.headers_mut().insert(
+        hyper::header::ACCESS_CONTROL_ALLOW_METHODS,
+        hyper::header::HeaderValue::from_static("GET,POST,PUT,PATCH,DELETE"),
+    );
+    res.headers_mut().insert(
+        hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
+        hyper::header::HeaderValue::from_static("Content-Type,Authorization"),
+    );
+}
+
+fn create_text_frame(id: &str, model: &str, created: i64, content: &str) -> Frame<Bytes> {
+    let delta = if content.is_empty() {
+        json!({ "role": "assistant", "content": content })
+    } else {
+        json!({ "content": content })
+    };
+    let choice = json!({
+        "index": 0,
+        "delta": delta,
+        "finish_reason": null,
+    });
+    let value = build_chat_completion_chunk_json(id, model, created, &choice);
+    Frame::data(Bytes::from(format!("data: {value}\n\n")))
+}
+
+fn create_tool_calls_frame(
+    id: &str,
+    model: &str,
+    created: i64,
+    tool_calls: &[ToolCall],
+) -> Frame<Bytes> {
+    let chunks = tool_calls
+        .iter()
+        .enumerate()
+        .flat_map(|(i, call)| {
+            let choice1 = json!({
+              "index": 0,
+              "delta": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [
+                  {
+                    "index": i,
+                    "id": call.id,
+                    "type": "function",
+                    "function": {
+                      "name": call.name,
+                      "arguments": ""
+                    }
+                  }
+                ]
+              },
+              "finish_reason": null
+            });
+            let choice2 = json!({
+              "index": 0,
+              "delta": {
+                "tool_calls": [
+                  {
+                    "index": i,
+                    "function": {
+                      "arguments": call.arguments.to_string(),
+                    }
+                  }
+                ]
+              },
+              "finish_reason": null
+            });
+            vec![
+                build_chat_completion_chunk_json(id, model, created, &choice1),
+                build_chat_completion_chunk_json(id, model, created, &choice2),
+            ]
+        })
+        .map(|v| format!("data: {v}\n\n"))
+        .collect::<Vec<String>>()
+        .join("");
+    Frame::data(Bytes::from(chunks))
+}
+
+fn create_done_frame(id: &str, model: &str, created: i64, has_tool_calls: bool) -> Frame<Bytes> {
+    let finish_reason = if has_tool_calls { "tool_calls" } else { "stop" };
+    let choice = json!({
+        "index": 0,
+        "delta": {},
+        "finish_reason": finish_reason,
+    });
+    let value = build_chat_completion_chunk_json(id, model, created, &choice);
+    Frame::data(Bytes::from(format!("data: {value}\n\ndata: [DONE]\n\n")))
+}
+
+fn build_chat_completion_chunk_json(id: &str, model: &str, created: i64, choice: &Value) -> Value {
+    json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": [choice],
+    })
+}
+
+fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsOutput) -> Bytes {
+    Bytes::from(chat_completion_json(id, model, created, output).to_string())
+}
+
+/// The body of a non-streaming `/v1/chat/completions` response
+pub(crate) fn chat_completion_json(
+    id: &str,
+    model: &str,
+    created: i64,
+    output: &ChatCompletionsOutput,
+) -> Value {
+    let id = output.id.as_deref().unwrap_or(id);
+    let input_tokens = output.input_tokens.unwrap_or_default();
+    let output_tokens = output.output_tokens.unwrap_or_default();
+    let total_tokens = input_tokens + output_tokens;
+    let choice = if output.tool_calls.is_empty() {
+        json!({
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": output.text,
+            },
+            "logprobs": null,
+            "finish_reason": "stop",
+        })
+    } else {
+        let content = if output.text.is_empty() {
+            Value::Null
+        } else {
+            output.text.clone().into()
+        };
+        let tool_calls: Vec<_> = output
+            .tool_calls
+            .iter()
+            .map(|call| {
+                json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": {
+                        "name": call.name,
+                        "arguments": call.arguments.to_string(),
+                    }
+                })
+            })
+            .collect();
+        json!({
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": content,
+                "tool_calls": tool_calls,
+            },
+            "logprobs": null,
+            "finish_reason": "tool_calls",
+        })
+    };
+    json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": [choice],
+        "usage": {
+            "prompt_tokens": input_tokens,
+            "completion_tokens": output_tokens,
+            "total_tokens": total_tokens,
+        },
+    })
+}
+
+fn ret_messages_non_stream(id: &str, model: &str, output: &ChatCompletionsOutput) -> Bytes {
+    let mut content = vec![];
+    if !output.text.is_empty() {
+        content.push(json!({ "type": "text", "text": output.text }));
+    }
+    for call in &output.tool_calls {
+        content.push(json!({
+            "type": "tool_use",
+            "id": call.id,
+            "name": call.name,
+            "input": call.arguments,
+        }));
+    }
+    let stop_reason = messages_stop_reason(
+        output.finish_reason.as_deref(),
+        !output.tool_calls.is_empty(),
+    );
+    let res_body = json!({
+        "id": id,
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": content,
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": output.input_tokens.unwrap_or_default(),
+            "output_tokens": output.output_tokens.unwrap_or_default(),
+        },
+    });
+    Bytes::from(res_body.to_string())
+}
+
+fn ret_err<T: std::fmt::Display>(err: T) -> AppResponse {
+    let data = json!({
+        "error": {
+            "message": err.to_string(),
+            "type": "invalid_request_error",
+        },
+    });
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(data.to_string())).boxed())
+        .unwrap()
+}
+
+fn ret_messages_err<T: std::fmt::Display>(err: T) -> AppResponse {
+    let data = json!({
+        "type": "error",
+        "error": {
+            "type": "invalid_request_error",
+            "message": err.to_string(),
+        },
+    });
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(data.to_string())).boxed())
+        .unwrap()
+}
+
+/// Convert Anthropic messages into the OpenAI shape understood by `parse_messages`.
+fn convert_anthropic_messages(system: Option<Value>, messages: Vec<Value>) -> Result<Vec<Value>> {
+    let mut output = vec![];
+    if let Some(system) = system {
+        output.push(json!({ "role": "system", "content": anthropic_text(&system) }));
+    }
+    for (i, message) in messages.into_iter().enumerate() {
+        let err = || anyhow!("Failed to parse '.messages[{i}]'");
+        let role = message["role"].as_str().ok_or_else(err)?;
+        let blocks = match &message["content"] {
+            Value::String(text) => vec![json!({ "type": "text", "text": text })],
+            Value::Array(blocks) => blocks.clone(),
+            _ => return Err(err()),
+        };
+        match role {
+            "user" => {
+                let mut parts = vec![];
+                for block in blocks {
+                    match block["type"].as_str() {
+                        Some("tool_result") => output.push(json!({
+                            "role": "tool",
+                            "tool_call_id": block["tool_use_id"],
+                            "content": anthropic_text(&block["content"]),
+                        })),
+                        Some("text") => {
+                            parts.push(json!({ "type": "text", "text": block["text"] }))
+                        }
+                        Some("image") => {
+                            let source = &block["source"];
+                            let url = match source["type"].as_str() {
+                                Some("base64") => format!(
+                                    "data:{};base64,{}",
+                                    source["media_type"].as_str().ok_or_else(err)?,
+                                    source["data"].as_str().ok_or_else(err)?
+                                ),
+                                Some("url") => source["url"].as_str().ok_or_else(err)?.to_string(),
+                                _ => return Err(err()),
+                            };
+                            parts.push(json!({ "type": "image_url", "image_url": { "url": url } }))
+                        }
+                        _ => return Err(err()),
+                    }
+                }
+                if !parts.is_empty() {
+                    output.push(json!({ "role": "user", "content": parts }));
+                }
+            }
+            "assistant" => {
+                let mut text = String::new();
+                let mut tool_calls = vec![];
+                for block in blocks {
+                    match block["type"].as_str() {
+                        Some("text") => text.push_str(block["text"].as_str().unwrap_or_default()),
+                        Some("tool_use") => tool_calls.push(json!({
+                            "id": block["id"],
+                            "type": "function",
+                            "function": {
+                                "name": block["name"],
+                                "arguments": block["input"].to_string(),
+                            }
+                        })),
+                        Some("thinking") | Some("redacted_thinking") => {}
+                        _ => return Err(err()),
+                    }
+                }
+                if tool_calls.is_empty() {
+                    output.push(json!({ "role": "assistant", "content": text }));
+                } else {
+                    output.push(
+                        json!({ "role": "assistant", "content": text, "tool_calls": tool_calls }),
+                    );
+                }
+            }
+            _ => return Err(err()),
+        }
+    }
+    Ok(output)
+}
+
+fn anthropic_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|v| v["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => String::new(),
+    }
+}
+
+fn parse_messages(message: Vec<Value>) -> Result<Vec<Message>> {
+    let mut output = vec![];
+    let mut tool_results = None;
+    for (i, message) in message.into_iter().enumerate() {
+        let err = || anyhow!("Failed to parse '.messages[{i}]'");
+        let role = message["role"].as_str().ok_or_else(err)?;
+        let content = match message.get("content") {
+            Some(value) => {
+                if let Some(value) = value.as_str() {
+                    MessageContent::Text(value.into())
+                } else if value.is_array() {
+                    let value = serde_json::from_value(value.clone()).map_err(|_| err())?;
+                    MessageContent::Array(value)
+                } else if value.is_null() {
+                    MessageContent::Text("".into())
+                } else {
+                    return Err(err());
+                }
+            }
+            None => MessageContent::Text("".into()),
+        };
+        match role {
+            "system" | "user" => {
+                let role = match role {
+                    "system" => MessageRole::System,
+                    "user" => MessageRole::User,
+                    _ => unreachable!(),
+                };
+                output.push(Message::new(role, content))
+            }
+            "assistant" => {
+                let role = MessageRole::Assistant;
+                match message["tool_calls"].as_array() {
+                    Some(tool_calls) => {
+                        if tool_results.is_some() {
+                            return Err(err());
+                        }
+                        let mut list = vec![];
+                        for tool_call in tool_calls {
+                            if let (id, Some(name), Some(arguments)) = (
+                                tool_call["id"].as_str().map(|v| v.to_string()),
+                                tool_call["function"]["name"].as_str(),
+                                tool_call["function"]["arguments"].as_str(),
+                            ) {
+                                let arguments =
+                                    serde_json::from_str(arguments).map_err(|_| err())?;
+                                list.push((id, name.to_string(), arguments));
+                            } else {
+                                return Err(err());
+                            }
+                        }
+                        tool_results = Some((content.to_text(), list, vec![]));
+                    }
+                    None => output.push(Message::new(role, content)),
+                }
+            }
+            "tool" => match tool_results.take() {
+                Some((text, tool_calls, mut tool_values)) => {
+                    let tool_call_id = message["tool_call_id"].as_str().map(|v| v.to_string());
+                    let content = content.to_text();
+                    let value: Value = serde_json::from_str(&content)
+                        .ok()
+                        .unwrap_or_else(|| content.into());
+
+                    tool_values.push((value, tool_call_id));
+
+                    if tool_calls.len() == tool_values.len() {
+                        let mut list = vec![];
+                        for ((id, name, arguments), (value, tool_call_id)) in
+                            tool_calls.into_iter().zip(tool_values)
+                        {
+                            if id != tool_call_id {
+                                return Err(err());
+                            }
+                            list.push(ToolResult::new(ToolCall::new(name, arguments, id), value))
+                        }
+                        output.push(Message::new(
+                            MessageRole::Assistant,
+                            MessageContent::ToolCalls(MessageContentToolCalls::new(list, text)),
+                        ));
+                        tool_results = None;
+                    } else {
+                        tool_results = Some((text, tool_calls, tool_values));
+                    }
+                }
+                None => return Err(err()),
+            },
+            _ => {
+                return Err(err());
+            }
+        }
+    }
+
+    if tool_results.is_some() {
+        bail!("Invalid messages");
+    }
+
+    Ok(output)
+}
+
+fn parse_tools(tools: Option<Vec<Value>>) -> Result<Option<Vec<FunctionDeclaration>>> {
+    let tools = match tools {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let mut functions = vec![];
+    for (i, tool) in tools.into_iter().enumerate() {
+        if let (Some("function"), Some(function)) = (
+            tool["type"].as_str(),
+            tool["function"]
+                .as_object()
+                .and_then(|v| serde_json::from_value(json!(v)).ok()),
+        ) {
+            functions.push(function);
+        } else {
+            bail!("Failed to parse '.tools[{i}]'")
+        }
+    }
+    Ok(Some(functions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_events(frame: Frame<Bytes>) -> Vec<Value> {
+        let data = frame.into_data().unwrap();
+        String::from_utf8_lossy(&data)
+            .lines()
+            .filter_map(|v| v.strip_prefix("data: "))
+            .map(|v| serde_json::from_str(v).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_messages_stream_done() {
+        let mut state = MessagesStreamState::new("msg_1".into(), "test".into());
+        state.frame(ResEvent::Text("hi".into())).unwrap();
+        let end = ReplyEnd {
+            finish_reason: Some("length".into()),
+            input_tokens: Some(12),
+            output_tokens: Some(34),
+        };
+        let events = frame_events(state.frame(ResEvent::Done(end)).unwrap());
+        let delta = events
+            .iter()
+            .find(|v| v["type"] == "message_delta")
+            .unwrap();
+        assert_eq!(delta["delta"]["stop_reason"], "max_tokens");
+        assert_eq!(
+            delta["usage"],
+            json!({ "input_tokens": 12, "output_tokens": 34 })
+        );
+        assert_eq!(events.last().unwrap()["type"], "message_stop");
+    }
+
+    #[test]
+    fn test_messages_non_stream() {
+        let output = ChatCompletionsOutput {
+            text: "hi".into(),
+            input_tokens: Some(5),
+            output_tokens: Some(7),
+            finish_reason: Some("stop_sequence".into()),
+            ..Default::default()
+        };
+        let body: Value =
+            serde_json::from_slice(&ret_messages_non_stream("msg_1", "test", &output)).unwrap();
+        assert_eq!(body["stop_reason"], "stop_sequence");
+        assert_eq!(
+            body["usage"],
+            json!({ "input_tokens": 5, "output_tokens": 7 })
+        );
+    }
+
+    #[test]
+    fn test_messages_stop_reason() {
+        assert_eq!(messages_stop_reason(None, false), "end_turn");
+        assert_eq!(messages_stop_reason(Some("stop"), false), "end_turn");
+        assert_eq!(messages_stop_reason(Some("length"), false), "max_tokens");
+        assert_eq!(
+            messages_stop_reason(Some("MAX_TOKENS"), false),
+            "max_tokens"
+        );
+        assert_eq!(
+            messages_stop_reason(Some("stop_sequence"), false),
+            "stop_sequence"
+        );
+        assert_eq!(messages_stop_reason(Some("length"), true), "tool_use");
+    }
+
+    #[test]
+    fn test_convert_anthropic_messages() {
+        let messages = vec![
+            json!({ "role": "user", "content": "weather?" }),
+            json!({ "role": "assistant", "content": [
+                { "type": "text", "text": "Checking" },
+                { "type": "tool_use", "id": "t1", "name": "get_weather", "input": { "city": "Paris" } },
+            ] }),
+            json!({ "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "t1", "content": [{ "type": "text", "text": "sunny" }] },
+            ] }),
+        ];
+        let output = convert_anthropic_messages(Some(json!("be brief")), messages).unwrap();
+        assert_eq!(
+            output[0],
+            json!({ "role": "system", "content": "be brief" })
+        );
+        assert_eq!(
+            output[2]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Paris"}"#
+        );
+        assert_eq!(
+            output[3],
+            json!({ "role": "tool", "tool_call_id": "t1", "content": "sunny" })
+        );
+        let messages = parse_messages(output).unwrap();
+        assert_eq!(messages.len(), 3);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::config::ServeLogMode;
+use crate::utils::{read_tail_lines, rotate_file, rotated_path};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// The log file is rotated once it grows beyond this size
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+/// Rotated files kept as `<file>.1` (newest) to `<file>.N`
+const MAX_ROTATED_FILES: usize = 3;
+/// Characters of the request and reply kept in the `truncated` mode
+const TRUNCATED_LENGTH: usize = 256;
+
+/// Appends one json line per served request, rotating the file by size.
+///
+/// Both `write` and `recent` do blocking IO, the server calls them through `spawn_blocking`.
+#[derive(Debug)]
+pub struct RequestLog {
+    mode: ServeLogMode,
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub time: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// Written once the streamed reply finishes instead of when the handler returns
+    #[serde(skip)]
+    pub deferred: bool,
+    #[serde(skip)]
+    started: Option<Instant>,
+    #[serde(skip)]
+    request_body: String,
+    #[serde(skip)]
+    response_text: String,
+}
+
+impl LogEntry {
+    pub fn new(method: &str, path: &str, request_body: &[u8]) -> Self {
+        let body: Option<Value> = serde_json::from_slice(request_body).ok();
+        Self {
+            time: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            method: method.to_string(),
+            path: path.to_string(),
+            status: 0,
+            duration_ms: 0,
+            model: body
+                .as_ref()
+                .and_then(|v| v["model"].as_str())
+                .map(|v| v.to_string()),
+            stream: body.as_ref().and_then(|v| v["stream"].as_bool()),
+            input_tokens: None,
+            output_tokens: None,
+            error: None,
+            request: None,
+            response: None,
+            deferred: false,
+            started: Some(Instant::now()),
+            request_body: String::from_utf8_lossy(request_body).to_string(),
+            response_text: String::new(),
+        }
+    }
+
+    pub fn push_response(&mut self, text: &str) {
+        self.response_text.push_str(text);
+    }
+}
+
+impl RequestLog {
+    pub fn new(mode: ServeLogMode, path: PathBuf) -> Self {
+        Self {
+            mode,
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&self, mut entry: LogEntry) -> Result<()> {
+        if let Some(started) = entry.started {
+            entry.duration_ms = started.elapsed().as_millis();
+        }
+        match self.mode {
+            ServeLogMode::Metadata => {}
+            ServeLogMode::Truncated => {
+                entry.request = non_empty(truncate(&entry.request_body)).map(Value::String);
+                entry.response = non_empty(truncate(&entry.response_text)).map(Value::String);
+            }
+            ServeLogMode::Full => {
+                entry.request = non_empty(entry.request_body.clone())
+                    .map(|v| serde_json::from_str(&v).unwrap_or(Value::String(v)));
+                entry.response = non_empty(entry.response_text.clone()).map(Value::String);
+            }
+        }
+        let line = serde_json::to_string(&entry)?;
+
+        let _guard = self.lock.lock();
+        rotate_file(&self.path, MAX_LOG_SIZE, MAX_ROTATED_FILES)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open '{}'", self.path.display()))?;
+        writeln!(file, "{line}")?;
+        Ok(())
+    }
+
+    /// The last `limit` entries, oldest first, reaching into the newest rotated file if needed.
+    ///
+    /// Only the tail of the files is read, without blocking writers. A line being written is skipped.
+    pub fn recent(&self, limit: usize) -> Result<Vec<Value>> {
+        let mut lines = read_tail_lines(&self.path, limit)?;
+        if lines.len() < limit {
+            let mut rotated = read_tail_lines(&rotated_path(&self.path, 1), limit - lines.len())?;
+            rotated.append(&mut lines);
+            lines = rotated;
+        }
+        Ok(lines
+            .into_iter()
+            .filter_map(|v| serde_json::from_str(&v).ok())
+            .collect())
+    }
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(TRUNCATED_LENGTH) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+fn non_empty(text: String) -> Option<String> {
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_log() {
+        let dir = std::env::temp_dir().join(format!("aichat-serve-log-{}", std::process::id()));
+        let path = dir.join("serve.log.jsonl");
+        let log = RequestLog::new(ServeLogMode::Truncated, path.clone());
+        let body = format!(r#"{{"model":"m1","messages":"{}"}}"#, "x".repeat(300));
+        let mut entry = LogEntry::new("POST", "/v1/chat/completions", body.as_bytes());
+        entry.status = 200;
+        entry.push_response("hello");
+        log.write(entry.clone()).unwrap();
+        log.write(entry).unwrap();
+
+        let recent = log.recent(1).unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0]["model"], "m1");
+        assert_eq!(recent[0]["response"], "hello");
+        assert!(recent[0]["request"].as_str().unwrap().ends_with('…'));
+
+        let log = RequestLog::new(ServeLogMode::Metadata, path);
+        log.write(LogEntry::new("GET", "/v1/models", b"")).unwrap();
+        assert!(log.recent(1).unwrap()[0].get("request").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
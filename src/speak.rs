@@ -0,0 +1,258 @@
+use crate::config::{GlobalConfig, Input};
+use crate::utils::{dimmed_text, IS_STDIN_TERMINAL, IS_STDOUT_TERMINAL};
+
+use anyhow::{anyhow, bail, Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use fancy_regex::Regex;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    static ref LINK_RE: Regex = Regex::new(r"!?\[([^\]]*)\]\([^)]*\)").unwrap();
+    static ref LIST_MARKER_RE: Regex = Regex::new(r"^(#{1,6}|[-*+]|\d+[.)]|>)\s+").unwrap();
+}
+
+/// Read the reply aloud if `speak` is on, with the voice of the role or `speak_voice`.
+pub async fn maybe_speak(config: &GlobalConfig, input: &Input, text: &str) -> Result<()> {
+    let (speak_command, voice) = {
+        let config = config.read();
+        if !config.speak || !*IS_STDOUT_TERMINAL || !*IS_STDIN_TERMINAL {
+            return Ok(());
+        }
+        (config.speak_command.clone(), config.speak_voice.clone())
+    };
+    let voice = input.role().voice().map(|v| v.to_string()).or(voice);
+    let cmd_args = speak_command_args(speak_command.as_deref(), voice.as_deref())?;
+    let segments = speech_segments(text);
+    if segments.is_empty() {
+        return Ok(());
+    }
+    let hint = if cfg!(unix) {
+        "🔊 space: pause/resume · n: skip · q: stop"
+    } else {
+        "🔊 n: skip · q: stop"
+    };
+    println!("{}", dimmed_text(hint));
+    tokio::task::spawn_blocking(move || {
+        terminal::enable_raw_mode()?;
+        let ret = play_segments(&cmd_args, &segments);
+        terminal::disable_raw_mode()?;
+        ret
+    })
+    .await?
+}
+
+enum Playback {
+    Finished,
+    Skipped,
+    Stopped,
+}
+
+fn play_segments(cmd_args: &[String], segments: &[String]) -> Result<()> {
+    for segment in segments {
+        if let Playback::Stopped = play_segment(cmd_args, segment)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn play_segment(cmd_args: &[String], text: &str) -> Result<Playback> {
+    let (cmd, args) = cmd_args.split_at(1);
+    let cmd = &cmd[0];
+    let mut child = Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Unable to run `{cmd}`, Perhaps it is not installed?"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let mut paused = false;
+    loop {
+        if child.try_wait()?.is_some() {
+            return Ok(Playback::Finished);
+        }
+        if !event::poll(Duration::from_millis(50))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char(' ') | KeyCode::Char('p') if pause_player(&child, !paused) => {
+                paused = !paused;
+            }
+            KeyCode::Char('n') | KeyCode::Right => {
+                stop_player(&mut child, paused);
+                return Ok(Playback::Skipped);
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                stop_player(&mut child, paused);
+                return Ok(Playback::Stopped);
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                stop_player(&mut child, paused);
+                return Ok(Playback::Stopped);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(unix)]
+fn pause_player(child: &Child, pause: bool) -> bool {
+    let signal = if pause { libc::SIGSTOP } else { libc::SIGCONT };
+    unsafe { libc::kill(child.id() as libc::pid_t, signal) == 0 }
+}
+
+#[cfg(not(unix))]
+fn pause_player(_child: &Child, _pause: bool) -> bool {
+    false
+}
+
+fn stop_player(child: &mut Child, paused: bool) {
+    if paused {
+        pause_player(child, false);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// The player reads the text from stdin, `$1` in `speak_command` is replaced by the voice.
+fn speak_command_args(speak_command: Option<&str>, voice: Option<&str>) -> Result<Vec<String>> {
+    let cmd_args = match speak_command {
+        Some(speak_command) => shell_words::split(speak_command)
+            .with_context(|| anyhow!("Invalid speak_command `{speak_command}`"))?
+            .into_iter()
+            .map(|v| v.replace("$1", voice.unwrap_or_default()))
+            .collect::<Vec<_>>(),
+        None => {
+            let mut cmd_args = if cfg!(target_os = "macos") {
+                vec!["say".to_string()]
+            } else {
+                vec!["espeak-ng".to_string(), "--stdin".to_string()]
+            };
+            if let Some(voice) = voice {
+                cmd_args.extend(["-v".to_string(), voice.to_string()]);
+            }
+            cmd_args
+        }
+    };
+    if cmd_args.is_empty() {
+        bail!("Empty speak_command");
+    }
+    Ok(cmd_args)
+}
+
+/// Split the markdown reply into paragraphs to speak, code blocks are only announced.
+fn speech_segments(text: &str) -> Vec<String> {
+    let mut segments = vec![];
+    let mut paragraph: Vec<String> = vec![];
+    let mut code_block: Option<(String, usize)> = None;
+    let flush = |paragraph: &mut Vec<String>, segments: &mut Vec<String>| {
+        if !paragraph.is_empty() {
+            segments.push(paragraph.join(" "));
+            paragraph.clear();
+        }
+    };
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(lang) = line.strip_prefix("```") {
+            match code_block.take() {
+                Some((lang, lines)) => segments.push(code_block_note(&lang, lines)),
+                None => {
+                    flush(&mut paragraph, &mut segments);
+                    code_block = Some((lang.trim().to_string(), 0));
+                }
+            }
+            continue;
+        }
+        if let Some((_, lines)) = code_block.as_mut() {
+            *lines += 1;
+            continue;
+        }
+        if line.is_empty() || line.chars().all(|c| matches!(c, '-' | '*' | '_' | '=')) {
+            flush(&mut paragraph, &mut segments);
+            continue;
+        }
+        let is_item = LIST_MARKER_RE.is_match(line).unwrap_or_default();
+        let line = strip_markup(line);
+        if is_item {
+            // Headings and list items are spoken, and skipped, on their own.
+            flush(&mut paragraph, &mut segments);
+            if !line.is_empty() {
+                segments.push(line);
+            }
+        } else if !line.is_empty() {
+            paragraph.push(line);
+        }
+    }
+    flush(&mut paragraph, &mut segments);
+    if let Some((lang, lines)) = code_block {
+        segments.push(code_block_note(&lang, lines));
+    }
+    segments
+}
+
+fn strip_markup(line: &str) -> String {
+    let line = LIST_MARKER_RE.replace(line, "");
+    let line = LINK_RE.replace_all(&line, "$1");
+    line.replace("**", "")
+        .replace("__", "")
+        .replace('`', "")
+        .trim()
+        .to_string()
+}
+
+fn code_block_note(lang: &str, lines: usize) -> String {
+    let unit = if lines == 1 { "line" } else { "lines" };
+    if lang.is_empty() {
+        format!("Skipped a code block of {lines} {unit}.")
+    } else {
+        format!("Skipped a {lang} code block of {lines} {unit}.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speech_segments() {
+        let text = r#"## Usage
+
+Run `cargo build` first,
+then see the [docs](https://example.com).
+
+```rust
+fn main() {
+    println!("hello");
+}
+```
+
+- **Fast**
+- Small"#;
+        assert_eq!(
+            speech_segments(text),
+            vec![
+                "Usage",
+                "Run cargo build first, then see the docs.",
+                "Skipped a rust code block of 3 lines.",
+                "Fast",
+                "Small",
+            ]
+        );
+        assert_eq!(
+            speech_segments("Partial\n```\nlet a = 1;"),
+            vec!["Partial", "Skipped a code block of 1 line."]
+        );
+    }
+}
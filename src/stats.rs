@@ -0,0 +1,246 @@
+use crate::config::{load_usage, Config, UsageEntry};
+use crate::utils::{dimmed_text, render_table};
+
+use anyhow::{bail, Result};
+use chrono::{Duration, Local, NaiveDate};
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+const SPARK_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Longer periods are drawn with several days per bar
+const MAX_BARS: usize = 60;
+
+#[derive(Debug, Default, Clone, Serialize)]
+struct Totals {
+    requests: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost: f64,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &UsageEntry) {
+        self.requests += 1;
+        self.input_tokens += entry.input_tokens;
+        self.output_tokens += entry.output_tokens;
+        self.cost += entry.cost.unwrap_or_default();
+    }
+
+    fn merge(&mut self, other: &Totals) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cost += other.cost;
+    }
+
+    fn tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// Print `--stats`, the usage recorded in `usage.jsonl` over the period: `7d`, `4w`, `3m` or `all`.
+pub fn run(period: &str, json: bool) -> Result<()> {
+    let days = parse_period(period)?;
+    let entries: Vec<(NaiveDate, UsageEntry)> = load_usage(&Config::usage_file())?
+        .into_iter()
+        .filter_map(|v| Some((v.time()?.with_timezone(&Local).date_naive(), v)))
+        .collect();
+    let today = Local::now().date_naive();
+    let start = match days {
+        Some(days) => today - Duration::days(days - 1),
+        None => entries.iter().map(|(date, _)| *date).min().unwrap_or(today),
+    };
+
+    let mut total = Totals::default();
+    let mut daily: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+    let mut models: IndexMap<String, Totals> = IndexMap::new();
+    let mut roles: IndexMap<String, Totals> = IndexMap::new();
+    let mut date = start;
+    while date <= today {
+        daily.insert(date, Totals::default());
+        date += Duration::days(1);
+    }
+    for (date, entry) in entries.iter().filter(|(date, _)| *date >= start) {
+        total.add(entry);
+        daily.entry(*date).or_default().add(entry);
+        models.entry(entry.model.clone()).or_default().add(entry);
+        let role = entry.role.clone().unwrap_or_else(|| "-".into());
+        roles.entry(role).or_default().add(entry);
+    }
+    models.sort_by(|_, a, _, b| b.tokens().cmp(&a.tokens()));
+    roles.sort_by(|_, a, _, b| b.tokens().cmp(&a.tokens()));
+
+    if json {
+        let days: Vec<Value> = daily
+            .iter()
+            .map(|(date, v)| with_key("date", &date.to_string(), v))
+            .collect();
+        let models: Vec<Value> = models
+            .iter()
+            .map(|(k, v)| with_key("model", k, v))
+            .collect();
+        let roles: Vec<Value> = roles.iter().map(|(k, v)| with_key("role", k, v)).collect();
+        let output = json!({
+            "since": start.to_string(),
+            "until": today.to_string(),
+            "total": total,
+            "days": days,
+            "models": models,
+            "roles": roles,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    let title = match days {
+        Some(days) => format!("Last {days} days"),
+        None => "All time".into(),
+    };
+    println!(
+        "{title} ({start} – {today}): {} requests, {} tokens, {}",
+        total.requests,
+        format_tokens(total.tokens()),
+        format_cost(total.cost)
+    );
+    if total.requests == 0 {
+        return Ok(());
+    }
+
+    let days_per_bar = daily.len().div_ceil(MAX_BARS);
+    let bars: Vec<Totals> = daily
+        .values()
+        .collect::<Vec<_>>()
+        .chunks(days_per_bar)
+        .map(|chunk| {
+            let mut bar = Totals::default();
+            chunk.iter().for_each(|v| bar.merge(v));
+            bar
+        })
+        .collect();
+    let unit = if days_per_bar == 1 {
+        "day".to_string()
+    } else {
+        format!("{days_per_bar} days")
+    };
+    let line = |name: &str, values: Vec<f64>, peak: String| {
+        println!(
+            "{name:<10}{}  {}",
+            sparkline(&values),
+            dimmed_text(&format!("peak {peak}/{unit}"))
+        );
+    };
+    println!();
+    let peak = |f: fn(&Totals) -> f64| bars.iter().map(f).fold(0.0, f64::max);
+    line(
+        "Requests",
+        bars.iter().map(|v| v.requests as f64).collect(),
+        peak(|v| v.requests as f64).to_string(),
+    );
+    line(
+        "Tokens",
+        bars.iter().map(|v| v.tokens() as f64).collect(),
+        format_tokens(peak(|v| v.tokens() as f64) as u64),
+    );
+    line(
+        "Cost",
+        bars.iter().map(|v| v.cost).collect(),
+        format_cost(peak(|v| v.cost)),
+    );
+
+    for (header, group) in [("MODEL", &models), ("ROLE", &roles)] {
+        let rows: Vec<Vec<String>> = group
+            .iter()
+            .map(|(name, v)| {
+                vec![
+                    name.clone(),
+                    v.requests.to_string(),
+                    format_tokens(v.input_tokens),
+                    format_tokens(v.output_tokens),
+                    format_cost(v.cost),
+                ]
+            })
+            .collect();
+        println!();
+        println!(
+            "{}",
+            render_table(&[header, "REQUESTS", "INPUT", "OUTPUT", "COST"], &rows)
+        );
+    }
+    Ok(())
+}
+
+/// `7d`, `4w` or `3m` in days, `None` for `all`.
+fn parse_period(period: &str) -> Result<Option<i64>> {
+    if period == "all" {
+        return Ok(None);
+    }
+    let count = period.trim_end_matches(char::is_alphabetic);
+    let unit = &period[count.len()..];
+    let days = match (count.parse::<i64>(), unit) {
+        (Ok(count), "d") if count > 0 => count,
+        (Ok(count), "w") if count > 0 => count * 7,
+        (Ok(count), "m") if count > 0 => count * 30,
+        _ => bail!("Invalid period '{period}', try 7d, 4w, 3m or all"),
+    };
+    Ok(Some(days))
+}
+
+fn sparkline(values: &[f64]) -> String {
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|v| {
+            if max <= 0.0 {
+                SPARK_CHARS[0]
+            } else {
+                SPARK_CHARS[((v / max) * (SPARK_CHARS.len() - 1) as f64).round() as usize]
+            }
+        })
+        .collect()
+}
+
+fn format_tokens(tokens: u64) -> String {
+    match tokens {
+        0..=999 => tokens.to_string(),
+        1_000..=999_999 => format!("{:.1}K", tokens as f64 / 1_000.0),
+        _ => format!("{:.1}M", tokens as f64 / 1_000_000.0),
+    }
+}
+
+fn format_cost(cost: f64) -> String {
+    if cost > 0.0 {
+        format!("${cost:.4}")
+    } else {
+        "-".into()
+    }
+}
+
+fn with_key(key: &str, name: &str, totals: &Totals) -> Value {
+    let mut value = json!(totals);
+    value[key] = name.into();
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_period() {
+        assert_eq!(parse_period("7d").unwrap(), Some(7));
+        assert_eq!(parse_period("2w").unwrap(), Some(14));
+        assert_eq!(parse_period("3m").unwrap(), Some(90));
+        assert_eq!(parse_period("all").unwrap(), None);
+        assert!(parse_period("0d").is_err());
+        assert!(parse_period("d").is_err());
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0.0, 1.0, 4.0, 8.0]), "▁▂▅█");
+        assert_eq!(sparkline(&[0.0, 0.0]), "▁▁");
+        assert_eq!(format_tokens(12_345), "12.3K");
+    }
+}
@@ -0,0 +1,109 @@
+use crate::batch::create_batch_input;
+use crate::config::GlobalConfig;
+use crate::utils::{dimmed_text, now, warning_text, AbortSignal};
+
+use anyhow::{bail, Result};
+use std::{
+    io::{stdin, BufRead},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// A partial window is sent after this long without new lines
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Lines buffered ahead of the model, reading stdin pauses once it is full
+const CHANNEL_WINDOWS: usize = 4;
+
+const NO_FINDINGS: &str = "NONE";
+
+/// Follow stdin, asking the model about each window of `window` lines and printing only the
+/// windows it flags.
+pub async fn run(
+    config: &GlobalConfig,
+    task: Option<String>,
+    window: usize,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let Some(task) = task else {
+        bail!("No instruction, e.g. `aichat --stream-stdin \"alert me when you see an anomaly\"`")
+    };
+    let window = window.max(1);
+    let prompt = format!(
+        r#"You watch a live stream of lines in windows. Task: {task}
+
+If nothing in the window is worth reporting, reply with {NO_FINDINGS} only.
+Otherwise list the findings concisely, quoting the relevant lines."#
+    );
+
+    let (tx, mut rx) = mpsc::channel::<String>(window * CHANNEL_WINDOWS);
+    std::thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.blocking_send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    let mut lines: Vec<String> = vec![];
+    let mut line_no = 0;
+    loop {
+        let next = tokio::select! {
+            v = tokio::time::timeout(FLUSH_INTERVAL, rx.recv()) => v,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let eof = match next {
+            Ok(Some(line)) => {
+                lines.push(line);
+                if lines.len() < window {
+                    continue;
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(_) => false,
+        };
+        if !lines.is_empty() {
+            let start = line_no + 1;
+            line_no += lines.len();
+            let text = std::mem::take(&mut lines).join("\n");
+            if let Err(err) = check_window(config, &prompt, &text, (start, line_no)).await {
+                eprintln!(
+                    "{}",
+                    warning_text(&format!("✗ lines {start}-{line_no}: {err:#}"))
+                );
+            }
+        }
+        if eof || abort_signal.aborted() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn check_window(
+    config: &GlobalConfig,
+    prompt: &str,
+    text: &str,
+    (start, end): (usize, usize),
+) -> Result<()> {
+    let input = create_batch_input(config, None, "stream-stdin", prompt, text)?;
+    let client = input.create_client()?;
+    let output = client.chat_completions(input).await?;
+    let findings = output.text.trim();
+    if findings.is_empty() || findings.trim_matches('.').eq_ignore_ascii_case(NO_FINDINGS) {
+        return Ok(());
+    }
+    println!(
+        "{}",
+        dimmed_text(&format!("[{}] lines {start}-{end}", now()))
+    );
+    config.read().print_markdown(findings)?;
+    println!();
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use crate::batch::{create_batch_input, run_batch_with_progress, DEFAULT_BATCH_CONCURRENCY};
+use crate::client::{call_chat_completions, call_chat_completions_streaming};
+use crate::config::{GlobalConfig, Input};
+use crate::rag::{get_separators, RecursiveCharacterTextSplitter};
+use crate::utils::{abortable_run_with_spinner_rx, estimate_token_length, AbortSignal, Spinner};
+
+use anyhow::{bail, Result};
+
+/// Used when the model doesn't declare `max_input_tokens`
+const DEFAULT_MAX_INPUT_TOKENS: usize = 8192;
+
+const SUMMARIZE_PROMPT: &str = "Summarize the following document concisely, keeping the key facts, names, numbers and conclusions.";
+const MAP_PROMPT: &str = "You are given one part of a longer document. Summarize this part concisely, keeping the key facts, names, numbers and conclusions. Do not add an introduction.";
+const REDUCE_PROMPT: &str = "The following are summaries of consecutive parts of one document. Merge them into a single coherent summary of the whole document, removing repetition and keeping the original order.";
+
+/// Summarize a file or url. Inputs beyond the token budget are split into chunks summarized
+/// concurrently (map), then the partial summaries are merged (reduce), in rounds if needed.
+pub async fn run(config: &GlobalConfig, path: &str, abort_signal: AbortSignal) -> Result<()> {
+    let input = Input::builder(config)
+        .file(path)
+        .spinner(abort_signal.clone())
+        .build()
+        .await?;
+    let text = input.text();
+    if text.trim().is_empty() {
+        bail!("Nothing to summarize in '{path}'");
+    }
+    let budget = token_budget(config);
+
+    if estimate_token_length(&text) <= budget {
+        return summarize(config, SUMMARIZE_PROMPT, &text, abort_signal).await;
+    }
+
+    let extension = path.rsplit_once('.').map(|(_, v)| v).unwrap_or_default();
+    let mut splitter =
+        RecursiveCharacterTextSplitter::new(budget, budget / 20, &get_separators(extension));
+    splitter.length_function = Box::new(estimate_token_length);
+    let chunks = splitter.split_text(&text);
+    let total = chunks.len();
+    let texts: Vec<String> = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| format!("Part {}/{total}:\n\n{chunk}", i + 1))
+        .collect();
+    let mut summaries = run_prompt_batch(
+        config,
+        MAP_PROMPT,
+        texts,
+        "Summarizing",
+        abort_signal.clone(),
+    )
+    .await?;
+
+    while estimate_token_length(&summaries.join("\n\n")) > budget {
+        let groups = group_by_budget(&summaries, budget);
+        if groups.len() >= summaries.len() {
+            bail!("The partial summaries no longer shrink to fit the context window");
+        }
+        summaries = run_prompt_batch(
+            config,
+            REDUCE_PROMPT,
+            groups,
+            "Merging",
+            abort_signal.clone(),
+        )
+        .await?;
+    }
+
+    summarize(
+        config,
+        REDUCE_PROMPT,
+        &summaries.join("\n\n---\n\n"),
+        abort_signal,
+    )
+    .await
+}
+
+/// Tokens of a chunk, leaving a quarter of the context window for the prompt and the reply
+fn token_budget(config: &GlobalConfig) -> usize {
+    let max_input_tokens = config
+        .read()
+        .current_model()
+        .max_input_tokens()
+        .unwrap_or(DEFAULT_MAX_INPUT_TOKENS);
+    (max_input_tokens * 3 / 4).max(256)
+}
+
+fn group_by_budget(summaries: &[String], budget: usize) -> Vec<String> {
+    let mut groups: Vec<String> = vec![];
+    let mut tokens = 0;
+    for summary in summaries {
+        let summary_tokens = estimate_token_length(summary);
+        match groups.last_mut() {
+            Some(group) if tokens + summary_tokens <= budget => {
+                group.push_str("\n\n---\n\n");
+                group.push_str(summary);
+                tokens += summary_tokens;
+            }
+            _ => {
+                groups.push(summary.clone());
+                tokens = summary_tokens;
+            }
+        }
+    }
+    groups
+}
+
+async fn run_prompt_batch(
+    config: &GlobalConfig,
+    prompt: &str,
+    texts: Vec<String>,
+    action: &str,
+    abort_signal: AbortSignal,
+) -> Result<Vec<String>> {
+    let total = texts.len();
+    let inputs = texts
+        .iter()
+        .map(|text| create_batch_input(config, None, "summarize", prompt, text))
+        .collect::<Result<Vec<_>>>()?;
+    let (spinner, spinner_rx) = Spinner::create(&format!("{action} 0/{total} chunks"));
+    let outputs = abortable_run_with_spinner_rx(
+        async {
+            Ok(
+                run_batch_with_progress(inputs, DEFAULT_BATCH_CONCURRENCY, |done, total| {
+                    let _ = spinner.set_message(format!("{action} {done}/{total} chunks"));
+                })
+                .await,
+            )
+        },
+        spinner_rx,
+        abort_signal,
+    )
+    .await?;
+    outputs
+        .into_iter()
+        .map(|output| output.map(|v| v.text))
+        .collect()
+}
+
+async fn summarize(
+    config: &GlobalConfig,
+    prompt: &str,
+    text: &str,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let input = create_batch_input(config, None, "summarize", prompt, text)?;
+    let client = input.create_client()?;
+    if input.stream() {
+        call_chat_completions_streaming(&input, client.as_ref(), abort_signal).await?;
+    } else {
+        call_chat_completions(&input, false, client.as_ref(), abort_signal).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_by_budget() {
+        let summaries: Vec<String> = ["a b c", "d e f", "g h i j k l m n"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let groups = group_by_budget(&summaries, 8);
+        assert_eq!(groups, vec!["a b c\n\n---\n\nd e f", "g h i j k l m n"]);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::app::start_directive;
+use crate::config::{GlobalConfig, Input};
+use crate::utils::{
+    abortable_run_with_spinner, detect_language, mask_code_blocks, unmask_code_blocks, AbortSignal,
+};
+
+use anyhow::Result;
+
+/// Translate the input with the `%translate%` role already in use.
+///
+/// Fenced code blocks are swapped for placeholders before sending and restored in the reply,
+/// so they reach the output byte for byte. Input already in `lang` is printed as is.
+pub async fn run(
+    config: &GlobalConfig,
+    lang: &str,
+    mut input: Input,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let text = input.text();
+    let (masked, blocks) = mask_code_blocks(&text);
+    let source = detect_language(&masked);
+    debug!("translate from {source:?} to {lang}");
+    if source.is_some_and(|v| v.eq_ignore_ascii_case(lang.trim())) || masked.trim().is_empty() {
+        println!("{}", text.trim_end());
+        return Ok(());
+    }
+    if blocks.is_empty() {
+        return start_directive(config, input, false, abort_signal).await;
+    }
+
+    input.set_text(masked);
+    let client = input.create_client()?;
+    config.write().before_chat_completion(&input)?;
+    let mut output = abortable_run_with_spinner(
+        client.chat_completions(input.clone()),
+        "Translating",
+        abort_signal,
+    )
+    .await?;
+    output.text = unmask_code_blocks(&output.text, &blocks);
+    config.read().print_markdown(&output.text)?;
+    input.set_text(text);
+    config.write().after_chat_completion(&input, &output, &[])?;
+    config.write().exit_session()?;
+    Ok(())
+}
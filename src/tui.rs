@@ -0,0 +1,678 @@
+use crate::client::{
+    format_footnotes, record_repro, take_streaming_output, ChatCompletionsOutput, MessageRole,
+    SseEvent, SseHandler,
+};
+use crate::config::{Config, GlobalConfig, Input};
+use crate::function::{eval_tool_calls, ToolResult};
+use crate::repl::{
+    follow_up, handle_repl_line, prepare_input, FollowUp, ReplAction, TRUNCATED_NOTICE,
+};
+use crate::utils::{
+    capture_stdout, create_abort_signal, plain_terminal_text, run_output_pipe, AbortSignal,
+};
+
+use anyhow::{bail, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use std::time::{Duration, Instant};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    task::JoinHandle,
+};
+use unicode_width::UnicodeWidthStr;
+
+const SIDEBAR_WIDTH: u16 = 28;
+const HELP: &str =
+    "Enter send · Alt+Enter newline · Tab sessions · PgUp/PgDn scroll · Ctrl+C cancel · Ctrl+D quit";
+/// Dot commands that may open an editor, they run outside the TUI with the real stdout
+const EDITOR_COMMANDS: [&str; 2] = [".edit", ".role"];
+/// Dot commands that may prompt, they leave the alternate screen while they run
+const PROMPTING_COMMANDS: [&str; 10] = [
+    ".model", ".delete", ".session", ".save", ".exit", ".rag", ".rebuild", ".agent", ".listen",
+    ".voice",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Input,
+    Sidebar,
+}
+
+struct Pending {
+    input: Input,
+    rx: UnboundedReceiver<SseEvent>,
+    task: JoinHandle<Result<ChatCompletionsOutput>>,
+    /// Where the text of this request starts in the reply, tool results continue a reply
+    reply_start: usize,
+}
+
+/// A full-screen alternative to the REPL. Lines go through the REPL's handler and replies
+/// through the same follow-ups, the printed output of dot commands is shown in the conversation.
+pub struct Tui {
+    config: GlobalConfig,
+    abort_signal: AbortSignal,
+    /// The system entries are notes: output of commands and reply footers
+    transcript: Vec<(MessageRole, String)>,
+    input: String,
+    scroll_back: u16,
+    focus: Focus,
+    sessions: Vec<String>,
+    sidebar: ListState,
+    pending: Option<Pending>,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost: f64,
+    notice: Option<String>,
+    fullscreen: bool,
+    redraw: bool,
+}
+
+impl Tui {
+    pub fn init(config: &GlobalConfig) -> Result<Self> {
+        let mut tui = Self {
+            config: config.clone(),
+            abort_signal: create_abort_signal(),
+            transcript: vec![],
+            input: String::new(),
+            scroll_back: 0,
+            focus: Focus::Input,
+            sessions: vec![],
+            sidebar: ListState::default(),
+            pending: None,
+            input_tokens: 0,
+            output_tokens: 0,
+            cost: 0.0,
+            notice: None,
+            fullscreen: false,
+            redraw: false,
+        };
+        tui.refresh_sessions();
+        tui.sync_transcript();
+        Ok(tui)
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let mut terminal = ratatui::init();
+        self.fullscreen = true;
+        let ret = self.event_loop(&mut terminal).await;
+        ratatui::restore();
+        self.fullscreen = false;
+        ret?;
+        self.config.write().exit_session()?;
+        Ok(())
+    }
+
+    async fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            if let Err(err) = self.poll_pending().await {
+                self.notice = Some(format!("{err:#}"));
+            }
+            if std::mem::take(&mut self.redraw) {
+                terminal.clear()?;
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if !tokio::task::block_in_place(|| event::poll(Duration::from_millis(50)))? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match self.on_key(key).await {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(err) => self.notice = Some(format!("{err:#}")),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_key(&mut self, key: KeyEvent) -> Result<bool> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') if ctrl => {
+                if self.pending.is_some() {
+                    self.abort_signal.set_ctrlc();
+                } else {
+                    self.input.clear();
+                }
+            }
+            KeyCode::Char('d') if ctrl => {
+                if self.pending.is_none() && self.input.is_empty() {
+                    return Ok(true);
+                }
+            }
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Input => {
+                        self.refresh_sessions();
+                        Focus::Sidebar
+                    }
+                    Focus::Sidebar => Focus::Input,
+                };
+            }
+            KeyCode::PageUp => self.scroll_back = self.scroll_back.saturating_add(10),
+            KeyCode::PageDown => self.scroll_back = self.scroll_back.saturating_sub(10),
+            _ => match self.focus {
+                Focus::Sidebar => self.on_sidebar_key(key)?,
+                Focus::Input => return self.on_input_key(key).await,
+            },
+        }
+        Ok(false)
+    }
+
+    fn on_sidebar_key(&mut self, key: KeyEvent) -> Result<()> {
+        match key.code {
+            KeyCode::Up => self.sidebar.select_previous(),
+            KeyCode::Down => self.sidebar.select_next(),
+            KeyCode::Esc => self.focus = Focus::Input,
+            KeyCode::Enter if self.pending.is_none() => {
+                let Some(name) = self
+                    .sidebar
+                    .selected()
+                    .and_then(|i| self.sessions.get(i))
+                    .cloned()
+                else {
+                    return Ok(());
+                };
+                let config = self.config.clone();
+                self.suspend(|| {
+                    let mut config = config.write();
+                    config.exit_session()?;
+                    config.use_session(Some(&name))
+                })?;
+                self.sync_transcript();
+                self.focus = Focus::Input;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn on_input_key(&mut self, key: KeyEvent) -> Result<bool> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter if key.modifiers.contains(KeyModifiers::ALT) => self.input.push('\n'),
+            KeyCode::Char('j') if ctrl => self.input.push('\n'),
+            KeyCode::Enter if self.pending.is_none() => {
+                let line = std::mem::take(&mut self.input);
+                return self.submit(&line).await;
+            }
+            KeyCode::Char(c) if !ctrl => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Up => self.scroll_back = self.scroll_back.saturating_add(1),
+            KeyCode::Down => self.scroll_back = self.scroll_back.saturating_sub(1),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    async fn submit(&mut self, line: &str) -> Result<bool> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(false);
+        }
+        self.notice = None;
+        self.scroll_back = 0;
+        self.transcript.push((MessageRole::User, line.to_string()));
+        let session = self.session_state();
+        let command = line
+            .starts_with('.')
+            .then(|| line.split_whitespace().next().unwrap_or_default());
+        let config = self.config.clone();
+        self.abort_signal.reset();
+        let abort_signal = self.abort_signal.clone();
+        let run = handle_repl_line(&config, abort_signal.clone(), line);
+        let ret = match command {
+            Some(command) if EDITOR_COMMANDS.contains(&command) => {
+                self.leave_screen();
+                let ret = run.await;
+                self.enter_screen()?;
+                ret
+            }
+            _ => {
+                if command.is_some_and(|v| PROMPTING_COMMANDS.contains(&v)) {
+                    self.leave_screen();
+                }
+                let captured = capture_stdout(run).await;
+                self.enter_screen()?;
+                let (ret, output) = captured?;
+                self.show_output(&output, session);
+                ret
+            }
+        };
+        self.refresh_sessions();
+        match ret? {
+            ReplAction::Exit => return Ok(true),
+            ReplAction::Done => {}
+            ReplAction::Ask(input, with_embeddings) => {
+                let (input, output) = capture_stdout(prepare_input(
+                    &config,
+                    abort_signal,
+                    *input,
+                    with_embeddings,
+                ))
+                .await?;
+                self.show_output(&output, None);
+                if let Some(input) = input? {
+                    self.start(input)?;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Show what a command printed, after the messages of the session if it changed.
+    fn show_output(&mut self, output: &str, session: Option<(String, usize)>) {
+        if session != self.session_state() {
+            self.sync_transcript();
+        }
+        let output = plain_terminal_text(output);
+        if !output.is_empty() {
+            self.transcript.push((MessageRole::System, output));
+        }
+    }
+
+    fn start(&mut self, input: Input) -> Result<()> {
+        self.config.write().before_chat_completion(&input)?;
+        self.abort_signal.reset();
+        if !matches!(self.transcript.last(), Some((MessageRole::Assistant, _))) {
+            self.transcript
+                .push((MessageRole::Assistant, String::new()));
+        }
+        let reply_start = self
+            .transcript
+            .last()
+            .map(|(_, v)| v.len())
+            .unwrap_or_default();
+        let (tx, rx) = unbounded_channel();
+        let abort_signal = self.abort_signal.clone();
+        let task_input = input.clone();
+        let task = tokio::spawn(async move {
+            let client = task_input.create_client()?;
+            let start = Instant::now();
+            let mut handler = SseHandler::new(tx, abort_signal);
+            client
+                .chat_completions_streaming(&task_input, &mut handler)
+                .await?;
+            if handler.abort().aborted() {
+                bail!("Aborted.");
+            }
+            let output = take_streaming_output(&task_input, handler, start);
+            record_repro(&task_input, client.as_ref(), &output);
+            Ok(output)
+        });
+        self.pending = Some(Pending {
+            input,
+            rx,
+            task,
+            reply_start,
+        });
+        Ok(())
+    }
+
+    async fn poll_pending(&mut self) -> Result<()> {
+        let Some(pending) = &mut self.pending else {
+            return Ok(());
+        };
+        while let Ok(event) = pending.rx.try_recv() {
+            if let (SseEvent::Text(text), Some((MessageRole::Assistant, reply))) =
+                (event, self.transcript.last_mut())
+            {
+                reply.push_str(&text);
+            }
+        }
+        if !pending.task.is_finished() {
+            return Ok(());
+        }
+        let Some(Pending {
+            input,
+            task,
+            reply_start,
+            ..
+        }) = self.pending.take()
+        else {
+            return Ok(());
+        };
+        let output = task.await??;
+        self.finish_reply(&input, &output, reply_start)?;
+        let tool_results: Vec<ToolResult> = if output.tool_calls.is_empty() {
+            vec![]
+        } else {
+            let config = self.config.clone();
+            let calls = output.tool_calls.clone();
+            self.suspend(|| eval_tool_calls(&config, calls))?
+        };
+        self.config
+            .write()
+            .after_chat_completion(&input, &output, &tool_results)?;
+        match follow_up(&self.config, &input, &output, tool_results) {
+            FollowUp::Send(input) => return self.start(*input),
+            FollowUp::Truncated => self
+                .transcript
+                .push((MessageRole::System, TRUNCATED_NOTICE.to_string())),
+            FollowUp::Done => {}
+        }
+        Config::maybe_autoname_session(self.config.clone());
+        Config::maybe_compress_session(self.config.clone());
+        Ok(())
+    }
+
+    /// Pipe the reply through `output_pipe`, add its footnotes and footer, and count the usage.
+    fn finish_reply(
+        &mut self,
+        input: &Input,
+        output: &ChatCompletionsOutput,
+        reply_start: usize,
+    ) -> Result<()> {
+        self.input_tokens += output.input_tokens.unwrap_or_default();
+        self.output_tokens += output.output_tokens.unwrap_or_default();
+        self.cost += output.cost.unwrap_or_default();
+        let (output_pipe, footer) = {
+            let config = self.config.read();
+            (
+                config.output_pipe.clone(),
+                config.footer_text(input, output),
+            )
+        };
+        if let Some((MessageRole::Assistant, reply)) = self.transcript.last_mut() {
+            if let (Some(command), false) = (&output_pipe, output.text.is_empty()) {
+                reply.truncate(reply_start);
+                reply.push_str(run_output_pipe(command, &output.text)?.trim_end());
+            }
+            if !output.text.is_empty() && !output.citations.is_empty() {
+                reply.push_str(&format_footnotes(&output.citations));
+            }
+        }
+        if let Some(footer) = footer {
+            self.transcript.push((MessageRole::System, footer));
+        }
+        Ok(())
+    }
+
+    /// The current session and its number of messages.
+    fn session_state(&self) -> Option<(String, usize)> {
+        let config = self.config.read();
+        let session = config.session.as_ref()?;
+        Some((session.name().to_string(), session.messages().len()))
+    }
+
+    /// Leave the alternate screen while `f` runs, since it may print or prompt.
+    fn suspend<T>(&mut self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.leave_screen();
+        let ret = f();
+        self.enter_screen()?;
+        ret
+    }
+
+    fn leave_screen(&self) {
+        if self.fullscreen {
+            ratatui::restore();
+        }
+    }
+
+    /// Back to the alternate screen in raw mode, a prompt may have left raw mode too.
+    fn enter_screen(&mut self) -> Result<()> {
+        if self.fullscreen {
+            crossterm::terminal::enable_raw_mode()?;
+            crossterm::execute!(std::io::stdout(), crossterm::terminal::EnterAlternateScreen)?;
+            self.redraw = true;
+        }
+        Ok(())
+    }
+
+    fn refresh_sessions(&mut self) {
+        let config = self.config.read();
+        self.sessions = config.list_sessions();
+        let current = config.session.as_ref().map(|v| v.name().to_string());
+        let selected = current.and_then(|name| self.sessions.iter().position(|v| *v == name));
+        self.sidebar
+            .select(selected.or(if self.sessions.is_empty() {
+                None
+            } else {
+                Some(0)
+            }));
+    }
+
+    /// Show the messages of the current session, if any.
+    fn sync_transcript(&mut self) {
+        if let Some(session) = &self.config.read().session {
+            self.transcript = session
+                .messages()
+                .iter()
+                .filter(|v| !matches!(v.role, MessageRole::System | MessageRole::Tool))
+                .map(|v| (v.role, v.content.to_text()))
+                .collect();
+            self.scroll_back = 0;
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [sidebar, main] =
+            Layout::horizontal([Constraint::Length(SIDEBAR_WIDTH), Constraint::Min(20)])
+                .areas(body);
+        let input_height = (self.input.split('\n').count() as u16 + 2).min(8);
+        let [conversation, input] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(input_height)]).areas(main);
+
+        self.draw_sidebar(frame, sidebar);
+        self.draw_conversation(frame, conversation);
+        self.draw_input(frame, input);
+        self.draw_status(frame, status);
+    }
+
+    fn draw_sidebar(&mut self, frame: &mut Frame, area: Rect) {
+        let current = self
+            .config
+            .read()
+            .session
+            .as_ref()
+            .map(|v| v.name().to_string());
+        let items: Vec<ListItem> = self
+            .sessions
+            .iter()
+            .map(|name| {
+                let marker = if Some(name) == current.as_ref() {
+                    "* "
+                } else {
+                    "  "
+                };
+                ListItem::new(format!("{marker}{name}"))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(focus_block(" Sessions ", self.focus == Focus::Sidebar))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.sidebar);
+    }
+
+    fn draw_conversation(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = vec![];
+        for (role, text) in &self.transcript {
+            if *role == MessageRole::System {
+                let style = Style::default().add_modifier(Modifier::DIM);
+                lines.extend(text.lines().map(|v| Line::styled(v.to_string(), style)));
+                lines.push(Line::raw(""));
+                continue;
+            }
+            let (label, color) = match role {
+                MessageRole::User => ("You", Color::Cyan),
+                _ => ("Assistant", Color::Green),
+            };
+            lines.push(Line::from(Span::styled(
+                label,
+                Style::default().fg(color).add_modifier(Modifier::BOLD),
+            )));
+            lines.extend(text.lines().map(|v| Line::raw(v.to_string())));
+            lines.push(Line::raw(""));
+        }
+        let inner_width = area.width.saturating_sub(2).max(1) as usize;
+        let height = area.height.saturating_sub(2);
+        let total: u16 = lines
+            .iter()
+            .map(|v| v.width().div_ceil(inner_width).max(1) as u16)
+            .sum();
+        let top = total.saturating_sub(height.saturating_add(self.scroll_back));
+        let paragraph = Paragraph::new(Text::from(lines))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Conversation "),
+            )
+            .wrap(Wrap { trim: false })
+            .scroll((top, 0));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_input(&self, frame: &mut Frame, area: Rect) {
+        let title = if self.pending.is_some() {
+            " Message (generating…) "
+        } else {
+            " Message "
+        };
+        let paragraph = Paragraph::new(self.input.as_str())
+            .block(focus_block(title, self.focus == Focus::Input));
+        frame.render_widget(paragraph, area);
+        if self.focus == Focus::Input {
+            let last_line = self.input.rsplit('\n').next().unwrap_or_default();
+            let row = self.input.split('\n').count() as u16 - 1;
+            frame.set_cursor_position(Position::new(
+                area.x + 1 + (last_line.width() as u16).min(area.width.saturating_sub(3)),
+                area.y + 1 + row.min(area.height.saturating_sub(3)),
+            ));
+        }
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let config = self.config.read();
+        let mut parts = vec![config.current_model().id()];
+        if let Some(session) = &config.session {
+            let (tokens, percent) = session.quick_tokens_usage();
+            parts.push(format!("ctx {tokens} ({percent}%)"));
+        }
+        parts.push(format!(
+            "tokens {}/{}",
+            self.input_tokens, self.output_tokens
+        ));
+        if self.cost > 0.0 {
+            parts.push(format!("${:.4}", self.cost));
+        }
+        let right = match &self.notice {
+            Some(notice) => Span::styled(notice.clone(), Style::default().fg(Color::Red)),
+            None => Span::styled(HELP, Style::default().add_modifier(Modifier::DIM)),
+        };
+        let line = Line::from(vec![
+            Span::styled(
+                format!(" {} ", parts.join(" │ ")),
+                Style::default().add_modifier(Modifier::REVERSED),
+            ),
+            Span::raw(" "),
+            right,
+        ]);
+        frame.render_widget(Paragraph::new(line), area);
+    }
+}
+
+fn focus_block(title: &str, focused: bool) -> Block<'_> {
+    let block = Block::default().borders(Borders::ALL).title(title);
+    if focused {
+        block.border_style(Style::default().fg(Color::Yellow))
+    } else {
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorkingMode;
+
+    use parking_lot::RwLock;
+    use ratatui::{backend::TestBackend, Terminal};
+    use std::sync::Arc;
+
+    fn test_config() -> GlobalConfig {
+        Config::use_config_dir(crate::tests::test_config_dir()).unwrap();
+        Arc::new(RwLock::new(
+            Config::init_with(WorkingMode::Repl, false).unwrap(),
+        ))
+    }
+
+    async fn wait_reply(tui: &mut Tui) {
+        while tui.pending.is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tui.poll_pending().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_submit_message() {
+        let config = test_config();
+        config.write().footer = true;
+        config.write().output_pipe = Some("tr a-z A-Z".into());
+        let mut tui = Tui::init(&config).unwrap();
+        assert!(!tui.submit("hello").await.unwrap());
+        wait_reply(&mut tui).await;
+        assert_eq!(tui.transcript[0], (MessageRole::User, "hello".into()));
+        assert_eq!(
+            tui.transcript[1],
+            (MessageRole::Assistant, "YOU SAID: HELLO".into())
+        );
+        assert_eq!(tui.transcript[2].0, MessageRole::System);
+        assert!(tui.transcript[2].1.contains("gpt-4o-mini"));
+    }
+
+    #[tokio::test]
+    async fn test_submit_command() {
+        let config = test_config();
+        let mut tui = Tui::init(&config).unwrap();
+        assert!(!tui.submit(".set temperature 0.5").await.unwrap());
+        assert_eq!(config.read().temperature, Some(0.5));
+        assert!(tui.pending.is_none());
+        assert!(tui.submit(".continue").await.is_err());
+        assert!(tui.submit(".exit").await.unwrap());
+    }
+
+    #[test]
+    fn test_draw() {
+        let config = test_config();
+        let mut tui = Tui::init(&config).unwrap();
+        tui.transcript = vec![
+            (MessageRole::User, "hello".into()),
+            (MessageRole::Assistant, "Hi there".into()),
+            (MessageRole::System, "note".into()),
+        ];
+        tui.input_tokens = 12;
+        tui.output_tokens = 3;
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| tui.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|v| v.symbol())
+            .collect();
+        for text in [
+            "Sessions",
+            "You",
+            "Hi there",
+            "note",
+            "gpt-4o-mini",
+            "tokens 12/3",
+        ] {
+            assert!(screen.contains(text), "missing {text}");
+        }
+    }
+}
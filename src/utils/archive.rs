@@ -0,0 +1,173 @@
+use super::is_valid_extension;
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::{fs::File, io::Read, path::Path};
+
+/// Larger entries are listed but not extracted
+const ARCHIVE_MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// Entries are no longer extracted once this much has been
+const ARCHIVE_MAX_TOTAL_SIZE: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn of(path: &str) -> Option<Self> {
+        let path = path.to_lowercase();
+        if path.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if path.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn is_archive(path: &str) -> bool {
+    ArchiveKind::of(path).is_some()
+}
+
+/// The text files of a zip or tar archive as `<archive>/<entry>`, after a listing of all its entries.
+///
+/// With `suffixes`, only the entries of these extensions are extracted, like `dir/**/*.{rs,md}`.
+/// The sizes in the headers aren't trusted, reads stop at the limits.
+pub fn extract_archive(path: &str, suffixes: &[String]) -> Result<Vec<(String, String)>> {
+    let kind = ArchiveKind::of(path).with_context(|| format!("Unsupported archive '{path}'"))?;
+    let file = File::open(path)?;
+    let mut listing = vec![];
+    let mut files = vec![];
+    let mut total_size = 0;
+    let mut visit = |name: String, size: u64, reader: &mut dyn Read| -> Result<()> {
+        let status = if !is_valid_extension(Some(suffixes), Path::new(&name)) {
+            ""
+        } else if size > ARCHIVE_MAX_FILE_SIZE {
+            " (too large)"
+        } else if total_size + size > ARCHIVE_MAX_TOTAL_SIZE {
+            " (skipped, the archive is too large)"
+        } else {
+            let mut data = vec![];
+            reader
+                .take(ARCHIVE_MAX_FILE_SIZE + 1)
+                .read_to_end(&mut data)?;
+            total_size += data.len() as u64;
+            if data.len() as u64 > ARCHIVE_MAX_FILE_SIZE {
+                " (too large)"
+            } else {
+                match String::from_utf8(data) {
+                    Ok(contents) => {
+                        files.push((format!("{path}/{name}"), contents));
+                        " (extracted)"
+                    }
+                    Err(_) => " (binary)",
+                }
+            }
+        };
+        listing.push(format!("{name}  {size} bytes{status}"));
+        Ok(())
+    };
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(file).context("Invalid zip archive")?;
+            for i in 0..archive.len() {
+                let mut entry = archive.by_index(i)?;
+                if entry.is_file() {
+                    let (name, size) = (entry.name().to_string(), entry.size());
+                    visit(name, size, &mut entry)?;
+                }
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let reader: Box<dyn Read> = match kind {
+                ArchiveKind::TarGz => Box::new(GzDecoder::new(file)),
+                _ => Box::new(file),
+            };
+            let mut archive = tar::Archive::new(reader);
+            for entry in archive.entries().context("Invalid tar archive")? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let name = entry.path()?.display().to_string();
+                    let size = entry.size();
+                    visit(name, size, &mut entry)?;
+                }
+            }
+        }
+    }
+    let mut output = vec![(path.to_string(), listing.join("\n"))];
+    output.extend(files);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_archive() {
+        let path = std::env::temp_dir().join(format!("aichat-archive-{}.tar", std::process::id()));
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for (name, data) in [
+            ("src/main.rs", &b"fn main() {}"[..]),
+            ("logo.png", &[0xff, 0xfe]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+        let path = path.display().to_string();
+        let output = extract_archive(&path, &[]).unwrap();
+        assert_eq!(
+            output[0].1,
+            "src/main.rs  12 bytes (extracted)\nlogo.png  2 bytes (binary)"
+        );
+        assert_eq!(
+            output[1],
+            (format!("{path}/src/main.rs"), "fn main() {}".into())
+        );
+        let output = extract_archive(&path, &["md".into()]).unwrap();
+        assert_eq!(output.len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_archive_limits() {
+        let path = crate::utils::temp_file("-archive-", ".tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        let data = vec![b'a'; ARCHIVE_MAX_FILE_SIZE as usize];
+        for i in 0..11 {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("{i}.txt"), &data[..])
+                .unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_size(ARCHIVE_MAX_FILE_SIZE + 1);
+        header.set_cksum();
+        let large = vec![b'a'; ARCHIVE_MAX_FILE_SIZE as usize + 1];
+        builder
+            .append_data(&mut header, "large.txt", &large[..])
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+        let path = path.display().to_string();
+        let output = extract_archive(&path, &["txt".into()]).unwrap();
+        let listing: Vec<&str> = output[0].1.lines().collect();
+        assert!(listing[9].ends_with("(extracted)"));
+        assert!(listing[10].ends_with("(skipped, the archive is too large)"));
+        assert!(listing[11].ends_with("(too large)"));
+        assert_eq!(output.len(), 11);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
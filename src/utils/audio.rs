@@ -0,0 +1,126 @@
+use super::{dimmed_text, run_command_with_output};
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{path::Path, process::Stdio};
+use tokio::process::{Child, Command};
+
+pub const DEFAULT_LISTEN_COMMAND: &str = "sox -q -d -c 1 -r 16000 $1 silence 1 0.1 1% 1 2.0 1%";
+pub const DEFAULT_MIC_COMMAND: &str = "sox -q -d -t raw -r $1 -e signed -b 16 -c 1 -";
+pub const DEFAULT_PLAYER_COMMAND: &str = "sox -q -t raw -r $1 -e signed -b 16 -c 1 - -d";
+
+/// Record from the default microphone into `path` until the recorder stops on silence or Ctrl+C is pressed.
+pub async fn record_audio(listen_command: &str, path: &Path) -> Result<()> {
+    let (cmd, args, cmd_eval) = expand_command(listen_command, &path.display().to_string())?;
+    let mut child = Command::new(&cmd)
+        .args(&args)
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| {
+            format!("Unable to run `{cmd_eval}`, Perhaps '{cmd}' is not installed?")
+        })?;
+    println!(
+        "{}",
+        dimmed_text("Listening... (stops on silence, Ctrl+C to finish)")
+    );
+    let status = tokio::select! {
+        status = child.wait() => status?,
+        _ = tokio::signal::ctrl_c() => {
+            // The recorder also gets SIGINT, let it finalize the file.
+            child.wait().await?
+        }
+    };
+    if !path.exists() {
+        bail!("The command `{cmd_eval}` recorded nothing (exit status: {status})");
+    }
+    Ok(())
+}
+
+/// Transcribe the audio file with `stt_command`, which prints the transcript.
+pub fn transcribe_audio(stt_command: &str, path: &Path) -> Result<String> {
+    let (cmd, args, cmd_eval) = expand_command(stt_command, &path.display().to_string())?;
+    let (success, stdout, stderr) =
+        run_command_with_output(&cmd, &args, None).with_context(|| {
+            format!("Unable to run `{cmd_eval}`, Perhaps '{cmd}' is not installed?")
+        })?;
+    if !success {
+        let err = if !stderr.is_empty() {
+            stderr
+        } else {
+            format!("The command `{cmd_eval}` exited with non-zero.")
+        };
+        bail!("{err}")
+    }
+    Ok(stdout.trim().to_string())
+}
+
+/// Spawn a command streaming raw PCM16 mono audio, from its stdout when capturing or into its stdin.
+pub fn spawn_audio_stream(command: &str, sample_rate: u32, capture: bool) -> Result<Child> {
+    let (cmd, args, cmd_eval) = expand_command(command, &sample_rate.to_string())?;
+    let (stdin, stdout) = if capture {
+        (Stdio::null(), Stdio::piped())
+    } else {
+        (Stdio::piped(), Stdio::null())
+    };
+    Command::new(&cmd)
+        .args(&args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Unable to run `{cmd_eval}`, Perhaps '{cmd}' is not installed?"))
+}
+
+/// Split the command and replace `$1` with the argument.
+fn expand_command(command: &str, arg: &str) -> Result<(String, Vec<String>, String)> {
+    let mut cmd_args = shell_words::split(command)
+        .with_context(|| anyhow!("Invalid command `{command}`"))?
+        .into_iter()
+        .map(|v| v.replace("$1", arg))
+        .collect::<Vec<_>>();
+    if cmd_args.is_empty() {
+        bail!("Empty command");
+    }
+    let cmd_eval = shell_words::join(&cmd_args);
+    let cmd = cmd_args.remove(0);
+    Ok((cmd, cmd_args, cmd_eval))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_file;
+
+    #[test]
+    fn test_expand_command() {
+        let (cmd, args, cmd_eval) = expand_command(DEFAULT_MIC_COMMAND, "24000").unwrap();
+        assert_eq!(cmd, "sox");
+        assert_eq!(args[5], "24000");
+        assert_eq!(cmd_eval, "sox -q -d -t raw -r 24000 -e signed -b 16 -c 1 -");
+        let (_, _, cmd_eval) = expand_command("whisper '$1'", "/tmp/a b.wav").unwrap();
+        assert_eq!(cmd_eval, "whisper '/tmp/a b.wav'");
+        assert!(expand_command("", "x").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_record_and_transcribe() {
+        let path = temp_file("-listen-", ".wav");
+        // A recorder that writes the file and a transcriber that prints it
+        record_audio(r#"sh -c 'echo "  what time is it  " > "$0"' $1"#, &path)
+            .await
+            .unwrap();
+        assert_eq!(
+            transcribe_audio("cat $1", &path).unwrap(),
+            "what time is it"
+        );
+        let err = transcribe_audio("sh -c 'echo no model >&2; exit 1'", &path).unwrap_err();
+        assert_eq!(err.to_string().trim(), "no model");
+        std::fs::remove_file(&path).unwrap();
+
+        let err = record_audio("true", &path).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("The command `true` recorded nothing"));
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use std::{future::Future, io::Write};
+
+/// fd 1 is shared by every thread, overlapping captures would restore it out of order
+#[cfg(unix)]
+static CAPTURE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Run `f` with the stdout of the process going to a temp file, returns what was printed.
+///
+/// Captures run one at a time, a capture started meanwhile waits for this one to finish.
+#[cfg(unix)]
+pub async fn capture_stdout<F: Future>(f: F) -> Result<(F::Output, String)> {
+    use super::temp_file;
+    use anyhow::bail;
+    use std::{fs, os::fd::AsRawFd};
+
+    let _guard = CAPTURE_LOCK.lock().await;
+    std::io::stdout().flush()?;
+    let path = temp_file("-stdout-", ".txt");
+    let file = fs::File::create(&path)?;
+    let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if saved < 0 {
+        let _ = fs::remove_file(&path);
+        bail!("Failed to capture stdout");
+    }
+    if unsafe { libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        unsafe { libc::close(saved) };
+        let _ = fs::remove_file(&path);
+        bail!("Failed to capture stdout");
+    }
+    let ret = f.await;
+    let _ = std::io::stdout().flush();
+    unsafe {
+        libc::dup2(saved, libc::STDOUT_FILENO);
+        libc::close(saved);
+    }
+    let output = fs::read(&path).unwrap_or_default();
+    let _ = fs::remove_file(&path);
+    Ok((ret, String::from_utf8_lossy(&output).to_string()))
+}
+
+/// Without file descriptors to swap, the output goes to the terminal as usual.
+#[cfg(not(unix))]
+pub async fn capture_stdout<F: Future>(f: F) -> Result<(F::Output, String)> {
+    let ret = f.await;
+    std::io::stdout().flush()?;
+    Ok((ret, String::new()))
+}
+
+/// Turn captured terminal output into plain text. Escape sequences are dropped, and a carriage
+/// return or a move to a column overwrites the line, as the spinner does.
+pub fn plain_terminal_text(text: &str) -> String {
+    let mut lines = vec![];
+    let mut line: Vec<char> = vec![];
+    let mut col = 0;
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    let mut params = String::new();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            match c {
+                                'G' => col = params.parse::<usize>().unwrap_or(1).saturating_sub(1),
+                                'J' | 'K' => line.truncate(col),
+                                _ => {}
+                            }
+                            break;
+                        }
+                        params.push(c);
+                    }
+                }
+                Some(']') => {
+                    let mut prev = ' ';
+                    for c in chars.by_ref() {
+                        if c == '\x07' || (prev == '\x1b' && c == '\\') {
+                            break;
+                        }
+                        prev = c;
+                    }
+                }
+                _ => {}
+            },
+            '\r' => col = 0,
+            '\n' => {
+                lines.push(line.drain(..).collect::<String>());
+                col = 0;
+            }
+            _ => {
+                if col > line.len() {
+                    line.resize(col, ' ');
+                }
+                if col < line.len() {
+                    line[col] = ch;
+                } else {
+                    line.push(ch);
+                }
+                col += 1;
+            }
+        }
+    }
+    lines.push(line.into_iter().collect());
+    lines.join("\n").trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_terminal_text() {
+        assert_eq!(
+            plain_terminal_text("\x1b[1G⠋ Loading\x1b[1G⠙ Loading.\x1b[1G\x1b[J\x1b[?25h✓ Done\n"),
+            "✓ Done"
+        );
+        assert_eq!(
+            plain_terminal_text("\x1b[2mdimmed\x1b[0m\r\nprogress 10%\rprogress 100%\n\n"),
+            "dimmed\nprogress 100%"
+        );
+        assert_eq!(
+            plain_terminal_text("\x1b]8;;https://example.com\x07link\x1b]8;;\x1b\\ text"),
+            "link text"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_capture_stdout() {
+        // The test harness captures `println!` by itself, write to the fd instead
+        let (ret, output) = capture_stdout(async {
+            std::io::stdout().write_all(b"captured\n").unwrap();
+            42
+        })
+        .await
+        .unwrap();
+        assert_eq!(ret, 42);
+        // Other tests may print meanwhile
+        assert!(output.contains("captured\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_capture_stdout_overlapping() {
+        // The first capture finishes while the second one is still printing
+        let capture = |text: &'static str, yields: usize| {
+            capture_stdout(async move {
+                std::io::stdout().write_all(text.as_bytes()).unwrap();
+                for _ in 0..yields {
+                    tokio::task::yield_now().await;
+                }
+                std::io::stdout().write_all(text.as_bytes()).unwrap();
+            })
+        };
+        let (first, second) = tokio::join!(capture("first\n", 1), capture("second\n", 3));
+        let (first, second) = (first.unwrap().1, second.unwrap().1);
+        assert!(first.contains("first\nfirst\n") && !first.contains("second"));
+        assert!(second.contains("second\nsecond\n") && !second.contains("first"));
+    }
+}
@@ -18,7 +18,53 @@ pub fn set_text(text: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The image in the clipboard as PNG
+#[cfg(not(any(target_os = "android", target_os = "emscripten")))]
+pub fn get_image_png() -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context;
+
+    let mut clipboard = CLIPBOARD.lock().unwrap();
+    let Some(clipboard) = clipboard.as_mut() else {
+        return Err(anyhow::anyhow!("No clipboard available").context("Failed to paste"));
+    };
+    let data = clipboard.get_image().context("No image in the clipboard")?;
+    encode_png(data.width, data.height, data.bytes.into_owned())
+}
+
+/// Encode RGBA pixels as PNG
+#[cfg(not(any(target_os = "android", target_os = "emscripten")))]
+fn encode_png(width: usize, height: usize, bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context;
+
+    let image = image::RgbaImage::from_raw(width as u32, height as u32, bytes)
+        .context("Invalid image in the clipboard")?;
+    let mut output = std::io::Cursor::new(vec![]);
+    image.write_to(&mut output, image::ImageFormat::Png)?;
+    Ok(output.into_inner())
+}
+
 #[cfg(any(target_os = "android", target_os = "emscripten"))]
 pub fn set_text(_text: &str) -> anyhow::Result<()> {
     Err(anyhow::anyhow!("No clipboard available").context("Failed to copy"))
 }
+
+#[cfg(any(target_os = "android", target_os = "emscripten"))]
+pub fn get_image_png() -> anyhow::Result<Vec<u8>> {
+    Err(anyhow::anyhow!("No clipboard available").context("Failed to paste"))
+}
+
+#[cfg(test)]
+#[cfg(not(any(target_os = "android", target_os = "emscripten")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_png() {
+        let pixels = vec![255, 0, 0, 255, 0, 0, 255, 128];
+        let output = encode_png(2, 1, pixels.clone()).unwrap();
+        let image = image::load_from_memory_with_format(&output, image::ImageFormat::Png).unwrap();
+        assert_eq!(image.into_rgba8().into_raw(), pixels);
+        let err = encode_png(2, 2, pixels).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid image in the clipboard");
+    }
+}
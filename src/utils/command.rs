@@ -7,7 +7,7 @@ use std::{
     fs::OpenOptions,
     io::{self, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -162,6 +162,68 @@ pub fn edit_file(editor: &str, path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Pipe `text` through the pager, `less` gets `-R` so that ANSI colors pass through.
+pub fn run_pager(pager: &str, text: &str) -> Result<()> {
+    let (program, args) = pager_command(pager)?;
+    let mut child = Command::new(&program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run pager '{pager}'"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may quit before reading everything
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Send `text` to the stdin of `command` and return what it prints.
+pub fn run_output_pipe(command: &str, text: &str) -> Result<String> {
+    let args = shell_words::split(command).with_context(|| format!("Invalid pipe '{command}'"))?;
+    let Some((program, args)) = args.split_first() else {
+        bail!("Empty pipe");
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run pipe '{command}'"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Write from another thread, the command may fill its stdout before reading everything
+        let text = text.to_string();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(text.as_bytes());
+        });
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("The pipe '{command}' exited with non-zero");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn pager_command(pager: &str) -> Result<(String, Vec<String>)> {
+    let mut args = shell_words::split(pager).with_context(|| format!("Invalid pager '{pager}'"))?;
+    if args.is_empty() {
+        bail!("Empty pager");
+    }
+    let program = args.remove(0);
+    let is_less = Path::new(&program)
+        .file_stem()
+        .map(|v| v == "less")
+        .unwrap_or_default();
+    if is_less
+        && !args
+            .iter()
+            .any(|v| v.starts_with('-') && !v.starts_with("--") && v.contains(['R', 'r']))
+    {
+        args.push("-R".into());
+    }
+    Ok((program, args))
+}
+
 pub fn append_to_shell_history(shell: &str, command: &str, exit_code: i32) -> io::Result<()> {
     if let Some(history_file) = get_history_file(shell) {
         let command = command.replace('\n', " ");
@@ -223,3 +285,58 @@ fn get_history_file(shell: &str) -> Option<PathBuf> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_command() {
+        let command = |v: &str| pager_command(v).unwrap();
+        assert_eq!(command("less"), ("less".into(), vec!["-R".into()]));
+        assert_eq!(
+            command("/usr/bin/less -S"),
+            ("/usr/bin/less".into(), vec!["-S".into(), "-R".into()])
+        );
+        // Raw control chars are already on
+        assert_eq!(command("less -FRX"), ("less".into(), vec!["-FRX".into()]));
+        assert_eq!(command("less -r"), ("less".into(), vec!["-r".into()]));
+        assert_eq!(
+            command("less --quit-if-one-screen"),
+            (
+                "less".into(),
+                vec!["--quit-if-one-screen".into(), "-R".into()]
+            )
+        );
+        assert_eq!(command("more"), ("more".into(), vec![]));
+        assert!(pager_command("").is_err());
+        assert!(pager_command("less 'unclosed").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_output_pipe() {
+        assert_eq!(run_output_pipe("tr a-z A-Z", "hello\n").unwrap(), "HELLO\n");
+        // More than a pipe buffer each way must not deadlock
+        let text = "line\n".repeat(100_000);
+        assert_eq!(run_output_pipe("cat", &text).unwrap(), text);
+        assert_eq!(
+            run_output_pipe("false", "hello").unwrap_err().to_string(),
+            "The pipe 'false' exited with non-zero"
+        );
+        assert!(run_output_pipe("", "hello").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_pager() {
+        let path = temp_file("-pager-", ".txt");
+        let pager = format!("sh -c 'cat > \"$0\"' '{}'", path.display());
+        run_pager(&pager, "\x1b[1mbold\x1b[0m\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "\x1b[1mbold\x1b[0m\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
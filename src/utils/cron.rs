@@ -0,0 +1,111 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+
+/// A five-field cron expression: minute, hour, day of month, month and day of week.
+///
+/// Fields accept `*`, numbers, ranges (`1-5`), steps (`*/15`, `0-30/10`) and lists of them.
+/// Day of week is 0-7 with both 0 and 7 for Sunday.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            v => v,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            bail!("Invalid cron expression '{expr}', expect 5 fields");
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    pub fn matches<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        // Like cron, a restricted day of month and day of week match either
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.months[time.month() as usize]
+            && day_matches
+    }
+}
+
+fn parse_field(field: &str, min: usize, max: usize) -> Result<Vec<bool>> {
+    let err = || anyhow!("Invalid cron field '{field}'");
+    let mut values = vec![false; max + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().map_err(|_| err())?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse().map_err(|_| err())?,
+                    end.parse().map_err(|_| err())?,
+                ),
+                None => {
+                    let value = range.parse().map_err(|_| err())?;
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(err());
+        }
+        for value in (start..=end).step_by(step) {
+            values[value] = true;
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_cron() {
+        // 2024-01-01 is a Monday
+        let time = Utc.with_ymd_and_hms(2024, 1, 1, 8, 30, 0).unwrap();
+        assert!(Cron::parse("30 8 * * *").unwrap().matches(&time));
+        assert!(Cron::parse("*/15 8-9 * * 1-5").unwrap().matches(&time));
+        assert!(!Cron::parse("0 8 * * *").unwrap().matches(&time));
+        assert!(!Cron::parse("30 8 * * 0,6").unwrap().matches(&time));
+        assert!(Cron::parse("30 8 15 * 1").unwrap().matches(&time));
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("61 * * * *").is_err());
+    }
+}
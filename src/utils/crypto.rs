@@ -1,4 +1,8 @@
-use base64::{engine::general_purpose::STANDARD, Engine};
+use anyhow::{anyhow, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
 use hmac::{Hmac, Mac};
 use sha2::{Digest, Sha256};
 
@@ -33,3 +37,104 @@ pub fn base64_encode<T: AsRef<[u8]>>(input: T) -> String {
 pub fn base64_decode<T: AsRef<[u8]>>(input: T) -> Result<Vec<u8>, base64::DecodeError> {
     STANDARD.decode(input)
 }
+
+pub fn base64url_encode<T: AsRef<[u8]>>(input: T) -> String {
+    URL_SAFE_NO_PAD.encode(input)
+}
+
+/// Sign `msg` with RS256 using a PEM-encoded PKCS#8 private key.
+pub fn rsa_sha256_sign(private_key_pem: &str, msg: &str) -> Result<Vec<u8>> {
+    let der: String = private_key_pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = base64_decode(der.trim()).map_err(|_| anyhow!("Invalid private key"))?;
+    let key_pair = ring::signature::RsaKeyPair::from_pkcs8(&der)
+        .map_err(|err| anyhow!("Invalid private key, {err}"))?;
+    let mut signature = vec![0; key_pair.public().modulus_len()];
+    key_pair
+        .sign(
+            &ring::signature::RSA_PKCS1_SHA256,
+            &ring::rand::SystemRandom::new(),
+            msg.as_bytes(),
+            &mut signature,
+        )
+        .map_err(|_| anyhow!("Failed to sign message"))?;
+    Ok(signature)
+}
+
+pub fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut bytes)
+        .map_err(|_| anyhow!("Failed to generate random bytes"))?;
+    Ok(bytes)
+}
+
+/// Encrypt with ChaCha20-Poly1305, the random nonce is prepended to the output.
+pub fn encrypt_data(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let key = aead_key(key)?;
+    let nonce = random_bytes(ring::aead::NONCE_LEN)?;
+    let mut output = data.to_vec();
+    key.seal_in_place_append_tag(
+        ring::aead::Nonce::try_assume_unique_for_key(&nonce)
+            .map_err(|_| anyhow!("Invalid nonce"))?,
+        ring::aead::Aad::empty(),
+        &mut output,
+    )
+    .map_err(|_| anyhow!("Failed to encrypt data"))?;
+    Ok([nonce, output].concat())
+}
+
+pub fn decrypt_data(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let key = aead_key(key)?;
+    if data.len() < ring::aead::NONCE_LEN {
+        return Err(anyhow!("Invalid encrypted data"));
+    }
+    let (nonce, data) = data.split_at(ring::aead::NONCE_LEN);
+    let mut output = data.to_vec();
+    let plain = key
+        .open_in_place(
+            ring::aead::Nonce::try_assume_unique_for_key(nonce)
+                .map_err(|_| anyhow!("Invalid nonce"))?,
+            ring::aead::Aad::empty(),
+            &mut output,
+        )
+        .map_err(|_| anyhow!("Failed to decrypt data"))?;
+    Ok(plain.to_vec())
+}
+
+fn aead_key(key: &[u8]) -> Result<ring::aead::LessSafeKey> {
+    let key = ring::aead::UnboundKey::new(&ring::aead::CHACHA20_POLY1305, key)
+        .map_err(|_| anyhow!("Invalid encryption key"))?;
+    Ok(ring::aead::LessSafeKey::new(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_data() {
+        let key = random_bytes(32).unwrap();
+        let data = b"{\"access_token\":\"abc\"}";
+        let encrypted = encrypt_data(&key, data).unwrap();
+        assert_ne!(&encrypted[ring::aead::NONCE_LEN..], data);
+        assert_eq!(decrypt_data(&key, &encrypted).unwrap(), data);
+        // A fresh nonce every time
+        assert_ne!(encrypt_data(&key, data).unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_decrypt_data_rejects_bad_input() {
+        let key = random_bytes(32).unwrap();
+        let encrypted = encrypt_data(&key, b"secret").unwrap();
+        let other_key = random_bytes(32).unwrap();
+        assert!(decrypt_data(&other_key, &encrypted).is_err());
+        let mut tampered = encrypted.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(decrypt_data(&key, &tampered).is_err());
+        assert!(decrypt_data(&key, &encrypted[..4]).is_err());
+        assert!(encrypt_data(&key[..16], b"secret").is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use super::{color_text, dimmed_text, NO_COLOR};
+
+use nu_ansi_term::Color;
+
+/// Beyond this many LCS cells, the differing middle is shown as a whole replacement
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Diff two texts word by word, whitespace runs count as words.
+pub fn diff_words<'a>(old: &'a str, new: &'a str) -> Vec<DiffOp<'a>> {
+    diff_tokens(tokenize(old), tokenize(new))
+}
+
+/// Diff two texts line by line, each line keeps its newline.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffOp<'a>> {
+    diff_tokens(
+        old.split_inclusive('\n').collect(),
+        new.split_inclusive('\n').collect(),
+    )
+}
+
+fn diff_tokens<'a>(old: Vec<&'a str>, new: Vec<&'a str>) -> Vec<DiffOp<'a>> {
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut ops: Vec<DiffOp> = old[..prefix].iter().map(|v| DiffOp::Equal(v)).collect();
+    if old_mid.len() * new_mid.len() > MAX_DIFF_CELLS {
+        ops.extend(old_mid.iter().map(|v| DiffOp::Delete(v)));
+        ops.extend(new_mid.iter().map(|v| DiffOp::Insert(v)));
+    } else {
+        ops.extend(lcs_diff(old_mid, new_mid));
+    }
+    ops.extend(old[old.len() - suffix..].iter().map(|v| DiffOp::Equal(v)));
+    ops
+}
+
+/// Render the word diff, deletions in red and insertions in green (or `[-...-]`/`{+...+}` without colors).
+pub fn render_word_diff(old: &str, new: &str) -> String {
+    let mut output = String::new();
+    for op in diff_words(old, new) {
+        match op {
+            DiffOp::Equal(v) => output.push_str(v),
+            DiffOp::Delete(v) if v.trim().is_empty() => {}
+            DiffOp::Delete(v) if *NO_COLOR => output.push_str(&format!("[-{v}-]")),
+            DiffOp::Delete(v) => output.push_str(&color_text(v, Color::Red)),
+            DiffOp::Insert(v) if *NO_COLOR => output.push_str(&format!("{{+{v}+}}")),
+            DiffOp::Insert(v) => output.push_str(&color_text(v, Color::Green)),
+        }
+    }
+    output
+}
+
+/// Render the changed lines prefixed with `-`/`+`, with `context` unchanged lines around them.
+pub fn render_line_diff(old: &str, new: &str, context: usize) -> String {
+    let ops = diff_lines(old, new);
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| !matches!(v, DiffOp::Equal(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let near_change = |i: usize| {
+        changed
+            .iter()
+            .any(|&j| i + context >= j && i <= j + context)
+    };
+    let mut output = String::new();
+    let mut skipped = false;
+    for (i, op) in ops.iter().enumerate() {
+        let line = match op {
+            DiffOp::Equal(v) if near_change(i) => format!("  {}", v.trim_end_matches('\n')),
+            DiffOp::Equal(_) => {
+                if !skipped {
+                    output.push_str(&dimmed_text("  ⋯"));
+                    output.push('\n');
+                    skipped = true;
+                }
+                continue;
+            }
+            DiffOp::Delete(v) => {
+                let line = format!("- {}", v.trim_end_matches('\n'));
+                if *NO_COLOR {
+                    line
+                } else {
+                    color_text(&line, Color::Red)
+                }
+            }
+            DiffOp::Insert(v) => {
+                let line = format!("+ {}", v.trim_end_matches('\n'));
+                if *NO_COLOR {
+                    line
+                } else {
+                    color_text(&line, Color::Green)
+                }
+            }
+        };
+        skipped = false;
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    let mut last_is_space = None;
+    for (i, c) in text.char_indices() {
+        let is_space = c.is_whitespace();
+        if last_is_space.is_some_and(|v| v != is_space) {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        last_is_space = Some(is_space);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+fn lcs_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffOp<'a>> {
+    let (n, m) = (old.len(), new.len());
+    let mut table = vec![0u32; (n + 1) * (m + 1)];
+    let index = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[index(i, j)] = if old[i] == new[j] {
+                table[index(i + 1, j + 1)] + 1
+            } else {
+                table[index(i + 1, j)].max(table[index(i, j + 1)])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut ops = vec![];
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(DiffOp::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if table[index(i + 1, j)] >= table[index(i, j + 1)] {
+            ops.push(DiffOp::Delete(old[i]));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|v| DiffOp::Delete(v)));
+    ops.extend(new[j..].iter().map(|v| DiffOp::Insert(v)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_words() {
+        let ops = diff_words("the quick brown fox", "the slow brown dog");
+        let changed: Vec<_> = ops
+            .into_iter()
+            .filter(|v| !matches!(v, DiffOp::Equal(_)))
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                DiffOp::Delete("quick"),
+                DiffOp::Insert("slow"),
+                DiffOp::Delete("fox"),
+                DiffOp::Insert("dog"),
+            ]
+        );
+        assert!(diff_words("same text", "same text")
+            .iter()
+            .all(|v| matches!(v, DiffOp::Equal(_))));
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let ops = diff_lines("a\nb\nc\n", "a\nB\nc\nd");
+        assert_eq!(
+            ops,
+            vec![
+                DiffOp::Equal("a\n"),
+                DiffOp::Delete("b\n"),
+                DiffOp::Insert("B\n"),
+                DiffOp::Equal("c\n"),
+                DiffOp::Insert("d"),
+            ]
+        );
+    }
+}
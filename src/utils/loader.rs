@@ -1,6 +1,6 @@
 use super::*;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use indexmap::IndexMap;
 use std::collections::HashMap;
 
@@ -8,6 +8,121 @@ pub const EXTENSION_METADATA: &str = "__extension__";
 
 pub type DocumentMetadata = IndexMap<String, String>;
 
+/// Reads an attachment from its path and extension
+pub type AttachmentLoadFn = fn(&str, &str, &AttachmentOptions) -> Result<Attachment>;
+
+/// The loaders of attachments, looked up in order. `text` loads everything else.
+pub const ATTACHMENT_LOADERS: &[AttachmentLoader] = &[
+    AttachmentLoader {
+        name: "image",
+        extensions: &["png", "jpeg", "jpg", "webp", "gif"],
+        mime_types: &["image/"],
+        media: true,
+        load: load_media,
+    },
+    AttachmentLoader {
+        name: "audio",
+        extensions: &["mp3", "wav", "ogg", "flac", "m4a"],
+        mime_types: &["audio/"],
+        media: true,
+        load: load_media,
+    },
+    AttachmentLoader {
+        name: "pdf",
+        extensions: &["pdf"],
+        mime_types: &["application/pdf"],
+        media: false,
+        load: load_text,
+    },
+    AttachmentLoader {
+        name: "table",
+        extensions: &["csv", "tsv", "parquet"],
+        mime_types: &["text/csv", "text/tab-separated-values"],
+        media: false,
+        load: load_table,
+    },
+    AttachmentLoader {
+        name: "notebook",
+        extensions: &["ipynb"],
+        mime_types: &["application/x-ipynb+json"],
+        media: false,
+        load: load_notebook,
+    },
+    AttachmentLoader {
+        name: "archive",
+        extensions: &["zip", "tar", "tgz", "gz"],
+        mime_types: &[],
+        media: false,
+        load: load_archive,
+    },
+    AttachmentLoader {
+        name: "text",
+        extensions: &[],
+        mime_types: &[],
+        media: false,
+        load: load_text,
+    },
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attachment {
+    Text(String),
+    /// A `data:` url
+    Media(String),
+    /// The files of an archive by their path
+    Files(Vec<(String, String)>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AttachmentOptions {
+    /// The `document_loaders` commands by extension, they take over the builtin loaders
+    pub commands: HashMap<String, String>,
+    /// Tables in full instead of their profile
+    pub full: bool,
+    /// The outputs of the code cells of notebooks
+    pub notebook_outputs: bool,
+}
+
+/// A kind of attachment, picked by the extension of a file or the MIME type of a url
+pub struct AttachmentLoader {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// A type ending with `/` matches all its subtypes
+    pub mime_types: &'static [&'static str],
+    pub media: bool,
+    pub load: AttachmentLoadFn,
+}
+
+impl AttachmentLoader {
+    pub fn for_path(path: &str) -> &'static Self {
+        Self::for_extension(&get_patch_extension(path).unwrap_or_default())
+    }
+
+    pub fn for_extension(extension: &str) -> &'static Self {
+        Self::find(|v| v.extensions.contains(&extension))
+    }
+
+    pub fn for_mime_type(mime_type: &str) -> &'static Self {
+        Self::find(|v| {
+            v.mime_types.iter().any(|t| match t.strip_suffix('/') {
+                Some(kind) => mime_type.split('/').next() == Some(kind),
+                None => mime_type == *t,
+            })
+        })
+    }
+
+    pub fn is_disabled(&self, disabled_loaders: &[String]) -> bool {
+        disabled_loaders.iter().any(|v| v == self.name)
+    }
+
+    fn find(predicate: impl Fn(&Self) -> bool) -> &'static Self {
+        ATTACHMENT_LOADERS
+            .iter()
+            .find(|v| predicate(v))
+            .unwrap_or(&ATTACHMENT_LOADERS[ATTACHMENT_LOADERS.len() - 1])
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LoadedDocument {
     pub path: String,
@@ -80,3 +195,88 @@ fn load_with_command(path: &str, extension: &str, loader_command: &str) -> Resul
     metadata.insert(EXTENSION_METADATA.into(), DEFAULT_EXTENSION.to_string());
     Ok(LoadedDocument::new(path.into(), contents, metadata))
 }
+
+fn load_text(path: &str, extension: &str, options: &AttachmentOptions) -> Result<Attachment> {
+    let contents = match options.commands.get(extension) {
+        Some(loader_command) => run_loader_command(path, extension, loader_command)?,
+        None => std::fs::read_to_string(path)?,
+    };
+    Ok(Attachment::Text(contents))
+}
+
+fn load_table(path: &str, extension: &str, options: &AttachmentOptions) -> Result<Attachment> {
+    if options.commands.contains_key(extension) || (options.full && extension != "parquet") {
+        return load_text(path, extension, options);
+    }
+    let contents = match extension {
+        #[cfg(feature = "parquet")]
+        "parquet" if options.full => read_parquet(path, usize::MAX)?,
+        #[cfg(feature = "parquet")]
+        "parquet" => profile_parquet(path)?,
+        #[cfg(not(feature = "parquet"))]
+        "parquet" => bail!("Build with the `parquet` feature or set a `document_loaders` command to load .parquet files"),
+        "tsv" => profile_csv(std::fs::File::open(path)?, b'\t')?,
+        _ => profile_csv(std::fs::File::open(path)?, b',')?,
+    };
+    Ok(Attachment::Text(contents))
+}
+
+fn load_notebook(path: &str, extension: &str, options: &AttachmentOptions) -> Result<Attachment> {
+    if options.commands.contains_key(extension) {
+        return load_text(path, extension, options);
+    }
+    let contents = std::fs::read_to_string(path)?;
+    Ok(Attachment::Text(render_notebook(
+        &contents,
+        options.notebook_outputs,
+    )?))
+}
+
+fn load_archive(path: &str, _: &str, _: &AttachmentOptions) -> Result<Attachment> {
+    Ok(Attachment::Files(extract_archive(path, &[])?))
+}
+
+fn load_media(path: &str, extension: &str, _: &AttachmentOptions) -> Result<Attachment> {
+    let mime_type = match extension {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        _ => bail!("Unexpected media type"),
+    };
+    let data = std::fs::read(path)?;
+    Ok(Attachment::Media(format!(
+        "data:{mime_type};base64,{}",
+        base64_encode(data)
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachment_loader() {
+        assert_eq!(AttachmentLoader::for_path("a/b.JPG").name, "image");
+        assert_eq!(AttachmentLoader::for_path("b.pdf").name, "pdf");
+        assert_eq!(AttachmentLoader::for_path("b.tsv").name, "table");
+        assert_eq!(AttachmentLoader::for_path("a.ipynb").name, "notebook");
+        assert_eq!(AttachmentLoader::for_path("a.tar.gz").name, "archive");
+        assert_eq!(AttachmentLoader::for_path("Makefile").name, "text");
+        assert_eq!(AttachmentLoader::for_mime_type("audio/mpeg").name, "audio");
+        assert_eq!(
+            AttachmentLoader::for_mime_type("application/pdf").name,
+            "pdf"
+        );
+        assert_eq!(
+            AttachmentLoader::for_mime_type("application/json").name,
+            "text"
+        );
+        assert!(AttachmentLoader::for_path("b.wav").is_disabled(&["audio".into()]));
+    }
+}
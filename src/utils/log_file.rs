@@ -0,0 +1,111 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// `read_tail_lines` reads the file backwards in blocks of this size
+const TAIL_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Move the file to `<file>.1` once it reaches `max_size`, shifting the older ones up to
+/// `<file>.<max_rotated>`.
+pub fn rotate_file(path: &Path, max_size: u64, max_rotated: usize) -> Result<()> {
+    let size = match fs::metadata(path) {
+        Ok(v) => v.len(),
+        Err(_) => return Ok(()),
+    };
+    if size < max_size {
+        return Ok(());
+    }
+    for i in (1..max_rotated).rev() {
+        let from = rotated_path(path, i);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, i + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+        .with_context(|| format!("Failed to rotate '{}'", path.display()))
+}
+
+pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(format!(".{index}"));
+    PathBuf::from(path)
+}
+
+/// The last `limit` complete lines of the file
+pub fn read_tail_lines(path: &Path, limit: usize) -> Result<Vec<String>> {
+    if limit == 0 {
+        return Ok(vec![]);
+    }
+    let mut file = match File::open(path) {
+        Ok(v) => v,
+        Err(_) => return Ok(vec![]),
+    };
+    let size = file.seek(SeekFrom::End(0))?;
+    let mut start = size;
+    let mut data = vec![];
+    // One more newline than lines wanted, the first one found may end a partial line
+    while start > 0 && data.iter().filter(|&&v| v == b'\n').count() <= limit {
+        let block = TAIL_BLOCK_SIZE.min(start);
+        start -= block;
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![0; block as usize];
+        file.read_exact(&mut buf)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        buf.append(&mut data);
+        data = buf;
+    }
+    let text = String::from_utf8_lossy(&data);
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    // Drop the unterminated last line, it's still being written
+    lines.pop();
+    if start > 0 {
+        lines.remove(0);
+    }
+    let skip = lines.len().saturating_sub(limit);
+    Ok(lines
+        .into_iter()
+        .skip(skip)
+        .map(|v| v.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_tail_lines() {
+        let path = crate::utils::temp_file("-tail-", ".jsonl");
+        let lines: Vec<String> = (0..20_000).map(|i| format!("line {i}")).collect();
+        fs::write(&path, format!("{}\npartial", lines.join("\n"))).unwrap();
+        assert_eq!(
+            read_tail_lines(&path, 2).unwrap(),
+            vec!["line 19998", "line 19999"]
+        );
+        assert_eq!(read_tail_lines(&path, 30_000).unwrap(), lines);
+        assert!(read_tail_lines(&path, 0).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_file() {
+        let dir = crate::utils::temp_file("-rotate-", "");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log.jsonl");
+        for i in 0..4 {
+            fs::write(&path, format!("{i}\n")).unwrap();
+            rotate_file(&path, 2, 2).unwrap();
+        }
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(rotated_path(&path, 1)).unwrap(), "3\n");
+        assert_eq!(fs::read_to_string(rotated_path(&path, 2)).unwrap(), "2\n");
+        assert!(!rotated_path(&path, 3).exists());
+        fs::write(&path, "4").unwrap();
+        rotate_file(&path, 2, 2).unwrap();
+        assert!(path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
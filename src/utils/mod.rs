@@ -1,41 +1,132 @@
 mod abort_signal;
+mod archive;
+#[cfg(feature = "voice")]
+mod audio;
+mod capture;
 mod clipboard;
 mod command;
+mod cron;
 mod crypto;
+mod diff;
 mod html_to_md;
+mod injection_guard;
 mod loader;
+mod log_file;
+mod notebook;
 mod path;
 mod prompt_input;
+mod redact;
 mod render_prompt;
 mod request;
+mod sandbox;
+mod screenshot;
 mod spinner;
+mod table;
+mod tabular;
+mod translate;
 mod variables;
+mod workdir;
 
 pub use self::abort_signal::*;
-pub use self::clipboard::set_text;
+pub use self::archive::*;
+#[cfg(feature = "voice")]
+pub use self::audio::*;
+pub use self::capture::*;
+pub use self::clipboard::{get_image_png, set_text};
 pub use self::command::*;
+pub use self::cron::Cron;
 pub use self::crypto::*;
+pub use self::diff::*;
 pub use self::html_to_md::*;
+pub use self::injection_guard::*;
 pub use self::loader::*;
+pub use self::log_file::*;
+pub use self::notebook::*;
 pub use self::path::*;
 pub use self::prompt_input::*;
+pub use self::redact::*;
 pub use self::render_prompt::render_prompt;
 pub use self::request::*;
+pub use self::sandbox::*;
+pub use self::screenshot::*;
 pub use self::spinner::*;
+pub use self::table::*;
+pub use self::tabular::*;
+pub use self::translate::*;
 pub use self::variables::*;
+pub use self::workdir::*;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
+use indexmap::IndexMap;
 use is_terminal::IsTerminal;
-use std::{env, path::PathBuf, process};
+use std::{
+    env,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use unicode_segmentation::UnicodeSegmentation;
 
 lazy_static::lazy_static! {
     pub static ref CODE_BLOCK_RE: Regex = Regex::new(r"(?ms)```\w*(.*)```").unwrap();
+    pub static ref IS_STDIN_TERMINAL: bool = std::io::stdin().is_terminal();
     pub static ref IS_STDOUT_TERMINAL: bool = std::io::stdout().is_terminal();
     pub static ref NO_COLOR: bool = env::var("NO_COLOR").ok().and_then(|v| parse_bool(&v)).unwrap_or_default() || !*IS_STDOUT_TERMINAL;
 }
 
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress spinners, warnings and progress messages, set by `--quiet`
+pub fn set_quiet(value: bool) {
+    QUIET.store(value, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Forbid network access except to this machine, set by `--offline`
+pub fn set_offline(value: bool) {
+    OFFLINE.store(value, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Fail fast if `--offline` is on and the url isn't on localhost.
+pub fn check_offline(url: &str) -> Result<()> {
+    if is_offline() && !is_local_url(url) {
+        bail!("Network access to '{url}' is blocked by --offline, only dry runs and local clients are allowed");
+    }
+    Ok(())
+}
+
+fn is_local_url(url: &str) -> bool {
+    let Some(host) = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|v| v.host_str().map(|v| v.to_string()))
+    else {
+        return false;
+    };
+    host == "localhost"
+        || host.ends_with(".localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|v| v.is_loopback())
+}
+
+pub fn print_warning(text: &str) {
+    if !is_quiet() {
+        eprintln!("{}", warning_text(text));
+    }
+}
+
 pub fn now() -> String {
     chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
 }
@@ -45,7 +136,7 @@ pub fn now_timestamp() -> i64 {
 }
 
 pub fn get_env_name(key: &str) -> String {
-    format!("{}_{key}", env!("CARGO_CRATE_NAME"),).to_ascii_uppercase()
+    format!("{}_{key}", env!("CARGO_PKG_NAME"),).to_ascii_uppercase()
 }
 
 pub fn normalize_env_name(value: &str) -> String {
@@ -60,10 +151,14 @@ pub fn parse_bool(value: &str) -> Option<bool> {
     }
 }
 
+/// Quick estimates below this share of a limit skip the word-based count
+const QUICK_TOKENS_MARGIN: f64 = 0.75;
+/// Tokens per char outside ASCII, mostly CJK
+const NON_ASCII_TOKENS_PER_CHAR: f32 = 0.6;
+
 pub fn estimate_token_length(text: &str) -> usize {
-    let words: Vec<&str> = text.unicode_words().collect();
     let mut output: f32 = 0.0;
-    for word in words {
+    for word in text.unicode_words() {
         if word.is_ascii() {
             output += 1.3;
         } else {
@@ -78,6 +173,101 @@ pub fn estimate_token_length(text: &str) -> usize {
     output.ceil() as usize
 }
 
+/// A single pass over the bytes, for previews and thresholds where `estimate_token_length`
+/// would be too slow on large attachments. `chars_per_token` depends on the tokenizer family.
+///
+/// Never below `estimate_token_length`, so it's safe for skipping the exact count:
+/// words don't cross ASCII whitespace, an ASCII run of `[A-Za-z0-9_]` is never split in two,
+/// and a word with other chars is worth at most 1 per such char plus 0.5 per ASCII char.
+pub fn quick_token_length(text: &str, chars_per_token: f32) -> usize {
+    let (mut ascii, mut others) = (0usize, 0usize);
+    let mut bound = 0.0f32;
+    // ASCII word runs, ASCII word chars and other chars of the current whitespace-separated chunk
+    let (mut runs, mut word_chars, mut chunk_others) = (0usize, 0usize, 0usize);
+    let mut in_run = false;
+    let mut flush = |runs: &mut usize, word_chars: &mut usize, chunk_others: &mut usize| {
+        bound += 1.3 * *runs as f32;
+        if *chunk_others > 0 {
+            bound += *chunk_others as f32 + 0.5 * *word_chars as f32;
+        }
+        (*runs, *word_chars, *chunk_others) = (0, 0, 0);
+    };
+    for b in text.bytes() {
+        if b.is_ascii() {
+            ascii += 1;
+        } else if b & 0xC0 != 0x80 {
+            others += 1;
+            chunk_others += 1;
+        }
+        let is_word = b.is_ascii_alphanumeric() || b == b'_';
+        if is_word {
+            word_chars += 1;
+            if !in_run {
+                runs += 1;
+            }
+        } else if b.is_ascii_whitespace() {
+            flush(&mut runs, &mut word_chars, &mut chunk_others);
+        }
+        in_run = is_word;
+    }
+    flush(&mut runs, &mut word_chars, &mut chunk_others);
+    let quick = ascii as f32 / chars_per_token + others as f32 * NON_ASCII_TOKENS_PER_CHAR;
+    quick.max(bound).ceil() as usize
+}
+
+/// Whether a quick estimate is far enough below `max_tokens` to trust it without counting.
+pub fn is_clearly_within(quick_tokens: usize, max_tokens: usize) -> bool {
+    (quick_tokens as f64) < max_tokens as f64 * QUICK_TOKENS_MARGIN
+}
+
+/// Cut `text` down to about `max_tokens`, keeping `head_ratio` of them from its start and the rest
+/// from its end around a `[...truncated N tokens]` marker. Returns the number of tokens removed.
+pub fn truncate_tokens(text: &str, max_tokens: usize, head_ratio: f64) -> (String, usize) {
+    let tokens = estimate_token_length(text);
+    if tokens <= max_tokens {
+        return (text.to_string(), 0);
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let keep_chars = chars.len() * max_tokens / tokens;
+    let head_chars = (keep_chars as f64 * head_ratio.clamp(0.0, 1.0)) as usize;
+    let tail_chars = keep_chars - head_chars;
+    let head: String = chars[..head_chars].iter().collect();
+    let tail: String = chars[chars.len() - tail_chars..].iter().collect();
+    let removed = tokens - max_tokens;
+    (
+        format!("{head}\n[...truncated {removed} tokens]\n{tail}"),
+        removed,
+    )
+}
+
+/// The length in bytes of the longest prefix of `next` that repeats the end of `previous`,
+/// e.g. the sentence fragment a model starts over with when asked to continue.
+pub fn overlap_len(previous: &str, next: &str) -> usize {
+    const MIN_OVERLAP: usize = 10;
+    const MAX_OVERLAP: usize = 200;
+    let mut best = 0;
+    for (count, (index, ch)) in next.char_indices().enumerate() {
+        if count >= MAX_OVERLAP {
+            break;
+        }
+        let end = index + ch.len_utf8();
+        if count + 1 >= MIN_OVERLAP && previous.ends_with(&next[..end]) {
+            best = end;
+        }
+    }
+    best
+}
+
+/// 0 when either vector is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    match norm(a) * norm(b) {
+        0.0 => 0.0,
+        v => dot / v,
+    }
+}
+
 pub fn light_theme_from_colorfgbg(colorfgbg: &str) -> Option<bool> {
     let parts: Vec<_> = colorfgbg.split(';').collect();
     let bg = match parts.len() {
@@ -199,10 +389,36 @@ pub fn dimmed_text(input: &str) -> String {
     nu_ansi_term::Style::new().dimmed().paint(input).to_string()
 }
 
+/// Compile the builtin rules, overridden or extended by `custom` ones of the same name.
+/// A custom rule with an empty pattern disables the builtin rule of the same name.
+pub fn compile_named_rules(
+    defaults: &[(&str, &str)],
+    custom: &IndexMap<String, String>,
+    what: &str,
+) -> Result<Vec<(String, Regex)>> {
+    let mut patterns: IndexMap<String, String> = defaults
+        .iter()
+        .map(|(name, pattern)| (name.to_string(), pattern.to_string()))
+        .collect();
+    for (name, pattern) in custom {
+        patterns.insert(name.clone(), pattern.clone());
+    }
+    let mut rules = vec![];
+    for (name, pattern) in patterns {
+        if pattern.is_empty() {
+            continue;
+        }
+        let re = Regex::new(&pattern)
+            .with_context(|| format!("Invalid {what} rule '{name}': {pattern}"))?;
+        rules.push((name, re));
+    }
+    Ok(rules)
+}
+
 pub fn temp_file(prefix: &str, suffix: &str) -> PathBuf {
     env::temp_dir().join(format!(
         "{}-{}{prefix}{}{suffix}",
-        env!("CARGO_CRATE_NAME").to_lowercase(),
+        env!("CARGO_PKG_NAME").to_lowercase(),
         process::id(),
         uuid::Uuid::new_v4()
     ))
@@ -212,21 +428,70 @@ pub fn is_url(path: &str) -> bool {
     path.starts_with("http://") || path.starts_with("https://")
 }
 
+/// Apply a per-client proxy, which takes precedence over the proxy environment variables.
+///
+/// Use an empty value or `-` to bypass any proxy. `no_proxy` is a comma-separated list of
+/// hosts, domains or CIDRs that connect directly, whether the proxy is per-client or from the
+/// environment.
 pub fn set_proxy(
     mut builder: reqwest::ClientBuilder,
     proxy: Option<&String>,
+    no_proxy: Option<&String>,
 ) -> Result<reqwest::ClientBuilder> {
-    if let Some(proxy) = proxy {
-        builder = builder.no_proxy();
-        if !proxy.is_empty() && proxy != "-" {
-            builder = builder.proxy(
-                reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy `{proxy}`"))?,
-            );
+    let proxies = match (proxy, no_proxy) {
+        (Some(proxy), _) => {
+            if proxy.is_empty() || proxy == "-" {
+                vec![]
+            } else {
+                let scheme = proxy.split_once("://").map(|(v, _)| v).unwrap_or_default();
+                if !["http", "https", "socks5", "socks5h"].contains(&scheme) {
+                    bail!("Invalid proxy `{proxy}`, expect http://, https://, socks5:// or socks5h://");
+                }
+                let proxy = reqwest::Proxy::all(proxy)
+                    .with_context(|| format!("Invalid proxy `{proxy}`"))?;
+                vec![proxy.no_proxy(no_proxy.and_then(|v| reqwest::NoProxy::from_string(v)))]
+            }
         }
+        (None, Some(no_proxy)) => env_proxies(|v| env::var(v).ok(), no_proxy)?,
+        (None, None) => return Ok(builder),
     };
+    builder = builder.no_proxy();
+    for proxy in proxies {
+        builder = builder.proxy(proxy);
+    }
     Ok(builder)
 }
 
+/// The proxies of the environment variables, skipping the hosts of `NO_PROXY` and `no_proxy`.
+fn env_proxies(
+    var: impl Fn(&str) -> Option<String>,
+    no_proxy: &str,
+) -> Result<Vec<reqwest::Proxy>> {
+    let var = |name: &str| {
+        var(name)
+            .or_else(|| var(&name.to_lowercase()))
+            .filter(|v| !v.is_empty())
+    };
+    let no_proxy = match var("NO_PROXY") {
+        Some(env_no_proxy) => format!("{env_no_proxy},{no_proxy}"),
+        None => no_proxy.to_string(),
+    };
+    let mut proxies = vec![];
+    for name in ["HTTPS_PROXY", "HTTP_PROXY", "ALL_PROXY"] {
+        let Some(url) = var(name) else {
+            continue;
+        };
+        let proxy = match name {
+            "HTTPS_PROXY" => reqwest::Proxy::https(&url),
+            "HTTP_PROXY" => reqwest::Proxy::http(&url),
+            _ => reqwest::Proxy::all(&url),
+        }
+        .with_context(|| format!("Invalid proxy `{url}` in {name}"))?;
+        proxies.push(proxy.no_proxy(reqwest::NoProxy::from_string(&no_proxy)));
+    }
+    Ok(proxies)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,6 +503,122 @@ mod tests {
         assert!(!fuzzy_match("openai:gpt-4-turbo", "4gpt"));
     }
 
+    #[test]
+    fn test_truncate_tokens() {
+        let text = (0..100)
+            .map(|i| format!("w{i}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(truncate_tokens(&text, 1000, 0.5), (text.clone(), 0));
+        let (output, removed) = truncate_tokens(&text, 26, 0.75);
+        assert_eq!(removed, estimate_token_length(&text) - 26);
+        assert!(output.starts_with("w0 w1 "));
+        assert!(output.ends_with(" w98 w99"));
+        assert!(output.contains(&format!("\n[...truncated {removed} tokens]\n")));
+        assert!(estimate_token_length(&output) < 40);
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]), 1.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+    }
+
+    #[test]
+    fn test_quick_token_length() {
+        let text = "The quick brown fox jumps over the lazy dog, then naps in the sun. ".repeat(50);
+        let (quick, exact) = (quick_token_length(&text, 4.0), estimate_token_length(&text));
+        assert!(quick.abs_diff(exact) * 5 < exact, "{quick} vs {exact}");
+        assert_eq!(quick_token_length("你好世界", 4.0), 4);
+        assert!(is_clearly_within(70, 100));
+        assert!(!is_clearly_within(80, 100));
+    }
+
+    #[test]
+    fn test_quick_token_length_upper_bound() {
+        use rand::{seq::SliceRandom, Rng};
+        let dense = "1 ".repeat(1000);
+        assert!(quick_token_length(&dense, 4.0) >= estimate_token_length(&dense));
+        let pieces = [
+            "a", "1", "_", " ", "\n", "'", ".", ",", "-", "é", "你", "—", "😀", "ñ", "ab1",
+        ];
+        let mut rng = rand::thread_rng();
+        for _ in 0..2000 {
+            let len = rng.gen_range(1..40);
+            let text: String = (0..len)
+                .map(|_| *pieces.choose(&mut rng).unwrap())
+                .collect();
+            let (quick, exact) = (quick_token_length(&text, 4.0), estimate_token_length(&text));
+            assert!(quick >= exact, "{text:?}: {quick} < {exact}");
+        }
+    }
+
+    #[test]
+    fn test_overlap_len() {
+        let previous = "Rust is fast. It also has a strong type sys";
+        let next = "It also has a strong type system.";
+        assert_eq!(&next[overlap_len(previous, next)..], "tem.");
+        assert_eq!(overlap_len(previous, "tem."), 0);
+        assert_eq!(overlap_len("ends with sys", "sys"), 0);
+    }
+
+    /// Answer every request with `label`, returns the port.
+    async fn serve_label(label: &'static str) -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{label}",
+                    label.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_set_proxy() {
+        let proxy_port = serve_label("proxy").await;
+        let direct_port = serve_label("direct").await;
+        let get = |client: reqwest::Client, host: &str| {
+            let url = format!("http://{host}:{direct_port}/");
+            async move { client.get(url).send().await.unwrap().text().await.unwrap() }
+        };
+        let proxy_url = format!("http://127.0.0.1:{proxy_port}");
+        let no_proxy = "localhost".to_string();
+
+        let client = set_proxy(
+            reqwest::Client::builder(),
+            Some(&proxy_url),
+            Some(&no_proxy),
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        assert_eq!(get(client.clone(), "localhost").await, "direct");
+        assert_eq!(get(client, "127.0.0.1").await, "proxy");
+
+        // The proxy of the environment skips the hosts of both lists
+        let env = |name: &str| match name {
+            "http_proxy" => Some(proxy_url.clone()),
+            "NO_PROXY" => Some("example.com".to_string()),
+            _ => None,
+        };
+        let mut builder = reqwest::Client::builder().no_proxy();
+        for proxy in env_proxies(env, &no_proxy).unwrap() {
+            builder = builder.proxy(proxy);
+        }
+        let client = builder.build().unwrap();
+        assert_eq!(get(client.clone(), "localhost").await, "direct");
+        assert_eq!(get(client, "127.0.0.1").await, "proxy");
+    }
+
     #[test]
     #[cfg(not(target_os = "windows"))]
     fn test_safe_join_path() {
@@ -259,4 +640,14 @@ mod tests {
         assert!(safe_join_path("C:\\Users\\user\\dir1", "/files/file1").is_none());
         assert!(safe_join_path("C:\\Users\\user\\dir1", "../file1").is_none());
     }
+
+    #[test]
+    fn test_is_local_url() {
+        assert!(is_local_url("http://localhost:11434/v1"));
+        assert!(is_local_url("http://127.0.0.1:8080"));
+        assert!(is_local_url("ws://[::1]:8080/v1"));
+        assert!(!is_local_url("https://api.openai.com/v1"));
+        assert!(!is_local_url("http://192.168.1.2:11434"));
+        assert!(!is_local_url("invalid"));
+    }
 }
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Render a Jupyter notebook as markdown, each cell under a numbered heading so that
+/// the model can refer to "cell 12". Code cells are fenced in the language of the kernel.
+pub fn render_notebook(contents: &str, with_outputs: bool) -> Result<String> {
+    let notebook: Value = serde_json::from_str(contents).context("Invalid notebook")?;
+    let language = notebook["metadata"]["language_info"]["name"]
+        .as_str()
+        .or_else(|| notebook["metadata"]["kernelspec"]["language"].as_str())
+        .unwrap_or_default();
+    let cells = notebook["cells"]
+        .as_array()
+        .context("Invalid notebook, no cells")?;
+    let mut output = vec![];
+    for (i, cell) in cells.iter().enumerate() {
+        let cell_type = cell["cell_type"].as_str().unwrap_or("raw");
+        let source = join_text(&cell["source"]);
+        output.push(format!("## Cell {} ({cell_type})\n", i + 1));
+        if cell_type == "code" {
+            output.push(format!("```{language}\n{}\n```\n", source.trim_end()));
+            if with_outputs {
+                let outputs = render_outputs(&cell["outputs"]);
+                if !outputs.is_empty() {
+                    output.push(format!("Output:\n```\n{}\n```\n", outputs.trim_end()));
+                }
+            }
+        } else {
+            output.push(format!("{}\n", source.trim_end()));
+        }
+    }
+    Ok(output.join("\n"))
+}
+
+fn render_outputs(outputs: &Value) -> String {
+    let Some(outputs) = outputs.as_array() else {
+        return String::new();
+    };
+    outputs
+        .iter()
+        .map(|v| match v["output_type"].as_str().unwrap_or_default() {
+            "stream" => join_text(&v["text"]),
+            "execute_result" | "display_data" => match v["data"]["text/plain"].is_null() {
+                false => join_text(&v["data"]["text/plain"]),
+                true if v["data"]["image/png"].is_string() => "[image]".into(),
+                true => String::new(),
+            },
+            "error" => format!(
+                "{}: {}",
+                v["ename"].as_str().unwrap_or_default(),
+                v["evalue"].as_str().unwrap_or_default()
+            ),
+            _ => String::new(),
+        })
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Texts of a notebook are a string or a list of lines
+fn join_text(value: &Value) -> String {
+    match value {
+        Value::String(v) => v.clone(),
+        Value::Array(lines) => lines.iter().filter_map(|v| v.as_str()).collect(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_notebook() {
+        let notebook = r##"{
+            "metadata": { "kernelspec": { "language": "python" } },
+            "cells": [
+                { "cell_type": "markdown", "source": ["# Title\n", "Intro"] },
+                { "cell_type": "code", "source": "print(1)", "outputs": [
+                    { "output_type": "stream", "text": ["1\n"] }
+                ] }
+            ]
+        }"##;
+        assert_eq!(
+            render_notebook(notebook, false).unwrap(),
+            "## Cell 1 (markdown)\n\n# Title\nIntro\n\n## Cell 2 (code)\n\n```python\nprint(1)\n```\n"
+        );
+        assert!(render_notebook(notebook, true)
+            .unwrap()
+            .ends_with("```\n\nOutput:\n```\n1\n```\n"));
+    }
+}
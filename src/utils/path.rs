@@ -1,7 +1,50 @@
+use super::is_archive;
+
+use std::collections::HashMap;
 use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
 
 use anyhow::{bail, Result};
 use indexmap::IndexSet;
+use parking_lot::Mutex;
+
+lazy_static::lazy_static! {
+    static ref FILE_NAMES: MtimeCache<Vec<String>> = MtimeCache::default();
+}
+
+/// Values loaded from a file or directory, loaded again once it is modified
+pub struct MtimeCache<T>(Mutex<HashMap<PathBuf, (SystemTime, T)>>);
+
+impl<T> Default for MtimeCache<T> {
+    fn default() -> Self {
+        Self(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<T: Clone> MtimeCache<T> {
+    pub fn get_or_load(&self, path: &Path, load: impl FnOnce() -> T) -> T {
+        let Ok(modified) = std::fs::metadata(path).and_then(|v| v.modified()) else {
+            return load();
+        };
+        if let Some((time, value)) = self.0.lock().get(path) {
+            if *time == modified {
+                return value.clone();
+            }
+        }
+        // Unlocked, loading may use the cache too
+        let value = load();
+        // Timestamps are coarse, a change in the same tick would keep the same mtime
+        let settled = modified
+            .elapsed()
+            .is_ok_and(|v| v > std::time::Duration::from_secs(1));
+        if settled {
+            self.0
+                .lock()
+                .insert(path.to_path_buf(), (modified, value.clone()));
+        }
+        value
+    }
+}
 
 pub fn safe_join_path<T1: AsRef<Path>, T2: AsRef<Path>>(
     base_path: T1,
@@ -52,21 +95,31 @@ pub async fn expand_glob_paths<T: AsRef<str>>(
     Ok(new_paths)
 }
 
+/// The names of the files in `dir` without `ext`, the directory is only read again once modified.
 pub fn list_file_names<T: AsRef<Path>>(dir: T, ext: &str) -> Vec<String> {
-    match std::fs::read_dir(dir.as_ref()) {
-        Ok(rd) => {
-            let mut names = vec![];
-            for entry in rd.flatten() {
-                let name = entry.file_name();
-                if let Some(name) = name.to_string_lossy().strip_suffix(ext) {
-                    names.push(name.to_string());
-                }
-            }
-            names.sort_unstable();
-            names
-        }
+    let dir = dir.as_ref();
+    let entries = FILE_NAMES.get_or_load(dir, || match std::fs::read_dir(dir) {
+        Ok(rd) => rd
+            .flatten()
+            .map(|v| v.file_name().to_string_lossy().to_string())
+            .collect(),
         Err(_) => vec![],
+    });
+    let mut names: Vec<String> = entries
+        .iter()
+        .filter_map(|v| v.strip_suffix(ext).map(|v| v.to_string()))
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+/// `project.zip/**/*.{rs,md}` to the archive and the extensions of the entries to extract
+pub fn parse_archive_glob(path: &str) -> Option<(String, Vec<String>)> {
+    let (base_path, suffixes) = parse_glob(path).ok()?;
+    if suffixes.is_empty() || !is_archive(&base_path) || !Path::new(&base_path).is_file() {
+        return None;
     }
+    Some((base_path, suffixes))
 }
 
 pub fn get_patch_extension(path: &str) -> Option<String> {
@@ -133,7 +186,7 @@ async fn list_files(
 }
 
 fn add_file(files: &mut IndexSet<String>, suffixes: Option<&Vec<String>>, path: &Path) {
-    if is_valid_extension(suffixes, path) {
+    if is_valid_extension(suffixes.map(|v| v.as_slice()), path) {
         let path = path.display().to_string();
         if !files.contains(&path) {
             files.insert(path);
@@ -141,7 +194,8 @@ fn add_file(files: &mut IndexSet<String>, suffixes: Option<&Vec<String>>, path:
     }
 }
 
-fn is_valid_extension(suffixes: Option<&Vec<String>>, path: &Path) -> bool {
+/// Whether the file matches the extensions of a glob like `dir/**/*.{rs,md}`, any file without one
+pub fn is_valid_extension(suffixes: Option<&[String]>, path: &Path) -> bool {
     if let Some(suffixes) = suffixes {
         if !suffixes.is_empty() {
             if let Some(extension) = path.extension().map(|v| v.to_string_lossy().to_string()) {
@@ -157,6 +211,19 @@ fn is_valid_extension(suffixes: Option<&Vec<String>>, path: &Path) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_list_file_names() {
+        let dir = std::env::temp_dir().join(format!("aichat-names-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.yaml"), "").unwrap();
+        std::fs::write(dir.join("c.md"), "").unwrap();
+        assert_eq!(list_file_names(&dir, ".yaml"), vec!["b"]);
+        std::fs::write(dir.join("a.yaml"), "").unwrap();
+        assert_eq!(list_file_names(&dir, ".yaml"), vec!["a", "b"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(list_file_names(&dir, ".yaml").is_empty());
+    }
+
     #[test]
     fn test_parse_glob() {
         assert_eq!(parse_glob("dir").unwrap(), ("dir".into(), vec![]));
@@ -6,8 +6,13 @@ use std::collections::HashMap;
 ///
 /// The syntax of `{...}`:
 /// - `{var}` - When `var` has a value, replace `var` with the value and eval `template`
+/// - `{var|trunc:10}` - Apply filters to the value: `trunc:<n>`, `upper`, `lower`
 /// - `{?var <template>}` - Eval `template` when `var` is evaluated as true
 /// - `{!var <template>}` - Eval `template` when `var` is evaluated as false
+/// - `{?var>80 <template>}` - Eval `template` when the comparison holds, with `>`, `>=`, `<`, `<=`,
+///   `==` or `!=`. Numbers are compared as numbers, other values as strings.
+///
+/// Blocks can be nested, e.g. `{?session {?consume_percent>80 {color.red}}{session}}`.
 pub fn render_prompt(template: &str, variables: &HashMap<&str, String>) -> String {
     let exprs = parse_template(template);
     eval_exprs(&exprs, variables)
@@ -53,15 +58,19 @@ fn parse_block(current: &mut Vec<char>) -> Expr {
         Some((name, tail)) => {
             if let Some(name) = name.strip_prefix('?') {
                 let block_exprs = parse_template(tail);
-                Expr::Block(BlockType::Yes, name.to_string(), block_exprs)
+                Expr::Block(BlockType::Yes, Condition::parse(name), block_exprs)
             } else if let Some(name) = name.strip_prefix('!') {
                 let block_exprs = parse_template(tail);
-                Expr::Block(BlockType::No, name.to_string(), block_exprs)
+                Expr::Block(BlockType::No, Condition::parse(name), block_exprs)
             } else {
                 Expr::Text(format!("{{{value}}}"))
             }
         }
-        None => Expr::Variable(value),
+        None => {
+            let mut parts = value.split('|');
+            let name = parts.next().unwrap_or_default().to_string();
+            Expr::Variable(name, parts.map(|v| v.to_string()).collect())
+        }
     }
 }
 
@@ -70,27 +79,27 @@ fn eval_exprs(exprs: &[Expr], variables: &HashMap<&str, String>) -> String {
     for part in exprs {
         match part {
             Expr::Text(text) => output.push_str(text),
-            Expr::Variable(variable) => {
+            Expr::Variable(variable, filters) => {
                 let value = variables
                     .get(variable.as_str())
                     .cloned()
                     .unwrap_or_default();
+                let value = filters
+                    .iter()
+                    .fold(value, |value, filter| apply_filter(value, filter));
                 output.push_str(&value);
             }
-            Expr::Block(typ, variable, block_exprs) => {
-                let value = variables
-                    .get(variable.as_str())
-                    .cloned()
-                    .unwrap_or_default();
+            Expr::Block(typ, condition, block_exprs) => {
+                let value = condition.eval(variables);
                 match typ {
                     BlockType::Yes => {
-                        if truly(&value) {
+                        if value {
                             let block_output = eval_exprs(block_exprs, variables);
                             output.push_str(&block_output)
                         }
                     }
                     BlockType::No => {
-                        if !truly(&value) {
+                        if !value {
                             let block_output = eval_exprs(block_exprs, variables);
                             output.push_str(&block_output)
                         }
@@ -114,11 +123,83 @@ fn truly(value: &str) -> bool {
     !(value.is_empty() || value == "0" || value == "false")
 }
 
+/// Unknown filters leave the value as it is
+fn apply_filter(value: String, filter: &str) -> String {
+    let (name, arg) = filter.split_once(':').unwrap_or((filter, ""));
+    match name {
+        "trunc" => match arg.parse::<usize>() {
+            Ok(max) if value.chars().count() > max => {
+                let mut value: String = value.chars().take(max.saturating_sub(1)).collect();
+                value.push('…');
+                value
+            }
+            _ => value,
+        },
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        _ => value,
+    }
+}
+
+const OPERATORS: [&str; 6] = [">=", "<=", "==", "!=", ">", "<"];
+
+#[derive(Debug)]
+struct Condition {
+    variable: String,
+    comparison: Option<(&'static str, String)>,
+}
+
+impl Condition {
+    fn parse(value: &str) -> Self {
+        let position = value.find(['>', '<', '=', '!']);
+        let comparison = position.and_then(|index| {
+            let tail = &value[index..];
+            let op = OPERATORS.into_iter().find(|v| tail.starts_with(v))?;
+            Some((index, op, tail[op.len()..].to_string()))
+        });
+        match comparison {
+            Some((index, op, operand)) => Self {
+                variable: value[..index].to_string(),
+                comparison: Some((op, operand)),
+            },
+            None => Self {
+                variable: value.to_string(),
+                comparison: None,
+            },
+        }
+    }
+
+    fn eval(&self, variables: &HashMap<&str, String>) -> bool {
+        let value = variables
+            .get(self.variable.as_str())
+            .map(|v| v.as_str())
+            .unwrap_or_default();
+        let Some((op, operand)) = &self.comparison else {
+            return truly(value);
+        };
+        let ordering = match (value.parse::<f64>(), operand.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b),
+            _ => Some(value.cmp(operand.as_str())),
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match *op {
+            ">" => ordering.is_gt(),
+            ">=" => ordering.is_ge(),
+            "<" => ordering.is_lt(),
+            "<=" => ordering.is_le(),
+            "==" => ordering.is_eq(),
+            _ => ordering.is_ne(),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Expr {
     Text(String),
-    Variable(String),
-    Block(BlockType, String, Vec<Expr>),
+    Variable(String, Vec<String>),
+    Block(BlockType, Condition, Vec<Expr>),
 }
 
 #[derive(Debug)]
@@ -152,4 +233,23 @@ mod tests {
             "temp/coder)"
         );
     }
+
+    #[test]
+    fn test_render_filters_and_comparisons() {
+        let prompt =
+            "{?consume_percent>=80 !}{!consume_percent>=80 {?session {session|trunc:5|upper}}}";
+        assert_render!(prompt, [("consume_percent", "85"),], "!");
+        assert_render!(prompt, [("consume_percent", "9"),], "");
+        assert_render!(
+            prompt,
+            [("consume_percent", "9"), ("session", "project"),],
+            "PROJ…"
+        );
+        assert_render!(
+            "{?role==coder c}{?role!=coder x}",
+            [("role", "coder"),],
+            "c"
+        );
+        assert_render!("{?n<10 a}{?n<10.5 b}", [("n", "10"),], "b");
+    }
 }
@@ -6,7 +6,7 @@ use futures_util::{stream, StreamExt};
 use http::header::CONTENT_TYPE;
 use reqwest::Url;
 use scraper::{Html, Selector};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
     collections::{HashMap, HashSet},
@@ -23,13 +23,14 @@ pub const MEDIA_URL_EXTENSION: &str = "media_url";
 pub const DEFAULT_EXTENSION: &str = "txt";
 
 const MAX_CRAWLS: usize = 5;
+const MAX_SEARCH_RESULTS: usize = 8;
 const BREAK_ON_ERROR: bool = false;
 const USER_AGENT: &str = "curl/8.6.0";
 
 lazy_static::lazy_static! {
     static ref CLIENT: Result<reqwest::Client> = {
         let builder = reqwest::ClientBuilder::new().timeout(Duration::from_secs(30));
-        let builder = set_proxy(builder, None)?;
+        let builder = set_proxy(builder, None, None)?;
         let client = builder.build()?;
         Ok(client)
     };
@@ -50,11 +51,163 @@ lazy_static::lazy_static! {
     static ref GITHUB_REPO_RE: Regex = Regex::new(r"^https://github\.com/([^/]+)/([^/]+)/tree/([^/]+)").unwrap();
 }
 
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+/// POST a json notification to a webhook.
+pub async fn post_webhook(url: &str, body: &Value) -> Result<()> {
+    check_offline(url)?;
+    let client = match *CLIENT {
+        Ok(ref client) => client,
+        Err(ref err) => bail!("{err}"),
+    };
+    let res = client
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .with_context(|| format!("Failed to post to '{url}'"))?;
+    let status = res.status();
+    if !status.is_success() {
+        bail!("Failed to post to '{url}', status {status}");
+    }
+    Ok(())
+}
+
+/// GET a url as text.
+pub async fn fetch_text(url: &str) -> Result<String> {
+    check_offline(url)?;
+    let client = match *CLIENT {
+        Ok(ref client) => client,
+        Err(ref err) => bail!("{err}"),
+    };
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch '{url}'"))?;
+    let status = res.status();
+    if !status.is_success() {
+        bail!("Failed to fetch '{url}', status {status}");
+    }
+    Ok(res.text().await?)
+}
+
+/// Query a web search engine, `searxng` (the default), `brave` or `bing`.
+pub async fn web_search(
+    engine: Option<&str>,
+    url: Option<&str>,
+    api_key: Option<&str>,
+    query: &str,
+) -> Result<Vec<SearchResult>> {
+    let client = match *CLIENT {
+        Ok(ref client) => client,
+        Err(ref err) => bail!("{err}"),
+    };
+    let ctx = || format!("Failed to search the web for '{query}'");
+    match engine.unwrap_or("searxng") {
+        "searxng" => {
+            let url = url.ok_or_else(|| anyhow!("Miss 'web_search_url' for searxng"))?;
+            check_offline(url)?;
+            let data: Value = client
+                .get(format!("{}/search", url.trim_end_matches('/')))
+                .query(&[("q", query), ("format", "json")])
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .with_context(ctx)?
+                .json()
+                .await
+                .with_context(ctx)?;
+            Ok(extract_search_results(
+                &data["results"],
+                "title",
+                "url",
+                "content",
+            ))
+        }
+        "brave" => {
+            let api_key = api_key.ok_or_else(|| anyhow!("Miss 'web_search_api_key' for brave"))?;
+            let url = url.unwrap_or("https://api.search.brave.com/res/v1/web/search");
+            check_offline(url)?;
+            let data: Value = client
+                .get(url)
+                .header("X-Subscription-Token", api_key)
+                .header("Accept", "application/json")
+                .query(&[("q", query)])
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .with_context(ctx)?
+                .json()
+                .await
+                .with_context(ctx)?;
+            Ok(extract_search_results(
+                &data["web"]["results"],
+                "title",
+                "url",
+                "description",
+            ))
+        }
+        "bing" => {
+            let api_key = api_key.ok_or_else(|| anyhow!("Miss 'web_search_api_key' for bing"))?;
+            let url = url.unwrap_or("https://api.bing.microsoft.com/v7.0/search");
+            check_offline(url)?;
+            let data: Value = client
+                .get(url)
+                .header("Ocp-Apim-Subscription-Key", api_key)
+                .query(&[("q", query)])
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .with_context(ctx)?
+                .json()
+                .await
+                .with_context(ctx)?;
+            Ok(extract_search_results(
+                &data["webPages"]["value"],
+                "name",
+                "url",
+                "snippet",
+            ))
+        }
+        engine => bail!("Unknown web search engine '{engine}'"),
+    }
+}
+
+fn extract_search_results(
+    data: &Value,
+    title_key: &str,
+    url_key: &str,
+    snippet_key: &str,
+) -> Vec<SearchResult> {
+    data.as_array()
+        .map(|items| {
+            items
+                .iter()
+                .take(MAX_SEARCH_RESULTS)
+                .filter_map(|item| {
+                    Some(SearchResult {
+                        title: item[title_key].as_str().unwrap_or_default().to_string(),
+                        url: item[url_key].as_str()?.to_string(),
+                        snippet: item[snippet_key].as_str().unwrap_or_default().to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 pub async fn fetch(
     loaders: &HashMap<String, String>,
     path: &str,
     allow_media: bool,
 ) -> Result<(String, String)> {
+    check_offline(path)?;
     if let Some(loader_command) = loaders.get(URL_LOADER) {
         let contents = run_loader_command(path, URL_LOADER, loader_command)?;
         return Ok((contents, DEFAULT_EXTENSION.into()));
@@ -129,7 +282,7 @@ pub async fn fetch(
                     save_file.write_all(&chunk).await?;
                 }
                 let contents = if size == 0 {
-                    println!("{}", warning_text(&format!("No content at '{path}'")));
+                    print_warning(&format!("No content at '{path}'"));
                     String::new()
                 } else {
                     run_loader_command(&save_path, &extension, loader_command)?
@@ -168,6 +321,7 @@ impl CrawlOptions {
 }
 
 pub async fn crawl_website(start_url: &str, options: CrawlOptions) -> Result<Vec<Page>> {
+    check_offline(start_url)?;
     let start_url = Url::parse(start_url)?;
     let mut paths = vec![start_url.path().to_string()];
     let normalized_start_url = normalize_start_url(&start_url);
@@ -272,6 +426,7 @@ async fn crawl_gh_tree(start_url: &Url, exclude: &[String]) -> Result<Vec<String
     let repo = path_segs[2];
     let branch = path_segs[4];
     let root_path = path_segs[5..].join("/");
+    check_offline("https://api.github.com")?;
 
     let url = format!(
         "https://api.github.com/repos/{}/{}/git/ref/heads/{}",
@@ -341,6 +496,7 @@ async fn crawl_page(
         Err(ref err) => bail!("{err}"),
     };
     let location = start_url.join(path)?;
+    check_offline(location.as_str())?;
     let response = client
         .get(location.as_str())
         .header("User-Agent", USER_AGENT)
@@ -425,3 +581,50 @@ fn match_link(path: &str, link: &str) -> bool {
                 .trim_end_matches("/index.html")
                 .trim_end_matches("/index.htm")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Answer one request with `body`, the returned task yields the request head.
+    async fn serve_once(body: &'static str) -> (u16, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_lowercase()
+        });
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn test_web_search_brave() {
+        let body = r#"{"web":{"results":[{"title":"Rust","url":"https://www.rust-lang.org","description":"A language"},{"title":"No url"}]}}"#;
+        let (port, request) = serve_once(body).await;
+        let url = format!("http://127.0.0.1:{port}/search");
+        let results = web_search(Some("brave"), Some(&url), Some("key-1"), "rust")
+            .await
+            .unwrap();
+        let request = request.await.unwrap();
+        assert!(request.starts_with("get /search?q=rust "));
+        assert!(request.contains("x-subscription-token: key-1\r\n"));
+        assert_eq!(
+            serde_json::to_value(&results).unwrap(),
+            serde_json::json!([
+                { "title": "Rust", "url": "https://www.rust-lang.org", "snippet": "A language" }
+            ])
+        );
+        let err = web_search(Some("brave"), Some(&url), None, "rust")
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Miss 'web_search_api_key' for brave");
+    }
+}
@@ -0,0 +1,109 @@
+use super::compile_named_rules;
+
+use anyhow::{anyhow, bail, Result};
+use fancy_regex::Regex;
+use indexmap::IndexMap;
+
+pub const DEFAULT_SHELL_DENYLIST: [(&str, &str); 6] = [
+    (
+        "rm_rf",
+        r"(?i)\brm\s+(?:-\S+\s+)*(?:-[a-z]*(?:r[a-z]*f|f[a-z]*r)[a-z]*\b|(?:-r|--recursive)\s+(?:-f|--force)\b|(?:-f|--force)\s+(?:-r|--recursive)\b)",
+    ),
+    ("mkfs", r"(?:^|[\s;&|(])mkfs(?:\.\w+)?\s"),
+    ("dd_device", r"\bdd\b[^|;&]*\bof=/dev/"),
+    (
+        "device_write",
+        r">\s*/dev/(?:sd[a-z]|hd[a-z]|nvme\d|mmcblk\d|disk\d)",
+    ),
+    ("fork_bomb", r"([\w:]+)\s*\(\)\s*\{\s*\1\s*\|\s*\1\s*&"),
+    (
+        "chmod_root",
+        r"\bch(?:mod|own)\s+(?:-\S+\s+)*-[a-zA-Z]*R[a-zA-Z]*\s+(?:\S+\s+)?/(?:\s|$|\*)",
+    ),
+];
+
+/// Refuses shell commands matching destructive patterns before they run.
+#[derive(Debug, Clone)]
+pub struct ShellGuard {
+    rules: Vec<(String, Regex)>,
+}
+
+impl ShellGuard {
+    /// Build a guard from the builtin denylist, overridden or extended by `custom_rules`.
+    pub fn new(custom_rules: &IndexMap<String, String>) -> Result<Self> {
+        let rules = compile_named_rules(&DEFAULT_SHELL_DENYLIST, custom_rules, "shell denylist")?;
+        Ok(Self { rules })
+    }
+
+    /// A rule that fails to evaluate, e.g. by hitting the backtrack limit, counts as a match.
+    pub fn check(&self, command: &str) -> Result<()> {
+        for (name, re) in &self.rules {
+            if re.is_match(command).unwrap_or(true) {
+                bail!("Refused to run a destructive command ({name}): {command}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prefix `cmd` and `args` with the sandbox command, e.g. `firejail --quiet --`
+/// or `docker run --rm -i -v {cwd}:{cwd} -w {cwd} alpine`.
+pub fn wrap_in_sandbox(sandbox: &str, cmd: &str, args: &[String]) -> Result<(String, Vec<String>)> {
+    let cwd = std::env::current_dir()
+        .map(|v| v.display().to_string())
+        .unwrap_or_default();
+    let mut words = shell_words::split(sandbox)
+        .map_err(|_| anyhow!("Invalid shell sandbox `{sandbox}`"))?
+        .into_iter()
+        .map(|v| v.replace("{cwd}", &cwd));
+    let Some(sandbox_cmd) = words.next() else {
+        bail!("Empty shell sandbox");
+    };
+    let mut sandbox_args: Vec<String> = words.collect();
+    sandbox_args.push(cmd.to_string());
+    sandbox_args.extend(args.iter().cloned());
+    Ok((sandbox_cmd, sandbox_args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_guard() {
+        let guard = ShellGuard::new(&Default::default()).unwrap();
+        for command in [
+            "rm -rf /",
+            "cd /tmp && rm -fr build",
+            "rm -r -f ~",
+            "sudo mkfs.ext4 /dev/sda1",
+            "dd if=/dev/zero of=/dev/sda bs=1M",
+            ":(){ :|:& };:",
+            "chmod -R 777 /",
+        ] {
+            assert!(guard.check(command).is_err(), "{command}");
+        }
+        for command in ["rm foo.txt", "ls -rf", "rm -r build", "echo mkfs-docs"] {
+            assert!(guard.check(command).is_ok(), "{command}");
+        }
+        let rules = IndexMap::from([("rm_rf".to_string(), String::new())]);
+        let guard = ShellGuard::new(&rules).unwrap();
+        assert!(guard.check("rm -rf build").is_ok());
+    }
+
+    #[test]
+    fn test_shell_guard_fails_closed() {
+        // Catastrophic backtracking exceeds fancy_regex's backtrack limit
+        let rules = IndexMap::from([("slow".to_string(), r"^(a+)+\1b$".to_string())]);
+        let guard = ShellGuard::new(&rules).unwrap();
+        assert!(guard.check(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_wrap_in_sandbox() {
+        let (cmd, args) =
+            wrap_in_sandbox("firejail --quiet --", "sh", &["-c".into(), "ls".into()]).unwrap();
+        assert_eq!(cmd, "firejail");
+        assert_eq!(args, vec!["--quiet", "--", "sh", "-c", "ls"]);
+    }
+}
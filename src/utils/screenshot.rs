@@ -0,0 +1,100 @@
+use super::{run_command_with_output, temp_file};
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{env, path::Path};
+
+/// Attach a capture of the screen, `@screen:region` to select a region
+pub const SCREENSHOT_PATH: &str = "@screen";
+const SCREENSHOT_REGION_PATH: &str = "@screen:region";
+
+/// Whether the path is a screenshot, and of a region
+pub fn parse_screenshot_path(path: &str) -> Option<bool> {
+    match path {
+        SCREENSHOT_PATH => Some(false),
+        SCREENSHOT_REGION_PATH => Some(true),
+        _ => None,
+    }
+}
+
+/// Capture the screen into a temporary PNG file with `screenshot_command` or the tool of
+/// the platform. `$1` is the file and `$2` is `screen` or `region`.
+pub fn capture_screenshot(command: Option<&str>, region: bool) -> Result<String> {
+    let path = temp_file("-screenshot-", ".png").display().to_string();
+    let command = match command {
+        Some(v) => v.to_string(),
+        None => default_screenshot_command(region)?.to_string(),
+    };
+    let area = if region { "region" } else { "screen" };
+    let cmd_args: Vec<String> = shell_words::split(&command)
+        .with_context(|| anyhow!("Invalid screenshot command `{command}`"))?
+        .into_iter()
+        .map(|v| v.replace("$1", &path).replace("$2", area))
+        .collect();
+    let cmd_eval = shell_words::join(&cmd_args);
+    let Some((cmd, args)) = cmd_args.split_first() else {
+        bail!("Empty screenshot command");
+    };
+    let (success, _, stderr) = run_command_with_output(cmd, args, None).with_context(|| {
+        format!("Unable to run `{cmd_eval}`, Perhaps '{cmd}' is not installed?")
+    })?;
+    if !success || !Path::new(&path).exists() {
+        let err = match stderr.trim() {
+            "" => format!("No screenshot taken by `{cmd_eval}`"),
+            v => v.to_string(),
+        };
+        bail!("{err}")
+    }
+    Ok(path)
+}
+
+fn default_screenshot_command(region: bool) -> Result<&'static str> {
+    let command = if cfg!(target_os = "macos") {
+        match region {
+            false => "screencapture -x $1",
+            true => "screencapture -x -i $1",
+        }
+    } else if cfg!(windows) {
+        match region {
+            false => "powershell -NoProfile -Command \"Add-Type -AssemblyName System.Windows.Forms,System.Drawing; $b = [System.Windows.Forms.SystemInformation]::VirtualScreen; $i = New-Object System.Drawing.Bitmap $b.Width, $b.Height; [System.Drawing.Graphics]::FromImage($i).CopyFromScreen($b.Location, [System.Drawing.Point]::Empty, $b.Size); $i.Save('$1')\"",
+            true => bail!("Set `screenshot_command` to capture a region on Windows"),
+        }
+    } else if env::var("WAYLAND_DISPLAY").is_ok() {
+        match region {
+            false => "grim $1",
+            true => r#"sh -c 'grim -g "$(slurp)" "$0"' $1"#,
+        }
+    } else {
+        match region {
+            false => "import -window root $1",
+            true => "import $1",
+        }
+    };
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_screenshot_path() {
+        assert_eq!(parse_screenshot_path("@screen"), Some(false));
+        assert_eq!(parse_screenshot_path("@screen:region"), Some(true));
+        assert_eq!(parse_screenshot_path("@screens"), None);
+        assert_eq!(parse_screenshot_path("screen.png"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capture_screenshot() {
+        let path = capture_screenshot(Some("sh -c 'printf $2 > $1'"), true).unwrap();
+        assert!(path.ends_with(".png"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "region");
+        std::fs::remove_file(&path).unwrap();
+
+        let err = capture_screenshot(Some("true"), false).unwrap_err();
+        assert_eq!(err.to_string(), "No screenshot taken by `true`");
+        let err = capture_screenshot(Some("sh -c 'echo canceled >&2; exit 1'"), true).unwrap_err();
+        assert_eq!(err.to_string(), "canceled");
+    }
+}
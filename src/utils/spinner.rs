@@ -1,4 +1,4 @@
-use super::{poll_abort_signal, wait_abort_signal, AbortSignal, IS_STDOUT_TERMINAL};
+use super::{is_quiet, poll_abort_signal, wait_abort_signal, AbortSignal, IS_STDOUT_TERMINAL};
 
 use anyhow::{bail, Result};
 use crossterm::{cursor, queue, style, terminal};
@@ -25,7 +25,7 @@ impl SpinnerInner {
     const DATA: [&'static str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
     fn step(&mut self) -> Result<()> {
-        if !*IS_STDOUT_TERMINAL || self.message.is_empty() {
+        if !*IS_STDOUT_TERMINAL || is_quiet() || self.message.is_empty() {
             return Ok(());
         }
         let mut writer = stdout();
@@ -79,13 +79,20 @@ impl Spinner {
 
     pub fn set_message(&self, message: String) -> Result<()> {
         self.0.send(SpinnerEvent::SetMessage(message))?;
-        std::thread::sleep(Duration::from_millis(10));
+        Self::wait_drawn();
         Ok(())
     }
 
     pub fn stop(&self) {
         let _ = self.0.send(SpinnerEvent::Stop);
-        std::thread::sleep(Duration::from_millis(10));
+        Self::wait_drawn();
+    }
+
+    /// Give the spinner a moment to update, unless it draws nothing anyway
+    fn wait_drawn() {
+        if *IS_STDOUT_TERMINAL && !is_quiet() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
     }
 }
 
@@ -145,7 +152,7 @@ pub async fn abortable_run_with_spinner_rx<F, T>(
 where
     F: Future<Output = Result<T>>,
 {
-    if *IS_STDOUT_TERMINAL {
+    if *IS_STDOUT_TERMINAL && !is_quiet() {
         let (done_tx, done_rx) = oneshot::channel();
         let run_task = async {
             tokio::select! {
@@ -0,0 +1,43 @@
+use unicode_width::UnicodeWidthStr;
+
+/// Render rows as left-aligned columns separated by two spaces, with a header line.
+pub fn render_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|v| v.width()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            if let Some(width) = widths.get_mut(i) {
+                *width = (*width).max(cell.width());
+            }
+        }
+    }
+    let render_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{cell}{}", " ".repeat(width - cell.width())))
+            .collect();
+        line.join("  ").trim_end().to_string()
+    };
+    let mut output = vec![render_row(headers.to_vec())];
+    for row in rows {
+        output.push(render_row(row.iter().map(|v| v.as_str()).collect()));
+    }
+    output.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let rows = vec![
+            vec!["gpt-4o".into(), "128000".into(), "vision,tools".into()],
+            vec!["o1".into(), "200000".into(), "".into()],
+        ];
+        assert_eq!(
+            render_table(&["MODEL", "CONTEXT", "CAPABILITIES"], &rows),
+            "MODEL   CONTEXT  CAPABILITIES\ngpt-4o  128000   vision,tools\no1      200000"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use super::render_table;
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use std::io::Read;
+
+/// Rows shown in the profile of a table
+const SAMPLE_ROWS: usize = 5;
+const NULL_VALUES: [&str; 6] = ["", "na", "n/a", "nan", "null", "none"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    Integer,
+    Float,
+    Boolean,
+    Date,
+    Text,
+}
+
+impl ValueType {
+    fn of(value: &str) -> Self {
+        if value.parse::<i64>().is_ok() {
+            Self::Integer
+        } else if value.parse::<f64>().is_ok() {
+            Self::Float
+        } else if value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false") {
+            Self::Boolean
+        } else if value
+            .get(..10)
+            .is_some_and(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d").is_ok())
+        {
+            Self::Date
+        } else {
+            Self::Text
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Self::Integer, Self::Float) | (Self::Float, Self::Integer) => Self::Float,
+            _ => Self::Text,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Integer => "integer",
+            Self::Float => "float",
+            Self::Boolean => "boolean",
+            Self::Date => "date",
+            Self::Text => "text",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ColumnStats {
+    nulls: usize,
+    value_type: Option<ValueType>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ColumnStats {
+    fn add(&mut self, value: &str) {
+        let value = value.trim();
+        if NULL_VALUES.iter().any(|v| value.eq_ignore_ascii_case(v)) {
+            self.nulls += 1;
+            return;
+        }
+        let value_type = ValueType::of(value);
+        self.value_type = Some(match self.value_type {
+            Some(v) => v.merge(value_type),
+            None => value_type,
+        });
+        if let Ok(number) = value.parse::<f64>() {
+            self.min = Some(self.min.map_or(number, |v| v.min(number)));
+            self.max = Some(self.max.map_or(number, |v| v.max(number)));
+        }
+    }
+
+    fn range(&self) -> String {
+        match (self.value_type, self.min, self.max) {
+            (Some(ValueType::Integer | ValueType::Float), Some(min), Some(max)) => {
+                format!("{min} – {max}")
+            }
+            _ => "-".into(),
+        }
+    }
+}
+
+/// The columns, types, null rates, row count and first rows of a CSV table,
+/// attached instead of the whole table.
+pub fn profile_csv<R: Read>(reader: R, delimiter: u8) -> Result<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(reader);
+    let headers: Vec<String> = reader
+        .headers()
+        .context("Invalid table header")?
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    let mut columns: Vec<ColumnStats> = headers.iter().map(|_| Default::default()).collect();
+    let mut samples = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    samples.write_record(&headers)?;
+    let mut rows = 0;
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Invalid table row {}", rows + 1))?;
+        for (i, column) in columns.iter_mut().enumerate() {
+            column.add(record.get(i).unwrap_or_default());
+        }
+        if rows < SAMPLE_ROWS {
+            samples.write_record(&record)?;
+        }
+        rows += 1;
+    }
+    let samples = String::from_utf8(samples.into_inner()?)?;
+    let columns = headers
+        .into_iter()
+        .zip(columns)
+        .map(|(name, v)| {
+            let value_type = v.value_type.map(|v| v.as_str()).unwrap_or("empty");
+            let range = v.range();
+            (name, value_type.to_string(), v.nulls, range)
+        })
+        .collect();
+    Ok(render_profile(rows, columns, samples.trim_end()))
+}
+
+/// Like `profile_csv`, with the types and null counts of the parquet metadata.
+#[cfg(feature = "parquet")]
+pub fn profile_parquet(path: &str) -> Result<String> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let reader = SerializedFileReader::new(std::fs::File::open(path)?)?;
+    let metadata = reader.metadata();
+    let rows = metadata.file_metadata().num_rows() as usize;
+    let schema = metadata.file_metadata().schema_descr();
+    let columns = (0..schema.num_columns())
+        .map(|i| {
+            let column = schema.column(i);
+            let value_type = match column.logical_type() {
+                Some(v) => format!("{v:?}"),
+                None => format!("{:?}", column.physical_type()),
+            };
+            let nulls = metadata
+                .row_groups()
+                .iter()
+                .filter_map(|v| v.column(i).statistics()?.null_count_opt())
+                .sum::<u64>();
+            let name = column.path().string();
+            (name, value_type.to_lowercase(), nulls as usize, "-".into())
+        })
+        .collect();
+    let samples = read_parquet(path, SAMPLE_ROWS)?;
+    Ok(render_profile(rows, columns, samples.trim_end()))
+}
+
+/// The first rows of a parquet file as CSV
+#[cfg(feature = "parquet")]
+pub fn read_parquet(path: &str, limit: usize) -> Result<String> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let reader = SerializedFileReader::new(std::fs::File::open(path)?)?;
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for (i, row) in reader.get_row_iter(None)?.take(limit).enumerate() {
+        let row = row?;
+        if i == 0 {
+            writer.write_record(row.get_column_iter().map(|(k, _)| k))?;
+        }
+        writer.write_record(row.get_column_iter().map(|(_, v)| v.to_string()))?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn render_profile(
+    rows: usize,
+    columns: Vec<(String, String, usize, String)>,
+    samples: &str,
+) -> String {
+    let count = columns.len();
+    let table: Vec<Vec<String>> = columns
+        .into_iter()
+        .map(|(name, value_type, nulls, range)| {
+            let null_rate = match rows {
+                0 => "-".into(),
+                _ => format!("{:.1}%", nulls as f64 * 100.0 / rows as f64),
+            };
+            vec![name, value_type, null_rate, range]
+        })
+        .collect();
+    format!(
+        "Profile of a table of {rows} rows and {count} columns, attach it with `--full` for every row.\n\n{}\n\nFirst rows:\n{samples}\n",
+        render_table(&["COLUMN", "TYPE", "NULLS", "RANGE"], &table)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_csv() {
+        let data = "id,score,name,day\n1,2.5,a,2024-01-02\n2,,b,2024-01-03\n3,4,,2024-01-04\n";
+        let output = profile_csv(data.as_bytes(), b',').unwrap();
+        assert!(output.starts_with("Profile of a table of 3 rows and 4 columns"));
+        assert!(output.contains("id      integer  0.0%   1 – 3"));
+        assert!(output.contains("score   float    33.3%  2.5 – 4"));
+        assert!(output.contains("day     date     0.0%   -"));
+        assert!(output.ends_with("First rows:\nid,score,name,day\n1,2.5,a,2024-01-02\n2,,b,2024-01-03\n3,4,,2024-01-04\n"));
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+const CODE_BLOCK_PLACEHOLDER: &str = "[[CODE_BLOCK_{}]]";
+
+/// Words frequent enough to tell apart the latin-script languages
+const STOPWORDS: [(&str, &[&str]); 6] = [
+    (
+        "English",
+        &[
+            "the", "and", "is", "are", "of", "to", "in", "that", "it", "with", "for", "this",
+            "you", "was", "not",
+        ],
+    ),
+    (
+        "French",
+        &[
+            "le", "la", "les", "et", "est", "des", "une", "un", "du", "que", "pour", "dans", "pas",
+            "vous", "avec",
+        ],
+    ),
+    (
+        "Spanish",
+        &[
+            "el", "la", "los", "las", "y", "es", "una", "un", "que", "por", "para", "con", "del",
+            "pero", "como",
+        ],
+    ),
+    (
+        "German",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "mit", "zu", "ich", "sie",
+            "auf", "für", "den",
+        ],
+    ),
+    (
+        "Portuguese",
+        &[
+            "o", "os", "as", "e", "é", "um", "uma", "que", "não", "para", "com", "do", "da", "em",
+            "mas",
+        ],
+    ),
+    (
+        "Italian",
+        &[
+            "il", "lo", "gli", "e", "è", "un", "una", "che", "non", "per", "con", "del", "della",
+            "sono", "ma",
+        ],
+    ),
+];
+
+/// Replace the fenced code blocks with placeholder lines, returning the masked text and the blocks.
+pub fn mask_code_blocks(text: &str) -> (String, Vec<String>) {
+    let mut output = String::new();
+    let mut blocks: Vec<String> = vec![];
+    let mut fence: Option<(char, usize)> = None;
+    for line in text.split_inclusive('\n') {
+        match fence {
+            Some((fence_char, fence_len)) => {
+                if let Some(block) = blocks.last_mut() {
+                    block.push_str(line);
+                }
+                if let Some((c, len)) = parse_fence(line) {
+                    let rest = line.trim_start().trim_start_matches(c).trim();
+                    if c == fence_char && len >= fence_len && rest.is_empty() {
+                        fence = None;
+                    }
+                }
+            }
+            None => match parse_fence(line) {
+                Some(v) => {
+                    fence = Some(v);
+                    output
+                        .push_str(&CODE_BLOCK_PLACEHOLDER.replace("{}", &blocks.len().to_string()));
+                    output.push('\n');
+                    blocks.push(line.to_string());
+                }
+                None => output.push_str(line),
+            },
+        }
+    }
+    for block in blocks.iter_mut() {
+        if block.ends_with('\n') {
+            block.pop();
+        }
+    }
+    (output, blocks)
+}
+
+/// Put the blocks back, the ones whose placeholder was lost are appended at the end.
+pub fn unmask_code_blocks(text: &str, blocks: &[String]) -> String {
+    let mut output = text.to_string();
+    let mut missing = vec![];
+    for (i, block) in blocks.iter().enumerate() {
+        let placeholder = CODE_BLOCK_PLACEHOLDER.replace("{}", &i.to_string());
+        if output.contains(&placeholder) {
+            output = output.replace(&placeholder, block);
+        } else {
+            missing.push(block.as_str());
+        }
+    }
+    if !missing.is_empty() {
+        output = format!("{}\n\n{}", output.trim_end(), missing.join("\n\n"));
+    }
+    output
+}
+
+/// Guess the language of a text by its dominant script, or by stopwords for latin scripts.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut scripts: HashMap<&str, usize> = HashMap::new();
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        let script = match c as u32 {
+            0x3040..=0x30ff => "Japanese",
+            0xac00..=0xd7af | 0x1100..=0x11ff => "Korean",
+            0x4e00..=0x9fff | 0x3400..=0x4dbf => "Chinese",
+            0x0400..=0x04ff => "Russian",
+            0x0600..=0x06ff => "Arabic",
+            0x0590..=0x05ff => "Hebrew",
+            0x0370..=0x03ff => "Greek",
+            0x0e00..=0x0e7f => "Thai",
+            0x0900..=0x097f => "Hindi",
+            _ if c.is_ascii_alphabetic() || ('\u{c0}'..='\u{24f}').contains(&c) => "Latin",
+            _ => continue,
+        };
+        *scripts.entry(script).or_default() += 1;
+    }
+    // Kana mixed with kanji is still japanese
+    if scripts.get("Japanese").copied().unwrap_or_default() > 0 {
+        let han = scripts.remove("Chinese").unwrap_or_default();
+        *scripts.entry("Japanese").or_default() += han;
+    }
+    let (script, _) = scripts.into_iter().max_by_key(|(_, count)| *count)?;
+    if script != "Latin" {
+        return Some(script);
+    }
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_lowercase())
+        .collect();
+    STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|v| stopwords.contains(&v.as_str()))
+                .count();
+            (*language, hits)
+        })
+        .filter(|(_, hits)| *hits > 0)
+        .max_by_key(|(_, hits)| *hits)
+        .map(|(language, _)| language)
+}
+
+fn parse_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let c = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.chars().take_while(|v| *v == c).count();
+    (len >= 3).then_some((c, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_code_blocks() {
+        let text = "Hello\n```rust\nfn main() {}\n```\nBye\n~~~\nraw\n";
+        let (masked, blocks) = mask_code_blocks(text);
+        assert_eq!(masked, "Hello\n[[CODE_BLOCK_0]]\nBye\n[[CODE_BLOCK_1]]\n");
+        assert_eq!(blocks, vec!["```rust\nfn main() {}\n```", "~~~\nraw"]);
+        assert_eq!(unmask_code_blocks(&masked, &blocks), text);
+        assert_eq!(
+            unmask_code_blocks("Hallo\n[[CODE_BLOCK_1]]\n", &blocks),
+            "Hallo\n~~~\nraw\n\n```rust\nfn main() {}\n```"
+        );
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language("今日はいい天気ですね"), Some("Japanese"));
+        assert_eq!(detect_language("今天天气很好"), Some("Chinese"));
+        assert_eq!(detect_language("The cat is on the table"), Some("English"));
+        assert_eq!(detect_language("Le chat est sur la table"), Some("French"));
+        assert_eq!(detect_language("123"), None);
+    }
+}
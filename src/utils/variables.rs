@@ -3,7 +3,30 @@ use fancy_regex::{Captures, Regex};
 
 lazy_static::lazy_static! {
     pub static ref RE_VARIABLE: Regex = Regex::new(r"\{\{(\w+)\}\}").unwrap();
+    static ref RE_ENV_VARIABLE: Regex = Regex::new(r"\$\{(\w+)\}").unwrap();
 }
+
+/// Replace `${NAME}` with the value `get_var` returns for `NAME`, usually an environment variable.
+pub fn expand_env_vars(
+    text: &str,
+    get_var: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<String> {
+    let mut missing = vec![];
+    let output = RE_ENV_VARIABLE
+        .replace_all(text, |caps: &Captures<'_>| match get_var(&caps[1]) {
+            Some(v) => v,
+            None => {
+                missing.push(caps[1].to_string());
+                String::new()
+            }
+        })
+        .to_string();
+    if !missing.is_empty() {
+        anyhow::bail!("Missing environment variable {}", missing.join(", "));
+    }
+    Ok(output)
+}
+
 pub fn interpolate_variables(text: &mut String) {
     *text = RE_VARIABLE
         .replace_all(text, |caps: &Captures<'_>| {
@@ -31,3 +54,23 @@ pub fn interpolate_variables(text: &mut String) {
         })
         .to_string();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_env_vars() {
+        let get_var = |name: &str| (name == "TOKEN").then(|| "secret".to_string());
+        assert_eq!(
+            expand_env_vars("Bearer ${TOKEN}", get_var).unwrap(),
+            "Bearer secret"
+        );
+        assert_eq!(expand_env_vars("$HOME {x}", get_var).unwrap(), "$HOME {x}");
+        let err = expand_env_vars("${MISSING} ${TOKEN} ${OTHER}", get_var).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing environment variable MISSING, OTHER"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use parking_lot::Mutex;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+lazy_static::lazy_static! {
+    /// The git directory of the last working directory, looked up again when it changes
+    static ref GIT_DIR: Mutex<Option<(PathBuf, Option<PathBuf>)>> = Mutex::new(None);
+    static ref HOSTNAME: String = load_hostname();
+}
+
+/// The working directory, under the home directory as `~/...`
+pub fn display_cwd() -> Option<String> {
+    let cwd = env::current_dir().ok()?;
+    match dirs::home_dir().and_then(|home| cwd.strip_prefix(home).ok().map(|v| v.to_path_buf())) {
+        Some(path) if path.as_os_str().is_empty() => Some("~".into()),
+        Some(path) => Some(format!("~{}{}", std::path::MAIN_SEPARATOR, path.display())),
+        None => Some(cwd.display().to_string()),
+    }
+}
+
+/// The branch checked out in the working directory, or the short hash of a detached HEAD
+pub fn git_branch() -> Option<String> {
+    let cwd = env::current_dir().ok()?;
+    let git_dir = {
+        let mut cache = GIT_DIR.lock();
+        match cache.as_ref() {
+            Some((dir, git_dir)) if *dir == cwd => git_dir.clone(),
+            _ => {
+                let git_dir = find_git_dir(&cwd);
+                *cache = Some((cwd, git_dir.clone()));
+                git_dir
+            }
+        }
+    }?;
+    parse_git_head(&fs::read_to_string(git_dir.join("HEAD")).ok()?)
+}
+
+/// The host name without its domain
+pub fn hostname() -> &'static str {
+    &HOSTNAME
+}
+
+/// `.git` is a directory, or a file pointing to it in worktrees and submodules
+fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        let path = dir.join(".git");
+        if path.is_dir() {
+            Some(path)
+        } else if path.is_file() {
+            let text = fs::read_to_string(&path).ok()?;
+            Some(dir.join(text.trim().strip_prefix("gitdir:")?.trim()))
+        } else {
+            None
+        }
+    })
+}
+
+fn parse_git_head(head: &str) -> Option<String> {
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => head.get(..7).map(|v| v.to_string()),
+    }
+}
+
+fn load_hostname() -> String {
+    let hostname = env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| {
+            let output = std::process::Command::new("hostname").output().ok()?;
+            Some(String::from_utf8_lossy(&output.stdout).to_string())
+        })
+        .unwrap_or_default();
+    let hostname = hostname.trim();
+    hostname.split('.').next().unwrap_or(hostname).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_head() {
+        assert_eq!(
+            parse_git_head("ref: refs/heads/feat/login\n").as_deref(),
+            Some("feat/login")
+        );
+        assert_eq!(
+            parse_git_head("4ff5ee6a0c1d2e3f\n").as_deref(),
+            Some("4ff5ee6")
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! How the output adapts to stdin and stdout being terminals or not.
+
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const MARKDOWN: &str = "# Title\n\n**bold** and `code`\n";
+
+fn temp_file(prefix: &str, suffix: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("aichat-{}{prefix}{count}{suffix}", process::id()))
+}
+
+struct ConfigDir(PathBuf);
+
+impl ConfigDir {
+    fn new() -> Self {
+        Self::with_config(
+            "model: openai:gpt-4o-mini\nmodel_aliases:\n  fast: openai:gpt-4o-mini\nclients:\n- type: openai\n  api_key: sk-test\n",
+        )
+    }
+
+    fn with_config(config: &str) -> Self {
+        let dir = temp_file("-terminal-", "");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.yaml"), config).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ConfigDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn aichat(config_dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_aichat"));
+    command
+        .env("AICHAT_CONFIG_DIR", config_dir)
+        .env_remove("NO_COLOR")
+        .args(["--no-stream", "--dry-run"]);
+    command
+}
+
+/// Run a shell command with stdout on a pseudo terminal, `None` without `script(1)`.
+fn run_in_terminal(command: &str) -> Option<String> {
+    let output = Command::new("script")
+        .env_remove("NO_COLOR")
+        .args([
+            "-qec",
+            &format!("stty cols 80 rows 24; {command}"),
+            "/dev/null",
+        ])
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[test]
+fn test_piped_stdin_and_stdout() {
+    let config_dir = ConfigDir::new();
+    let mut child = aichat(config_dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(MARKDOWN.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+        MARKDOWN.trim_end()
+    );
+    assert!(output.stderr.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_piped_stdin_with_terminal_stdout() {
+    let config_dir = ConfigDir::new();
+    let input = temp_file("-terminal-", ".md");
+    fs::write(&input, MARKDOWN).unwrap();
+    let command = format!(
+        "AICHAT_CONFIG_DIR='{}' '{}' --no-stream --dry-run < '{}'",
+        config_dir.path().display(),
+        env!("CARGO_BIN_EXE_aichat"),
+        input.display()
+    );
+    let output = run_in_terminal(&command);
+    let _ = fs::remove_file(&input);
+    let Some(output) = output else {
+        return;
+    };
+    // Rendered, with the markdown highlighted
+    assert!(output.contains('\x1b'));
+    assert!(output.contains("Title"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_terminal_stdin_with_piped_stdout() {
+    let config_dir = ConfigDir::new();
+    let command = format!(
+        "AICHAT_CONFIG_DIR='{}' '{}' --no-stream --dry-run '**bold**' | od -An -c",
+        config_dir.path().display(),
+        env!("CARGO_BIN_EXE_aichat"),
+    );
+    let Some(output) = run_in_terminal(&command) else {
+        return;
+    };
+    // `od` shows the bytes aichat wrote to the pipe, an escape would show up as `033`
+    assert!(output.contains("*   *   b   o   l   d   *   *"));
+    assert!(!output.contains("033"));
+}
+
+#[test]
+fn test_piped_list_models() {
+    let config_dir = ConfigDir::new();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_aichat"))
+            .env("AICHAT_CONFIG_DIR", config_dir.path())
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    // One model id per line and no aliases, as scripts expect
+    let output = run(&["--list-models"]);
+    assert!(output.lines().any(|v| v == "openai:gpt-4o-mini"));
+    assert!(output.lines().all(|v| v.starts_with("openai:")));
+    let output = run(&["--list-models", "--json"]);
+    let items: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    assert!(items
+        .iter()
+        .all(|v| v["model"].as_str().unwrap().starts_with("openai:")));
+}
+
+#[test]
+fn test_quiet() {
+    // A model this small gets the attachment truncated, with a warning
+    let config_dir = ConfigDir::with_config(
+        "model: openai:tiny\nclients:\n- type: openai\n  api_key: sk-test\n  models:\n  - name: tiny\n    max_input_tokens: 200\n",
+    );
+    let file = temp_file("-terminal-", ".txt");
+    fs::write(&file, "word ".repeat(2000)).unwrap();
+    let run = |quiet: bool| {
+        let mut command = aichat(config_dir.path());
+        if quiet {
+            command.arg("--quiet");
+        }
+        command
+            .args(["-f", &file.display().to_string(), "summarize"])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let (loud, quiet) = (run(false), run(true));
+    let _ = fs::remove_file(&file);
+    assert!(loud.status.success());
+    assert!(String::from_utf8_lossy(&loud.stderr).contains("Truncated"));
+    assert!(quiet.status.success());
+    assert!(quiet.stderr.is_empty());
+    assert_eq!(quiet.stdout, loud.stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pipe() {
+    let config_dir = ConfigDir::new();
+    let output = aichat(config_dir.path())
+        .args(["--pipe", "tr a-z A-Z", "hello"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "HELLO");
+}
+
+#[test]
+fn test_output_format_openai_json() {
+    let config_dir = ConfigDir::with_config(
+        "model: openai:gpt-4o-mini\nmock:\n  text: 'You said: {{input}}'\nclients:\n- type: openai\n  api_key: sk-test\n",
+    );
+    let output = aichat(config_dir.path())
+        .args(["--output-format", "openai-json", "hi"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let exchange: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(exchange["object"], "chat.completion");
+    assert_eq!(exchange["model"], "gpt-4o-mini");
+    // The empty stdin is appended to the text
+    let messages = exchange["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages[0]["content"].as_str().unwrap().trim_end(), "hi");
+    let reply = exchange["choices"][0]["message"]["content"].as_str();
+    assert_eq!(reply.unwrap().trim_end(), "You said: hi");
+    assert_eq!(exchange["choices"][0]["finish_reason"], "stop");
+    assert!(exchange["usage"]["total_tokens"].is_u64());
+}